@@ -0,0 +1,6 @@
+DROP INDEX person_requirements_search_idx;
+DROP INDEX person_skills_search_idx;
+DROP INDEX persons_search_idx;
+DROP INDEX cases_search_idx;
+DROP FUNCTION search_vector(TEXT);
+DROP FUNCTION persian_normalize(TEXT);
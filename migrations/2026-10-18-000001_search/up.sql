@@ -0,0 +1,38 @@
+-- Folds the Arabic forms of yeh and kaf into the Persian ones, maps Persian
+-- and Arabic-Indic digits to ASCII, turns zero-width non-joiners into spaces
+-- and drops tatweel and diacritics. Keep in sync with `persian::normalize`.
+CREATE FUNCTION persian_normalize(input TEXT) RETURNS TEXT AS $$
+	SELECT translate(
+		input,
+		'يىك' || '۰۱۲۳۴۵۶۷۸۹' || '٠١٢٣٤٥٦٧٨٩' || chr(8204)
+			|| chr(1600) || chr(1611) || chr(1612) || chr(1613) || chr(1614)
+			|| chr(1615) || chr(1616) || chr(1617) || chr(1618),
+		'ییک' || '0123456789' || '0123456789' || ' '
+	)
+$$ LANGUAGE SQL IMMUTABLE STRICT PARALLEL SAFE;
+
+CREATE FUNCTION search_vector(document TEXT) RETURNS TSVECTOR AS $$
+	SELECT to_tsvector('simple', persian_normalize(document))
+$$ LANGUAGE SQL IMMUTABLE STRICT PARALLEL SAFE;
+
+-- The expressions below must match the ones used by the search queries,
+-- otherwise PostgreSQL will not pick up these indexes.
+CREATE INDEX cases_search_idx ON cases USING GIN (
+	search_vector(coalesce(address, '') || ' ' || coalesce(description, ''))
+);
+
+CREATE INDEX persons_search_idx ON persons USING GIN (
+	search_vector(
+		first_name || ' ' || last_name || ' ' || father_name || ' '
+		|| national_number || ' ' || ltrim(phone_number, '+') || ' '
+		|| regexp_replace(phone_number, '^\+98', '0')
+	)
+);
+
+CREATE INDEX person_skills_search_idx ON person_skills USING GIN (
+	search_vector(skill)
+);
+
+CREATE INDEX person_requirements_search_idx ON person_requirements USING GIN (
+	search_vector(description)
+);
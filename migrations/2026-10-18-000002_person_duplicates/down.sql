@@ -0,0 +1,4 @@
+DROP INDEX persons_birthday_idx;
+DROP INDEX persons_national_number_idx;
+
+DROP EXTENSION IF EXISTS pg_trgm;
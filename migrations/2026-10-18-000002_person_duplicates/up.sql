@@ -0,0 +1,4 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX persons_national_number_idx ON persons (national_number);
+CREATE INDEX persons_birthday_idx ON persons (birthday);
@@ -0,0 +1,16 @@
+-- before/after hold full snapshots for creations and deletions and only the
+-- changed fields for updates. A user who is the actor of any entry cannot be
+-- deleted, so no entry loses who made the change.
+CREATE TABLE audit_log (
+	id UUID PRIMARY KEY,
+	actor UUID NULL REFERENCES users ON DELETE RESTRICT,
+	entity_type TEXT NOT NULL,
+	entity_id UUID NOT NULL,
+	operation TEXT NOT NULL,
+	created_at TIMESTAMP NOT NULL,
+	before JSONB NULL,
+	after JSONB NULL
+);
+
+CREATE INDEX audit_log_entity_idx ON audit_log (entity_type, entity_id, created_at);
+CREATE INDEX audit_log_actor_idx ON audit_log (actor, created_at);
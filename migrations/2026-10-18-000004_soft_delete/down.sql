@@ -0,0 +1,10 @@
+DROP INDEX persons_deleted_at_idx;
+DROP INDEX cases_deleted_at_idx;
+
+ALTER TABLE persons
+	DROP COLUMN deleted_by,
+	DROP COLUMN deleted_at;
+
+ALTER TABLE cases
+	DROP COLUMN deleted_by,
+	DROP COLUMN deleted_at;
@@ -0,0 +1,12 @@
+-- Deleted cases and persons stay in the table until purged, so a mistaken
+-- delete can be restored together with the rows that hang off them.
+ALTER TABLE cases
+	ADD COLUMN deleted_at TIMESTAMP NULL,
+	ADD COLUMN deleted_by UUID NULL REFERENCES users ON DELETE RESTRICT;
+
+ALTER TABLE persons
+	ADD COLUMN deleted_at TIMESTAMP NULL,
+	ADD COLUMN deleted_by UUID NULL REFERENCES users ON DELETE RESTRICT;
+
+CREATE INDEX cases_deleted_at_idx ON cases (deleted_at) WHERE deleted_at IS NOT NULL;
+CREATE INDEX persons_deleted_at_idx ON persons (deleted_at) WHERE deleted_at IS NOT NULL;
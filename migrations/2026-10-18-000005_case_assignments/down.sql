@@ -0,0 +1 @@
+DROP TABLE case_assignments;
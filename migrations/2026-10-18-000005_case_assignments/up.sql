@@ -0,0 +1,15 @@
+-- Which users may work on which case: 0 viewer, 1 editor, 2 owner.
+-- Admins are not listed here; they can access every case.
+CREATE TABLE case_assignments (
+	case_id UUID NOT NULL REFERENCES cases ON DELETE CASCADE,
+	user_id UUID NOT NULL REFERENCES users ON DELETE CASCADE,
+	permission INTEGER NOT NULL CHECK (permission BETWEEN 0 AND 2),
+
+	PRIMARY KEY (case_id, user_id)
+);
+
+CREATE INDEX case_assignments_user_id_idx ON case_assignments (user_id);
+
+-- Whoever registered a case keeps access to it.
+INSERT INTO case_assignments (case_id, user_id, permission)
+SELECT id, editor, 2 FROM cases;
@@ -0,0 +1,11 @@
+-- Argon2id hashes cannot be turned back into SHA-256 ones; accounts that only
+-- have a PHC string are left with an empty hash and need a password reset.
+UPDATE users
+SET password_hash = '', password_salt = ''
+WHERE password_hash IS NULL OR password_salt IS NULL;
+
+ALTER TABLE users
+	DROP CONSTRAINT users_password_check,
+	ALTER COLUMN password_salt SET NOT NULL,
+	ALTER COLUMN password_hash SET NOT NULL,
+	DROP COLUMN password_phc;
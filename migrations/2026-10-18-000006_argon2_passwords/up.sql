@@ -0,0 +1,11 @@
+-- Passwords are stored as Argon2id PHC strings. Accounts that still have the
+-- old salted SHA-256 hash keep it until their next successful login, when it
+-- is replaced by a PHC string and cleared.
+ALTER TABLE users
+	ADD COLUMN password_phc TEXT NULL,
+	ALTER COLUMN password_hash DROP NOT NULL,
+	ALTER COLUMN password_salt DROP NOT NULL,
+	ADD CONSTRAINT users_password_check CHECK (
+		password_phc IS NOT NULL
+		OR (password_hash IS NOT NULL AND password_salt IS NOT NULL)
+	);
@@ -0,0 +1,5 @@
+-- Disabled users cannot log in. `must_change_password` is set when an admin
+-- resets a password and cleared once the user picks a new one.
+ALTER TABLE users
+	ADD COLUMN active BOOLEAN NOT NULL DEFAULT TRUE,
+	ADD COLUMN must_change_password BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,7 @@
+ALTER TABLE case_actions DROP CONSTRAINT case_actions_status_check;
+
+ALTER TABLE persons DROP CONSTRAINT persons_family_role_check;
+
+ALTER TABLE users
+	DROP CONSTRAINT users_role_check,
+	ALTER COLUMN role SET DEFAULT 3;
@@ -0,0 +1,13 @@
+-- The integer columns only hold the numbers of their Rust enums:
+-- users.role: Admin 0, Editor 1, User 2
+-- persons.family_role: Father 0, Mother 1, Children 2, NA 3
+-- case_actions.status: Todo 0, Doing 1, Done 2
+ALTER TABLE users
+	ALTER COLUMN role SET DEFAULT 2,
+	ADD CONSTRAINT users_role_check CHECK (role BETWEEN 0 AND 2);
+
+ALTER TABLE persons
+	ADD CONSTRAINT persons_family_role_check CHECK (family_role BETWEEN 0 AND 3);
+
+ALTER TABLE case_actions
+	ADD CONSTRAINT case_actions_status_check CHECK (status BETWEEN 0 AND 2);
@@ -0,0 +1,14 @@
+DROP TABLE case_action_comments;
+
+DROP INDEX case_actions_assignee_idx;
+
+-- Cancelled actions have no place in the old states; they become Done.
+UPDATE case_actions SET status = 2 WHERE status = 3;
+
+ALTER TABLE case_actions
+	DROP CONSTRAINT case_actions_status_check,
+	ADD CONSTRAINT case_actions_status_check CHECK (status BETWEEN 0 AND 2),
+	DROP COLUMN result_note,
+	DROP COLUMN completed_by,
+	DROP COLUMN completed_at,
+	DROP COLUMN assignee;
@@ -0,0 +1,22 @@
+-- Actions move Todo -> Doing -> Done, or to Cancelled (3), through explicit
+-- transitions. `result_note` holds the outcome of a completed action or the
+-- reason it was cancelled.
+ALTER TABLE case_actions
+	ADD COLUMN assignee UUID NULL REFERENCES users ON DELETE SET NULL,
+	ADD COLUMN completed_at TIMESTAMP NULL,
+	ADD COLUMN completed_by UUID NULL REFERENCES users ON DELETE RESTRICT,
+	ADD COLUMN result_note TEXT NULL,
+	DROP CONSTRAINT case_actions_status_check,
+	ADD CONSTRAINT case_actions_status_check CHECK (status BETWEEN 0 AND 3);
+
+CREATE INDEX case_actions_assignee_idx ON case_actions (assignee) WHERE assignee IS NOT NULL;
+
+CREATE TABLE case_action_comments (
+	id UUID PRIMARY KEY,
+	case_action_id UUID NOT NULL REFERENCES case_actions ON DELETE CASCADE,
+	author UUID NULL REFERENCES users ON DELETE RESTRICT,
+	body TEXT NOT NULL,
+	created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX case_action_comments_case_action_id_idx ON case_action_comments (case_action_id, created_at);
@@ -0,0 +1,6 @@
+ALTER TABLE case_actions
+	DROP CONSTRAINT case_actions_series_occurrence_key,
+	DROP COLUMN occurrence_at,
+	DROP COLUMN series_id;
+
+DROP TABLE case_action_series;
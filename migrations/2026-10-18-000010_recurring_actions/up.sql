@@ -0,0 +1,26 @@
+-- A series describes a repeating action; its occurrences are ordinary case
+-- actions created ahead of time up to a horizon, each remembering the slot
+-- of the series it fills so it is never created twice.
+CREATE TABLE case_action_series (
+	id UUID PRIMARY KEY,
+	case_id UUID NOT NULL REFERENCES cases ON DELETE CASCADE,
+	action TEXT NOT NULL,
+	assignee UUID NULL REFERENCES users ON DELETE SET NULL,
+	starts_at TIMESTAMP NOT NULL,
+	-- Daily 0, Weekly 1, Monthly 2
+	frequency INTEGER NOT NULL CHECK (frequency BETWEEN 0 AND 2),
+	repeat_every INTEGER NOT NULL DEFAULT 1 CHECK (repeat_every >= 1),
+	repeat_until TIMESTAMP NULL,
+	occurrence_count INTEGER NULL CHECK (occurrence_count >= 1),
+	materialized_until TIMESTAMP NULL,
+	cancelled_at TIMESTAMP NULL,
+
+	CHECK (repeat_until IS NULL OR occurrence_count IS NULL)
+);
+
+CREATE INDEX case_action_series_case_id_idx ON case_action_series (case_id);
+
+ALTER TABLE case_actions
+	ADD COLUMN series_id UUID NULL REFERENCES case_action_series ON DELETE SET NULL,
+	ADD COLUMN occurrence_at TIMESTAMP NULL,
+	ADD CONSTRAINT case_actions_series_occurrence_key UNIQUE (series_id, occurrence_at);
@@ -0,0 +1,4 @@
+-- IANA name such as `Asia/Tehran`. Agenda queries read "today" in this
+-- zone unless the request names another; NULL means UTC.
+ALTER TABLE users
+	ADD COLUMN timezone TEXT NULL;
@@ -0,0 +1 @@
+DROP TABLE need_weights;
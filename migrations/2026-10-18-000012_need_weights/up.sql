@@ -0,0 +1,21 @@
+-- Weights of the need score that ranks cases for aid. There is exactly one
+-- row; admins tune it instead of the code.
+CREATE TABLE need_weights (
+	id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+	-- How far per-capita income is below the poverty line.
+	income DOUBLE PRECISION NOT NULL DEFAULT 0.5 CHECK (income >= 0),
+	-- How close the household is to `large_household` members.
+	household_size DOUBLE PRECISION NOT NULL DEFAULT 0.2 CHECK (household_size >= 0),
+	-- Share of children and elderly in the household.
+	dependents DOUBLE PRECISION NOT NULL DEFAULT 0.2 CHECK (dependents >= 0),
+	-- Whether nobody in the household has an income.
+	no_earner DOUBLE PRECISION NOT NULL DEFAULT 0.1 CHECK (no_earner >= 0),
+	-- Monthly income per person, in Toman, at or above which a household
+	-- scores nothing for income.
+	poverty_line INTEGER NOT NULL DEFAULT 5000000 CHECK (poverty_line > 0),
+	large_household INTEGER NOT NULL DEFAULT 6 CHECK (large_household > 0),
+
+	CHECK (income + household_size + dependents + no_earner > 0)
+);
+
+INSERT INTO need_weights DEFAULT VALUES;
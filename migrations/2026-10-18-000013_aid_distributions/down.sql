@@ -0,0 +1,2 @@
+DROP TABLE aid_distribution_items;
+DROP TABLE aid_distributions;
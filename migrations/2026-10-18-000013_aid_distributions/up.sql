@@ -0,0 +1,36 @@
+-- What was actually given to a case: cash in Toman, or items in kind, each
+-- optionally meant for one person and one of their requirements. Cases and
+-- persons that were given aid cannot be removed, so the ledger stays whole.
+CREATE TABLE aid_distributions (
+	id UUID PRIMARY KEY,
+	case_id UUID NOT NULL REFERENCES cases ON DELETE RESTRICT,
+	person_id UUID NULL REFERENCES persons ON DELETE RESTRICT,
+	requirement_id UUID NULL REFERENCES person_requirements ON DELETE SET NULL,
+	-- Cash 0, InKind 1
+	kind INTEGER NOT NULL CHECK (kind BETWEEN 0 AND 1),
+	-- Toman; only for cash.
+	amount INTEGER NULL CHECK (amount > 0),
+	distributed_on DATE NOT NULL,
+	-- Donor or fund the aid came from.
+	source VARCHAR NOT NULL,
+	issued_by UUID NULL REFERENCES users ON DELETE RESTRICT,
+	note TEXT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+
+	CHECK ((kind = 0) = (amount IS NOT NULL))
+);
+
+CREATE INDEX aid_distributions_case_id_idx ON aid_distributions (case_id, distributed_on);
+
+CREATE TABLE aid_distribution_items (
+	id UUID PRIMARY KEY,
+	distribution_id UUID NOT NULL REFERENCES aid_distributions ON DELETE CASCADE,
+	name VARCHAR NOT NULL,
+	quantity INTEGER NOT NULL CHECK (quantity > 0),
+	unit VARCHAR NULL,
+	-- Toman for the whole quantity, counted against the monthly cap.
+	estimated_value INTEGER NULL CHECK (estimated_value >= 0)
+);
+
+CREATE INDEX aid_distribution_items_distribution_id_idx
+	ON aid_distribution_items (distribution_id);
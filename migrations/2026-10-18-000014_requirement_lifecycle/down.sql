@@ -0,0 +1,9 @@
+DROP INDEX person_requirements_open_idx;
+
+ALTER TABLE person_requirements
+	DROP COLUMN fulfilment_note,
+	DROP COLUMN due_date,
+	DROP COLUMN estimated_cost,
+	DROP COLUMN status,
+	DROP COLUMN priority,
+	DROP COLUMN category;
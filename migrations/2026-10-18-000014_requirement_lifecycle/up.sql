@@ -0,0 +1,17 @@
+-- Requirements existing before this get the Other category, Normal priority
+-- and stay Open.
+-- category: Medical 0, Housing 1, Education 2, Employment 3, Food 4, Other 5
+-- priority: Low 0, Normal 1, High 2, Urgent 3
+-- status: Open 0, InProgress 1, Fulfilled 2, Rejected 3
+ALTER TABLE person_requirements
+	ADD COLUMN category INTEGER NOT NULL DEFAULT 5 CHECK (category BETWEEN 0 AND 5),
+	ADD COLUMN priority INTEGER NOT NULL DEFAULT 1 CHECK (priority BETWEEN 0 AND 3),
+	ADD COLUMN status INTEGER NOT NULL DEFAULT 0 CHECK (status BETWEEN 0 AND 3),
+	-- Toman.
+	ADD COLUMN estimated_cost INTEGER NULL CHECK (estimated_cost >= 0),
+	ADD COLUMN due_date DATE NULL,
+	-- How a fulfilled requirement was met, or why it was rejected.
+	ADD COLUMN fulfilment_note TEXT NULL;
+
+CREATE INDEX person_requirements_open_idx ON person_requirements (category, priority)
+	WHERE status IN (0, 1);
@@ -0,0 +1,16 @@
+ALTER TABLE person_skills ADD COLUMN skill VARCHAR(200) NULL;
+
+UPDATE person_skills SET skill = skills.name
+FROM skills
+WHERE skills.id = person_skills.skill_id;
+
+ALTER TABLE person_skills
+	ALTER COLUMN skill SET NOT NULL,
+	DROP COLUMN skill_id;
+
+CREATE INDEX person_skills_search_idx ON person_skills USING GIN (
+	search_vector(skill)
+);
+
+DROP TABLE skill_synonyms;
+DROP TABLE skills;
@@ -0,0 +1,52 @@
+-- A managed catalogue of skills. `name_key` is the name passed through
+-- persian_normalize with runs of spaces collapsed and lower-cased, as
+-- skills::name_key does, so names that only differ in keyboard layout or
+-- spacing are the same skill. Keys are unique across skills and synonyms.
+CREATE TABLE skills (
+	id UUID PRIMARY KEY,
+	name VARCHAR(200) NOT NULL,
+	name_key VARCHAR(200) NOT NULL UNIQUE,
+	category VARCHAR(100) NULL
+);
+
+-- Other names a skill is known by, such as نجار for نجاری.
+CREATE TABLE skill_synonyms (
+	name_key VARCHAR(200) PRIMARY KEY,
+	skill_id UUID NOT NULL REFERENCES skills ON DELETE CASCADE,
+	name VARCHAR(200) NOT NULL
+);
+
+CREATE INDEX skill_synonyms_skill_id_idx ON skill_synonyms (skill_id);
+
+CREATE INDEX skills_search_idx ON skills USING GIN (search_vector(name));
+CREATE INDEX skill_synonyms_search_idx ON skill_synonyms USING GIN (search_vector(name));
+
+-- Every skill typed so far becomes an entry of the catalogue.
+INSERT INTO skills (id, name, name_key)
+SELECT gen_random_uuid(), min(name), name_key
+FROM (
+	SELECT
+		btrim(skill) AS name,
+		lower(btrim(regexp_replace(persian_normalize(skill), '\s+', ' ', 'g'))) AS name_key
+	FROM person_skills
+) typed
+GROUP BY name_key;
+
+ALTER TABLE person_skills ADD COLUMN skill_id UUID NULL REFERENCES skills;
+
+UPDATE person_skills SET skill_id = skills.id
+FROM skills
+WHERE skills.name_key
+	= lower(btrim(regexp_replace(persian_normalize(person_skills.skill), '\s+', ' ', 'g')));
+
+-- A person who typed the same skill twice keeps one of them.
+DELETE FROM person_skills a
+USING person_skills b
+WHERE a.person_id = b.person_id AND a.skill_id = b.skill_id AND a.id > b.id;
+
+ALTER TABLE person_skills
+	ALTER COLUMN skill_id SET NOT NULL,
+	DROP COLUMN skill,
+	ADD CONSTRAINT person_skills_person_id_skill_id_key UNIQUE (person_id, skill_id);
+
+CREATE INDEX person_skills_skill_id_idx ON person_skills (skill_id);
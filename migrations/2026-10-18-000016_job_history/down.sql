@@ -0,0 +1,7 @@
+ALTER TABLE person_jobs
+	DROP CONSTRAINT person_jobs_dates_check,
+	DROP COLUMN monthly_income,
+	DROP COLUMN income_period,
+	DROP COLUMN employment_type,
+	DROP COLUMN end_date,
+	DROP COLUMN start_date;
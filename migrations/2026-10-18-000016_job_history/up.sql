@@ -0,0 +1,13 @@
+-- Jobs without dates, as every job before this, count as current.
+-- employment_type: FullTime 0, PartTime 1, DailyWage 2, SelfEmployed 3
+-- income_period: Monthly 0, Daily 1; income was monthly until now.
+ALTER TABLE person_jobs
+	ADD COLUMN start_date DATE NULL,
+	ADD COLUMN end_date DATE NULL,
+	ADD COLUMN employment_type INTEGER NULL CHECK (employment_type BETWEEN 0 AND 3),
+	ADD COLUMN income_period INTEGER NOT NULL DEFAULT 0 CHECK (income_period BETWEEN 0 AND 1),
+	-- `income` per month, whatever period it is given in.
+	ADD COLUMN monthly_income INTEGER NULL CHECK (monthly_income >= 0),
+	ADD CONSTRAINT person_jobs_dates_check CHECK (end_date >= start_date);
+
+UPDATE person_jobs SET monthly_income = income WHERE income >= 0;
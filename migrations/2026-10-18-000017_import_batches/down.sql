@@ -0,0 +1,4 @@
+DROP TABLE import_batch_jobs;
+DROP TABLE import_batch_persons;
+ALTER TABLE cases DROP COLUMN import_batch_id;
+DROP TABLE import_batches;
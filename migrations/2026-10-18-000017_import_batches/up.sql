@@ -0,0 +1,32 @@
+-- Families imported from one spreadsheet in a single transaction, so that
+-- they can be taken out again together.
+CREATE TABLE import_batches (
+	id UUID PRIMARY KEY,
+	file_name VARCHAR NOT NULL,
+	imported_by UUID NULL REFERENCES users ON DELETE RESTRICT,
+	imported_at TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+	row_count INTEGER NOT NULL CHECK (row_count >= 0),
+	case_count INTEGER NOT NULL CHECK (case_count >= 0),
+	person_count INTEGER NOT NULL CHECK (person_count >= 0),
+	job_count INTEGER NOT NULL CHECK (job_count >= 0),
+	rolled_back_by UUID NULL REFERENCES users ON DELETE RESTRICT,
+	rolled_back_at TIMESTAMP NULL
+);
+
+ALTER TABLE cases ADD COLUMN import_batch_id UUID NULL REFERENCES import_batches ON DELETE SET NULL;
+
+CREATE INDEX cases_import_batch_id_idx ON cases (import_batch_id) WHERE import_batch_id IS NOT NULL;
+
+-- The persons and jobs a batch created. Rolling the batch back removes
+-- these and nothing else.
+CREATE TABLE import_batch_persons (
+	import_batch_id UUID NOT NULL REFERENCES import_batches ON DELETE CASCADE,
+	person_id UUID NOT NULL REFERENCES persons ON DELETE CASCADE,
+	PRIMARY KEY (import_batch_id, person_id)
+);
+
+CREATE TABLE import_batch_jobs (
+	import_batch_id UUID NOT NULL REFERENCES import_batches ON DELETE CASCADE,
+	person_job_id UUID NOT NULL REFERENCES person_jobs ON DELETE CASCADE,
+	PRIMARY KEY (import_batch_id, person_job_id)
+);
@@ -0,0 +1 @@
+DROP TABLE dossier_template;
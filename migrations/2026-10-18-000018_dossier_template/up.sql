@@ -0,0 +1,10 @@
+-- Template case dossiers are printed from. There is exactly one row; a
+-- missing body means the built-in template.
+CREATE TABLE dossier_template (
+	id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+	body TEXT NULL,
+	updated_by UUID NULL REFERENCES users ON DELETE RESTRICT,
+	updated_at TIMESTAMP NULL
+);
+
+INSERT INTO dossier_template DEFAULT VALUES;
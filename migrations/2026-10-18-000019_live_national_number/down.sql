@@ -0,0 +1 @@
+DROP INDEX IF EXISTS persons_national_number_live_idx;
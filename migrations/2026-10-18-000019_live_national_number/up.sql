@@ -0,0 +1,16 @@
+-- Live persons may not share a national number under the "reject" duplicate
+-- policy. Under "warn" duplicates are allowed, so the index is left out when
+-- some already exist; admins add or drop it later through
+-- /person/unique-national-numbers.
+DO $$
+BEGIN
+	IF NOT EXISTS (
+		SELECT national_number FROM persons
+		WHERE deleted_at IS NULL
+		GROUP BY national_number
+		HAVING count(*) > 1
+	) THEN
+		CREATE UNIQUE INDEX persons_national_number_live_idx
+			ON persons (national_number) WHERE deleted_at IS NULL;
+	END IF;
+END $$;
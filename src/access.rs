@@ -0,0 +1,313 @@
+use crate::audit::{self, Auditable, Operation};
+use crate::errors::*;
+use crate::schema::*;
+use crate::website::Db;
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::Integer;
+use diesel::PgConnection;
+use rocket::serde::{Deserialize, Serialize};
+use std::io::Write;
+use uuid::Uuid;
+
+/// What a user assigned to a case may do with it. Levels are ordered, so a
+/// higher level includes everything the lower ones allow.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    AsExpression,
+    FromSqlRow,
+)]
+#[sql_type = "Integer"]
+#[serde(rename_all = "lowercase")]
+pub enum CasePermission {
+    Viewer,
+    Editor,
+    Owner,
+}
+
+impl ToSql<Integer, Pg> for CasePermission {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        let value = match self {
+            CasePermission::Viewer => 0,
+            CasePermission::Editor => 1,
+            CasePermission::Owner => 2,
+        };
+        ToSql::<Integer, Pg>::to_sql(&value, out)
+    }
+}
+
+impl FromSql<Integer, Pg> for CasePermission {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        match <i32 as FromSql<Integer, Pg>>::from_sql(bytes)? {
+            0 => Ok(CasePermission::Viewer),
+            1 => Ok(CasePermission::Editor),
+            2 => Ok(CasePermission::Owner),
+            n => Err(format!("invalid case permission {}", n).into()),
+        }
+    }
+}
+
+#[derive(Debug, Queryable, Insertable, Serialize, Deserialize, Clone)]
+pub struct CaseAssignment {
+    pub case_id: Uuid,
+    pub user_id: Uuid,
+    pub permission: CasePermission,
+}
+
+/// Keyed by case, so a case's history shows who was given access to it.
+impl Auditable for CaseAssignment {
+    const ENTITY_TYPE: &'static str = "case_assignment";
+
+    fn entity_id(&self) -> Uuid {
+        self.case_id
+    }
+}
+
+/// Which cases a user may list. Admins see every case, everyone else only
+/// the cases they are assigned to.
+#[derive(Debug, Clone, Copy)]
+pub enum Visibility {
+    All,
+    AssignedTo(Uuid),
+}
+
+pub type AssignedCaseIds = diesel::dsl::Filter<
+    diesel::dsl::Select<case_assignments::table, case_assignments::case_id>,
+    diesel::dsl::Eq<case_assignments::user_id, Uuid>,
+>;
+
+/// Ids of the cases `user` is assigned to, for filtering list queries.
+pub fn assigned_case_ids(user: Uuid) -> AssignedCaseIds {
+    case_assignments::table
+        .select(case_assignments::case_id)
+        .filter(case_assignments::user_id.eq(user))
+}
+
+/// A row whose access is decided by the case it belongs to.
+#[derive(Debug, Clone, Copy)]
+pub enum Target {
+    Case(Uuid),
+    Person(Uuid),
+    PersonJob(Uuid),
+    PersonSkill(Uuid),
+    PersonRequirement(Uuid),
+    CaseAction(Uuid),
+    CaseActionSeries(Uuid),
+    AidDistribution(Uuid),
+}
+
+impl Target {
+    /// The case the target belongs to, or `None` when it does not exist or
+    /// is in the trash.
+    fn case_id(self, c: &PgConnection) -> QueryResult<Option<Uuid>> {
+        let live_persons = || {
+            persons::table
+                .filter(persons::deleted_at.is_null())
+                .select(persons::case_id)
+        };
+
+        let p_case_id = match self {
+            Target::Case(p_id) => Some(p_id),
+            Target::Person(p_id) => live_persons()
+                .filter(persons::id.eq(p_id))
+                .first::<Uuid>(c)
+                .optional()?,
+            Target::PersonJob(p_id) => live_persons()
+                .filter(
+                    persons::id.eq_any(
+                        person_jobs::table
+                            .select(person_jobs::person_id)
+                            .filter(person_jobs::id.eq(p_id)),
+                    ),
+                )
+                .first::<Uuid>(c)
+                .optional()?,
+            Target::PersonSkill(p_id) => live_persons()
+                .filter(
+                    persons::id.eq_any(
+                        person_skills::table
+                            .select(person_skills::person_id)
+                            .filter(person_skills::id.eq(p_id)),
+                    ),
+                )
+                .first::<Uuid>(c)
+                .optional()?,
+            Target::PersonRequirement(p_id) => live_persons()
+                .filter(
+                    persons::id.eq_any(
+                        person_requirements::table
+                            .select(person_requirements::person_id)
+                            .filter(person_requirements::id.eq(p_id)),
+                    ),
+                )
+                .first::<Uuid>(c)
+                .optional()?,
+            Target::CaseAction(p_id) => case_actions::table
+                .find(p_id)
+                .select(case_actions::case_id)
+                .first::<Uuid>(c)
+                .optional()?,
+            Target::CaseActionSeries(p_id) => case_action_series::table
+                .find(p_id)
+                .select(case_action_series::case_id)
+                .first::<Uuid>(c)
+                .optional()?,
+            Target::AidDistribution(p_id) => aid_distributions::table
+                .find(p_id)
+                .select(aid_distributions::case_id)
+                .first::<Uuid>(c)
+                .optional()?,
+        };
+
+        match p_case_id {
+            None => Ok(None),
+            Some(p_case_id) => cases::table
+                .find(p_case_id)
+                .filter(cases::deleted_at.is_null())
+                .select(cases::id)
+                .first::<Uuid>(c)
+                .optional(),
+        }
+    }
+}
+
+/// Implemented by request bodies so the cases they touch can be checked
+/// before they are saved.
+pub trait Targets {
+    fn targets(&self) -> Vec<Target>;
+}
+
+impl Targets for Target {
+    fn targets(&self) -> Vec<Target> {
+        vec![*self]
+    }
+}
+
+/// Fails unless `user` holds at least `level` on the case of every target.
+pub async fn check(
+    conn: &Db,
+    user: Uuid,
+    targets: Vec<Target>,
+    level: CasePermission,
+) -> Result<()> {
+    conn.run(move |c| {
+        for target in targets {
+            let p_case_id = match target.case_id(c)? {
+                Some(p_case_id) => p_case_id,
+                None => return Err(Errors::BadRequest("id not found".to_owned())),
+            };
+
+            let permission = case_assignments::table
+                .find((p_case_id, user))
+                .select(case_assignments::permission)
+                .first::<CasePermission>(c)
+                .optional()?;
+
+            match permission {
+                Some(permission) if permission >= level => {}
+                _ => {
+                    return Err(Errors::Forbidden(format!(
+                        "{:?} access to case {} is required",
+                        level, p_case_id
+                    )))
+                }
+            }
+        }
+        Ok(())
+    })
+    .await
+}
+
+impl CaseAssignment {
+    pub async fn all_by_case_id(conn: &Db, p_case_id: Uuid) -> Result<Vec<CaseAssignment>> {
+        use self::case_assignments::dsl::*;
+
+        conn.run(move |c| {
+            case_assignments
+                .filter(case_id.eq(p_case_id))
+                .order(permission.desc())
+                .load::<CaseAssignment>(c)
+        })
+        .await
+        .map_err(|e| Errors::DatabaseError(e.to_string()))
+    }
+
+    pub async fn all_by_user_id(conn: &Db, p_user_id: Uuid) -> Result<Vec<CaseAssignment>> {
+        use self::case_assignments::dsl::*;
+
+        conn.run(move |c| {
+            case_assignments
+                .filter(user_id.eq(p_user_id))
+                .load::<CaseAssignment>(c)
+        })
+        .await
+        .map_err(|e| Errors::DatabaseError(e.to_string()))
+    }
+
+    /// Gives the user access to the case, replacing the level they had.
+    pub async fn assign(self, conn: &Db, actor: Uuid) -> Result<()> {
+        conn.run(move |c| c.transaction(|| self.assign_in(c, actor)))
+            .await
+    }
+
+    pub fn assign_in(self, c: &PgConnection, actor: Uuid) -> Result<()> {
+        use self::case_assignments::dsl::*;
+
+        let exists = cases::table
+            .find(self.case_id)
+            .filter(cases::deleted_at.is_null())
+            .count()
+            .get_result::<i64>(c)?;
+        if exists == 0 {
+            return Err(Errors::BadRequest("case not found".to_owned()));
+        }
+
+        let before = case_assignments
+            .find((self.case_id, self.user_id))
+            .get_result::<CaseAssignment>(c)
+            .optional()?;
+        let after = diesel::insert_into(case_assignments)
+            .values(&self)
+            .on_conflict((case_id, user_id))
+            .do_update()
+            .set(permission.eq(self.permission))
+            .get_result::<CaseAssignment>(c)?;
+
+        let operation = match before {
+            Some(_) => Operation::Update,
+            None => Operation::Create,
+        };
+        audit::record(c, actor, operation, before.as_ref(), Some(&after))?;
+        Ok(())
+    }
+
+    pub async fn unassign(conn: &Db, p_case_id: Uuid, p_user_id: Uuid, actor: Uuid) -> Result<()> {
+        use self::case_assignments::dsl::*;
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = diesel::delete(case_assignments.find((p_case_id, p_user_id)))
+                    .get_result::<CaseAssignment>(c)
+                    .optional()?;
+                match before {
+                    None => Err(Errors::BadRequest("assignment not found".to_owned())),
+                    Some(before) => {
+                        audit::record(c, actor, Operation::Delete, Some(&before), None)?;
+                        Ok(())
+                    }
+                }
+            })
+        })
+        .await
+    }
+}
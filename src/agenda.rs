@@ -0,0 +1,173 @@
+use crate::enums::ActionStatus;
+use crate::errors::*;
+use crate::filters::{AgendaFilter, AgendaPeriod};
+use crate::jalali::{self, Calendar};
+use crate::pagination::QueryDate;
+use crate::schema::users;
+use crate::validation;
+use crate::website::Db;
+use chrono::{Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+/// Longest range one agenda request may cover.
+const MAX_AGENDA_DAYS: i64 = 366;
+
+/// Half-open range `[from, to)` of UTC times, which is how `action_date` is
+/// stored.
+#[derive(Debug, Clone, Copy)]
+pub struct Window {
+    pub from: NaiveDateTime,
+    pub to: NaiveDateTime,
+}
+
+impl Window {
+    /// The local days `first` to `last`, both included, in `tz`.
+    pub fn days(tz: Tz, first: NaiveDate, last: NaiveDate) -> Window {
+        Window {
+            from: start_of_day(tz, first),
+            to: start_of_day(tz, last.succ()),
+        }
+    }
+
+    pub fn today(tz: Tz) -> Window {
+        let today = today(tz);
+        Window::days(tz, today, today)
+    }
+
+    /// Today and the six days after it.
+    pub fn week(tz: Tz) -> Window {
+        let today = today(tz);
+        Window::days(tz, today, today + Duration::days(6))
+    }
+}
+
+/// What an agenda lists: open actions in a window, actions that are overdue,
+/// or both.
+#[derive(Debug, Clone)]
+pub struct AgendaQuery {
+    pub window: Option<Window>,
+    /// Statuses of the actions in `window`.
+    pub statuses: Vec<ActionStatus>,
+    /// Also lists actions still open whose date is before this time.
+    pub overdue_before: Option<NaiveDateTime>,
+    pub case_id: Option<Uuid>,
+    pub assignee: Option<Uuid>,
+}
+
+impl AgendaQuery {
+    /// Open actions of the given window.
+    pub fn window(window: Window) -> Self {
+        AgendaQuery {
+            window: Some(window),
+            statuses: ActionStatus::OPEN.to_vec(),
+            overdue_before: None,
+            case_id: None,
+            assignee: None,
+        }
+    }
+
+    /// Open actions whose date has passed.
+    pub fn overdue() -> Self {
+        AgendaQuery {
+            window: None,
+            statuses: Vec::new(),
+            overdue_before: Some(Utc::now().naive_utc()),
+            case_id: None,
+            assignee: None,
+        }
+    }
+
+    /// Reads `from` and `to` as local days in `tz`. Without them the agenda
+    /// covers today; with only `from` it covers that one day. A `period`
+    /// covers the week or month of `calendar` that `from`, or today, is in.
+    pub fn from_filter(filter: AgendaFilter, tz: Tz, calendar: Calendar) -> Result<Self> {
+        let day = filter
+            .from
+            .map_or_else(|| today(tz), |QueryDate(from)| from);
+        let (first, last) = match filter.period {
+            Some(_) if filter.to.is_some() => {
+                return Err(validation::field_error(
+                    "period",
+                    "conflict",
+                    "cannot be given with to".to_owned(),
+                ))
+            }
+            Some(AgendaPeriod::Week) => jalali::week_of(day, calendar),
+            Some(AgendaPeriod::Month) => jalali::month_of(day, calendar),
+            None => (day, filter.to.map_or(day, |QueryDate(to)| to)),
+        };
+
+        if last < first {
+            return Err(validation::field_error(
+                "to",
+                "out_of_range",
+                "must not be before from".to_owned(),
+            ));
+        }
+        if (last - first).num_days() >= MAX_AGENDA_DAYS {
+            return Err(validation::field_error(
+                "to",
+                "out_of_range",
+                format!("an agenda covers at most {} days", MAX_AGENDA_DAYS),
+            ));
+        }
+
+        let statuses = match filter.status.is_empty() {
+            true => ActionStatus::OPEN.to_vec(),
+            false => filter.status,
+        };
+
+        Ok(AgendaQuery {
+            window: Some(Window::days(tz, first, last)),
+            statuses,
+            overdue_before: filter
+                .overdue
+                .unwrap_or(false)
+                .then(|| Utc::now().naive_utc()),
+            case_id: filter.case_id,
+            assignee: filter.assignee,
+        })
+    }
+}
+
+pub fn today(tz: Tz) -> NaiveDate {
+    Utc::now().with_timezone(&tz).date().naive_local()
+}
+
+/// UTC time at which `day` starts in `tz`. Where a clock change skips
+/// midnight, the day starts at its first local time that exists.
+fn start_of_day(tz: Tz, day: NaiveDate) -> NaiveDateTime {
+    let midnight = day.and_hms(0, 0, 0);
+    (0..=2)
+        .find_map(|hours| {
+            tz.from_local_datetime(&(midnight + Duration::hours(hours)))
+                .earliest()
+        })
+        .map_or(midnight, |start| start.naive_utc())
+}
+
+/// Zone an agenda is read in: `requested` when given, otherwise the one in
+/// the user's profile, otherwise UTC.
+pub async fn timezone(conn: &Db, user_id: Uuid, requested: Option<String>) -> Result<Tz> {
+    if let Some(requested) = requested {
+        return validation::timezone(&requested)
+            .map_err(|(code, message)| validation::field_error("tz", code, message));
+    }
+
+    let stored = conn
+        .run(move |c| {
+            users::table
+                .find(user_id)
+                .select(users::timezone)
+                .first::<Option<String>>(c)
+                .optional()
+        })
+        .await?
+        .flatten();
+
+    Ok(stored
+        .and_then(|stored| validation::timezone(&stored).ok())
+        .unwrap_or(Tz::UTC))
+}
@@ -0,0 +1,561 @@
+use crate::access::{self, Target, Targets, Visibility};
+use crate::audit::{self, Auditable, Operation};
+use crate::enums::AidKind;
+use crate::errors::*;
+use crate::filters::{AidPeriod, AidTotalsFilter};
+use crate::jalali::{self, Calendar};
+use crate::models::{live_case_ids, Toman};
+use crate::pagination::QueryDate;
+use crate::schema::*;
+use crate::validation::{self, Validate, Validator};
+use crate::website::Db;
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+use diesel::PgConnection;
+use rocket::serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+const SOURCE_MAX_LENGTH: usize = 200;
+const NOTE_MAX_LENGTH: usize = 2000;
+const ITEM_NAME_MAX_LENGTH: usize = 200;
+const UNIT_MAX_LENGTH: usize = 30;
+
+#[derive(Debug, Queryable, Serialize, Clone)]
+pub struct AidDistribution {
+    id: Uuid,
+    case_id: Uuid,
+    person_id: Option<Uuid>,
+    requirement_id: Option<Uuid>,
+    kind: AidKind,
+    amount: Option<Toman>,
+    distributed_on: NaiveDate,
+    source: String,
+    issued_by: Option<Uuid>,
+    note: Option<String>,
+    created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Queryable, Serialize, Clone)]
+pub struct AidItem {
+    id: Uuid,
+    distribution_id: Uuid,
+    name: String,
+    quantity: i32,
+    unit: Option<String>,
+    /// Toman for the whole quantity.
+    estimated_value: Option<Toman>,
+}
+
+/// A distribution with its items, as it is returned and audited.
+#[derive(Debug, Serialize, Clone)]
+pub struct AidDistributionDetails {
+    #[serde(flatten)]
+    distribution: AidDistribution,
+    items: Vec<AidItem>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct NewAidItem {
+    name: String,
+    quantity: i32,
+    #[serde(default)]
+    unit: Option<String>,
+    #[serde(default)]
+    estimated_value: Option<Toman>,
+}
+
+/// Body of both creating and replacing a distribution. Cash has an `amount`
+/// and no items; aid in kind has items and no `amount`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct NewAidDistribution {
+    case_id: Uuid,
+    #[serde(default)]
+    person_id: Option<Uuid>,
+    #[serde(default)]
+    requirement_id: Option<Uuid>,
+    kind: AidKind,
+    #[serde(default)]
+    amount: Option<Toman>,
+    distributed_on: NaiveDate,
+    source: String,
+    #[serde(default)]
+    note: Option<String>,
+    #[serde(default)]
+    items: Vec<NewAidItem>,
+}
+
+/// What a case was given in one period.
+#[derive(Debug, Serialize, Clone)]
+pub struct AidTotal {
+    /// `YYYY-MM` or `YYYY`.
+    pub period: String,
+    pub cash: i64,
+    /// Sum of the estimated values of items given in kind.
+    pub in_kind_value: i64,
+    pub distributions: usize,
+}
+
+impl Auditable for AidDistributionDetails {
+    const ENTITY_TYPE: &'static str = "aid_distribution";
+
+    fn entity_id(&self) -> Uuid {
+        self.distribution.id
+    }
+}
+
+impl Targets for NewAidDistribution {
+    fn targets(&self) -> Vec<Target> {
+        vec![Target::Case(self.case_id)]
+    }
+}
+
+impl Validate for NewAidDistribution {
+    fn validate(self) -> Result<Self> {
+        let mut validator = Validator::new();
+        validator.required("source", &self.source, SOURCE_MAX_LENGTH);
+        validator.optional("note", &self.note, NOTE_MAX_LENGTH);
+
+        match self.kind {
+            AidKind::Cash => {
+                if !matches!(self.amount, Some(amount) if amount > 0) {
+                    validator.check::<()>(
+                        "amount",
+                        Err(("required", "cash aid needs an amount above 0".into())),
+                    );
+                }
+                if !self.items.is_empty() {
+                    validator.check::<()>(
+                        "items",
+                        Err(("not_allowed", "cash aid has no items".into())),
+                    );
+                }
+            }
+            AidKind::InKind => {
+                if self.amount.is_some() {
+                    validator.check::<()>(
+                        "amount",
+                        Err(("not_allowed", "aid in kind has no amount".into())),
+                    );
+                }
+                if self.items.is_empty() {
+                    validator.check::<()>(
+                        "items",
+                        Err(("required", "aid in kind needs at least one item".into())),
+                    );
+                }
+            }
+        }
+
+        for item in &self.items {
+            validator.required("items", &item.name, ITEM_NAME_MAX_LENGTH);
+            validator.optional("items", &item.unit, UNIT_MAX_LENGTH);
+            if item.quantity <= 0 {
+                validator.check::<()>(
+                    "items",
+                    Err(("out_of_range", "quantity must be above 0".into())),
+                );
+            }
+            if matches!(item.estimated_value, Some(value) if value < 0) {
+                validator.check::<()>(
+                    "items",
+                    Err((
+                        "out_of_range",
+                        "estimated_value must not be negative".into(),
+                    )),
+                );
+            }
+        }
+
+        validator.finish()?;
+        Ok(self)
+    }
+}
+
+impl NewAidDistribution {
+    /// Toman the distribution counts for against the monthly cap.
+    fn value(&self) -> i64 {
+        i64::from(self.amount.unwrap_or(0))
+            + self
+                .items
+                .iter()
+                .filter_map(|item| item.estimated_value)
+                .map(i64::from)
+                .sum::<i64>()
+    }
+
+    /// The person has to be in the case, and the requirement has to be one
+    /// of the person's or, without a person, of someone in the case.
+    fn check_links(&self, c: &PgConnection) -> Result<()> {
+        if let Some(p_person_id) = self.person_id {
+            let in_case = persons::table
+                .find(p_person_id)
+                .filter(persons::case_id.eq(self.case_id))
+                .filter(persons::deleted_at.is_null())
+                .count()
+                .get_result::<i64>(c)?;
+            if in_case == 0 {
+                return Err(validation::field_error(
+                    "person_id",
+                    "not_in_case",
+                    "is not a person of this case".to_owned(),
+                ));
+            }
+        }
+
+        if let Some(p_requirement_id) = self.requirement_id {
+            let owner = person_requirements::table
+                .inner_join(persons::table)
+                .filter(person_requirements::id.eq(p_requirement_id))
+                .filter(persons::case_id.eq(self.case_id))
+                .filter(persons::deleted_at.is_null())
+                .select(persons::id)
+                .first::<Uuid>(c)
+                .optional()?;
+            match owner {
+                Some(owner) if self.person_id.is_none() || self.person_id == Some(owner) => {}
+                _ => {
+                    return Err(validation::field_error(
+                        "requirement_id",
+                        "not_in_case",
+                        "is not a requirement of this person or case".to_owned(),
+                    ))
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fails when the distribution would take its case over `cap` for the
+    /// month it is given in. `replacing` is left out of the month's total.
+    /// Locks the case row so two distributions cannot both slip under it.
+    fn check_cap(&self, c: &PgConnection, cap: Option<i64>, replacing: Option<Uuid>) -> Result<()> {
+        let cap = match cap {
+            Some(cap) => cap,
+            None => return Ok(()),
+        };
+
+        cases::table
+            .find(self.case_id)
+            .select(cases::id)
+            .for_update()
+            .first::<Uuid>(c)?;
+
+        let (first, next) = month_of(self.distributed_on);
+        let mut month = aid_distributions::table
+            .filter(aid_distributions::case_id.eq(self.case_id))
+            .filter(aid_distributions::distributed_on.ge(first))
+            .filter(aid_distributions::distributed_on.lt(next))
+            .into_boxed();
+        if let Some(replacing) = replacing {
+            month = month.filter(aid_distributions::id.ne(replacing));
+        }
+        let month = month.load::<AidDistribution>(c)?;
+
+        let given: i64 = load_details(c, month)?.iter().map(|d| d.value()).sum();
+        let total = given + self.value();
+        if total > cap {
+            return Err(validation::field_error(
+                "amount",
+                "cap_exceeded",
+                format!(
+                    "the case would receive {} Toman in {}, over the monthly cap of {}",
+                    total,
+                    first.format("%Y-%m"),
+                    cap
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn insert_items(&self, c: &PgConnection, p_distribution_id: Uuid) -> QueryResult<Vec<AidItem>> {
+        use self::aid_distribution_items::dsl::*;
+
+        self.items
+            .iter()
+            .map(|item| {
+                diesel::insert_into(aid_distribution_items)
+                    .values((
+                        id.eq(Uuid::from_u128(rand::random())),
+                        distribution_id.eq(p_distribution_id),
+                        name.eq(&item.name),
+                        quantity.eq(item.quantity),
+                        unit.eq(&item.unit),
+                        estimated_value.eq(item.estimated_value),
+                    ))
+                    .get_result::<AidItem>(c)
+            })
+            .collect()
+    }
+}
+
+impl AidDistributionDetails {
+    fn value(&self) -> i64 {
+        i64::from(self.distribution.amount.unwrap_or(0)) + self.in_kind_value()
+    }
+
+    fn in_kind_value(&self) -> i64 {
+        self.items
+            .iter()
+            .filter_map(|item| item.estimated_value)
+            .map(i64::from)
+            .sum()
+    }
+}
+
+/// First day of the month `date` is in, and of the month after.
+fn month_of(date: NaiveDate) -> (NaiveDate, NaiveDate) {
+    let first = NaiveDate::from_ymd(date.year(), date.month(), 1);
+    let next = match date.month() {
+        12 => NaiveDate::from_ymd(date.year() + 1, 1, 1),
+        month => NaiveDate::from_ymd(date.year(), month + 1, 1),
+    };
+    (first, next)
+}
+
+/// Attaches their items to `distributions`, keeping their order.
+fn load_details(
+    c: &PgConnection,
+    distributions: Vec<AidDistribution>,
+) -> QueryResult<Vec<AidDistributionDetails>> {
+    let ids: Vec<Uuid> = distributions.iter().map(|d| d.id).collect();
+    let items = aid_distribution_items::table
+        .filter(aid_distribution_items::distribution_id.eq_any(ids))
+        .order(aid_distribution_items::name.asc())
+        .load::<AidItem>(c)?;
+
+    Ok(distributions
+        .into_iter()
+        .map(|distribution| AidDistributionDetails {
+            items: items
+                .iter()
+                .filter(|item| item.distribution_id == distribution.id)
+                .cloned()
+                .collect(),
+            distribution,
+        })
+        .collect())
+}
+
+impl AidDistribution {
+    pub async fn create(
+        conn: &Db,
+        entity: NewAidDistribution,
+        cap: Option<i64>,
+        actor: Uuid,
+    ) -> Result<AidDistributionDetails> {
+        use self::aid_distributions::dsl::*;
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                entity.check_links(c)?;
+                entity.check_cap(c, cap, None)?;
+
+                let distribution = diesel::insert_into(aid_distributions)
+                    .values((
+                        id.eq(Uuid::from_u128(rand::random())),
+                        case_id.eq(entity.case_id),
+                        person_id.eq(entity.person_id),
+                        requirement_id.eq(entity.requirement_id),
+                        kind.eq(entity.kind),
+                        amount.eq(entity.amount),
+                        distributed_on.eq(entity.distributed_on),
+                        source.eq(&entity.source),
+                        issued_by.eq(Some(actor)),
+                        note.eq(&entity.note),
+                    ))
+                    .get_result::<AidDistribution>(c)?;
+                let created = AidDistributionDetails {
+                    items: entity.insert_items(c, distribution.id)?,
+                    distribution,
+                };
+
+                audit::record(c, actor, Operation::Create, None, Some(&created))?;
+                Ok(created)
+            })
+        })
+        .await
+    }
+
+    pub async fn get(conn: &Db, p_id: Uuid) -> Result<Option<AidDistributionDetails>> {
+        use self::aid_distributions::dsl::*;
+
+        conn.run(move |c| {
+            let distribution = aid_distributions
+                .find(p_id)
+                .filter(case_id.eq_any(live_case_ids()))
+                .get_result::<AidDistribution>(c)
+                .optional()?;
+            Ok(load_details(c, distribution.into_iter().collect())?.pop())
+        })
+        .await
+    }
+
+    /// Replaces the distribution and its items. It stays with its case.
+    pub async fn update(
+        conn: &Db,
+        p_id: Uuid,
+        entity: NewAidDistribution,
+        cap: Option<i64>,
+        actor: Uuid,
+    ) -> Result<AidDistributionDetails> {
+        use self::aid_distributions::dsl::*;
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match aid_distributions
+                    .find(p_id)
+                    .for_update()
+                    .get_result::<AidDistribution>(c)
+                    .optional()?
+                {
+                    Some(before) => load_details(c, vec![before])?.remove(0),
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+                if before.distribution.case_id != entity.case_id {
+                    return Err(Errors::BadRequest(
+                        "a distribution cannot move to another case".into(),
+                    ));
+                }
+                entity.check_links(c)?;
+                entity.check_cap(c, cap, Some(p_id))?;
+
+                let distribution = diesel::update(aid_distributions.find(p_id))
+                    .set((
+                        person_id.eq(entity.person_id),
+                        requirement_id.eq(entity.requirement_id),
+                        kind.eq(entity.kind),
+                        amount.eq(entity.amount),
+                        distributed_on.eq(entity.distributed_on),
+                        source.eq(&entity.source),
+                        note.eq(&entity.note),
+                    ))
+                    .get_result::<AidDistribution>(c)?;
+                diesel::delete(
+                    aid_distribution_items::table
+                        .filter(aid_distribution_items::distribution_id.eq(p_id)),
+                )
+                .execute(c)?;
+                let after = AidDistributionDetails {
+                    items: entity.insert_items(c, p_id)?,
+                    distribution,
+                };
+
+                audit::record(c, actor, Operation::Update, Some(&before), Some(&after))?;
+                Ok(after)
+            })
+        })
+        .await
+    }
+
+    pub async fn delete(conn: &Db, p_id: Uuid, actor: Uuid) -> Result<()> {
+        use self::aid_distributions::dsl::*;
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match aid_distributions
+                    .find(p_id)
+                    .get_result::<AidDistribution>(c)
+                    .optional()?
+                {
+                    Some(before) => load_details(c, vec![before])?.remove(0),
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+                diesel::delete(aid_distributions.find(p_id)).execute(c)?;
+
+                audit::record(c, actor, Operation::Delete, Some(&before), None)?;
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    /// Hands the aid given to `from` over to `into`, for when both are the
+    /// same person. The distributions stay with the case they were given to.
+    pub fn move_to_person_in(c: &PgConnection, from: Uuid, into: Uuid, actor: Uuid) -> Result<()> {
+        use self::aid_distributions::dsl::*;
+
+        let moved = diesel::update(aid_distributions.filter(person_id.eq(from)))
+            .set(person_id.eq(into))
+            .get_results::<AidDistribution>(c)?;
+        for after in load_details(c, moved)? {
+            let before = AidDistributionDetails {
+                distribution: AidDistribution {
+                    person_id: Some(from),
+                    ..after.distribution.clone()
+                },
+                items: after.items.clone(),
+            };
+            audit::record(c, actor, Operation::Update, Some(&before), Some(&after))?;
+        }
+        Ok(())
+    }
+
+    /// Everything the case was given, most recent first.
+    pub async fn all_by_case_id(conn: &Db, p_case_id: Uuid) -> Result<Vec<AidDistributionDetails>> {
+        use self::aid_distributions::dsl::*;
+
+        conn.run(move |c| {
+            let distributions = aid_distributions
+                .filter(case_id.eq(p_case_id))
+                .filter(case_id.eq_any(live_case_ids()))
+                .order((distributed_on.desc(), created_at.desc()))
+                .load::<AidDistribution>(c)?;
+            Ok(load_details(c, distributions)?)
+        })
+        .await
+    }
+
+    /// Cash and in-kind value given per month or year of `calendar`, oldest
+    /// period first.
+    pub async fn totals(
+        conn: &Db,
+        filter: AidTotalsFilter,
+        calendar: Calendar,
+        visibility: Visibility,
+    ) -> Result<Vec<AidTotal>> {
+        use self::aid_distributions::dsl::*;
+
+        conn.run(move |c| {
+            let mut query = aid_distributions
+                .filter(case_id.eq_any(live_case_ids()))
+                .into_boxed();
+            if let Visibility::AssignedTo(user) = visibility {
+                query = query.filter(case_id.eq_any(access::assigned_case_ids(user)));
+            }
+            if let Some(p_case_id) = filter.case_id {
+                query = query.filter(case_id.eq(p_case_id));
+            }
+            if let Some(p_kind) = filter.kind {
+                query = query.filter(kind.eq(p_kind));
+            }
+            if let Some(QueryDate(from)) = filter.from {
+                query = query.filter(distributed_on.ge(from));
+            }
+            if let Some(QueryDate(to)) = filter.to {
+                query = query.filter(distributed_on.le(to));
+            }
+            let distributions = load_details(c, query.load::<AidDistribution>(c)?)?;
+
+            let yearly = filter.period == Some(AidPeriod::Year);
+            let mut totals: BTreeMap<String, AidTotal> = BTreeMap::new();
+            for details in distributions {
+                let period =
+                    jalali::period_label(details.distribution.distributed_on, calendar, yearly);
+                let total = totals.entry(period.clone()).or_insert(AidTotal {
+                    period,
+                    cash: 0,
+                    in_kind_value: 0,
+                    distributions: 0,
+                });
+                total.cash += i64::from(details.distribution.amount.unwrap_or(0));
+                total.in_kind_value += details.in_kind_value();
+                total.distributions += 1;
+            }
+            Ok(totals.into_values().collect())
+        })
+        .await
+    }
+}
@@ -0,0 +1,183 @@
+use crate::errors::*;
+use crate::pagination::{Cursor, Page, PageRequest};
+use crate::schema::audit_log;
+use crate::website::Db;
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::PgConnection;
+use rocket::serde::Serialize;
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+    Create,
+    Update,
+    Delete,
+    Restore,
+    Purge,
+}
+
+impl Operation {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Operation::Create => "create",
+            Operation::Update => "update",
+            Operation::Delete => "delete",
+            Operation::Restore => "restore",
+            Operation::Purge => "purge",
+        }
+    }
+}
+
+/// Actor recorded for changes the service makes on its own, such as purging
+/// the trash.
+pub const SYSTEM: Option<Uuid> = None;
+
+/// Implemented by every model whose changes end up in the audit log.
+pub trait Auditable: Serialize {
+    const ENTITY_TYPE: &'static str;
+
+    fn entity_id(&self) -> Uuid;
+
+    /// JSON stored in the log; override to leave out secrets.
+    fn snapshot(&self) -> Value {
+        serde_json::to_value(self).unwrap_or(Value::Null)
+    }
+}
+
+#[derive(Debug, Queryable, Serialize)]
+pub struct AuditEntry {
+    pub id: Uuid,
+    pub actor: Option<Uuid>,
+    pub entity_type: String,
+    pub entity_id: Uuid,
+    pub operation: String,
+    pub created_at: NaiveDateTime,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+/// Writes one audit entry. Call it with the same connection, and inside the
+/// same transaction, as the change being recorded.
+pub fn record<T: Auditable>(
+    c: &PgConnection,
+    actor: impl Into<Option<Uuid>>,
+    operation: Operation,
+    before: Option<&T>,
+    after: Option<&T>,
+) -> QueryResult<()> {
+    use self::audit_log::dsl;
+
+    let p_entity_id = match after.or(before) {
+        Some(entity) => entity.entity_id(),
+        None => return Ok(()),
+    };
+
+    let (before, after) = match (before.map(T::snapshot), after.map(T::snapshot)) {
+        (Some(before), Some(after)) => {
+            let (before, after) = diff(before, after);
+            (Some(before), Some(after))
+        }
+        snapshots => snapshots,
+    };
+
+    diesel::insert_into(dsl::audit_log)
+        .values((
+            dsl::id.eq(Uuid::from_u128(rand::random())),
+            dsl::actor.eq(actor.into()),
+            dsl::entity_type.eq(T::ENTITY_TYPE),
+            dsl::entity_id.eq(p_entity_id),
+            dsl::operation.eq(operation.as_str()),
+            dsl::created_at.eq(Utc::now().naive_utc()),
+            dsl::before.eq(before),
+            dsl::after.eq(after),
+        ))
+        .execute(c)
+        .map(|_| ())
+}
+
+/// Keeps only the fields whose value changed between two snapshots.
+fn diff(before: Value, after: Value) -> (Value, Value) {
+    match (before, after) {
+        (Value::Object(before), Value::Object(mut after)) => {
+            let mut old = Map::new();
+            let mut new = Map::new();
+            for (key, value) in before {
+                let changed = after.remove(&key);
+                if changed.as_ref() != Some(&value) {
+                    new.insert(key.clone(), changed.unwrap_or(Value::Null));
+                    old.insert(key, value);
+                }
+            }
+            for (key, value) in after {
+                old.insert(key.clone(), Value::Null);
+                new.insert(key, value);
+            }
+            (Value::Object(old), Value::Object(new))
+        }
+        (before, after) => (before, after),
+    }
+}
+
+impl AuditEntry {
+    pub async fn page_by_entity(
+        conn: &Db,
+        p_entity_type: String,
+        p_entity_id: Uuid,
+        page: PageRequest<()>,
+    ) -> Result<Page<AuditEntry>> {
+        use self::audit_log::dsl;
+
+        conn.run(move |c| {
+            let filtered = || {
+                dsl::audit_log
+                    .filter(dsl::entity_type.eq(p_entity_type.clone()))
+                    .filter(dsl::entity_id.eq(p_entity_id))
+                    .into_boxed()
+            };
+
+            let total = filtered()
+                .count()
+                .get_result::<i64>(c)
+                .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+
+            let items = keyset!(filtered(), page, dsl::created_at, dsl::id, NaiveDateTime)
+                .limit(page.limit + 1)
+                .load::<AuditEntry>(c)
+                .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+
+            Ok(Page::of(items, page.limit, total, |entry| {
+                Cursor::new(&entry.created_at, entry.id)
+            }))
+        })
+        .await
+    }
+
+    pub async fn page_by_actor(
+        conn: &Db,
+        p_actor: Uuid,
+        page: PageRequest<()>,
+    ) -> Result<Page<AuditEntry>> {
+        use self::audit_log::dsl;
+
+        conn.run(move |c| {
+            let filtered = || dsl::audit_log.filter(dsl::actor.eq(p_actor)).into_boxed();
+
+            let total = filtered()
+                .count()
+                .get_result::<i64>(c)
+                .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+
+            let items = keyset!(filtered(), page, dsl::created_at, dsl::id, NaiveDateTime)
+                .limit(page.limit + 1)
+                .load::<AuditEntry>(c)
+                .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+
+            Ok(Page::of(items, page.limit, total, |entry| {
+                Cursor::new(&entry.created_at, entry.id)
+            }))
+        })
+        .await
+    }
+}
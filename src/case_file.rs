@@ -0,0 +1,420 @@
+use crate::audit::Auditable;
+use crate::errors::*;
+use crate::models::{
+    Case, CaseAction, NewCase, NewCaseAction, NewPerson, NewPersonJob, NewPersonRequirement,
+    NewPersonSkill, Person, PersonJob, PersonRequirement, PersonSkill,
+};
+use crate::schema::*;
+use crate::service_options::DuplicatePolicy;
+use crate::validation::{self, Validate, Validator};
+use crate::website::Db;
+use chrono::NaiveDate;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use rocket::serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+/// Most entries in any one list of a new case file.
+const MAX_ENTRIES: usize = 50;
+
+/// A case with its household and first actions, created together. Persons,
+/// jobs, requirements and actions take the fields of `NewPerson`,
+/// `NewPersonJob`, `NewPersonRequirement` and `NewCaseAction` without the id
+/// of what they belong to.
+#[derive(Debug, Deserialize)]
+pub struct NewCaseFile {
+    #[serde(flatten)]
+    case: NewCase,
+    #[serde(default)]
+    persons: Vec<NewMember>,
+    #[serde(default)]
+    actions: Vec<Map<String, Value>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewMember {
+    #[serde(flatten)]
+    person: Map<String, Value>,
+    #[serde(default)]
+    jobs: Vec<Map<String, Value>>,
+    /// Ids of skills in the catalogue.
+    #[serde(default)]
+    skills: Vec<Uuid>,
+    #[serde(default)]
+    requirements: Vec<Map<String, Value>>,
+}
+
+/// A case with everything recorded about its household.
+#[derive(Debug, Serialize)]
+pub struct CaseFile {
+    #[serde(flatten)]
+    case: Case,
+    /// Leaders first.
+    persons: Vec<MemberFile>,
+    /// By date, undated ones last.
+    actions: Vec<CaseAction>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemberFile {
+    #[serde(flatten)]
+    person: Person,
+    jobs: Vec<PersonJob>,
+    default_job_id: Option<Uuid>,
+    skills: Vec<MemberSkill>,
+    requirements: Vec<PersonRequirement>,
+}
+
+/// A skill of a person, with its name from the catalogue.
+#[derive(Debug, Serialize)]
+pub struct MemberSkill {
+    #[serde(flatten)]
+    skill: PersonSkill,
+    name: String,
+    category: Option<String>,
+}
+
+impl NewCaseFile {
+    /// Checks every part of the file, reporting each failing field by its
+    /// path, as in `persons[1].jobs[0].title`.
+    fn validate(&self) -> Result<()> {
+        let mut v = Validator::new();
+        let leaders = self
+            .persons
+            .iter()
+            .filter(|member| member.person.get("is_leader") == Some(&Value::Bool(true)))
+            .count();
+        if !self.persons.is_empty() && leaders != 1 {
+            v.check::<()>(
+                "persons",
+                Err(("leader", "exactly one person must lead the case".to_owned())),
+            );
+        }
+        check_length(&mut v, "persons", self.persons.len());
+        check_length(&mut v, "actions", self.actions.len());
+
+        let mut national_numbers = HashMap::new();
+        for (index, member) in self.persons.iter().enumerate() {
+            let path = format!("persons[{}]", index);
+            v.nested(&path, parse::<NewPerson>(&member.person, "case_id"))?;
+            if let Some(number) = national_number(member) {
+                if let Some(first) = national_numbers.insert(number, index) {
+                    v.check::<()>(
+                        format!("{}.national_number", path),
+                        Err((
+                            "duplicate",
+                            format!("is the same as that of persons[{}]", first),
+                        )),
+                    );
+                }
+            }
+
+            check_length(&mut v, format!("{}.jobs", path), member.jobs.len());
+            for (job, fields) in member.jobs.iter().enumerate() {
+                let job_path = format!("{}.jobs[{}]", path, job);
+                v.nested(&job_path, parse::<NewPersonJob>(fields, "person_id"))?;
+            }
+            check_length(&mut v, format!("{}.skills", path), member.skills.len());
+            let mut skills = HashSet::new();
+            for (skill, skill_id) in member.skills.iter().enumerate() {
+                if !skills.insert(skill_id) {
+                    v.check::<()>(
+                        format!("{}.skills[{}]", path, skill),
+                        Err(("taken", "is listed more than once".to_owned())),
+                    );
+                }
+            }
+            check_length(
+                &mut v,
+                format!("{}.requirements", path),
+                member.requirements.len(),
+            );
+            for (requirement, fields) in member.requirements.iter().enumerate() {
+                let requirement_path = format!("{}.requirements[{}]", path, requirement);
+                v.nested(
+                    &requirement_path,
+                    parse::<NewPersonRequirement>(fields, "person_id"),
+                )?;
+            }
+        }
+        for (index, fields) in self.actions.iter().enumerate() {
+            let path = format!("actions[{}]", index);
+            v.nested(&path, read::<NewCaseAction>(fields, "case_id"))?;
+        }
+        v.finish()
+    }
+
+    /// Checks what needs the database: that skills are in the catalogue and,
+    /// under the "reject" policy, that nobody is registered already.
+    fn validate_in(&self, c: &PgConnection, policy: DuplicatePolicy) -> Result<()> {
+        let mut v = Validator::new();
+
+        if policy == DuplicatePolicy::Reject {
+            let numbers: Vec<String> = self.persons.iter().filter_map(national_number).collect();
+            let registered: HashSet<String> = persons::table
+                .filter(persons::national_number.eq_any(&numbers))
+                .filter(persons::deleted_at.is_null())
+                .select(persons::national_number)
+                .load::<String>(c)?
+                .into_iter()
+                .collect();
+            for (index, member) in self.persons.iter().enumerate() {
+                if national_number(member).is_some_and(|number| registered.contains(&number)) {
+                    v.check::<()>(
+                        format!("persons[{}].national_number", index),
+                        Err(("duplicate", "is already registered".to_owned())),
+                    );
+                }
+            }
+        }
+
+        let skill_ids: Vec<Uuid> = self
+            .persons
+            .iter()
+            .flat_map(|member| member.skills.iter().copied())
+            .collect();
+        let known: HashSet<Uuid> = skills::table
+            .filter(skills::id.eq_any(&skill_ids))
+            .select(skills::id)
+            .load::<Uuid>(c)?
+            .into_iter()
+            .collect();
+        for (index, member) in self.persons.iter().enumerate() {
+            for (skill, skill_id) in member.skills.iter().enumerate() {
+                if !known.contains(skill_id) {
+                    v.check::<()>(
+                        format!("persons[{}].skills[{}]", index, skill),
+                        Err(("not_found", "no such skill in the catalogue".to_owned())),
+                    );
+                }
+            }
+        }
+        v.finish()
+    }
+
+    /// Creates the case and everything in it, or nothing when any part
+    /// fails. Jobs current on `today` become their person's default.
+    pub async fn create(
+        self,
+        conn: &Db,
+        policy: DuplicatePolicy,
+        actor: Uuid,
+        today: NaiveDate,
+    ) -> Result<CaseFile> {
+        self.validate()?;
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                self.validate_in(c, policy)?;
+                let case_id = self.save(c, actor, today)?;
+                CaseFile::load_in(c, case_id)?
+                    .ok_or_else(|| Errors::InternalError("created case not found".to_owned()))
+            })
+        })
+        .await
+    }
+
+    /// Inserts the validated file. Assignees can only be checked once the
+    /// case exists, so their errors are gathered here.
+    fn save(self, c: &PgConnection, actor: Uuid, today: NaiveDate) -> Result<Uuid> {
+        let mut v = Validator::new();
+        let case_id = Case::create_in(c, self.case, actor, None)?.entity_id();
+
+        for (index, member) in self.persons.into_iter().enumerate() {
+            let person = parse::<NewPerson>(&with(member.person, "case_id", case_id), "case_id")?;
+            // Someone else may have registered the national number since it
+            // was checked.
+            let person_id = Person::create_in(c, person, actor)
+                .map_err(|e| at(&format!("persons[{}]", index), e))?
+                .entity_id();
+
+            for job in member.jobs {
+                let job = parse::<NewPersonJob>(&with(job, "person_id", person_id), "person_id")?;
+                PersonJob::create_in(c, job, actor, today)?;
+            }
+            for skill_id in member.skills {
+                let skill = serde_json::from_value::<NewPersonSkill>(json!({
+                    "person_id": person_id,
+                    "skill_id": skill_id,
+                }))
+                .map_err(|e| Errors::InternalError(e.to_string()))?;
+                PersonSkill::create_in(c, skill, actor)?;
+            }
+            for requirement in member.requirements {
+                let requirement = parse::<NewPersonRequirement>(
+                    &with(requirement, "person_id", person_id),
+                    "person_id",
+                )?;
+                PersonRequirement::create_in(c, requirement, actor)?;
+            }
+        }
+
+        for (index, action) in self.actions.into_iter().enumerate() {
+            let action = read::<NewCaseAction>(&with(action, "case_id", case_id), "case_id")?;
+            v.nested(
+                &format!("actions[{}]", index),
+                CaseAction::create_in(c, action, actor),
+            )?;
+        }
+        v.finish()?;
+        Ok(case_id)
+    }
+}
+
+impl CaseFile {
+    pub async fn get(conn: &Db, p_case_id: Uuid) -> Result<Option<CaseFile>> {
+        conn.run(move |c| CaseFile::load_in(c, p_case_id)).await
+    }
+
+    fn load_in(c: &PgConnection, p_case_id: Uuid) -> Result<Option<CaseFile>> {
+        let case = cases::table
+            .find(p_case_id)
+            .filter(cases::deleted_at.is_null())
+            .first::<Case>(c)
+            .optional()?;
+        let case = match case {
+            Some(case) => case,
+            None => return Ok(None),
+        };
+
+        let persons = persons::table
+            .filter(persons::case_id.eq(p_case_id))
+            .filter(persons::deleted_at.is_null())
+            .order((persons::is_leader.desc(), persons::birthday.asc()))
+            .load::<Person>(c)?;
+        let person_ids: Vec<Uuid> = persons.iter().map(Auditable::entity_id).collect();
+
+        let mut jobs = by_person(
+            person_jobs::table
+                .filter(person_jobs::person_id.eq_any(&person_ids))
+                .select((person_jobs::person_id, person_jobs::all_columns))
+                .order(person_jobs::start_date.desc())
+                .load::<(Uuid, PersonJob)>(c)?,
+        );
+        let default_jobs: HashMap<Uuid, Uuid> = person_default_job::table
+            .filter(person_default_job::person_id.eq_any(&person_ids))
+            .select((
+                person_default_job::person_id,
+                person_default_job::person_job_id,
+            ))
+            .load::<(Uuid, Uuid)>(c)?
+            .into_iter()
+            .collect();
+        let mut skills = by_person(
+            person_skills::table
+                .inner_join(skills::table)
+                .filter(person_skills::person_id.eq_any(&person_ids))
+                .select((
+                    person_skills::person_id,
+                    person_skills::all_columns,
+                    skills::name,
+                    skills::category,
+                ))
+                .order(skills::name.asc())
+                .load::<(Uuid, PersonSkill, String, Option<String>)>(c)?
+                .into_iter()
+                .map(|(person_id, skill, name, category)| {
+                    let skill = MemberSkill {
+                        skill,
+                        name,
+                        category,
+                    };
+                    (person_id, skill)
+                })
+                .collect(),
+        );
+        let mut requirements = by_person(
+            person_requirements::table
+                .filter(person_requirements::person_id.eq_any(&person_ids))
+                .select((
+                    person_requirements::person_id,
+                    person_requirements::all_columns,
+                ))
+                .order(person_requirements::priority.desc())
+                .load::<(Uuid, PersonRequirement)>(c)?,
+        );
+
+        let persons = persons
+            .into_iter()
+            .map(|person| {
+                let id = person.entity_id();
+                MemberFile {
+                    person,
+                    jobs: jobs.remove(&id).unwrap_or_default(),
+                    default_job_id: default_jobs.get(&id).copied(),
+                    skills: skills.remove(&id).unwrap_or_default(),
+                    requirements: requirements.remove(&id).unwrap_or_default(),
+                }
+            })
+            .collect();
+
+        let actions = case_actions::table
+            .filter(case_actions::case_id.eq(p_case_id))
+            .order(case_actions::action_date.asc())
+            .load::<CaseAction>(c)?;
+
+        Ok(Some(CaseFile {
+            case,
+            persons,
+            actions,
+        }))
+    }
+}
+
+fn check_length(v: &mut Validator, field: impl Into<std::borrow::Cow<'static, str>>, len: usize) {
+    if len > MAX_ENTRIES {
+        v.check::<()>(
+            field,
+            Err((
+                "too_many",
+                format!("must have at most {} entries", MAX_ENTRIES),
+            )),
+        );
+    }
+}
+
+/// Reads and validates an entity of the file, with a stand-in for the id
+/// of what it belongs to until that has been created.
+fn parse<T: DeserializeOwned + Validate>(fields: &Map<String, Value>, parent: &str) -> Result<T> {
+    read::<T>(fields, parent)?.validate()
+}
+
+fn read<T: DeserializeOwned>(fields: &Map<String, Value>, parent: &str) -> Result<T> {
+    let mut fields = fields.clone();
+    fields
+        .entry(parent)
+        .or_insert_with(|| Uuid::nil().to_string().into());
+    serde_json::from_value::<T>(Value::Object(fields))
+        .map_err(|e| validation::field_error("", "invalid", e.to_string()))
+}
+
+/// Puts the field errors of a failed insert under `path`.
+fn at(path: &str, e: Errors) -> Errors {
+    let mut v = Validator::new();
+    match v.nested::<()>(path, Err(e)) {
+        Ok(_) => v
+            .finish()
+            .err()
+            .unwrap_or_else(|| Errors::InternalError("insert failed".to_owned())),
+        Err(e) => e,
+    }
+}
+
+fn with(mut fields: Map<String, Value>, parent: &str, id: Uuid) -> Map<String, Value> {
+    fields.insert(parent.to_owned(), id.to_string().into());
+    fields
+}
+
+fn national_number(member: &NewMember) -> Option<String> {
+    let number = member.person.get("national_number")?.as_str()?;
+    validation::national_number(number).ok()
+}
+
+fn by_person<T>(items: Vec<(Uuid, T)>) -> HashMap<Uuid, Vec<T>> {
+    let mut grouped: HashMap<Uuid, Vec<T>> = HashMap::new();
+    for (person_id, item) in items {
+        grouped.entry(person_id).or_default().push(item);
+    }
+    grouped
+}
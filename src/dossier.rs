@@ -0,0 +1,622 @@
+use crate::agenda;
+use crate::audit::{self, Auditable, Operation};
+use crate::enums::{
+    ActionStatus, EmploymentType, FamilyRole, IncomePeriod, Priority, RequirementCategory,
+    RequirementStatus,
+};
+use crate::errors::*;
+use crate::export::Label;
+use crate::household::{self, CaseSummary};
+use crate::jalali::JalaliDate;
+use crate::models::current_jobs;
+use crate::pdf;
+use crate::persian;
+use crate::schema::*;
+use crate::validation::{self, Validate, Validator};
+use crate::website::Db;
+use chrono::{NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use minijinja::value::Value;
+use minijinja::{Environment, UndefinedBehavior};
+use rocket::serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Template used until an admin saves one of their own.
+pub const DEFAULT_TEMPLATE: &str = include_str!("../templates/dossier.txt");
+
+const TEMPLATE_MAX_LENGTH: usize = 100_000;
+/// Instructions a template may run while rendering one dossier, so that a
+/// runaway loop cannot hold a worker.
+const TEMPLATE_FUEL: u64 = 2_000_000;
+const RECENT_ACTIONS: usize = 15;
+
+/// The single row of `dossier_template`. Without a body, dossiers are
+/// printed from `DEFAULT_TEMPLATE`.
+#[derive(Debug, Queryable, Serialize)]
+pub struct DossierTemplate {
+    /// Always true, as the table has a single row.
+    #[serde(skip)]
+    #[allow(dead_code)]
+    id: bool,
+    pub body: Option<String>,
+    pub updated_by: Option<Uuid>,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+impl Auditable for DossierTemplate {
+    const ENTITY_TYPE: &'static str = "dossier_template";
+
+    fn entity_id(&self) -> Uuid {
+        Uuid::nil()
+    }
+}
+
+/// The template dossiers are printed from, whether it is the built-in one
+/// or an admin's.
+#[derive(Debug, Serialize)]
+pub struct DossierTemplateDetails {
+    pub body: String,
+    pub custom: bool,
+    pub updated_by: Option<Uuid>,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+impl From<DossierTemplate> for DossierTemplateDetails {
+    fn from(template: DossierTemplate) -> Self {
+        DossierTemplateDetails {
+            custom: template.body.is_some(),
+            body: template.body.unwrap_or_else(|| DEFAULT_TEMPLATE.to_owned()),
+            updated_by: template.updated_by,
+            updated_at: template.updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewDossierTemplate {
+    pub body: String,
+}
+
+impl Validate for NewDossierTemplate {
+    fn validate(self) -> Result<Self> {
+        let mut validator = Validator::new();
+        validator.required("body", &self.body, TEMPLATE_MAX_LENGTH);
+        validator.finish()?;
+
+        render_markup(&self.body, &Dossier::sample())
+            .map_err(|message| validation::field_error("body", "invalid_template", message))?;
+        Ok(self)
+    }
+}
+
+impl DossierTemplate {
+    pub async fn get(conn: &Db) -> Result<DossierTemplate> {
+        conn.run(|c| dossier_template::table.first::<DossierTemplate>(c))
+            .await
+            .map_err(|e| Errors::DatabaseError(e.to_string()))
+    }
+
+    /// Saves `template`, or goes back to the built-in one when it is none.
+    pub async fn update(
+        conn: &Db,
+        template: Option<NewDossierTemplate>,
+        actor: Uuid,
+    ) -> Result<DossierTemplate> {
+        use self::dossier_template::dsl::*;
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = dossier_template.for_update().first::<DossierTemplate>(c)?;
+                let after = diesel::update(dossier_template)
+                    .set((
+                        body.eq(template.map(|template| template.body)),
+                        updated_by.eq(Some(actor)),
+                        updated_at.eq(Some(Utc::now().naive_utc())),
+                    ))
+                    .get_result::<DossierTemplate>(c)?;
+                audit::record(c, actor, Operation::Update, Some(&before), Some(&after))?;
+                Ok(after)
+            })
+        })
+        .await
+    }
+
+    fn source(&self) -> &str {
+        self.body.as_deref().unwrap_or(DEFAULT_TEMPLATE)
+    }
+}
+
+/// Everything a dossier template can show. Dates are ISO strings, which the
+/// `jalali` filter turns into Jalali ones; times are local to the time zone
+/// the dossier was asked for.
+#[derive(Debug, Serialize)]
+pub struct Dossier {
+    case: DossierCase,
+    summary: Option<CaseSummary>,
+    persons: Vec<DossierPerson>,
+    /// The latest actions first.
+    actions: Vec<DossierAction>,
+    generated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize)]
+struct DossierCase {
+    number: i32,
+    active: bool,
+    registration_date: NaiveDateTime,
+    address: Option<String>,
+    description: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct DossierPerson {
+    #[serde(skip)]
+    id: Uuid,
+    first_name: String,
+    last_name: String,
+    full_name: String,
+    father_name: String,
+    birthday: NaiveDate,
+    age: i32,
+    national_number: String,
+    phone_number: String,
+    is_leader: bool,
+    family_role: &'static str,
+    education_field: Option<String>,
+    education_location: Option<String>,
+    description: Option<String>,
+    /// The job marked as the person's default.
+    job: Option<DossierJob>,
+    /// Monthly, in Toman, from every job the person has now.
+    monthly_income: Option<i64>,
+    skills: Vec<String>,
+    /// Requirements still open, the most pressing first.
+    requirements: Vec<DossierRequirement>,
+}
+
+#[derive(Debug, Serialize)]
+struct DossierJob {
+    title: String,
+    employment_type: Option<&'static str>,
+    income: Option<i32>,
+    income_period: &'static str,
+    monthly_income: Option<i32>,
+    location: Option<String>,
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+}
+
+#[derive(Debug, Serialize)]
+struct DossierRequirement {
+    description: String,
+    category: &'static str,
+    priority: &'static str,
+    status: &'static str,
+    estimated_cost: Option<i32>,
+    due_date: Option<NaiveDate>,
+}
+
+#[derive(Debug, Serialize)]
+struct DossierAction {
+    action: String,
+    status: &'static str,
+    action_date: Option<NaiveDateTime>,
+    completed_at: Option<NaiveDateTime>,
+    result_note: Option<String>,
+}
+
+#[derive(Queryable)]
+struct PersonRow {
+    id: Uuid,
+    first_name: String,
+    last_name: String,
+    father_name: String,
+    birthday: NaiveDate,
+    national_number: String,
+    phone_number: String,
+    is_leader: bool,
+    family_role: FamilyRole,
+    education_field: Option<String>,
+    education_location: Option<String>,
+    description: Option<String>,
+}
+
+#[derive(Queryable)]
+struct JobRow {
+    person_id: Uuid,
+    title: String,
+    employment_type: Option<EmploymentType>,
+    income: Option<i32>,
+    income_period: IncomePeriod,
+    monthly_income: Option<i32>,
+    location: Option<String>,
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+}
+
+#[derive(Queryable)]
+struct RequirementRow {
+    person_id: Uuid,
+    description: String,
+    category: RequirementCategory,
+    priority: Priority,
+    status: RequirementStatus,
+    estimated_cost: Option<i32>,
+    due_date: Option<NaiveDate>,
+}
+
+#[derive(Queryable)]
+struct ActionRow {
+    action: String,
+    status: ActionStatus,
+    action_date: Option<NaiveDateTime>,
+    completed_at: Option<NaiveDateTime>,
+    result_note: Option<String>,
+}
+
+impl Dossier {
+    /// The dossier of a live case, with times moved to `tz`.
+    pub async fn load(conn: &Db, p_case_id: Uuid, tz: Tz) -> Result<Option<Dossier>> {
+        let summary = household::summary(conn, p_case_id).await?;
+        conn.run(move |c| {
+            let dossier = Dossier::load_in(c, p_case_id, tz)?;
+            Ok(dossier.map(|dossier| Dossier { summary, ..dossier }))
+        })
+        .await
+    }
+
+    fn load_in(c: &PgConnection, p_case_id: Uuid, tz: Tz) -> Result<Option<Dossier>> {
+        let local = |at: NaiveDateTime| Utc.from_utc_datetime(&at).with_timezone(&tz).naive_local();
+        let today = agenda::today(tz);
+
+        let case = cases::table
+            .find(p_case_id)
+            .filter(cases::deleted_at.is_null())
+            .select((
+                cases::number,
+                cases::active,
+                cases::registration_date,
+                cases::address,
+                cases::description,
+            ))
+            .first::<(i32, bool, NaiveDateTime, Option<String>, Option<String>)>(c)
+            .optional()?;
+        let case = match case {
+            Some((number, active, registration_date, address, description)) => DossierCase {
+                number,
+                active,
+                registration_date: local(registration_date),
+                address,
+                description,
+            },
+            None => return Ok(None),
+        };
+
+        let mut persons: Vec<DossierPerson> = persons::table
+            .filter(persons::case_id.eq(p_case_id))
+            .filter(persons::deleted_at.is_null())
+            .select((
+                persons::id,
+                persons::first_name,
+                persons::last_name,
+                persons::father_name,
+                persons::birthday,
+                persons::national_number,
+                persons::phone_number,
+                persons::is_leader,
+                persons::family_role,
+                persons::education_field,
+                persons::education_location,
+                persons::description,
+            ))
+            .load::<PersonRow>(c)?
+            .into_iter()
+            .map(|row| DossierPerson {
+                id: row.id,
+                full_name: format!("{} {}", row.first_name, row.last_name),
+                first_name: row.first_name,
+                last_name: row.last_name,
+                father_name: row.father_name,
+                birthday: row.birthday,
+                age: household::age(row.birthday, today),
+                national_number: row.national_number.trim().to_owned(),
+                phone_number: row.phone_number.trim().to_owned(),
+                is_leader: row.is_leader,
+                family_role: row.family_role.label(),
+                education_field: row.education_field,
+                education_location: row.education_location,
+                description: row.description,
+                job: None,
+                monthly_income: None,
+                skills: Vec::new(),
+                requirements: Vec::new(),
+            })
+            .collect();
+        persons.sort_by_key(|person| (!person.is_leader, person.birthday));
+        let person_ids: Vec<Uuid> = persons.iter().map(|person| person.id).collect();
+
+        let job_columns = (
+            person_jobs::person_id,
+            person_jobs::title,
+            person_jobs::employment_type,
+            person_jobs::income,
+            person_jobs::income_period,
+            person_jobs::monthly_income,
+            person_jobs::location,
+            person_jobs::start_date,
+            person_jobs::end_date,
+        );
+        let mut jobs: HashMap<Uuid, DossierJob> = person_default_job::table
+            .inner_join(
+                person_jobs::table.on(person_jobs::id.eq(person_default_job::person_job_id)),
+            )
+            .filter(person_default_job::person_id.eq_any(&person_ids))
+            .select(job_columns)
+            .load::<JobRow>(c)?
+            .into_iter()
+            .map(|row| {
+                let job = DossierJob {
+                    title: row.title,
+                    employment_type: row.employment_type.map(Label::label),
+                    income: row.income,
+                    income_period: row.income_period.label(),
+                    monthly_income: row.monthly_income,
+                    location: row.location,
+                    start_date: row.start_date,
+                    end_date: row.end_date,
+                };
+                (row.person_id, job)
+            })
+            .collect();
+
+        let mut incomes: HashMap<Uuid, i64> = HashMap::new();
+        for (person_id, income) in person_jobs::table
+            .filter(person_jobs::person_id.eq_any(&person_ids))
+            .filter(current_jobs(today))
+            .select((person_jobs::person_id, person_jobs::monthly_income))
+            .load::<(Uuid, Option<i32>)>(c)?
+        {
+            if let Some(income) = income {
+                *incomes.entry(person_id).or_insert(0) += i64::from(income);
+            }
+        }
+
+        let skills = person_skills::table
+            .inner_join(skills::table)
+            .filter(person_skills::person_id.eq_any(&person_ids))
+            .select((person_skills::person_id, skills::name))
+            .order(skills::name.asc())
+            .load::<(Uuid, String)>(c)?;
+
+        let requirements = person_requirements::table
+            .filter(person_requirements::person_id.eq_any(&person_ids))
+            .filter(person_requirements::status.eq_any(RequirementStatus::OPEN.to_vec()))
+            .select((
+                person_requirements::person_id,
+                person_requirements::description,
+                person_requirements::category,
+                person_requirements::priority,
+                person_requirements::status,
+                person_requirements::estimated_cost,
+                person_requirements::due_date,
+            ))
+            .order(person_requirements::priority.desc())
+            .load::<RequirementRow>(c)?;
+
+        for person in &mut persons {
+            person.job = jobs.remove(&person.id);
+            person.monthly_income = incomes.get(&person.id).copied();
+            person.skills = skills
+                .iter()
+                .filter(|(person_id, _)| *person_id == person.id)
+                .map(|(_, name)| name.clone())
+                .collect();
+            person.requirements = requirements
+                .iter()
+                .filter(|requirement| requirement.person_id == person.id)
+                .map(|requirement| DossierRequirement {
+                    description: requirement.description.clone(),
+                    category: requirement.category.label(),
+                    priority: requirement.priority.label(),
+                    status: requirement.status.label(),
+                    estimated_cost: requirement.estimated_cost,
+                    due_date: requirement.due_date,
+                })
+                .collect();
+        }
+
+        let mut actions = case_actions::table
+            .filter(case_actions::case_id.eq(p_case_id))
+            .select((
+                case_actions::action,
+                case_actions::status,
+                case_actions::action_date,
+                case_actions::completed_at,
+                case_actions::result_note,
+            ))
+            .load::<ActionRow>(c)?;
+        actions.sort_by_key(|action| Reverse(action.completed_at.or(action.action_date)));
+        let actions = actions
+            .into_iter()
+            .take(RECENT_ACTIONS)
+            .map(|action| DossierAction {
+                action: action.action,
+                status: action.status.label(),
+                action_date: action.action_date.map(local),
+                completed_at: action.completed_at.map(local),
+                result_note: action.result_note,
+            })
+            .collect();
+
+        Ok(Some(Dossier {
+            case,
+            summary: None,
+            persons,
+            actions,
+            generated_at: local(Utc::now().naive_utc()),
+        }))
+    }
+
+    /// A made-up family a template is tried on before it is saved.
+    fn sample() -> Dossier {
+        let day = NaiveDate::from_ymd(2024, 3, 20);
+        let at = day.and_hms(10, 30, 0);
+        let person = DossierPerson {
+            id: Uuid::nil(),
+            first_name: "علی".to_owned(),
+            last_name: "رضایی".to_owned(),
+            full_name: "علی رضایی".to_owned(),
+            father_name: "محمد".to_owned(),
+            birthday: NaiveDate::from_ymd(1985, 6, 1),
+            age: 38,
+            national_number: "0012345678".to_owned(),
+            phone_number: "09120000000".to_owned(),
+            is_leader: true,
+            family_role: FamilyRole::Father.label(),
+            education_field: Some("دیپلم".to_owned()),
+            education_location: None,
+            description: None,
+            job: Some(DossierJob {
+                title: "راننده".to_owned(),
+                employment_type: Some(EmploymentType::DailyWage.label()),
+                income: Some(400_000),
+                income_period: IncomePeriod::Daily.label(),
+                monthly_income: Some(10_400_000),
+                location: None,
+                start_date: Some(day),
+                end_date: None,
+            }),
+            monthly_income: Some(10_400_000),
+            skills: vec!["رانندگی".to_owned()],
+            requirements: vec![DossierRequirement {
+                description: "داروی ماهانه".to_owned(),
+                category: RequirementCategory::Medical.label(),
+                priority: Priority::High.label(),
+                status: RequirementStatus::Open.label(),
+                estimated_cost: Some(2_000_000),
+                due_date: Some(day),
+            }],
+        };
+        Dossier {
+            case: DossierCase {
+                number: 1,
+                active: true,
+                registration_date: at,
+                address: Some("تهران".to_owned()),
+                description: None,
+            },
+            summary: None,
+            persons: vec![person],
+            actions: vec![DossierAction {
+                action: "بازدید از منزل".to_owned(),
+                status: ActionStatus::Done.label(),
+                action_date: Some(at),
+                completed_at: Some(at),
+                result_note: None,
+            }],
+            generated_at: at,
+        }
+    }
+
+    pub fn file_name(&self) -> String {
+        format!("dossier-{}.pdf", self.case.number)
+    }
+}
+
+/// Prints `dossier` from `template` as a PDF.
+pub fn render(template: &DossierTemplate, dossier: &Dossier) -> Result<Vec<u8>> {
+    let markup = render_markup(template.source(), dossier)
+        .map_err(|message| Errors::InternalError(format!("dossier template: {}", message)))?;
+    let title = format!("پرونده {}", dossier.case.number);
+    pdf::render(&pdf::parse(&markup), &title)
+}
+
+/// The markup `pdf::parse` reads, or why the template could not make it.
+fn render_markup(source: &str, dossier: &Dossier) -> std::result::Result<String, String> {
+    environment()
+        .render_str(source, dossier)
+        .map_err(|e| e.to_string())
+}
+
+fn environment() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.set_trim_blocks(true);
+    env.set_lstrip_blocks(true);
+    env.set_undefined_behavior(UndefinedBehavior::Chainable);
+    env.set_fuel(Some(TEMPLATE_FUEL));
+    // Every value stays on its line and in its table cell.
+    env.set_formatter(|out, _state, value| {
+        if value.is_none() || value.is_undefined() {
+            return Ok(());
+        }
+        let text = value
+            .to_string()
+            .replace(['\r', '\n'], " ")
+            .replace('|', "¦");
+        out.write_str(&text)?;
+        Ok(())
+    });
+    env.add_filter("jalali", jalali);
+    env.add_filter("amount", amount);
+    env.add_filter("digits", digits);
+    env
+}
+
+/// An ISO date, or date and time, written as a Jalali one in Persian digits,
+/// `۱۴۰۳/۰۱/۱۵ ۱۴:۳۰`. Anything else is left as it is.
+fn jalali(value: Value) -> String {
+    let text = match value.as_str() {
+        Some(text) => text,
+        None if value.is_none() || value.is_undefined() => return String::new(),
+        None => return value.to_string(),
+    };
+    let (day, time) = match NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f") {
+        Ok(at) => (at.date(), Some(at.format(" %H:%M").to_string())),
+        Err(_) => match NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+            Ok(day) => (day, None),
+            Err(_) => return text.to_owned(),
+        },
+    };
+    match JalaliDate::from_gregorian(day) {
+        Some(day) => persian::to_persian_digits(&format!(
+            "{:04}/{:02}/{:02}{}",
+            day.year,
+            day.month,
+            day.day,
+            time.unwrap_or_default()
+        )),
+        None => text.to_owned(),
+    }
+}
+
+/// A whole number with its thousands grouped, in Persian digits.
+fn amount(value: Value) -> String {
+    let number = match i64::try_from(value.clone()) {
+        Ok(number) => number,
+        Err(_) if value.is_none() || value.is_undefined() => return String::new(),
+        Err(_) => return value.to_string(),
+    };
+    let digits = number.unsigned_abs().to_string();
+    let mut grouped = String::new();
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index) % 3 == 0 {
+            grouped.push('٬');
+        }
+        grouped.push(digit);
+    }
+    if number < 0 {
+        grouped.insert(0, '-');
+    }
+    persian::to_persian_digits(&grouped)
+}
+
+fn digits(value: Value) -> String {
+    if value.is_none() || value.is_undefined() {
+        return String::new();
+    }
+    persian::to_persian_digits(&value.to_string())
+}
@@ -0,0 +1,243 @@
+use crate::errors::Errors;
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::Pg;
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::Integer;
+use rocket::form::{self, FromFormField, ValueField};
+use rocket::serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::io::Write;
+
+/// Declares an enum stored in an `INTEGER` column, giving every variant its
+/// number and the name it has in JSON and query strings. Reading a number
+/// that is not listed fails instead of panicking, and the column has a
+/// matching CHECK constraint.
+macro_rules! integer_enum {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident {
+            $($(#[$variant_meta:meta])* $variant:ident = $number:literal as $text:literal,)+
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(
+            Debug,
+            Clone,
+            Copy,
+            PartialEq,
+            Eq,
+            PartialOrd,
+            Ord,
+            Serialize,
+            Deserialize,
+            AsExpression,
+            FromSqlRow,
+        )]
+        #[sql_type = "Integer"]
+        pub enum $name {
+            $($(#[$variant_meta])* #[serde(rename = $text)] $variant,)+
+        }
+
+        impl<'v> FromFormField<'v> for $name {
+            fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+                $(
+                    if field.value.eq_ignore_ascii_case($text) {
+                        return Ok($name::$variant);
+                    }
+                )+
+                Err(form::Error::validation(concat!(
+                    "expected one of"
+                    $(, " ", $text)+
+                ))
+                .into())
+            }
+        }
+
+        impl From<$name> for i32 {
+            fn from(value: $name) -> Self {
+                match value {
+                    $($name::$variant => $number,)+
+                }
+            }
+        }
+
+        impl TryFrom<i32> for $name {
+            type Error = Errors;
+
+            fn try_from(number: i32) -> Result<Self, Self::Error> {
+                match number {
+                    $($number => Ok($name::$variant),)+
+                    _ => Err(Errors::InternalError(format!(
+                        "invalid {} number {}",
+                        stringify!($name),
+                        number
+                    ))),
+                }
+            }
+        }
+
+        impl ToSql<Integer, Pg> for $name {
+            fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+                ToSql::<Integer, Pg>::to_sql(&i32::from(*self), out)
+            }
+        }
+
+        impl FromSql<Integer, Pg> for $name {
+            fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+                let number = <i32 as FromSql<Integer, Pg>>::from_sql(bytes)?;
+                $name::try_from(number).map_err(|_| {
+                    format!("invalid {} number {}", stringify!($name), number).into()
+                })
+            }
+        }
+    };
+}
+
+integer_enum! {
+    pub enum Role {
+        Admin = 0 as "Admin",
+        Editor = 1 as "Editor",
+        User = 2 as "User",
+    }
+}
+
+integer_enum! {
+    /// Place of a person in their family.
+    pub enum FamilyRole {
+        Father = 0 as "Father",
+        Mother = 1 as "Mother",
+        Children = 2 as "Children",
+        NotApplicable = 3 as "NA",
+    }
+}
+
+integer_enum! {
+    pub enum ActionStatus {
+        Todo = 0 as "Todo",
+        Doing = 1 as "Doing",
+        Done = 2 as "Done",
+        Cancelled = 3 as "Cancelled",
+    }
+}
+
+integer_enum! {
+    /// Unit of the interval between the actions of a recurring series.
+    pub enum Frequency {
+        Daily = 0 as "Daily",
+        Weekly = 1 as "Weekly",
+        Monthly = 2 as "Monthly",
+    }
+}
+
+integer_enum! {
+    pub enum AidKind {
+        Cash = 0 as "Cash",
+        InKind = 1 as "InKind",
+    }
+}
+
+integer_enum! {
+    /// What a person's requirement is about, so it can be matched to a donor.
+    #[derive(Default)]
+    pub enum RequirementCategory {
+        Medical = 0 as "Medical",
+        Housing = 1 as "Housing",
+        Education = 2 as "Education",
+        Employment = 3 as "Employment",
+        Food = 4 as "Food",
+        #[default]
+        Other = 5 as "Other",
+    }
+}
+
+integer_enum! {
+    #[derive(Default)]
+    pub enum Priority {
+        Low = 0 as "Low",
+        #[default]
+        Normal = 1 as "Normal",
+        High = 2 as "High",
+        Urgent = 3 as "Urgent",
+    }
+}
+
+integer_enum! {
+    #[derive(Default)]
+    pub enum RequirementStatus {
+        #[default]
+        Open = 0 as "Open",
+        InProgress = 1 as "InProgress",
+        Fulfilled = 2 as "Fulfilled",
+        Rejected = 3 as "Rejected",
+    }
+}
+
+integer_enum! {
+    pub enum EmploymentType {
+        FullTime = 0 as "FullTime",
+        PartTime = 1 as "PartTime",
+        DailyWage = 2 as "DailyWage",
+        SelfEmployed = 3 as "SelfEmployed",
+    }
+}
+
+integer_enum! {
+    /// What a job's `income` is paid for.
+    #[derive(Default)]
+    pub enum IncomePeriod {
+        #[default]
+        Monthly = 0 as "Monthly",
+        Daily = 1 as "Daily",
+    }
+}
+
+/// Days a daily wage is counted as paid for in a month, a six-day week.
+pub const WORKING_DAYS_PER_MONTH: i32 = 26;
+
+impl IncomePeriod {
+    /// `income` as a monthly figure, or `None` when that does not fit.
+    pub fn monthly(self, income: i32) -> Option<i32> {
+        match self {
+            IncomePeriod::Monthly => Some(income),
+            IncomePeriod::Daily => income.checked_mul(WORKING_DAYS_PER_MONTH),
+        }
+    }
+}
+
+impl RequirementStatus {
+    /// Statuses of requirements still waiting to be met.
+    pub const OPEN: [RequirementStatus; 2] =
+        [RequirementStatus::Open, RequirementStatus::InProgress];
+
+    pub fn is_closed(self) -> bool {
+        !RequirementStatus::OPEN.contains(&self)
+    }
+}
+
+/// A move of a case action from one status to another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    Start,
+    Complete,
+    Cancel,
+    Reopen,
+}
+
+impl ActionStatus {
+    /// Statuses of actions that still need doing.
+    pub const OPEN: [ActionStatus; 2] = [ActionStatus::Todo, ActionStatus::Doing];
+
+    /// The status `transition` leads to, or `None` when it is not allowed
+    /// from this one.
+    pub fn after(self, transition: Transition) -> Option<ActionStatus> {
+        use ActionStatus::*;
+
+        match (self, transition) {
+            (Todo, Transition::Start) => Some(Doing),
+            (Todo | Doing, Transition::Complete) => Some(Done),
+            (Todo | Doing, Transition::Cancel) => Some(Cancelled),
+            (Done | Cancelled, Transition::Reopen) => Some(Todo),
+            _ => None,
+        }
+    }
+}
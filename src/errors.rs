@@ -1,3 +1,6 @@
+use crate::validation::ValidationErrors;
+use rocket::serde::json::Json;
+
 #[derive(Responder, Debug)]
 pub enum Errors {
     #[response(status = 500, content_type = "json")]
@@ -6,8 +9,26 @@ pub enum Errors {
     #[response(status = 400, content_type = "json")]
     BadRequest(String),
 
+    #[response(status = 400, content_type = "json")]
+    ValidationError(Json<ValidationErrors>),
+
+    #[response(status = 403, content_type = "json")]
+    Forbidden(String),
+
     #[response(status = 501, content_type = "json")]
     InternalError(String),
 }
 
+impl From<diesel::result::Error> for Errors {
+    fn from(e: diesel::result::Error) -> Self {
+        Errors::DatabaseError(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for Errors {
+    fn from(e: std::io::Error) -> Self {
+        Errors::InternalError(e.to_string())
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Errors>;
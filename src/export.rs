@@ -0,0 +1,1141 @@
+use crate::access::Visibility;
+use crate::agenda;
+use crate::enums::{
+    ActionStatus, EmploymentType, FamilyRole, IncomePeriod, Priority, RequirementCategory,
+    RequirementStatus,
+};
+use crate::errors::*;
+use crate::filters::{CaseFilter, ExportSheet, SpreadsheetFormat};
+use crate::jalali::{Calendar, JalaliDate};
+use crate::models::{case_query, current_jobs};
+use crate::schema::*;
+use crate::website::JobDatabase;
+use chrono::{NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use rocket::tokio::runtime::Handle;
+use rocket::tokio::sync::mpsc::{self, error::SendTimeoutError};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::Duration;
+use uuid::Uuid;
+use zip::write::{SimpleFileOptions, StreamWriter};
+use zip::{CompressionMethod, ZipWriter};
+
+/// Cases read from the database at a time.
+const BATCH_SIZE: i64 = 200;
+/// Bytes gathered before they are handed on to the client.
+const CHUNK_SIZE: usize = 32 * 1024;
+/// Chunks that may wait for a slow client before the export stops reading.
+const CHANNEL_CHUNKS: usize = 4;
+/// How long the export waits for a client that stopped reading before it
+/// gives up and closes its connection.
+const STALLED_CLIENT_SECS: u64 = 60;
+
+/// Lets Excel tell that a CSV file is UTF-8, which it otherwise reads in the
+/// system code page and shows Persian text garbled.
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+const LIST_SEPARATOR: &str = "، ";
+
+/// Every entity sheet, in the order an XLSX export holds them.
+const ENTITY_SHEETS: [ExportSheet; 6] = [
+    ExportSheet::Cases,
+    ExportSheet::Persons,
+    ExportSheet::Jobs,
+    ExportSheet::Skills,
+    ExportSheet::Requirements,
+    ExportSheet::Actions,
+];
+
+impl ExportSheet {
+    fn title(self) -> &'static str {
+        match self {
+            ExportSheet::Cases => "پرونده‌ها",
+            ExportSheet::Persons => "افراد",
+            ExportSheet::Jobs => "مشاغل",
+            ExportSheet::Skills => "مهارت‌ها",
+            ExportSheet::Requirements => "نیازها",
+            ExportSheet::Actions => "اقدامات",
+            ExportSheet::Household => "خانوارها",
+        }
+    }
+
+    fn headers(self) -> &'static [&'static str] {
+        match self {
+            ExportSheet::Cases => &["شماره پرونده", "فعال", "تاریخ ثبت", "نشانی", "توضیحات"],
+            ExportSheet::Persons => &[
+                "شماره پرونده",
+                "نام",
+                "نام خانوادگی",
+                "نام پدر",
+                "تاریخ تولد",
+                "کد ملی",
+                "تلفن",
+                "سرپرست",
+                "نقش در خانواده",
+                "رشته تحصیلی",
+                "محل تحصیل",
+                "توضیحات",
+            ],
+            ExportSheet::Jobs => &[
+                "شماره پرونده",
+                "کد ملی",
+                "نام و نام خانوادگی",
+                "عنوان شغل",
+                "نوع اشتغال",
+                "درآمد",
+                "دوره درآمد",
+                "درآمد ماهانه",
+                "محل کار",
+                "تاریخ شروع",
+                "تاریخ پایان",
+            ],
+            ExportSheet::Skills => &[
+                "شماره پرونده",
+                "کد ملی",
+                "نام و نام خانوادگی",
+                "مهارت",
+                "دسته",
+            ],
+            ExportSheet::Requirements => &[
+                "شماره پرونده",
+                "کد ملی",
+                "نام و نام خانوادگی",
+                "شرح",
+                "دسته",
+                "اولویت",
+                "وضعیت",
+                "هزینه برآوردی",
+                "مهلت",
+                "نتیجه",
+            ],
+            ExportSheet::Actions => &[
+                "شماره پرونده",
+                "اقدام",
+                "وضعیت",
+                "تاریخ",
+                "زمان انجام",
+                "نتیجه",
+            ],
+            ExportSheet::Household => &[
+                "شماره پرونده",
+                "فعال",
+                "نشانی",
+                "نام",
+                "نام خانوادگی",
+                "نام پدر",
+                "تاریخ تولد",
+                "کد ملی",
+                "تلفن",
+                "نقش در خانواده",
+                "سرپرست",
+                "شغل فعلی",
+                "درآمد ماهانه",
+                "مهارت‌ها",
+                "نیازهای باز",
+            ],
+        }
+    }
+}
+
+/// Persian name of a value, as the export shows it.
+pub trait Label {
+    fn label(self) -> &'static str;
+}
+
+impl Label for bool {
+    fn label(self) -> &'static str {
+        match self {
+            true => "بله",
+            false => "خیر",
+        }
+    }
+}
+
+impl Label for FamilyRole {
+    fn label(self) -> &'static str {
+        match self {
+            FamilyRole::Father => "پدر",
+            FamilyRole::Mother => "مادر",
+            FamilyRole::Children => "فرزند",
+            FamilyRole::NotApplicable => "سایر",
+        }
+    }
+}
+
+impl Label for EmploymentType {
+    fn label(self) -> &'static str {
+        match self {
+            EmploymentType::FullTime => "تمام‌وقت",
+            EmploymentType::PartTime => "پاره‌وقت",
+            EmploymentType::DailyWage => "روزمزد",
+            EmploymentType::SelfEmployed => "خویش‌فرما",
+        }
+    }
+}
+
+impl Label for IncomePeriod {
+    fn label(self) -> &'static str {
+        match self {
+            IncomePeriod::Monthly => "ماهانه",
+            IncomePeriod::Daily => "روزانه",
+        }
+    }
+}
+
+impl Label for RequirementCategory {
+    fn label(self) -> &'static str {
+        match self {
+            RequirementCategory::Medical => "درمانی",
+            RequirementCategory::Housing => "مسکن",
+            RequirementCategory::Education => "تحصیلی",
+            RequirementCategory::Employment => "اشتغال",
+            RequirementCategory::Food => "غذایی",
+            RequirementCategory::Other => "سایر",
+        }
+    }
+}
+
+impl Label for Priority {
+    fn label(self) -> &'static str {
+        match self {
+            Priority::Low => "کم",
+            Priority::Normal => "عادی",
+            Priority::High => "زیاد",
+            Priority::Urgent => "فوری",
+        }
+    }
+}
+
+impl Label for RequirementStatus {
+    fn label(self) -> &'static str {
+        match self {
+            RequirementStatus::Open => "باز",
+            RequirementStatus::InProgress => "در حال انجام",
+            RequirementStatus::Fulfilled => "برآورده‌شده",
+            RequirementStatus::Rejected => "ردشده",
+        }
+    }
+}
+
+impl Label for ActionStatus {
+    fn label(self) -> &'static str {
+        match self {
+            ActionStatus::Todo => "انجام‌نشده",
+            ActionStatus::Doing => "در حال انجام",
+            ActionStatus::Done => "انجام‌شده",
+            ActionStatus::Cancelled => "لغوشده",
+        }
+    }
+}
+
+/// One value of a row. Numbers stay numbers in XLSX so they can be added up.
+#[derive(Debug, Clone)]
+enum Cell {
+    Text(String),
+    Number(i64),
+    Empty,
+}
+
+impl From<String> for Cell {
+    fn from(text: String) -> Self {
+        Cell::Text(text)
+    }
+}
+
+impl From<&str> for Cell {
+    fn from(text: &str) -> Self {
+        Cell::Text(text.to_owned())
+    }
+}
+
+impl<T: Label> From<T> for Cell {
+    fn from(value: T) -> Self {
+        Cell::Text(value.label().to_owned())
+    }
+}
+
+impl From<i64> for Cell {
+    fn from(number: i64) -> Self {
+        Cell::Number(number)
+    }
+}
+
+impl From<i32> for Cell {
+    fn from(number: i32) -> Self {
+        Cell::Number(i64::from(number))
+    }
+}
+
+fn optional<T: Into<Cell>>(value: Option<T>) -> Cell {
+    value.map_or(Cell::Empty, Into::into)
+}
+
+/// How dates are written: in `calendar`, with times moved to `tz`.
+#[derive(Debug, Clone, Copy)]
+pub struct DateStyle {
+    pub calendar: Calendar,
+    pub tz: Tz,
+}
+
+impl DateStyle {
+    fn date(self, day: Option<NaiveDate>) -> Cell {
+        let day = match day {
+            Some(day) => day,
+            None => return Cell::Empty,
+        };
+        match JalaliDate::from_gregorian(day) {
+            Some(jalali) if self.calendar == Calendar::Jalali => Cell::Text(jalali.to_string()),
+            _ => Cell::Text(day.format("%Y-%m-%d").to_string()),
+        }
+    }
+
+    fn date_time(self, at: Option<NaiveDateTime>) -> Cell {
+        let at = match at {
+            Some(at) => Utc
+                .from_utc_datetime(&at)
+                .with_timezone(&self.tz)
+                .naive_local(),
+            None => return Cell::Empty,
+        };
+        match self.date(Some(at.date())) {
+            Cell::Text(day) => Cell::Text(format!("{} {}", day, at.format("%H:%M"))),
+            cell => cell,
+        }
+    }
+}
+
+/// One export: what it holds and which cases it covers.
+pub struct Export {
+    pub format: SpreadsheetFormat,
+    pub sheets: Vec<ExportSheet>,
+    pub filter: CaseFilter,
+    /// Covers only this case, when given.
+    pub case_id: Option<Uuid>,
+    pub visibility: Visibility,
+    pub dates: DateStyle,
+}
+
+impl Export {
+    /// A CSV file holds one sheet, the household view unless `sheet` is
+    /// given; an XLSX file holds `sheet`, or every entity sheet.
+    pub fn sheets(format: SpreadsheetFormat, sheet: Option<ExportSheet>) -> Vec<ExportSheet> {
+        match (format, sheet) {
+            (_, Some(sheet)) => vec![sheet],
+            (SpreadsheetFormat::Csv, None) => vec![ExportSheet::Household],
+            (SpreadsheetFormat::Xlsx, None) => ENTITY_SHEETS.to_vec(),
+        }
+    }
+
+    pub fn file_name(&self) -> String {
+        let name = match self.case_id {
+            Some(case_id) => format!("case-{}", case_id),
+            None => format!("cases-{}", agenda::today(self.dates.tz).format("%Y-%m-%d")),
+        };
+        let extension = match self.format {
+            SpreadsheetFormat::Csv => "csv",
+            SpreadsheetFormat::Xlsx => "xlsx",
+        };
+        format!("{}.{}", name, extension)
+    }
+}
+
+/// Starts writing `export` and returns the chunks as they are written. The
+/// export reads the cases a batch at a time and waits while the client is
+/// behind, so it never holds the whole file. It reads on a connection of its
+/// own rather than a pooled one, which slow downloads would use up, and stops
+/// once the client has not read for `STALLED_CLIENT_SECS`. An error after the
+/// first chunk can only cut the file short, so it is logged.
+pub fn spawn(db: JobDatabase, export: Export) -> mpsc::Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel(CHANNEL_CHUNKS);
+    rocket::tokio::spawn(async move {
+        let result = db
+            .run(move |c| {
+                let out = ChunkSender {
+                    chunk: Vec::with_capacity(CHUNK_SIZE),
+                    sender,
+                    runtime: Handle::current(),
+                };
+                match export.format {
+                    SpreadsheetFormat::Csv => write(c, &export, CsvWriter::new(out)?),
+                    SpreadsheetFormat::Xlsx => {
+                        write(c, &export, XlsxWriter::new(out, &export.sheets)?)
+                    }
+                }
+            })
+            .await;
+        if let Err(e) = result {
+            error!("case export failed: {:?}", e);
+        }
+    });
+    receiver
+}
+
+/// Hands what is written to it on in chunks of `CHUNK_SIZE`. Writing fails
+/// once the client has gone away or stopped reading.
+struct ChunkSender {
+    chunk: Vec<u8>,
+    sender: mpsc::Sender<Vec<u8>>,
+    runtime: Handle,
+}
+
+impl Write for ChunkSender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.chunk.extend_from_slice(buf);
+        if self.chunk.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.chunk.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.chunk, Vec::with_capacity(CHUNK_SIZE));
+        let stalled = Duration::from_secs(STALLED_CLIENT_SECS);
+        self.runtime
+            .block_on(self.sender.send_timeout(chunk, stalled))
+            .map_err(|e| match e {
+                SendTimeoutError::Timeout(_) => {
+                    io::Error::new(io::ErrorKind::TimedOut, "export was not read in time")
+                }
+                SendTimeoutError::Closed(_) => {
+                    io::Error::new(io::ErrorKind::BrokenPipe, "export was not read")
+                }
+            })
+    }
+}
+
+trait SheetWriter {
+    /// Ends the sheet being written, if any, and starts `sheet` with its
+    /// header row.
+    fn sheet(&mut self, sheet: ExportSheet) -> io::Result<()>;
+    fn row(&mut self, cells: &[Cell]) -> io::Result<()>;
+    fn finish(self) -> io::Result<()>;
+}
+
+struct CsvWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> CsvWriter<W> {
+    fn new(mut out: W) -> io::Result<Self> {
+        out.write_all(UTF8_BOM)?;
+        Ok(CsvWriter { out })
+    }
+
+    fn line<'a>(
+        &mut self,
+        fields: impl Iterator<Item = std::borrow::Cow<'a, str>>,
+    ) -> io::Result<()> {
+        let line = fields
+            .map(|field| csv_field(&field))
+            .collect::<Vec<_>>()
+            .join(",");
+        write!(self.out, "{}\r\n", line)
+    }
+}
+
+/// Whether a spreadsheet would read `text` as a formula when it is opened
+/// or edited. Phone numbers (`+98…`) only look like one and are left alone.
+fn is_formula(text: &str) -> bool {
+    match text.strip_prefix('+') {
+        Some(rest) => rest.is_empty() || !rest.chars().all(|c| c.is_ascii_digit()),
+        None => text.starts_with(['=', '-', '@', '\t', '\r']),
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains(['"', ',', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+impl<W: Write> SheetWriter for CsvWriter<W> {
+    fn sheet(&mut self, sheet: ExportSheet) -> io::Result<()> {
+        self.line(sheet.headers().iter().map(|&header| header.into()))
+    }
+
+    fn row(&mut self, cells: &[Cell]) -> io::Result<()> {
+        self.line(cells.iter().map(|cell| match cell {
+            Cell::Text(text) if is_formula(text) => format!("'{}", text).into(),
+            Cell::Text(text) => text.as_str().into(),
+            Cell::Number(number) => number.to_string().into(),
+            Cell::Empty => "".into(),
+        }))
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+const XLSX_CONTENT_TYPE: &str = "application/vnd.openxmlformats-officedocument.spreadsheetml";
+const XLSX_MAIN_NS: &str = "http://schemas.openxmlformats.org/spreadsheetml/2006/main";
+const XLSX_RELATIONSHIP_NS: &str = "http://schemas.openxmlformats.org/package/2006/relationships";
+const XLSX_RELATIONSHIP_TYPE: &str =
+    "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
+const XML_DECLARATION: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#;
+
+/// Default font, the bold one of the header row as style 1, and as style 2
+/// text that must not be taken for a formula when the cell is edited.
+const XLSX_STYLES: &str = concat!(
+    r#"<fonts count="2"><font><sz val="11"/><name val="Tahoma"/></font>"#,
+    r#"<font><b/><sz val="11"/><name val="Tahoma"/></font></fonts>"#,
+    r#"<fills count="2"><fill><patternFill patternType="none"/></fill>"#,
+    r#"<fill><patternFill patternType="gray125"/></fill></fills>"#,
+    r#"<borders count="1"><border><left/><right/><top/><bottom/><diagonal/></border></borders>"#,
+    r#"<cellStyleXfs count="1"><xf numFmtId="0" fontId="0" fillId="0" borderId="0"/></cellStyleXfs>"#,
+    r#"<cellXfs count="3"><xf numFmtId="0" fontId="0" fillId="0" borderId="0" xfId="0"/>"#,
+    r#"<xf numFmtId="0" fontId="1" fillId="0" borderId="0" xfId="0" applyFont="1"/>"#,
+    r#"<xf numFmtId="0" fontId="0" fillId="0" borderId="0" xfId="0" quotePrefix="1"/></cellXfs>"#,
+);
+
+/// Writes an XLSX workbook with one right-to-left sheet per `ExportSheet`.
+/// Strings are written inline rather than to a shared table, so each row
+/// can be sent as soon as it is read.
+struct XlsxWriter<W: Write> {
+    zip: ZipWriter<StreamWriter<W>>,
+    sheets: usize,
+}
+
+impl<W: Write> XlsxWriter<W> {
+    fn new(out: W, sheets: &[ExportSheet]) -> io::Result<Self> {
+        let mut writer = XlsxWriter {
+            zip: ZipWriter::new_stream(out),
+            sheets: 0,
+        };
+
+        let mut types = format!(
+            r#"<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="{0}.sheet.main+xml"/><Override PartName="/xl/styles.xml" ContentType="{0}.styles+xml"/>"#,
+            XLSX_CONTENT_TYPE
+        );
+        let mut workbook = format!(
+            r#"<workbook xmlns="{}" xmlns:r="{}"><sheets>"#,
+            XLSX_MAIN_NS, XLSX_RELATIONSHIP_TYPE
+        );
+        let mut relationships = format!(r#"<Relationships xmlns="{}">"#, XLSX_RELATIONSHIP_NS);
+        for (index, sheet) in sheets.iter().enumerate() {
+            let number = index + 1;
+            types += &format!(
+                r#"<Override PartName="/xl/worksheets/sheet{}.xml" ContentType="{}.worksheet+xml"/>"#,
+                number, XLSX_CONTENT_TYPE
+            );
+            workbook += &format!(
+                r#"<sheet name="{}" sheetId="{1}" r:id="rId{1}"/>"#,
+                xml_text(sheet.title()),
+                number
+            );
+            relationships += &format!(
+                r#"<Relationship Id="rId{0}" Type="{1}/worksheet" Target="worksheets/sheet{0}.xml"/>"#,
+                number, XLSX_RELATIONSHIP_TYPE
+            );
+        }
+        types += "</Types>";
+        workbook += "</sheets></workbook>";
+        relationships += &format!(
+            r#"<Relationship Id="rId{}" Type="{}/styles" Target="styles.xml"/></Relationships>"#,
+            sheets.len() + 1,
+            XLSX_RELATIONSHIP_TYPE
+        );
+
+        writer.part("[Content_Types].xml", &types)?;
+        writer.part(
+            "_rels/.rels",
+            &format!(
+                r#"<Relationships xmlns="{}"><Relationship Id="rId1" Type="{}/officeDocument" Target="xl/workbook.xml"/></Relationships>"#,
+                XLSX_RELATIONSHIP_NS, XLSX_RELATIONSHIP_TYPE
+            ),
+        )?;
+        writer.part("xl/workbook.xml", &workbook)?;
+        writer.part("xl/_rels/workbook.xml.rels", &relationships)?;
+        writer.part(
+            "xl/styles.xml",
+            &format!(
+                r#"<styleSheet xmlns="{}">{}</styleSheet>"#,
+                XLSX_MAIN_NS, XLSX_STYLES
+            ),
+        )?;
+        Ok(writer)
+    }
+
+    fn start(&mut self, name: &str) -> io::Result<()> {
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        self.zip.start_file(name, options)?;
+        self.zip.write_all(XML_DECLARATION.as_bytes())
+    }
+
+    fn part(&mut self, name: &str, xml: &str) -> io::Result<()> {
+        self.start(name)?;
+        self.zip.write_all(xml.as_bytes())
+    }
+
+    fn end_sheet(&mut self) -> io::Result<()> {
+        match self.sheets {
+            0 => Ok(()),
+            _ => self.zip.write_all(b"</sheetData></worksheet>"),
+        }
+    }
+}
+
+impl<W: Write> SheetWriter for XlsxWriter<W> {
+    fn sheet(&mut self, sheet: ExportSheet) -> io::Result<()> {
+        self.end_sheet()?;
+        self.sheets += 1;
+        self.start(&format!("xl/worksheets/sheet{}.xml", self.sheets))?;
+        write!(
+            self.zip,
+            r#"<worksheet xmlns="{}"><sheetViews><sheetView rightToLeft="1" workbookViewId="0"><pane ySplit="1" topLeftCell="A2" activePane="bottomLeft" state="frozen"/></sheetView></sheetViews><sheetData><row>"#,
+            XLSX_MAIN_NS
+        )?;
+        for header in sheet.headers() {
+            write!(
+                self.zip,
+                r#"<c t="inlineStr" s="1"><is><t>{}</t></is></c>"#,
+                xml_text(header)
+            )?;
+        }
+        self.zip.write_all(b"</row>")
+    }
+
+    fn row(&mut self, cells: &[Cell]) -> io::Result<()> {
+        let mut row = String::from("<row>");
+        for cell in cells {
+            match cell {
+                Cell::Text(text) => {
+                    row += match is_formula(text) {
+                        true => r#"<c t="inlineStr" s="2"><is><t xml:space="preserve">"#,
+                        false => r#"<c t="inlineStr"><is><t xml:space="preserve">"#,
+                    };
+                    row += &xml_text(text);
+                    row += "</t></is></c>";
+                }
+                Cell::Number(number) => row += &format!("<c><v>{}</v></c>", number),
+                Cell::Empty => row += "<c/>",
+            }
+        }
+        row += "</row>";
+        self.zip.write_all(row.as_bytes())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.end_sheet()?;
+        let mut out = self.zip.finish()?.into_inner();
+        out.flush()
+    }
+}
+
+/// Escapes `text` for XML and drops the control characters XML 1.0 does not
+/// allow.
+fn xml_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c < ' ' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[derive(Queryable)]
+struct CaseRow {
+    id: Uuid,
+    number: i32,
+    active: bool,
+    registration_date: NaiveDateTime,
+    address: Option<String>,
+    description: Option<String>,
+}
+
+#[derive(Queryable)]
+struct PersonRow {
+    id: Uuid,
+    case_id: Uuid,
+    first_name: String,
+    last_name: String,
+    father_name: String,
+    birthday: NaiveDate,
+    national_number: String,
+    phone_number: String,
+    is_leader: bool,
+    family_role: FamilyRole,
+    education_field: Option<String>,
+    education_location: Option<String>,
+    description: Option<String>,
+}
+
+impl PersonRow {
+    fn full_name(&self) -> String {
+        format!("{} {}", self.first_name, self.last_name)
+    }
+}
+
+#[derive(Queryable)]
+struct JobRow {
+    person_id: Uuid,
+    title: String,
+    employment_type: Option<EmploymentType>,
+    income: Option<i32>,
+    income_period: IncomePeriod,
+    monthly_income: Option<i32>,
+    location: Option<String>,
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+}
+
+#[derive(Queryable)]
+struct SkillRow {
+    person_id: Uuid,
+    name: String,
+    category: Option<String>,
+}
+
+#[derive(Queryable)]
+struct RequirementRow {
+    person_id: Uuid,
+    description: String,
+    category: RequirementCategory,
+    priority: Priority,
+    status: RequirementStatus,
+    estimated_cost: Option<i32>,
+    due_date: Option<NaiveDate>,
+    fulfilment_note: Option<String>,
+}
+
+#[derive(Queryable)]
+struct ActionRow {
+    case_id: Uuid,
+    action: String,
+    status: ActionStatus,
+    action_date: Option<NaiveDateTime>,
+    completed_at: Option<NaiveDateTime>,
+    result_note: Option<String>,
+}
+
+/// The cases of one batch and their live members, leaders and the oldest
+/// first.
+struct Batch {
+    cases: Vec<CaseRow>,
+    persons: Vec<PersonRow>,
+}
+
+impl Batch {
+    fn case(&self, case_id: Uuid) -> Option<&CaseRow> {
+        self.cases.iter().find(|case| case.id == case_id)
+    }
+
+    fn person_ids(&self) -> Vec<Uuid> {
+        self.persons.iter().map(|person| person.id).collect()
+    }
+
+    /// Rows of `items` next to the member they belong to, in member order.
+    fn by_person<'a, T>(
+        &'a self,
+        items: &'a [T],
+        person_id: impl Fn(&T) -> Uuid,
+    ) -> Vec<(&'a CaseRow, &'a PersonRow, &'a T)> {
+        let position: HashMap<Uuid, usize> = self
+            .persons
+            .iter()
+            .enumerate()
+            .map(|(position, person)| (person.id, position))
+            .collect();
+        let mut rows: Vec<_> = items
+            .iter()
+            .filter_map(|item| {
+                let person = &self.persons[*position.get(&person_id(item))?];
+                Some((self.case(person.case_id)?, person, item))
+            })
+            .collect();
+        rows.sort_by_key(|(_, person, _)| position[&person.id]);
+        rows
+    }
+}
+
+fn write(c: &PgConnection, export: &Export, mut writer: impl SheetWriter) -> Result<()> {
+    for &sheet in &export.sheets {
+        writer.sheet(sheet)?;
+        let mut after = None;
+        loop {
+            let batch = load_batch(c, export, sheet, after)?;
+            let last = match batch.cases.last() {
+                Some(last) => last.number,
+                None => break,
+            };
+            for row in rows(c, export.dates, sheet, &batch)? {
+                writer.row(&row)?;
+            }
+            after = Some(last);
+        }
+    }
+    Ok(writer.finish()?)
+}
+
+/// The next `BATCH_SIZE` cases by number after `after`, and their members
+/// when `sheet` needs them.
+fn load_batch(
+    c: &PgConnection,
+    export: &Export,
+    sheet: ExportSheet,
+    after: Option<i32>,
+) -> Result<Batch> {
+    let mut query = case_query(&export.filter, export.visibility);
+    if let Some(case_id) = export.case_id {
+        query = query.filter(cases::id.eq(case_id));
+    }
+    if let Some(after) = after {
+        query = query.filter(cases::number.gt(after));
+    }
+    let cases = query
+        .select((
+            cases::id,
+            cases::number,
+            cases::active,
+            cases::registration_date,
+            cases::address,
+            cases::description,
+        ))
+        .order(cases::number.asc())
+        .limit(BATCH_SIZE)
+        .load::<CaseRow>(c)?;
+
+    let persons = match sheet {
+        ExportSheet::Cases | ExportSheet::Actions => Vec::new(),
+        _ => {
+            let position: HashMap<Uuid, usize> = cases
+                .iter()
+                .enumerate()
+                .map(|(position, case)| (case.id, position))
+                .collect();
+            let mut persons = persons::table
+                .filter(persons::case_id.eq_any(position.keys().copied().collect::<Vec<_>>()))
+                .filter(persons::deleted_at.is_null())
+                .select((
+                    persons::id,
+                    persons::case_id,
+                    persons::first_name,
+                    persons::last_name,
+                    persons::father_name,
+                    persons::birthday,
+                    persons::national_number,
+                    persons::phone_number,
+                    persons::is_leader,
+                    persons::family_role,
+                    persons::education_field,
+                    persons::education_location,
+                    persons::description,
+                ))
+                .load::<PersonRow>(c)?;
+            persons.sort_by_key(|person| {
+                (
+                    position[&person.case_id],
+                    !person.is_leader,
+                    person.birthday,
+                )
+            });
+            persons
+        }
+    };
+
+    Ok(Batch { cases, persons })
+}
+
+fn rows(
+    c: &PgConnection,
+    dates: DateStyle,
+    sheet: ExportSheet,
+    batch: &Batch,
+) -> Result<Vec<Vec<Cell>>> {
+    let rows = match sheet {
+        ExportSheet::Cases => batch
+            .cases
+            .iter()
+            .map(|case| {
+                vec![
+                    case.number.into(),
+                    case.active.into(),
+                    dates.date_time(Some(case.registration_date)),
+                    optional(case.address.clone()),
+                    optional(case.description.clone()),
+                ]
+            })
+            .collect(),
+        ExportSheet::Persons => batch
+            .persons
+            .iter()
+            .filter_map(|person| {
+                let case = batch.case(person.case_id)?;
+                Some(vec![
+                    case.number.into(),
+                    person.first_name.clone().into(),
+                    person.last_name.clone().into(),
+                    person.father_name.clone().into(),
+                    dates.date(Some(person.birthday)),
+                    person.national_number.trim().into(),
+                    person.phone_number.trim().into(),
+                    person.is_leader.into(),
+                    person.family_role.into(),
+                    optional(person.education_field.clone()),
+                    optional(person.education_location.clone()),
+                    optional(person.description.clone()),
+                ])
+            })
+            .collect(),
+        ExportSheet::Jobs => {
+            let jobs = load_jobs(c, batch, None)?;
+            batch
+                .by_person(&jobs, |job| job.person_id)
+                .into_iter()
+                .map(|(case, person, job)| {
+                    vec![
+                        case.number.into(),
+                        person.national_number.trim().into(),
+                        person.full_name().into(),
+                        job.title.clone().into(),
+                        optional(job.employment_type),
+                        optional(job.income),
+                        job.income_period.into(),
+                        optional(job.monthly_income),
+                        optional(job.location.clone()),
+                        dates.date(job.start_date),
+                        dates.date(job.end_date),
+                    ]
+                })
+                .collect()
+        }
+        ExportSheet::Skills => {
+            let skills = load_skills(c, batch)?;
+            batch
+                .by_person(&skills, |skill| skill.person_id)
+                .into_iter()
+                .map(|(case, person, skill)| {
+                    vec![
+                        case.number.into(),
+                        person.national_number.trim().into(),
+                        person.full_name().into(),
+                        skill.name.clone().into(),
+                        optional(skill.category.clone()),
+                    ]
+                })
+                .collect()
+        }
+        ExportSheet::Requirements => {
+            let requirements = load_requirements(c, batch, false)?;
+            batch
+                .by_person(&requirements, |requirement| requirement.person_id)
+                .into_iter()
+                .map(|(case, person, requirement)| {
+                    vec![
+                        case.number.into(),
+                        person.national_number.trim().into(),
+                        person.full_name().into(),
+                        requirement.description.clone().into(),
+                        requirement.category.into(),
+                        requirement.priority.into(),
+                        requirement.status.into(),
+                        optional(requirement.estimated_cost),
+                        dates.date(requirement.due_date),
+                        optional(requirement.fulfilment_note.clone()),
+                    ]
+                })
+                .collect()
+        }
+        ExportSheet::Actions => {
+            let case_ids: Vec<Uuid> = batch.cases.iter().map(|case| case.id).collect();
+            let mut actions = case_actions::table
+                .filter(case_actions::case_id.eq_any(case_ids))
+                .select((
+                    case_actions::case_id,
+                    case_actions::action,
+                    case_actions::status,
+                    case_actions::action_date,
+                    case_actions::completed_at,
+                    case_actions::result_note,
+                ))
+                .load::<ActionRow>(c)?;
+            actions.sort_by_key(|action| (action.action_date.is_none(), action.action_date));
+            batch
+                .cases
+                .iter()
+                .flat_map(|case| {
+                    actions
+                        .iter()
+                        .filter(move |action| action.case_id == case.id)
+                        .map(move |action| {
+                            vec![
+                                case.number.into(),
+                                action.action.clone().into(),
+                                action.status.into(),
+                                dates.date_time(action.action_date),
+                                dates.date_time(action.completed_at),
+                                optional(action.result_note.clone()),
+                            ]
+                        })
+                })
+                .collect()
+        }
+        ExportSheet::Household => household_rows(c, dates, batch)?,
+    };
+    Ok(rows)
+}
+
+/// One row per member, with their current jobs, skills and open
+/// requirements joined into single cells.
+fn household_rows(c: &PgConnection, dates: DateStyle, batch: &Batch) -> Result<Vec<Vec<Cell>>> {
+    let jobs = load_jobs(c, batch, Some(agenda::today(dates.tz)))?;
+    let skills = load_skills(c, batch)?;
+    let requirements = load_requirements(c, batch, true)?;
+
+    let rows = batch
+        .persons
+        .iter()
+        .filter_map(|person| {
+            let case = batch.case(person.case_id)?;
+            let jobs: Vec<&JobRow> = jobs
+                .iter()
+                .filter(|job| job.person_id == person.id)
+                .collect();
+            let income = jobs
+                .iter()
+                .filter_map(|job| job.monthly_income)
+                .map(i64::from)
+                .reduce(|total, income| total + income);
+            let list = |items: Vec<&str>| match items.is_empty() {
+                true => Cell::Empty,
+                false => Cell::Text(items.join(LIST_SEPARATOR)),
+            };
+
+            Some(vec![
+                case.number.into(),
+                case.active.into(),
+                optional(case.address.clone()),
+                person.first_name.clone().into(),
+                person.last_name.clone().into(),
+                person.father_name.clone().into(),
+                dates.date(Some(person.birthday)),
+                person.national_number.trim().into(),
+                person.phone_number.trim().into(),
+                person.family_role.into(),
+                person.is_leader.into(),
+                list(jobs.iter().map(|job| job.title.as_str()).collect()),
+                optional(income),
+                list(
+                    skills
+                        .iter()
+                        .filter(|skill| skill.person_id == person.id)
+                        .map(|skill| skill.name.as_str())
+                        .collect(),
+                ),
+                list(
+                    requirements
+                        .iter()
+                        .filter(|requirement| requirement.person_id == person.id)
+                        .map(|requirement| requirement.description.as_str())
+                        .collect(),
+                ),
+            ])
+        })
+        .collect();
+    Ok(rows)
+}
+
+/// Jobs of the batch's members, only those current on `current_on` when it
+/// is given.
+fn load_jobs(
+    c: &PgConnection,
+    batch: &Batch,
+    current_on: Option<NaiveDate>,
+) -> Result<Vec<JobRow>> {
+    let mut query = person_jobs::table
+        .filter(person_jobs::person_id.eq_any(batch.person_ids()))
+        .into_boxed();
+    if let Some(day) = current_on {
+        query = query.filter(current_jobs(day));
+    }
+    let mut jobs = query
+        .select((
+            person_jobs::person_id,
+            person_jobs::title,
+            person_jobs::employment_type,
+            person_jobs::income,
+            person_jobs::income_period,
+            person_jobs::monthly_income,
+            person_jobs::location,
+            person_jobs::start_date,
+            person_jobs::end_date,
+        ))
+        .load::<JobRow>(c)?;
+    jobs.sort_by_key(|job| job.start_date);
+    Ok(jobs)
+}
+
+fn load_skills(c: &PgConnection, batch: &Batch) -> Result<Vec<SkillRow>> {
+    Ok(person_skills::table
+        .inner_join(skills::table)
+        .filter(person_skills::person_id.eq_any(batch.person_ids()))
+        .select((person_skills::person_id, skills::name, skills::category))
+        .order(skills::name.asc())
+        .load::<SkillRow>(c)?)
+}
+
+/// Requirements of the batch's members, the most pressing first, only those
+/// still open when `open` is set.
+fn load_requirements(c: &PgConnection, batch: &Batch, open: bool) -> Result<Vec<RequirementRow>> {
+    let mut query = person_requirements::table
+        .filter(person_requirements::person_id.eq_any(batch.person_ids()))
+        .into_boxed();
+    if open {
+        query = query.filter(person_requirements::status.eq_any(RequirementStatus::OPEN.to_vec()));
+    }
+    Ok(query
+        .select((
+            person_requirements::person_id,
+            person_requirements::description,
+            person_requirements::category,
+            person_requirements::priority,
+            person_requirements::status,
+            person_requirements::estimated_cost,
+            person_requirements::due_date,
+            person_requirements::fulfilment_note,
+        ))
+        .order(person_requirements::priority.desc())
+        .load::<RequirementRow>(c)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formulas_are_neutralised() {
+        for text in ["=1+1", "+1+1", "+A1", "-2", "@SUM(A1)", "\t=1", "+"] {
+            assert!(is_formula(text), "{:?}", text);
+        }
+        for text in ["+989121234567", "علی", "1-2", "a=b", ""] {
+            assert!(!is_formula(text), "{:?}", text);
+        }
+
+        let mut out = Vec::new();
+        let mut csv = CsvWriter { out: &mut out };
+        csv.row(&[
+            "=HYPERLINK(\"x\")".into(),
+            "+989121234567".into(),
+            Cell::Number(-5),
+        ])
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\"'=HYPERLINK(\"\"x\"\")\",+989121234567,-5\r\n"
+        );
+    }
+}
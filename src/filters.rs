@@ -0,0 +1,182 @@
+use crate::enums::{
+    ActionStatus, AidKind, FamilyRole, Priority, RequirementCategory, RequirementStatus, Role,
+};
+use crate::pagination::{QueryDate, Sort};
+use uuid::Uuid;
+
+#[derive(Debug, FromForm)]
+pub struct UserFilter {
+    pub role: Option<Role>,
+    pub active: Option<bool>,
+}
+
+#[derive(Debug, FromFormField, Clone, Copy)]
+pub enum UserSortKey {
+    Id,
+    Username,
+}
+
+impl Default for Sort<UserSortKey> {
+    fn default() -> Self {
+        Sort::desc(UserSortKey::Id)
+    }
+}
+
+#[derive(Debug, Default, FromForm)]
+pub struct CaseFilter {
+    pub active: Option<bool>,
+    pub editor: Option<Uuid>,
+    pub import_batch_id: Option<Uuid>,
+    pub registered_from: Option<QueryDate>,
+    pub registered_to: Option<QueryDate>,
+}
+
+#[derive(Debug, FromFormField, Clone, Copy)]
+pub enum CaseSortKey {
+    #[field(value = "registration_date")]
+    RegistrationDate,
+    Number,
+}
+
+impl Default for Sort<CaseSortKey> {
+    fn default() -> Self {
+        Sort::desc(CaseSortKey::RegistrationDate)
+    }
+}
+
+/// File type of a case export or a family import.
+#[derive(Debug, FromFormField, Clone, Copy, PartialEq)]
+pub enum SpreadsheetFormat {
+    Csv,
+    Xlsx,
+}
+
+/// What a case export holds: the rows of one entity, or the household view
+/// with one row per person and their case, job, skills and open
+/// requirements flattened into it.
+#[derive(Debug, FromFormField, Clone, Copy, PartialEq)]
+pub enum ExportSheet {
+    Cases,
+    Persons,
+    Jobs,
+    Skills,
+    Requirements,
+    Actions,
+    Household,
+}
+
+#[derive(Debug, FromForm)]
+pub struct PersonFilter {
+    pub case_id: Option<Uuid>,
+    pub family_role: Option<FamilyRole>,
+    pub is_leader: Option<bool>,
+}
+
+#[derive(Debug, FromFormField, Clone, Copy)]
+pub enum PersonSortKey {
+    Id,
+    #[field(value = "last_name")]
+    LastName,
+    Birthday,
+}
+
+impl Default for Sort<PersonSortKey> {
+    fn default() -> Self {
+        Sort::desc(PersonSortKey::Id)
+    }
+}
+
+/// Without a `status`, only requirements still open are listed.
+#[derive(Debug, FromForm)]
+pub struct RequirementFilter {
+    pub case_id: Option<Uuid>,
+    pub person_id: Option<Uuid>,
+    pub category: Option<RequirementCategory>,
+    pub priority: Option<Priority>,
+    pub status: Vec<RequirementStatus>,
+    /// Only requirements due on or before this day.
+    pub due_by: Option<QueryDate>,
+}
+
+#[derive(Debug, FromFormField, Clone, Copy)]
+pub enum RequirementSortKey {
+    Id,
+    Priority,
+    #[field(value = "due_date")]
+    DueDate,
+}
+
+impl Default for Sort<RequirementSortKey> {
+    fn default() -> Self {
+        Sort::desc(RequirementSortKey::Priority)
+    }
+}
+
+#[derive(Debug, FromForm)]
+pub struct SkillFilter {
+    /// Part of a name or synonym.
+    pub q: Option<String>,
+    pub category: Option<String>,
+}
+
+#[derive(Debug, FromForm)]
+pub struct CaseActionFilter {
+    pub case_id: Option<Uuid>,
+    pub status: Option<ActionStatus>,
+    pub assignee: Option<Uuid>,
+    pub from: Option<QueryDate>,
+    pub to: Option<QueryDate>,
+}
+
+/// Days are local to `tz`, or to the caller's own time zone when it is left
+/// out.
+#[derive(Debug, FromForm)]
+pub struct AgendaFilter {
+    pub from: Option<QueryDate>,
+    pub to: Option<QueryDate>,
+    pub tz: Option<String>,
+    pub status: Vec<ActionStatus>,
+    pub case_id: Option<Uuid>,
+    pub assignee: Option<Uuid>,
+    /// Also list open actions whose date has passed.
+    pub overdue: Option<bool>,
+    /// The week or month around `from`, or around today, instead of `to`.
+    pub period: Option<AgendaPeriod>,
+}
+
+/// Weeks start on Saturday and months follow the Jalali calendar when the
+/// caller asked for Jalali dates.
+#[derive(Debug, FromFormField, Clone, Copy, PartialEq)]
+pub enum AgendaPeriod {
+    Week,
+    Month,
+}
+
+#[derive(Debug, FromForm)]
+pub struct AidTotalsFilter {
+    pub case_id: Option<Uuid>,
+    pub kind: Option<AidKind>,
+    pub from: Option<QueryDate>,
+    pub to: Option<QueryDate>,
+    pub period: Option<AidPeriod>,
+}
+
+/// Length of the periods aid totals are added up over.
+#[derive(Debug, FromFormField, Clone, Copy, PartialEq)]
+pub enum AidPeriod {
+    Month,
+    Year,
+}
+
+#[derive(Debug, FromFormField, Clone, Copy)]
+pub enum CaseActionSortKey {
+    Id,
+    #[field(value = "action_date")]
+    ActionDate,
+}
+
+impl Default for Sort<CaseActionSortKey> {
+    fn default() -> Self {
+        Sort::asc(CaseActionSortKey::ActionDate)
+    }
+}
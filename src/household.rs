@@ -0,0 +1,369 @@
+use crate::access::Visibility;
+use crate::audit::{self, Auditable, Operation};
+use crate::enums::FamilyRole;
+use crate::errors::*;
+use crate::models::current_jobs;
+use crate::schema::*;
+use crate::validation::{Validate, Validator};
+use crate::website::Db;
+use chrono::{Datelike, NaiveDate, Utc};
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Date, Int4, Nullable, Uuid as SqlUuid};
+use diesel::PgConnection;
+use rocket::serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+
+/// Members younger than this are counted as children.
+pub const ADULT_AGE: i32 = 18;
+/// Members this old or older are counted as elderly.
+const ELDERLY_AGE: i32 = 65;
+
+/// `NeedWeights::score` over a household `h` of the ranking query and the
+/// weights `w`. The two must stay identical, or the ranking is not ordered
+/// by the scores it shows.
+const NEED_SCORE: &str = "CASE WHEN h.size = 0 THEN 0 ELSE round((100 * ( \
+     w.income * (1 - least((h.income / h.size)::FLOAT8 / w.poverty_line, 1)) \
+     + w.household_size * least(h.size::FLOAT8 / w.large_household, 1) \
+     + w.dependents * h.dependents / h.size::FLOAT8 \
+     + w.no_earner * (h.earners = 0)::INT) \
+     / (w.income + w.household_size + w.dependents + w.no_earner))::NUMERIC, 2) END";
+
+/// How the need score is made up, from the single row of `need_weights`.
+/// Each factor is between 0 and 1; the score is their weighted mean, scaled
+/// to 0–100.
+#[derive(Debug, Queryable, Serialize, Deserialize, Clone)]
+pub struct NeedWeights {
+    /// Always true, as the table has a single row.
+    #[serde(skip)]
+    #[allow(dead_code)]
+    id: bool,
+    pub income: f64,
+    pub household_size: f64,
+    pub dependents: f64,
+    pub no_earner: f64,
+    /// Monthly Toman per person at or above which income adds nothing.
+    pub poverty_line: i32,
+    /// Household size at which the size factor is full.
+    pub large_household: i32,
+}
+
+impl Auditable for NeedWeights {
+    const ENTITY_TYPE: &'static str = "need_weights";
+
+    fn entity_id(&self) -> Uuid {
+        Uuid::nil()
+    }
+}
+
+impl Validate for NeedWeights {
+    fn validate(self) -> Result<Self> {
+        let weight = |value: f64| {
+            if value.is_finite() && value >= 0.0 {
+                Ok(())
+            } else {
+                Err(("out_of_range", "must be a number of at least 0".to_owned()))
+            }
+        };
+        let positive = |value: i32| {
+            if value > 0 {
+                Ok(())
+            } else {
+                Err(("out_of_range", "must be greater than 0".to_owned()))
+            }
+        };
+
+        let mut validator = Validator::new();
+        validator.check("income", weight(self.income));
+        validator.check("household_size", weight(self.household_size));
+        validator.check("dependents", weight(self.dependents));
+        validator.check("no_earner", weight(self.no_earner));
+        validator.check("poverty_line", positive(self.poverty_line));
+        validator.check("large_household", positive(self.large_household));
+        if self.total() <= 0.0 {
+            validator.check::<()>(
+                "income",
+                Err(("out_of_range", "at least one weight must be above 0".into())),
+            );
+        }
+        validator.finish()?;
+        Ok(self)
+    }
+}
+
+impl NeedWeights {
+    pub async fn get(conn: &Db) -> Result<NeedWeights> {
+        conn.run(|c| need_weights::table.first::<NeedWeights>(c))
+            .await
+            .map_err(|e| Errors::DatabaseError(e.to_string()))
+    }
+
+    pub async fn update(conn: &Db, weights: NeedWeights, actor: Uuid) -> Result<NeedWeights> {
+        use self::need_weights::dsl::*;
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = need_weights.for_update().first::<NeedWeights>(c)?;
+                let after = diesel::update(need_weights)
+                    .set((
+                        income.eq(weights.income),
+                        household_size.eq(weights.household_size),
+                        dependents.eq(weights.dependents),
+                        no_earner.eq(weights.no_earner),
+                        poverty_line.eq(weights.poverty_line),
+                        large_household.eq(weights.large_household),
+                    ))
+                    .get_result::<NeedWeights>(c)?;
+
+                audit::record(c, actor, Operation::Update, Some(&before), Some(&after))?;
+                Ok(after)
+            })
+        })
+        .await
+    }
+
+    fn total(&self) -> f64 {
+        self.income + self.household_size + self.dependents + self.no_earner
+    }
+
+    /// Need score of a household, from 0 to 100 with two decimals. An empty
+    /// household scores 0, as there is nothing to go on.
+    fn score(
+        &self,
+        size: usize,
+        per_capita_income: Option<i64>,
+        dependents: usize,
+        earners: usize,
+    ) -> f64 {
+        let per_capita_income = match per_capita_income {
+            Some(per_capita_income) if size > 0 => per_capita_income,
+            _ => return 0.0,
+        };
+        let size = size as f64;
+
+        let income = 1.0 - (per_capita_income as f64 / f64::from(self.poverty_line)).min(1.0);
+        let household_size = (size / f64::from(self.large_household)).min(1.0);
+        let share_of_dependents = dependents as f64 / size;
+        let no_earner = match earners {
+            0 => 1.0,
+            _ => 0.0,
+        };
+
+        let score = 100.0
+            * (self.income * income
+                + self.household_size * household_size
+                + self.dependents * share_of_dependents
+                + self.no_earner * no_earner)
+            / self.total();
+        (score * 100.0).round() / 100.0
+    }
+}
+
+#[derive(Debug, Serialize, Default, Clone)]
+pub struct AgeGroups {
+    pub under_6: usize,
+    pub from_6_to_17: usize,
+    pub from_18_to_64: usize,
+    pub from_65: usize,
+}
+
+/// Figures about the people of a case, from its live persons and the income
+/// of the jobs each one has now.
+#[derive(Debug, Serialize, Clone)]
+pub struct CaseSummary {
+    pub case_id: Uuid,
+    pub case_number: i32,
+    pub household_size: usize,
+    pub family_roles: BTreeMap<FamilyRole, usize>,
+    /// Monthly, in Toman, from the members' current jobs.
+    pub total_income: i64,
+    pub per_capita_income: Option<i64>,
+    /// Members whose current jobs pay an income.
+    pub earners: usize,
+    pub age_groups: AgeGroups,
+    pub need_score: f64,
+}
+
+#[derive(Debug, QueryableByName)]
+struct Ranked {
+    #[sql_type = "SqlUuid"]
+    id: Uuid,
+    #[sql_type = "Int4"]
+    number: i32,
+}
+
+/// A person as far as the summary is concerned.
+struct Member {
+    family_role: FamilyRole,
+    birthday: NaiveDate,
+    /// Monthly, from current jobs; none without any.
+    income: Option<i64>,
+}
+
+pub fn age(birthday: NaiveDate, today: NaiveDate) -> i32 {
+    let years = today.year() - birthday.year();
+    if (today.month(), today.day()) < (birthday.month(), birthday.day()) {
+        years - 1
+    } else {
+        years
+    }
+}
+
+fn summarize(
+    case_id: Uuid,
+    case_number: i32,
+    members: &[Member],
+    weights: &NeedWeights,
+    today: NaiveDate,
+) -> CaseSummary {
+    let mut family_roles = BTreeMap::new();
+    let mut age_groups = AgeGroups::default();
+    let mut dependents = 0;
+    for member in members {
+        *family_roles.entry(member.family_role).or_insert(0) += 1;
+
+        let age = age(member.birthday, today);
+        if age < 6 {
+            age_groups.under_6 += 1;
+        } else if age < ADULT_AGE {
+            age_groups.from_6_to_17 += 1;
+        } else if age < ELDERLY_AGE {
+            age_groups.from_18_to_64 += 1;
+        } else {
+            age_groups.from_65 += 1;
+        }
+        if !(ADULT_AGE..ELDERLY_AGE).contains(&age) {
+            dependents += 1;
+        }
+    }
+
+    let incomes = members.iter().filter_map(|member| member.income);
+    let earners = incomes.clone().filter(|income| *income > 0).count();
+    let total_income: i64 = incomes.sum();
+    let household_size = members.len();
+    let per_capita_income = match household_size {
+        0 => None,
+        size => Some(total_income / size as i64),
+    };
+
+    CaseSummary {
+        case_id,
+        case_number,
+        household_size,
+        family_roles,
+        total_income,
+        per_capita_income,
+        earners,
+        age_groups,
+        need_score: weights.score(household_size, per_capita_income, dependents, earners),
+    }
+}
+
+/// Summaries of the given live cases, in the order given.
+fn summaries(c: &PgConnection, cases: Vec<(Uuid, i32)>) -> Result<Vec<CaseSummary>> {
+    let weights = need_weights::table.first::<NeedWeights>(c)?;
+    let case_ids: Vec<Uuid> = cases.iter().map(|(id, _)| *id).collect();
+
+    let persons = persons::table
+        .filter(persons::case_id.eq_any(&case_ids))
+        .filter(persons::deleted_at.is_null())
+        .select((
+            persons::id,
+            persons::case_id,
+            persons::family_role,
+            persons::birthday,
+        ))
+        .load::<(Uuid, Uuid, FamilyRole, NaiveDate)>(c)?;
+
+    let today = Utc::now().date().naive_utc();
+    let person_ids: Vec<Uuid> = persons.iter().map(|(id, ..)| *id).collect();
+    let mut incomes: BTreeMap<Uuid, i64> = BTreeMap::new();
+    for (person_id, income) in person_jobs::table
+        .filter(person_jobs::person_id.eq_any(person_ids))
+        .filter(current_jobs(today))
+        .select((person_jobs::person_id, person_jobs::monthly_income))
+        .load::<(Uuid, Option<i32>)>(c)?
+    {
+        *incomes.entry(person_id).or_insert(0) += income.map_or(0, i64::from);
+    }
+
+    let mut members: HashMap<Uuid, Vec<Member>> = HashMap::new();
+    for (id, case_id, family_role, birthday) in persons {
+        members.entry(case_id).or_default().push(Member {
+            family_role,
+            birthday,
+            income: incomes.get(&id).copied(),
+        });
+    }
+
+    Ok(cases
+        .into_iter()
+        .map(|(case_id, number)| {
+            let of_case = members.get(&case_id).map_or(&[][..], Vec::as_slice);
+            summarize(case_id, number, of_case, &weights, today)
+        })
+        .collect())
+}
+
+pub async fn summary(conn: &Db, p_case_id: Uuid) -> Result<Option<CaseSummary>> {
+    conn.run(move |c| {
+        let case = cases::table
+            .find(p_case_id)
+            .filter(cases::deleted_at.is_null())
+            .select((cases::id, cases::number))
+            .first::<(Uuid, i32)>(c)
+            .optional()?;
+
+        match case {
+            None => Ok(None),
+            Some(case) => Ok(summaries(c, vec![case])?.pop()),
+        }
+    })
+    .await
+}
+
+/// The active cases the caller can see, neediest first. Only the `limit`
+/// neediest are summarized; the scores they are picked by are worked out by
+/// the database with `NEED_SCORE`.
+pub async fn ranking(conn: &Db, visibility: Visibility, limit: i64) -> Result<Vec<CaseSummary>> {
+    let user = match visibility {
+        Visibility::All => None,
+        Visibility::AssignedTo(user) => Some(user),
+    };
+
+    conn.run(move |c| {
+        let today = Utc::now().date().naive_utc();
+        let ranked = diesel::sql_query(format!(
+            "SELECT h.id, h.number FROM ( \
+                 SELECT c.id, c.number, count(p.id) AS size, \
+                 count(p.id) FILTER (WHERE NOT date_part('year', age($1, p.birthday)) \
+                     BETWEEN {adult} AND {elderly} - 1) AS dependents, \
+                 count(p.id) FILTER (WHERE j.income > 0) AS earners, \
+                 coalesce(sum(j.income), 0)::BIGINT AS income \
+                 FROM cases c \
+                 LEFT JOIN persons p ON p.case_id = c.id AND p.deleted_at IS NULL \
+                 LEFT JOIN LATERAL ( \
+                     SELECT sum(monthly_income) AS income FROM person_jobs \
+                     WHERE person_id = p.id \
+                     AND (start_date IS NULL OR start_date <= $1) \
+                     AND (end_date IS NULL OR end_date >= $1) \
+                 ) j ON TRUE \
+                 WHERE c.deleted_at IS NULL AND c.active \
+                 AND ($3::uuid IS NULL \
+                     OR c.id IN (SELECT case_id FROM case_assignments WHERE user_id = $3)) \
+                 GROUP BY c.id \
+             ) h, need_weights w \
+             ORDER BY {score} DESC, h.number LIMIT $2",
+            adult = ADULT_AGE,
+            elderly = ELDERLY_AGE,
+            score = NEED_SCORE,
+        ))
+        .bind::<Date, _>(today)
+        .bind::<BigInt, _>(limit)
+        .bind::<Nullable<SqlUuid>, _>(user)
+        .load::<Ranked>(c)?;
+
+        summaries(c, ranked.into_iter().map(|r| (r.id, r.number)).collect())
+    })
+    .await
+}
@@ -0,0 +1,98 @@
+use chrono::{NaiveDateTime, Utc};
+use uuid::Uuid;
+
+/// Content lines longer than this many octets are folded (RFC 5545 3.1).
+const MAX_LINE_OCTETS: usize = 75;
+
+const DATE_TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Events have no end in the database, so each is shown as lasting this long.
+const EVENT_DURATION: &str = "PT1H";
+
+/// One VEVENT. Its UID comes from the row it shows, so calendar apps update
+/// the event they already have instead of adding a copy when it changes.
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub id: Uuid,
+    /// UTC, as `action_date` is stored.
+    pub start: NaiveDateTime,
+    pub summary: String,
+    pub location: Option<String>,
+    pub description: Option<String>,
+    pub cancelled: bool,
+}
+
+/// Renders a VCALENDAR named `name` holding `events`.
+pub fn calendar(name: &str, events: &[Event]) -> String {
+    let stamp = Utc::now().naive_utc().format(DATE_TIME_FORMAT).to_string();
+
+    let mut out = String::new();
+    line(&mut out, "BEGIN", "VCALENDAR");
+    line(&mut out, "VERSION", "2.0");
+    line(&mut out, "PRODID", "-//form-website//case actions//EN");
+    line(&mut out, "CALSCALE", "GREGORIAN");
+    line(&mut out, "METHOD", "PUBLISH");
+    line(&mut out, "X-WR-CALNAME", &text(name));
+
+    for event in events {
+        line(&mut out, "BEGIN", "VEVENT");
+        line(&mut out, "UID", &format!("{}@form-website", event.id));
+        line(&mut out, "DTSTAMP", &stamp);
+        line(
+            &mut out,
+            "DTSTART",
+            &event.start.format(DATE_TIME_FORMAT).to_string(),
+        );
+        line(&mut out, "DURATION", EVENT_DURATION);
+        line(&mut out, "SUMMARY", &text(&event.summary));
+        if let Some(location) = &event.location {
+            line(&mut out, "LOCATION", &text(location));
+        }
+        if let Some(description) = &event.description {
+            line(&mut out, "DESCRIPTION", &text(description));
+        }
+        let status = match event.cancelled {
+            true => "CANCELLED",
+            false => "CONFIRMED",
+        };
+        line(&mut out, "STATUS", status);
+        line(&mut out, "END", "VEVENT");
+    }
+
+    line(&mut out, "END", "VCALENDAR");
+    out
+}
+
+/// Escapes a TEXT value (RFC 5545 3.3.11).
+fn text(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Appends `NAME:value` ending in CRLF, folding it so no line is longer than
+/// `MAX_LINE_OCTETS`. Folds never split a UTF-8 character, which matters for
+/// Persian text.
+fn line(out: &mut String, name: &str, value: &str) {
+    let content = format!("{}:{}", name, value);
+    let mut octets = 0;
+    for c in content.chars() {
+        // Continuation lines start with a space, which counts towards them.
+        if octets + c.len_utf8() > MAX_LINE_OCTETS {
+            out.push_str("\r\n ");
+            octets = 1;
+        }
+        out.push(c);
+        octets += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
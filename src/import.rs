@@ -0,0 +1,1076 @@
+use crate::access::CaseAssignment;
+use crate::audit::{self, Auditable, Operation};
+use crate::enums::{EmploymentType, FamilyRole, IncomePeriod};
+use crate::errors::*;
+use crate::export::Label;
+use crate::filters::SpreadsheetFormat;
+use crate::jalali::JalaliDate;
+use crate::models::{Case, DefaultJob, NewCase, NewPerson, NewPersonJob, Person, PersonJob};
+use crate::persian;
+use crate::schema::*;
+use crate::service_options::DuplicatePolicy;
+use crate::validation::{self, FieldError, Validate, ValidationErrors};
+use crate::website::Db;
+use calamine::{Data, Reader, Xlsx};
+use chrono::{Duration, NaiveDate, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::PgConnection;
+use rocket::serde::json::Json;
+use rocket::serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Cursor;
+use uuid::Uuid;
+
+/// Column naming the family a row belongs to. Rows sharing a value become
+/// the persons of one case; the value itself is not stored.
+const CASE_KEY: &str = "case_id";
+
+const CASE_FIELDS: [&str; 3] = [CASE_KEY, "address", "case_description"];
+const PERSON_FIELDS: [&str; 11] = [
+    "first_name",
+    "last_name",
+    "father_name",
+    "birthday",
+    "national_number",
+    "phone_number",
+    "family_role",
+    "is_leader",
+    "description",
+    "education_field",
+    "education_location",
+];
+const JOB_FIELDS: [&str; 7] = [
+    "job_title",
+    "job_income",
+    "job_income_period",
+    "job_employment_type",
+    "job_location",
+    "job_start_date",
+    "job_end_date",
+];
+const REQUIRED_FIELDS: [&str; 7] = [
+    CASE_KEY,
+    "first_name",
+    "last_name",
+    "father_name",
+    "birthday",
+    "national_number",
+    "phone_number",
+];
+
+/// Parameters are bound per national number when looking for duplicates.
+const DUPLICATE_CHUNK: usize = 1000;
+
+/// Day 0 of Excel's date serials, chosen so that serials after February
+/// 1900 come out right despite Excel counting 1900 as a leap year.
+fn excel_epoch() -> NaiveDate {
+    NaiveDate::from_ymd(1899, 12, 30)
+}
+
+#[derive(Debug, Queryable, Serialize, Clone)]
+pub struct ImportBatch {
+    pub id: Uuid,
+    pub file_name: String,
+    pub imported_by: Option<Uuid>,
+    pub imported_at: NaiveDateTime,
+    pub row_count: i32,
+    pub case_count: i32,
+    pub person_count: i32,
+    pub job_count: i32,
+    pub rolled_back_by: Option<Uuid>,
+    pub rolled_back_at: Option<NaiveDateTime>,
+}
+
+impl Auditable for ImportBatch {
+    const ENTITY_TYPE: &'static str = "import_batch";
+
+    fn entity_id(&self) -> Uuid {
+        self.id
+    }
+}
+
+/// A problem with one cell or row of the file. Rows are numbered as the
+/// spreadsheet shows them, the header being row 1.
+#[derive(Debug, Serialize, Clone)]
+pub struct RowError {
+    pub row: usize,
+    pub field: String,
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// What an import found, and saved when it was not a dry run.
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    /// Set when the rows were saved.
+    pub batch_id: Option<Uuid>,
+    pub rows: usize,
+    pub cases: usize,
+    pub persons: usize,
+    pub jobs: usize,
+    /// Nothing is saved while there are any.
+    pub errors: Vec<RowError>,
+    pub warnings: Vec<RowError>,
+}
+
+/// Header of the column each field is read from, by field name.
+pub type Mapping = HashMap<String, String>;
+
+/// An uploaded spreadsheet and how its columns map onto the fields above.
+/// Without a mapping, headers are read as field names.
+pub struct Upload {
+    pub file_name: String,
+    pub format: SpreadsheetFormat,
+    pub content: Vec<u8>,
+    pub mapping: Option<Mapping>,
+}
+
+/// The first sheet of the file: its header and the rows below it that are
+/// not blank, with their row numbers.
+struct Sheet {
+    header: Vec<String>,
+    rows: Vec<(usize, Vec<String>)>,
+}
+
+struct Row<'a> {
+    number: usize,
+    cells: &'a [String],
+    columns: &'a HashMap<&'static str, usize>,
+}
+
+impl Row<'_> {
+    /// Trimmed text of the field's cell; `None` when it is not mapped or is
+    /// empty.
+    fn get(&self, field: &str) -> Option<&str> {
+        let cell = self.cells.get(*self.columns.get(field)?)?.trim();
+        match cell.is_empty() {
+            true => None,
+            false => Some(cell),
+        }
+    }
+
+    fn error(&self, field: &str, code: &'static str, message: String) -> RowError {
+        RowError {
+            row: self.number,
+            field: field.to_owned(),
+            code,
+            message,
+        }
+    }
+}
+
+struct Family {
+    key: String,
+    row: usize,
+    case: Map<String, Value>,
+    members: Vec<Member>,
+}
+
+struct Member {
+    row: usize,
+    national_number: Option<String>,
+    is_leader: Option<bool>,
+    person: Map<String, Value>,
+    jobs: Vec<Map<String, Value>>,
+}
+
+pub async fn import(
+    conn: &Db,
+    upload: Upload,
+    policy: DuplicatePolicy,
+    commit: bool,
+    actor: Uuid,
+    today: NaiveDate,
+) -> Result<ImportReport> {
+    let sheet = match upload.format {
+        SpreadsheetFormat::Csv => read_csv(&upload.content)?,
+        SpreadsheetFormat::Xlsx => read_xlsx(upload.content)?,
+    };
+    let columns = map_columns(&sheet.header, upload.mapping)?;
+    let file_name = upload.file_name;
+
+    conn.run(move |c| {
+        let mut report = ImportReport {
+            batch_id: None,
+            rows: sheet.rows.len(),
+            cases: 0,
+            persons: 0,
+            jobs: 0,
+            errors: Vec::new(),
+            warnings: Vec::new(),
+        };
+        let families = plan(&sheet, &columns, &mut report);
+        check_duplicates(c, &families, policy, &mut report)?;
+
+        report.cases = families.len();
+        report.persons = families.iter().map(|family| family.members.len()).sum();
+        report.jobs = families
+            .iter()
+            .flat_map(|family| &family.members)
+            .map(|member| member.jobs.len())
+            .sum();
+        report.errors.sort_by_key(|error| error.row);
+        report.warnings.sort_by_key(|warning| warning.row);
+
+        if commit && report.errors.is_empty() {
+            let batch = c.transaction::<_, Errors, _>(|| {
+                save(c, &file_name, &report, families, actor, today)
+            })?;
+            report.batch_id = Some(batch.id);
+        }
+        Ok(report)
+    })
+    .await
+}
+
+fn read_csv(content: &[u8]) -> Result<Sheet> {
+    let text = std::str::from_utf8(content)
+        .map_err(|_| Errors::BadRequest("CSV files must be encoded as UTF-8".to_owned()))?;
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let delimiter = detect_delimiter(text);
+
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            '\n' if quoted => {
+                line += 1;
+                field.push(ch);
+            }
+            _ if quoted => field.push(ch),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' | '\r' => {
+                record.push(std::mem::take(&mut field));
+                records.push((record_line, std::mem::take(&mut record)));
+                line += 1;
+                record_line = line;
+            }
+            _ if ch == delimiter => record.push(std::mem::take(&mut field)),
+            _ => field.push(ch),
+        }
+    }
+    if quoted {
+        return Err(Errors::BadRequest(format!(
+            "quoted field opened on line {} is never closed",
+            record_line
+        )));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push((record_line, record));
+    }
+
+    sheet(records)
+}
+
+/// Comma, semicolon or tab, whichever the header line uses most. Excel
+/// writes semicolons in locales where the comma is the decimal separator.
+fn detect_delimiter(text: &str) -> char {
+    let header = text.lines().next().unwrap_or("");
+    [',', ';', '\t']
+        .iter()
+        .copied()
+        .max_by_key(|delimiter| (header.matches(*delimiter).count(), *delimiter == ','))
+        .unwrap_or(',')
+}
+
+fn read_xlsx(content: Vec<u8>) -> Result<Sheet> {
+    let unreadable =
+        |e: calamine::XlsxError| Errors::BadRequest(format!("cannot read the XLSX file: {}", e));
+    let mut workbook = Xlsx::new(Cursor::new(content)).map_err(unreadable)?;
+    let range = match workbook.worksheet_range_at(0) {
+        Some(range) => range.map_err(unreadable)?,
+        None => return Err(Errors::BadRequest("the XLSX file has no sheets".to_owned())),
+    };
+
+    let first_row = range.start().map_or(0, |(row, _)| row as usize);
+    let first_column = range.start().map_or(0, |(_, column)| column as usize);
+    let records = range
+        .rows()
+        .enumerate()
+        .map(|(index, cells)| {
+            let mut record = vec![String::new(); first_column];
+            record.extend(cells.iter().map(cell_text));
+            (first_row + index + 1, record)
+        })
+        .collect();
+
+    sheet(records)
+}
+
+/// A cell as it would be typed: whole numbers without a decimal point and
+/// dates as `YYYY-MM-DD`.
+fn cell_text(cell: &Data) -> String {
+    match cell {
+        Data::String(text) | Data::DateTimeIso(text) | Data::DurationIso(text) => text.clone(),
+        Data::Int(number) => number.to_string(),
+        Data::Float(number) if number.fract() == 0.0 && number.abs() < 1e15 => {
+            format!("{}", *number as i64)
+        }
+        Data::Float(number) => number.to_string(),
+        Data::Bool(value) => value.to_string(),
+        Data::DateTime(at) if !at.is_duration() => (excel_epoch()
+            + Duration::days(at.as_f64().floor() as i64))
+        .format("%Y-%m-%d")
+        .to_string(),
+        Data::DateTime(at) => at.as_f64().to_string(),
+        Data::Error(_) | Data::Empty => String::new(),
+    }
+}
+
+fn sheet(records: Vec<(usize, Vec<String>)>) -> Result<Sheet> {
+    let is_blank = |record: &[String]| record.iter().all(|cell| cell.trim().is_empty());
+    let mut records = records.into_iter().filter(|(_, record)| !is_blank(record));
+    let header = match records.next() {
+        Some((_, header)) => header.iter().map(|cell| cell.trim().to_owned()).collect(),
+        None => return Err(Errors::BadRequest("the file is empty".to_owned())),
+    };
+    Ok(Sheet {
+        header,
+        rows: records.collect(),
+    })
+}
+
+/// Column of every mapped field. Headers are matched after Persian
+/// normalisation, so that Arabic and Persian letters compare equal.
+fn map_columns(
+    header: &[String],
+    mapping: Option<Mapping>,
+) -> Result<HashMap<&'static str, usize>> {
+    let fields = || {
+        CASE_FIELDS
+            .iter()
+            .chain(&PERSON_FIELDS)
+            .chain(&JOB_FIELDS)
+            .copied()
+    };
+    let key = |text: &str| persian::normalize(text.trim()).to_lowercase();
+    let headers: HashMap<String, usize> = header
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(index, text)| (key(text), index))
+        .collect();
+
+    let mut errors = Vec::new();
+    let mut error = |code: &'static str, message: String| {
+        errors.push(FieldError {
+            field: "mapping".into(),
+            code,
+            message,
+        })
+    };
+    let mapping = mapping.unwrap_or_else(|| {
+        fields()
+            .filter(|field| headers.contains_key(*field))
+            .map(|field| (field.to_owned(), field.to_owned()))
+            .collect()
+    });
+    for name in mapping.keys() {
+        if !fields().any(|field| field == name) {
+            error(
+                "unknown_field",
+                format!("{} is not a field that can be imported", name),
+            );
+        }
+    }
+
+    let mut columns = HashMap::new();
+    for field in fields() {
+        match mapping.get(field) {
+            Some(column) => match headers.get(&key(column)) {
+                Some(index) => {
+                    columns.insert(field, *index);
+                }
+                None => error(
+                    "unknown_column",
+                    format!("the file has no column {} for {}", column, field),
+                ),
+            },
+            None if REQUIRED_FIELDS.contains(&field) => {
+                error("required", format!("{} must be mapped to a column", field))
+            }
+            None => {}
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(columns),
+        false => Err(Errors::ValidationError(Json(ValidationErrors { errors }))),
+    }
+}
+
+/// Reads every row into the families it describes, reporting what does
+/// not parse or does not validate. Rows repeating a person of the same
+/// family add another job to them.
+fn plan(
+    sheet: &Sheet,
+    columns: &HashMap<&'static str, usize>,
+    report: &mut ImportReport,
+) -> Vec<Family> {
+    let mut families: Vec<Family> = Vec::new();
+    let mut family_of: HashMap<String, usize> = HashMap::new();
+    let mut person_of: HashMap<String, (usize, usize, usize)> = HashMap::new();
+
+    for (number, cells) in &sheet.rows {
+        let row = Row {
+            number: *number,
+            cells,
+            columns,
+        };
+        let key = match row.get(CASE_KEY) {
+            Some(key) => key.to_owned(),
+            None => {
+                report
+                    .errors
+                    .push(row.error(CASE_KEY, "required", "must not be empty".to_owned()));
+                continue;
+            }
+        };
+
+        let index = *family_of.entry(key.clone()).or_insert_with(|| {
+            families.push(Family {
+                key: key.clone(),
+                row: row.number,
+                case: Map::new(),
+                members: Vec::new(),
+            });
+            families.len() - 1
+        });
+        let family = &mut families[index];
+        merge_case(family, &row, &mut report.errors);
+
+        let job = read_job(&row, &mut report.errors);
+        let national_number = row
+            .get("national_number")
+            .and_then(|text| validation::national_number(text).ok());
+        if let Some(national_number) = &national_number {
+            if let Some((first_row, other, member)) = person_of.get(national_number) {
+                if *other == index {
+                    family.members[*member].jobs.extend(job);
+                } else {
+                    report.errors.push(row.error(
+                        "national_number",
+                        "duplicate",
+                        format!("is already given to a person on row {}", first_row),
+                    ));
+                }
+                continue;
+            }
+            person_of.insert(
+                national_number.clone(),
+                (row.number, index, family.members.len()),
+            );
+        }
+
+        let person = read_person(&row, &mut report.errors);
+        family.members.push(Member {
+            row: row.number,
+            national_number,
+            is_leader: person.1,
+            person: person.0,
+            jobs: job.into_iter().collect(),
+        });
+    }
+
+    for family in &mut families {
+        choose_leader(
+            family,
+            columns.contains_key("is_leader"),
+            &mut report.errors,
+        );
+    }
+    families
+}
+
+/// Takes the case fields of a family from its first row that has them,
+/// reporting later rows that disagree.
+fn merge_case(family: &mut Family, row: &Row<'_>, errors: &mut Vec<RowError>) {
+    for (field, name) in [("address", "address"), ("case_description", "description")] {
+        let value = match row.get(field) {
+            Some(value) => value,
+            None => continue,
+        };
+        match family.case.get(name).and_then(Value::as_str) {
+            Some(first) if first != value => errors.push(row.error(
+                field,
+                "conflict",
+                format!(
+                    "differs from earlier rows of case {}, which give {}",
+                    family.key, first
+                ),
+            )),
+            Some(_) => {}
+            None => {
+                family.case.insert(name.to_owned(), value.into());
+            }
+        }
+    }
+}
+
+/// Exactly one person of every family must be marked as its leader when
+/// the file says who leads; otherwise the first person listed leads.
+fn choose_leader(family: &mut Family, marked: bool, errors: &mut Vec<RowError>) {
+    if !marked {
+        for (index, member) in family.members.iter_mut().enumerate() {
+            member
+                .person
+                .insert("is_leader".to_owned(), (index == 0).into());
+        }
+        return;
+    }
+
+    let leaders: Vec<usize> = family
+        .members
+        .iter()
+        .filter(|member| member.is_leader == Some(true))
+        .map(|member| member.row)
+        .collect();
+    match leaders.as_slice() {
+        [] if !family.members.is_empty() => errors.push(RowError {
+            row: family.row,
+            field: "is_leader".to_owned(),
+            code: "leader",
+            message: format!("case {} has no leader", family.key),
+        }),
+        [_, others @ ..] => errors.extend(others.iter().map(|row| RowError {
+            row: *row,
+            field: "is_leader".to_owned(),
+            code: "leader",
+            message: format!(
+                "case {} already has a leader on row {}",
+                family.key, leaders[0]
+            ),
+        })),
+        _ => {}
+    }
+    for member in &mut family.members {
+        let is_leader = member.is_leader.unwrap_or(false);
+        member
+            .person
+            .insert("is_leader".to_owned(), is_leader.into());
+    }
+}
+
+/// The person of a row, and whether it is marked as the leader. The fields
+/// are checked the way `NewPerson` is when it is posted.
+fn read_person(row: &Row<'_>, errors: &mut Vec<RowError>) -> (Map<String, Value>, Option<bool>) {
+    let mut person = Map::new();
+    for field in [
+        "first_name",
+        "last_name",
+        "father_name",
+        "national_number",
+        "phone_number",
+    ] {
+        person.insert(field.to_owned(), row.get(field).unwrap_or("").into());
+    }
+    for field in ["description", "education_field", "education_location"] {
+        person.insert(field.to_owned(), to_value(row.get(field)));
+    }
+
+    match row.get("birthday").map(parse_date) {
+        Some(Some(birthday)) => {
+            person.insert("birthday".to_owned(), birthday.to_string().into());
+        }
+        Some(None) => errors.push(invalid_date(row, "birthday")),
+        None => errors.push(row.error("birthday", "required", "must not be empty".to_owned())),
+    }
+    let family_role = parse_field(row, "family_role", errors).unwrap_or(FamilyRole::NotApplicable);
+    person.insert("family_role".to_owned(), to_value(family_role));
+    let is_leader = row
+        .get("is_leader")
+        .and_then(|text| match parse_bool(text) {
+            Some(is_leader) => Some(is_leader),
+            None => {
+                errors.push(row.error(
+                    "is_leader",
+                    "invalid_value",
+                    format!("{} is not yes or no", text),
+                ));
+                None
+            }
+        });
+
+    // A birthday that did not parse is already reported; any date lets the
+    // other fields be checked.
+    let mut checked = person.clone();
+    checked
+        .entry("birthday")
+        .or_insert_with(|| excel_epoch().to_string().into());
+    checked.insert("case_id".to_owned(), Uuid::nil().to_string().into());
+    checked.insert("is_leader".to_owned(), false.into());
+    validate::<NewPerson>(row, checked, "", errors);
+    (person, is_leader)
+}
+
+/// The job of a row, when any of its job cells are filled in.
+fn read_job(row: &Row<'_>, errors: &mut Vec<RowError>) -> Option<Map<String, Value>> {
+    if !JOB_FIELDS.iter().any(|field| row.get(field).is_some()) {
+        return None;
+    }
+
+    let mut job = Map::new();
+    job.insert(
+        "title".to_owned(),
+        row.get("job_title").unwrap_or("").into(),
+    );
+    job.insert("location".to_owned(), to_value(row.get("job_location")));
+    if let Some(text) = row.get("job_income") {
+        match parse_amount(text) {
+            Some(income) => {
+                job.insert("income".to_owned(), income.into());
+            }
+            None => errors.push(row.error(
+                "job_income",
+                "invalid_value",
+                format!("{} is not a whole number of Toman", text),
+            )),
+        }
+    }
+    for (field, name) in [
+        ("job_start_date", "start_date"),
+        ("job_end_date", "end_date"),
+    ] {
+        match row.get(field).map(parse_date) {
+            Some(Some(date)) => {
+                job.insert(name.to_owned(), date.to_string().into());
+            }
+            Some(None) => errors.push(invalid_date(row, field)),
+            None => {}
+        }
+    }
+    if let Some(period) = parse_field::<IncomePeriod>(row, "job_income_period", errors) {
+        job.insert("income_period".to_owned(), to_value(period));
+    }
+    if let Some(kind) = parse_field::<EmploymentType>(row, "job_employment_type", errors) {
+        job.insert("employment_type".to_owned(), to_value(kind));
+    }
+
+    let mut checked = job.clone();
+    checked.insert("person_id".to_owned(), Uuid::nil().to_string().into());
+    validate::<NewPersonJob>(row, checked, "job_", errors);
+    Some(job)
+}
+
+/// Runs the checks of `T` on the row, reporting failing fields under the
+/// name they are mapped by.
+fn validate<T: DeserializeOwned + Validate>(
+    row: &Row<'_>,
+    fields: Map<String, Value>,
+    prefix: &str,
+    errors: &mut Vec<RowError>,
+) {
+    let failure = match from_fields::<T>(fields).and_then(T::validate) {
+        Ok(_) => return,
+        Err(e) => e,
+    };
+    match failure {
+        Errors::ValidationError(Json(failed)) => {
+            errors.extend(failed.errors.into_iter().map(|error| RowError {
+                row: row.number,
+                field: format!("{}{}", prefix, error.field),
+                code: error.code,
+                message: error.message,
+            }))
+        }
+        other => errors.push(row.error("", "invalid", format!("{:?}", other))),
+    }
+}
+
+fn from_fields<T: DeserializeOwned>(fields: Map<String, Value>) -> Result<T> {
+    serde_json::from_value(Value::Object(fields)).map_err(|e| Errors::InternalError(e.to_string()))
+}
+
+fn to_value<T: Serialize>(value: T) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
+
+fn invalid_date(row: &Row<'_>, field: &str) -> RowError {
+    row.error(
+        field,
+        "invalid_date",
+        "must be a date such as 1985-03-21 or 1364/01/01".to_owned(),
+    )
+}
+
+/// Reads a Gregorian or, for years before 1700, a Jalali date, separated
+/// with `-`, `/` or `.` and in ASCII or Persian digits. A time after the
+/// date is ignored.
+fn parse_date(text: &str) -> Option<NaiveDate> {
+    let text = persian::normalize_digits(text);
+    let date = text.split_whitespace().next()?.split('T').next()?;
+    let parts: Vec<&str> = date.split(['-', '/', '.']).collect();
+    let (year, month, day) = match parts.as_slice() {
+        [year, month, day] => (
+            year.parse::<i32>().ok()?,
+            month.parse().ok()?,
+            day.parse().ok()?,
+        ),
+        _ => return None,
+    };
+    match year {
+        0..=1699 => JalaliDate::new(year, month, day)?.to_gregorian(),
+        _ => NaiveDate::from_ymd_opt(year, month, day),
+    }
+}
+
+fn parse_bool(text: &str) -> Option<bool> {
+    match persian::normalize(text).trim().to_lowercase().as_str() {
+        "true" | "1" | "yes" | "y" | "بله" | "بلی" | "آری" | "x" => Some(true),
+        "false" | "0" | "no" | "n" | "خیر" | "نه" => Some(false),
+        _ => None,
+    }
+}
+
+/// A whole amount, allowing thousands separators.
+fn parse_amount(text: &str) -> Option<i32> {
+    let digits: String = persian::normalize_digits(text)
+        .chars()
+        .filter(|c| !matches!(c, ',' | '٬' | '،' | '_' | ' '))
+        .collect();
+    digits.parse().ok()
+}
+
+/// Values of an enum column, which files may give by their JSON name or
+/// by the Persian label the export shows.
+trait Choice: Label + Serialize + Copy + 'static {
+    const ALL: &'static [Self];
+}
+
+impl Choice for FamilyRole {
+    const ALL: &'static [Self] = &[
+        FamilyRole::Father,
+        FamilyRole::Mother,
+        FamilyRole::Children,
+        FamilyRole::NotApplicable,
+    ];
+}
+
+impl Choice for EmploymentType {
+    const ALL: &'static [Self] = &[
+        EmploymentType::FullTime,
+        EmploymentType::PartTime,
+        EmploymentType::DailyWage,
+        EmploymentType::SelfEmployed,
+    ];
+}
+
+impl Choice for IncomePeriod {
+    const ALL: &'static [Self] = &[IncomePeriod::Monthly, IncomePeriod::Daily];
+}
+
+fn parse_field<T: Choice>(row: &Row<'_>, field: &str, errors: &mut Vec<RowError>) -> Option<T> {
+    let text = row.get(field)?;
+    let wanted = persian::normalize(text).to_lowercase();
+    let found = T::ALL.iter().copied().find(|choice| {
+        let name = to_value(*choice);
+        name.as_str().map(str::to_lowercase).as_deref() == Some(wanted.as_str())
+            || persian::normalize(choice.label()) == wanted
+    });
+    if found.is_none() {
+        let choices: Vec<String> = T::ALL
+            .iter()
+            .map(|choice| {
+                format!(
+                    "{} ({})",
+                    to_value(*choice).as_str().unwrap_or(""),
+                    choice.label()
+                )
+            })
+            .collect();
+        errors.push(row.error(
+            field,
+            "invalid_value",
+            format!("{} is not one of {}", text, choices.join(", ")),
+        ));
+    }
+    found
+}
+
+/// Reports national numbers that persons already registered have, as
+/// errors or warnings depending on the duplicate policy.
+fn check_duplicates(
+    c: &PgConnection,
+    families: &[Family],
+    policy: DuplicatePolicy,
+    report: &mut ImportReport,
+) -> Result<()> {
+    let rows: BTreeMap<&str, usize> = families
+        .iter()
+        .flat_map(|family| &family.members)
+        .filter_map(|member| Some((member.national_number.as_deref()?, member.row)))
+        .collect();
+    let numbers: Vec<&str> = rows.keys().copied().collect();
+
+    for chunk in numbers.chunks(DUPLICATE_CHUNK) {
+        let registered = persons::table
+            .filter(persons::national_number.eq_any(chunk))
+            .filter(persons::deleted_at.is_null())
+            .select(persons::national_number)
+            .load::<String>(c)?;
+        for national_number in registered {
+            let found = RowError {
+                row: rows[national_number.as_str()],
+                field: "national_number".to_owned(),
+                code: "duplicate",
+                message: "is already registered".to_owned(),
+            };
+            match policy {
+                DuplicatePolicy::Reject => report.errors.push(found),
+                DuplicatePolicy::Warn => report.warnings.push(found),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Saves the planned families as the cases of a new batch. Runs inside the
+/// import's transaction, so a failure leaves nothing behind.
+fn save(
+    c: &PgConnection,
+    file_name: &str,
+    report: &ImportReport,
+    families: Vec<Family>,
+    actor: Uuid,
+    today: NaiveDate,
+) -> Result<ImportBatch> {
+    let count = |n: usize| i32::try_from(n).unwrap_or(i32::MAX);
+    let batch = diesel::insert_into(import_batches::table)
+        .values((
+            import_batches::id.eq(Uuid::from_u128(rand::random())),
+            import_batches::file_name.eq(file_name),
+            import_batches::imported_by.eq(actor),
+            import_batches::imported_at.eq(Utc::now().naive_utc()),
+            import_batches::row_count.eq(count(report.rows)),
+            import_batches::case_count.eq(count(report.cases)),
+            import_batches::person_count.eq(count(report.persons)),
+            import_batches::job_count.eq(count(report.jobs)),
+        ))
+        .get_result::<ImportBatch>(c)?;
+    audit::record(c, actor, Operation::Create, None, Some(&batch))?;
+
+    for family in families {
+        let case = Case::create_in(
+            c,
+            from_fields::<NewCase>(family.case)?,
+            actor,
+            Some(batch.id),
+        )?;
+        for member in family.members {
+            let mut person = member.person;
+            person.insert("case_id".to_owned(), case.entity_id().to_string().into());
+            let person = from_fields::<NewPerson>(person)?.validate()?;
+            let person = Person::create_in(c, person, actor)?;
+            diesel::insert_into(import_batch_persons::table)
+                .values((
+                    import_batch_persons::import_batch_id.eq(batch.id),
+                    import_batch_persons::person_id.eq(person.entity_id()),
+                ))
+                .execute(c)?;
+
+            for mut job in member.jobs {
+                job.insert(
+                    "person_id".to_owned(),
+                    person.entity_id().to_string().into(),
+                );
+                let job = PersonJob::create_in(
+                    c,
+                    from_fields::<NewPersonJob>(job)?.validate()?,
+                    actor,
+                    today,
+                )?;
+                diesel::insert_into(import_batch_jobs::table)
+                    .values((
+                        import_batch_jobs::import_batch_id.eq(batch.id),
+                        import_batch_jobs::person_job_id.eq(job.entity_id()),
+                    ))
+                    .execute(c)?;
+            }
+        }
+    }
+    Ok(batch)
+}
+
+impl ImportBatch {
+    pub async fn all(conn: &Db) -> Result<Vec<ImportBatch>> {
+        conn.run(|c| {
+            import_batches::table
+                .order(import_batches::imported_at.desc())
+                .load::<ImportBatch>(c)
+        })
+        .await
+        .map_err(|e| Errors::DatabaseError(e.to_string()))
+    }
+
+    pub async fn get(conn: &Db, p_id: Uuid) -> Result<ImportBatch> {
+        conn.run(move |c| {
+            import_batches::table
+                .find(p_id)
+                .first::<ImportBatch>(c)
+                .optional()
+        })
+        .await?
+        .ok_or_else(|| Errors::BadRequest("id not found".to_owned()))
+    }
+
+    /// Permanently removes the cases of the batch with their persons and
+    /// jobs, recording each removed row in the audit log. Refused once any of
+    /// them has had actions, aid, skills or requirements recorded, as that
+    /// work would be lost with them, and once the cases no longer hold just
+    /// the persons and jobs the batch created.
+    pub async fn rollback(conn: &Db, p_id: Uuid, actor: Uuid) -> Result<ImportBatch> {
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match import_batches::table
+                    .find(p_id)
+                    .for_update()
+                    .first::<ImportBatch>(c)
+                    .optional()?
+                {
+                    Some(batch) => batch,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+                if before.rolled_back_at.is_some() {
+                    return Err(Errors::BadRequest(
+                        "the import is already rolled back".to_owned(),
+                    ));
+                }
+
+                let case_ids = cases::table
+                    .filter(cases::import_batch_id.eq(p_id))
+                    .select(cases::id)
+                    .load::<Uuid>(c)?;
+                let actions = case_actions::table
+                    .filter(case_actions::case_id.eq_any(&case_ids))
+                    .count()
+                    .get_result::<i64>(c)?;
+                let series = case_action_series::table
+                    .filter(case_action_series::case_id.eq_any(&case_ids))
+                    .count()
+                    .get_result::<i64>(c)?;
+                let aid = aid_distributions::table
+                    .filter(aid_distributions::case_id.eq_any(&case_ids))
+                    .count()
+                    .get_result::<i64>(c)?;
+                if actions + series + aid > 0 {
+                    return Err(Errors::BadRequest(format!(
+                        "cases of this import already have {} actions, {} recurring actions and \
+                         {} aid distributions recorded",
+                        actions, series, aid
+                    )));
+                }
+
+                let person_ids = import_batch_persons::table
+                    .filter(import_batch_persons::import_batch_id.eq(p_id))
+                    .select(import_batch_persons::person_id)
+                    .load::<Uuid>(c)?;
+                let job_ids = import_batch_jobs::table
+                    .filter(import_batch_jobs::import_batch_id.eq(p_id))
+                    .select(import_batch_jobs::person_job_id)
+                    .load::<Uuid>(c)?;
+                let added_persons = persons::table
+                    .filter(persons::case_id.eq_any(&case_ids))
+                    .filter(diesel::dsl::not(persons::id.eq_any(&person_ids)))
+                    .count()
+                    .get_result::<i64>(c)?;
+                let moved_persons = persons::table
+                    .filter(persons::id.eq_any(&person_ids))
+                    .filter(diesel::dsl::not(persons::case_id.eq_any(&case_ids)))
+                    .count()
+                    .get_result::<i64>(c)?;
+                let added_jobs = person_jobs::table
+                    .filter(person_jobs::person_id.eq_any(&person_ids))
+                    .filter(diesel::dsl::not(person_jobs::id.eq_any(&job_ids)))
+                    .count()
+                    .get_result::<i64>(c)?;
+                if added_persons + moved_persons + added_jobs > 0 {
+                    return Err(Errors::BadRequest(format!(
+                        "since this import {} persons were added to its cases, {} of its persons \
+                         were moved to other cases and {} jobs were added to its persons",
+                        added_persons, moved_persons, added_jobs
+                    )));
+                }
+
+                // The import never creates skills or requirements, so any
+                // found were added to the persons after it.
+                let skills = person_skills::table
+                    .filter(person_skills::person_id.eq_any(&person_ids))
+                    .count()
+                    .get_result::<i64>(c)?;
+                let requirements = person_requirements::table
+                    .filter(person_requirements::person_id.eq_any(&person_ids))
+                    .count()
+                    .get_result::<i64>(c)?;
+                if skills + requirements > 0 {
+                    return Err(Errors::BadRequest(format!(
+                        "persons of this import already have {} skills and {} requirements \
+                         recorded",
+                        skills, requirements
+                    )));
+                }
+
+                let default_jobs = diesel::delete(
+                    person_default_job::table
+                        .filter(person_default_job::person_id.eq_any(&person_ids)),
+                )
+                .get_results::<DefaultJob>(c)?;
+                for default in &default_jobs {
+                    audit::record(c, actor, Operation::Purge, Some(default), None)?;
+                }
+                // Jobs a merge moved to a person outside the batch stay with
+                // that person.
+                let jobs = diesel::delete(
+                    person_jobs::table
+                        .filter(person_jobs::id.eq_any(&job_ids))
+                        .filter(person_jobs::person_id.eq_any(&person_ids)),
+                )
+                .get_results::<PersonJob>(c)?;
+                for job in &jobs {
+                    audit::record(c, actor, Operation::Purge, Some(job), None)?;
+                }
+                let assignments = diesel::delete(
+                    case_assignments::table.filter(case_assignments::case_id.eq_any(&case_ids)),
+                )
+                .get_results::<CaseAssignment>(c)?;
+                for assignment in &assignments {
+                    audit::record(c, actor, Operation::Purge, Some(assignment), None)?;
+                }
+
+                let persons =
+                    diesel::delete(persons::table.filter(persons::id.eq_any(&person_ids)))
+                        .get_results::<Person>(c)?;
+                for person in &persons {
+                    audit::record(c, actor, Operation::Purge, Some(person), None)?;
+                }
+                let cases = diesel::delete(cases::table.filter(cases::id.eq_any(&case_ids)))
+                    .get_results::<Case>(c)?;
+                for case in &cases {
+                    audit::record(c, actor, Operation::Purge, Some(case), None)?;
+                }
+
+                let after = diesel::update(import_batches::table.find(p_id))
+                    .set((
+                        import_batches::rolled_back_by.eq(actor),
+                        import_batches::rolled_back_at.eq(Utc::now().naive_utc()),
+                    ))
+                    .get_result::<ImportBatch>(c)?;
+                audit::record(c, actor, Operation::Update, Some(&before), Some(&after))?;
+                Ok(after)
+            })
+        })
+        .await
+    }
+}
@@ -0,0 +1,307 @@
+use crate::persian;
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+use std::fmt;
+
+/// Jalali years whose leap cycle breaks, from the jalaali-js algorithm by
+/// Borkowski. Conversions are only defined between the first and the last.
+const BREAKS: [i32; 20] = [
+    -61, 9, 38, 199, 426, 686, 756, 818, 1111, 1181, 1210, 1635, 2060, 2097, 2192, 2262, 2324,
+    2394, 2456, 3178,
+];
+
+const DATE_TIME_FORMAT: &str = "%H:%M:%S";
+
+/// Calendar dates are read and shown in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Calendar {
+    Gregorian,
+    Jalali,
+}
+
+/// A day of the Solar Hijri calendar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct JalaliDate {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+}
+
+/// Where Farvardin 1st of `year` falls, as the Gregorian year and the day of
+/// March, and the number of years since the last leap year, 0 being a leap
+/// year itself.
+fn year_info(year: i32) -> Option<(i32, u32, i32)> {
+    if year < BREAKS[0] || year >= BREAKS[BREAKS.len() - 1] {
+        return None;
+    }
+
+    let mut leap_jalali = -14;
+    let mut previous = BREAKS[0];
+    let mut jump = 0;
+    for &next in &BREAKS[1..] {
+        jump = next - previous;
+        if year < next {
+            break;
+        }
+        leap_jalali += jump / 33 * 8 + jump % 33 / 4;
+        previous = next;
+    }
+    let mut n = year - previous;
+
+    leap_jalali += n / 33 * 8 + (n % 33 + 3) / 4;
+    if jump % 33 == 4 && jump - n == 4 {
+        leap_jalali += 1;
+    }
+
+    let gregorian_year = year + 621;
+    let leap_gregorian = gregorian_year / 4 - (gregorian_year / 100 + 1) * 3 / 4 - 150;
+    let march = 20 + leap_jalali - leap_gregorian;
+
+    if jump - n < 6 {
+        n = n - jump + (jump + 4) / 33 * 33;
+    }
+    let leap = match ((n + 1) % 33 - 1) % 4 {
+        -1 => 4,
+        leap => leap,
+    };
+    Some((gregorian_year, march as u32, leap))
+}
+
+fn farvardin_first(year: i32) -> Option<(NaiveDate, i32)> {
+    let (gregorian_year, march, leap) = year_info(year)?;
+    Some((NaiveDate::from_ymd_opt(gregorian_year, 3, march)?, leap))
+}
+
+pub fn is_leap_year(year: i32) -> bool {
+    matches!(year_info(year), Some((_, _, 0)))
+}
+
+/// Days in `month` of `year`: 31 in the first six months, 30 in the next
+/// five and 29 in Esfand, or 30 in a leap year.
+pub fn month_length(year: i32, month: u32) -> u32 {
+    match month {
+        1..=6 => 31,
+        7..=11 => 30,
+        _ if is_leap_year(year) => 30,
+        _ => 29,
+    }
+}
+
+impl JalaliDate {
+    pub fn new(year: i32, month: u32, day: u32) -> Option<Self> {
+        if !(1..=12).contains(&month) || day < 1 || day > month_length(year, month) {
+            return None;
+        }
+        year_info(year)?;
+        Some(JalaliDate { year, month, day })
+    }
+
+    /// `None` for days outside the years the calendar is computed for.
+    pub fn from_gregorian(date: NaiveDate) -> Option<Self> {
+        let mut year = date.year() - 621;
+        let (first, leap) = farvardin_first(year)?;
+
+        let mut days = (date - first).num_days() as i32;
+        if days >= 0 {
+            if days <= 185 {
+                return Some(JalaliDate {
+                    year,
+                    month: 1 + (days / 31) as u32,
+                    day: 1 + (days % 31) as u32,
+                });
+            }
+            days -= 186;
+        } else {
+            // The year before is a leap year when this one is the first after.
+            year -= 1;
+            days += 179;
+            if leap == 1 {
+                days += 1;
+            }
+        }
+        Some(JalaliDate {
+            year,
+            month: 7 + (days / 30) as u32,
+            day: 1 + (days % 30) as u32,
+        })
+    }
+
+    pub fn to_gregorian(self) -> Option<NaiveDate> {
+        let (first, _) = farvardin_first(self.year)?;
+        let month = self.month as i64;
+        let days = (month - 1) * 31 - month / 7 * (month - 7) + self.day as i64 - 1;
+        Some(first + Duration::days(days))
+    }
+
+    /// Reads `1403-01-15` or `1403/1/15`, in ASCII or Persian digits.
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = persian::normalize_digits(text.trim()).replace('/', "-");
+        let mut parts = text.splitn(3, '-');
+        let year = parts.next()?.parse().ok()?;
+        let month = parts.next()?.parse().ok()?;
+        let day = parts.next()?.parse().ok()?;
+        JalaliDate::new(year, month, day)
+    }
+}
+
+impl fmt::Display for JalaliDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// Shows `at` as a Jalali date and time, `1403-01-15T14:30:00`.
+pub fn format_date_time(at: NaiveDateTime) -> Option<String> {
+    let date = JalaliDate::from_gregorian(at.date())?;
+    Some(format!("{}T{}", date, at.time().format(DATE_TIME_FORMAT)))
+}
+
+/// Reads a Jalali date and time written as `format_date_time` shows it, or
+/// with a space instead of the `T`.
+pub fn parse_date_time(text: &str) -> Option<NaiveDateTime> {
+    let text = persian::normalize_digits(text.trim());
+    let (date, time) = text.split_once(['T', ' '])?;
+    let date = JalaliDate::parse(date)?.to_gregorian()?;
+    let time = NaiveTime::parse_from_str(time, DATE_TIME_FORMAT)
+        .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+        .ok()?;
+    Some(date.and_time(time))
+}
+
+/// First and last day of the month `date` is in, in `calendar`.
+pub fn month_of(date: NaiveDate, calendar: Calendar) -> (NaiveDate, NaiveDate) {
+    let gregorian = || {
+        let first = date.with_day(1).unwrap_or(date);
+        let next = match first.month() {
+            12 => NaiveDate::from_ymd(first.year() + 1, 1, 1),
+            month => NaiveDate::from_ymd(first.year(), month + 1, 1),
+        };
+        (first, next.pred())
+    };
+    if calendar == Calendar::Gregorian {
+        return gregorian();
+    }
+
+    match JalaliDate::from_gregorian(date) {
+        Some(jalali) => {
+            let first = date - Duration::days(i64::from(jalali.day) - 1);
+            let length = month_length(jalali.year, jalali.month);
+            (first, first + Duration::days(i64::from(length) - 1))
+        }
+        None => gregorian(),
+    }
+}
+
+/// First and last day of the week `date` is in: Saturday to Friday in the
+/// Jalali calendar, Monday to Sunday in the Gregorian one.
+pub fn week_of(date: NaiveDate, calendar: Calendar) -> (NaiveDate, NaiveDate) {
+    let first_day = match calendar {
+        Calendar::Jalali => Weekday::Sat,
+        Calendar::Gregorian => Weekday::Mon,
+    };
+    let offset = (7 + date.weekday().num_days_from_monday() - first_day.num_days_from_monday()) % 7;
+    let first = date - Duration::days(i64::from(offset));
+    (first, first + Duration::days(6))
+}
+
+/// Label of the month or year `date` is in, `1403-01` or `1403`.
+pub fn period_label(date: NaiveDate, calendar: Calendar, yearly: bool) -> String {
+    let jalali = match calendar {
+        Calendar::Jalali => JalaliDate::from_gregorian(date),
+        Calendar::Gregorian => None,
+    };
+    match (jalali, yearly) {
+        (Some(jalali), true) => format!("{:04}", jalali.year),
+        (Some(jalali), false) => format!("{:04}-{:02}", jalali.year, jalali.month),
+        (None, true) => date.format("%Y").to_string(),
+        (None, false) => date.format("%Y-%m").to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gregorian(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd(year, month, day)
+    }
+
+    #[test]
+    fn nowruz() {
+        let nowruz = [
+            (1398, gregorian(2019, 3, 21)),
+            (1399, gregorian(2020, 3, 20)),
+            (1400, gregorian(2021, 3, 21)),
+            (1403, gregorian(2024, 3, 20)),
+            (1404, gregorian(2025, 3, 21)),
+        ];
+        for (year, date) in nowruz {
+            let first = JalaliDate::new(year, 1, 1).unwrap();
+            assert_eq!(first.to_gregorian(), Some(date));
+            assert_eq!(JalaliDate::from_gregorian(date), Some(first));
+        }
+    }
+
+    #[test]
+    fn known_dates() {
+        assert_eq!(
+            JalaliDate::from_gregorian(gregorian(1980, 1, 1)),
+            JalaliDate::new(1358, 10, 11)
+        );
+        assert_eq!(
+            JalaliDate::from_gregorian(gregorian(2026, 10, 18)),
+            JalaliDate::new(1405, 7, 26)
+        );
+    }
+
+    #[test]
+    fn leap_years() {
+        assert!(is_leap_year(1399));
+        assert!(is_leap_year(1403));
+        assert!(!is_leap_year(1400));
+        assert!(!is_leap_year(1402));
+
+        assert_eq!(
+            JalaliDate::new(1399, 12, 30).unwrap().to_gregorian(),
+            Some(gregorian(2021, 3, 20))
+        );
+        assert_eq!(
+            JalaliDate::new(1403, 12, 30).unwrap().to_gregorian(),
+            Some(gregorian(2025, 3, 20))
+        );
+        assert_eq!(JalaliDate::new(1402, 12, 30), None);
+        assert_eq!(
+            JalaliDate::from_gregorian(gregorian(2024, 3, 19)),
+            JalaliDate::new(1402, 12, 29)
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut date = gregorian(1900, 1, 1);
+        let mut previous = JalaliDate::from_gregorian(date.pred()).unwrap();
+        while date < gregorian(2100, 1, 1) {
+            let jalali = JalaliDate::from_gregorian(date).unwrap();
+            assert_eq!(jalali.to_gregorian(), Some(date));
+            assert!(jalali > previous);
+            assert_eq!(
+                JalaliDate::new(jalali.year, jalali.month, jalali.day),
+                Some(jalali)
+            );
+            previous = jalali;
+            date = date.succ();
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let expected = JalaliDate::new(1403, 1, 15);
+        assert_eq!(JalaliDate::parse("1403-01-15"), expected);
+        assert_eq!(JalaliDate::parse("1403/1/15"), expected);
+        assert_eq!(JalaliDate::parse("۱۴۰۳/۱/۱۵"), expected);
+        assert_eq!(JalaliDate::parse("1403-13-01"), None);
+
+        let at = parse_date_time("1403-01-15T14:30:00").unwrap();
+        assert_eq!(at, gregorian(2024, 4, 3).and_hms(14, 30, 0));
+        assert_eq!(format_date_time(at).as_deref(), Some("1403-01-15T14:30:00"));
+    }
+}
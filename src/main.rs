@@ -4,11 +4,33 @@ extern crate rocket;
 extern crate diesel;
 
 mod errors;
+mod filters;
+#[macro_use]
+mod pagination;
+mod access;
+mod agenda;
+mod aid;
+mod audit;
+mod case_file;
+mod dossier;
+mod enums;
+mod export;
+mod household;
+mod ical;
+mod import;
+mod jalali;
 mod models;
+mod password;
+mod pdf;
+mod persian;
+mod recurrence;
 mod repository;
 mod schema;
+mod search;
 mod service_options;
+mod skills;
 mod user_token_service;
+mod validation;
 mod website;
 
 #[tokio::main]
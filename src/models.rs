@@ -1,16 +1,38 @@
+use super::access::{self, CaseAssignment, CasePermission, Target, Targets, Visibility};
+use super::agenda::AgendaQuery;
+use super::aid::AidDistribution;
+use super::audit::{self, Auditable, Operation};
+use super::enums::{
+    ActionStatus, EmploymentType, FamilyRole, Frequency, IncomePeriod, Priority,
+    RequirementCategory, RequirementStatus, Role, Transition,
+};
 use super::errors::*;
+use super::filters::*;
+use super::ical;
+use super::pagination::{Cursor, Page, PageRequest, QueryDate};
+use super::password::{self, HashingParams, StoredPassword, Verification};
+use super::recurrence::{Rule, RuleRequest, MAX_OCCURRENCES};
 use super::schema::*;
+use super::service_options::DuplicatePolicy;
+use super::validation::{self, Validate, Validator};
 use super::website::Db;
 use chrono::prelude::*;
 use chrono::Duration;
+use diesel::pg::Pg;
 use diesel::prelude::*;
-use diesel::Insertable;
+use diesel::result::DatabaseErrorKind;
+use diesel::sql_types::{Bool, Date, Float, Nullable, Timestamp, Uuid as SqlUuid};
+use diesel::{Insertable, PgConnection};
 use rocket::serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-const ACTION_STATUS_DONE: i32 = 2;
+sql_function!(fn coalesce(x: Nullable<Timestamp>, y: Timestamp) -> Timestamp);
+sql_function! {
+    #[sql_name = "coalesce"]
+    fn coalesce_date(x: Nullable<Date>, y: Date) -> Date;
+}
 
-type Toman = i32;
+pub type Toman = i32;
 
 #[derive(
     Debug, Queryable, Serialize, Deserialize, Insertable, Clone, Identifiable, AsChangeset,
@@ -21,9 +43,20 @@ pub struct User {
     pub username: String,
     pub first_name: String,
     pub last_name: String,
-    pub password_hash: Vec<u8>,
-    pub password_salt: Vec<u8>,
-    pub role: i32,
+    pub password_hash: Option<Vec<u8>>,
+    pub password_salt: Option<Vec<u8>>,
+    pub role: Role,
+    pub password_phc: Option<String>,
+    pub active: bool,
+    pub must_change_password: bool,
+    pub timezone: Option<String>,
+}
+
+/// What the auth guards need to know about a token's user on each request.
+#[derive(Debug, Queryable, Clone, Copy)]
+pub struct AccountStatus {
+    pub active: bool,
+    pub must_change_password: bool,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -32,6 +65,21 @@ pub struct NewUser {
     first_name: String,
     last_name: String,
     password: String,
+    #[serde(default = "default_role")]
+    role: Role,
+}
+
+fn default_role() -> Role {
+    Role::User
+}
+
+/// Fields of a user an admin may edit directly.
+#[derive(Debug, Deserialize, Clone, AsChangeset)]
+#[table_name = "users"]
+pub struct UserProfile {
+    username: String,
+    first_name: String,
+    last_name: String,
 }
 
 #[derive(
@@ -46,6 +94,13 @@ pub struct Case {
     editor: Uuid,
     address: Option<String>,
     description: Option<String>,
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<NaiveDateTime>,
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    deleted_by: Option<Uuid>,
+    /// Import the case came from, if any.
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    import_batch_id: Option<Uuid>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -68,10 +123,14 @@ pub struct Person {
     phone_number: String,
     case_id: Uuid,
     is_leader: bool,
-    family_role: i32,
+    family_role: FamilyRole,
     description: Option<String>,
     education_field: Option<String>,
     education_location: Option<String>,
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<NaiveDateTime>,
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    deleted_by: Option<Uuid>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -84,7 +143,7 @@ pub struct NewPerson {
     phone_number: String,
     case_id: Uuid,
     is_leader: bool,
-    family_role: i32,
+    family_role: FamilyRole,
     description: Option<String>,
     education_field: Option<String>,
     education_location: Option<String>,
@@ -98,8 +157,63 @@ pub struct PersonJob {
     id: Uuid,
     person_id: Uuid,
     title: String,
+    /// Toman per `income_period`.
     income: Option<Toman>,
     location: Option<String>,
+    #[serde(default)]
+    start_date: Option<NaiveDate>,
+    /// Last day of the job; none while it goes on.
+    #[serde(default)]
+    end_date: Option<NaiveDate>,
+    #[serde(default)]
+    employment_type: Option<EmploymentType>,
+    #[serde(default)]
+    income_period: IncomePeriod,
+    /// `income` per month, worked out on save.
+    #[serde(default, skip_deserializing)]
+    monthly_income: Option<Toman>,
+}
+
+#[derive(Debug, Queryable, Serialize, Clone)]
+pub struct DefaultJob {
+    person_id: Uuid,
+    person_job_id: Uuid,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateReason {
+    NationalNumber,
+    NameAndBirthday,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DuplicatePerson {
+    #[serde(flatten)]
+    pub person: Person,
+    pub case_number: i32,
+    pub case_active: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DuplicateGroup {
+    pub reason: DuplicateReason,
+    pub persons: Vec<DuplicatePerson>,
+}
+
+#[derive(Debug, QueryableByName)]
+struct DuplicatePair {
+    #[sql_type = "SqlUuid"]
+    first: Uuid,
+    #[sql_type = "SqlUuid"]
+    second: Uuid,
+}
+
+/// Deleted cases and persons that have not been purged yet.
+#[derive(Debug, Serialize)]
+pub struct Trash {
+    pub cases: Vec<Case>,
+    pub persons: Vec<Person>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -108,6 +222,41 @@ pub struct NewPersonJob {
     title: String,
     income: Option<i32>,
     location: Option<String>,
+    #[serde(default)]
+    start_date: Option<NaiveDate>,
+    #[serde(default)]
+    end_date: Option<NaiveDate>,
+    #[serde(default)]
+    employment_type: Option<EmploymentType>,
+    #[serde(default)]
+    income_period: IncomePeriod,
+}
+
+/// A job as the employment timeline shows it.
+#[derive(Debug, Serialize)]
+pub struct TimelineJob {
+    #[serde(flatten)]
+    pub job: PersonJob,
+    pub is_current: bool,
+    pub is_default: bool,
+}
+
+/// Days no job is known to cover, between the first known start and today.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct EmploymentGap {
+    pub from: NaiveDate,
+    /// None while the gap goes on.
+    pub to: Option<NaiveDate>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmploymentTimeline {
+    pub person_id: Uuid,
+    /// Latest start first; jobs without a start date come last.
+    pub jobs: Vec<TimelineJob>,
+    /// Sum of the current jobs, in Toman.
+    pub monthly_income: i64,
+    pub gaps: Vec<EmploymentGap>,
 }
 
 #[derive(
@@ -117,13 +266,13 @@ pub struct NewPersonJob {
 pub struct PersonSkill {
     id: Uuid,
     person_id: Uuid,
-    skill: String,
+    skill_id: Uuid,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct NewPersonSkill {
     person_id: Uuid,
-    skill: String,
+    skill_id: Uuid,
 }
 
 #[derive(Debug, Queryable, Serialize, Deserialize, Insertable, AsChangeset, Clone)]
@@ -132,12 +281,34 @@ pub struct PersonRequirement {
     id: Uuid,
     person_id: Uuid,
     description: String,
+    #[serde(default)]
+    category: RequirementCategory,
+    #[serde(default)]
+    priority: Priority,
+    #[serde(default)]
+    status: RequirementStatus,
+    /// Toman.
+    #[serde(default)]
+    estimated_cost: Option<Toman>,
+    #[serde(default)]
+    due_date: Option<NaiveDate>,
+    /// How the requirement was met, or why it was rejected.
+    #[serde(default)]
+    fulfilment_note: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct NewPersonRequirement {
     person_id: Uuid,
     description: String,
+    #[serde(default)]
+    category: RequirementCategory,
+    #[serde(default)]
+    priority: Priority,
+    #[serde(default)]
+    estimated_cost: Option<Toman>,
+    #[serde(default)]
+    due_date: Option<NaiveDate>,
 }
 
 #[derive(Debug, Queryable, Serialize, Deserialize, Insertable, AsChangeset, Clone)]
@@ -146,8 +317,23 @@ pub struct CaseAction {
     id: Uuid,
     case_id: Uuid,
     action: String,
-    status: i32,
+    status: ActionStatus,
     action_date: Option<NaiveDateTime>,
+    #[serde(default)]
+    assignee: Option<Uuid>,
+    #[serde(default)]
+    completed_at: Option<NaiveDateTime>,
+    #[serde(default)]
+    completed_by: Option<Uuid>,
+    /// Outcome of a completed action, or why it was cancelled.
+    #[serde(default)]
+    result_note: Option<String>,
+    /// Series the action is an occurrence of, and when the series had it
+    /// scheduled before any rescheduling.
+    #[serde(default)]
+    series_id: Option<Uuid>,
+    #[serde(default)]
+    occurrence_at: Option<NaiveDateTime>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -155,354 +341,1238 @@ pub struct NewCaseAction {
     case_id: Uuid,
     action: String,
     action_date: Option<NaiveDateTime>,
+    #[serde(default)]
+    assignee: Option<Uuid>,
 }
 
-impl User {
-    pub async fn new(conn: &Db, entity: NewUser) -> Result<Self> {
-        use self::users::dsl::*;
-        use sha2::{Digest, Sha256};
-
-        let mut hasher = Sha256::new();
-        let salt: [u8; 32] = rand::random();
-        hasher.update(entity.password.as_bytes());
-        hasher.update(salt);
-        let pass_hash = hasher.finalize();
+#[derive(Debug, Queryable, Serialize, Clone)]
+pub struct CaseActionSeries {
+    id: Uuid,
+    case_id: Uuid,
+    action: String,
+    assignee: Option<Uuid>,
+    starts_at: NaiveDateTime,
+    frequency: Frequency,
+    repeat_every: i32,
+    repeat_until: Option<NaiveDateTime>,
+    occurrence_count: Option<i32>,
+    /// Occurrences up to here have been created as case actions.
+    materialized_until: Option<NaiveDateTime>,
+    cancelled_at: Option<NaiveDateTime>,
+}
 
-        let mut results = conn
-            .run(move |c| {
-                diesel::insert_into(users)
-                    .values((
-                        id.eq(Uuid::from_u128(rand::random())),
-                        username.eq(entity.username),
-                        first_name.eq(entity.first_name),
-                        last_name.eq(entity.last_name),
-                        password_hash.eq(pass_hash.as_slice()),
-                        password_salt.eq(salt.as_slice()),
-                        role.eq(2),
-                    ))
-                    .get_results(c)
-            })
-            .await
-            .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+/// A series with its rule also written as an RRULE.
+#[derive(Debug, Serialize, Clone)]
+pub struct CaseActionSeriesDetails {
+    #[serde(flatten)]
+    series: CaseActionSeries,
+    rrule: String,
+}
 
-        match results.pop() {
-            Some(entity) => Ok(entity),
-            None => Err(Errors::DatabaseError(
-                "error while inserting new item to database".to_string(),
-            )),
-        }
+impl From<CaseActionSeries> for CaseActionSeriesDetails {
+    fn from(series: CaseActionSeries) -> Self {
+        let rrule = series.rule().to_rrule();
+        CaseActionSeriesDetails { series, rrule }
     }
+}
 
-    pub async fn insert(self, conn: &Db) -> Result<()> {
-        use self::users::dsl::*;
+#[derive(Debug, Deserialize, Clone)]
+pub struct NewCaseActionSeries {
+    case_id: Uuid,
+    action: String,
+    #[serde(default)]
+    assignee: Option<Uuid>,
+    starts_at: NaiveDateTime,
+    #[serde(flatten)]
+    rule: RuleRequest,
+}
 
-        let result = conn
-            .run(move |c| {
-                diesel::insert_into(users)
-                    .values(self)
-                    .returning(id)
-                    .get_results::<Uuid>(c)
-            })
-            .await;
+/// Changes to a series, applied to its occurrences that have not happened
+/// yet. Leaving out the rule keeps the current one.
+#[derive(Debug, Deserialize, Clone)]
+pub struct CaseActionSeriesUpdate {
+    action: String,
+    #[serde(default)]
+    assignee: Option<Uuid>,
+    #[serde(default)]
+    starts_at: Option<NaiveDateTime>,
+    #[serde(flatten)]
+    rule: RuleRequest,
+}
 
-        match result {
-            Ok(_) => Ok(()),
-            Err(e) => Err(Errors::DatabaseError(e.to_string())),
-        }
-    }
+#[derive(Debug, Queryable, Serialize, Clone)]
+pub struct CaseActionComment {
+    id: Uuid,
+    case_action_id: Uuid,
+    author: Option<Uuid>,
+    body: String,
+    created_at: NaiveDateTime,
+}
 
-    pub async fn update(self, conn: &Db) -> Result<()> {
-        use self::users::dsl::*;
+#[derive(Debug, Deserialize, Clone)]
+pub struct NewCaseActionComment {
+    body: String,
+}
 
-        let result = conn
-            .run(move |c| {
-                diesel::update(users)
-                    .filter(id.eq(self.id))
-                    .set(self)
-                    .execute(c)
-            })
-            .await;
+const COMMENT_MAX_LENGTH: usize = 4000;
 
-        match result {
-            Ok(count) if count == 1 => Ok(()),
-            Ok(_) => Err(Errors::BadRequest("id not found".to_owned())),
-            Err(e) => Err(Errors::DatabaseError(e.to_string())),
-        }
+impl Validate for NewCaseActionComment {
+    fn validate(self) -> Result<Self> {
+        let mut validator = Validator::new();
+        validator.required("body", &self.body, COMMENT_MAX_LENGTH);
+        validator.finish()?;
+        Ok(self)
     }
+}
 
-    pub async fn all(conn: &Db) -> Result<Vec<User>> {
-        use self::users::dsl::*;
+impl Auditable for User {
+    const ENTITY_TYPE: &'static str = "user";
 
-        conn.run(|c| users.order(id.desc()).load::<User>(c))
-            .await
-            .map_err(|e| Errors::DatabaseError(e.to_string()))
+    fn entity_id(&self) -> Uuid {
+        self.id
     }
 
-    pub async fn get(conn: &Db, p_id: Uuid) -> Result<Option<User>> {
-        use self::users::dsl::*;
-
-        let result = conn
-            .run(move |c| users.find(p_id).get_result::<User>(c))
-            .await;
-        match result {
-            Ok(r) => Ok(Some(r)),
-            Err(diesel::result::Error::NotFound) => Ok(None),
-            Err(e) => Err(Errors::DatabaseError(e.to_string())),
+    fn snapshot(&self) -> serde_json::Value {
+        let mut value = serde_json::to_value(self).unwrap_or_default();
+        if let Some(fields) = value.as_object_mut() {
+            fields.remove("password_hash");
+            fields.remove("password_salt");
+            fields.remove("password_phc");
         }
+        value
     }
+}
 
-    pub async fn get_by_username(conn: &Db, p_username: String) -> Result<Option<User>> {
-        use self::users::dsl::*;
+impl Auditable for Case {
+    const ENTITY_TYPE: &'static str = "case";
 
-        let result = conn
-            .run(move |c| users.filter(username.eq(p_username)).get_result::<User>(c))
-            .await;
-        match result {
-            Ok(r) => Ok(Some(r)),
-            Err(diesel::result::Error::NotFound) => Ok(None),
-            Err(e) => Err(Errors::DatabaseError(e.to_string())),
-        }
+    fn entity_id(&self) -> Uuid {
+        self.id
     }
+}
 
-    pub async fn delete(conn: &Db, p_id: Uuid) -> Result<()> {
-        use self::users::dsl::*;
-
-        let count = conn
-            .run(move |c| diesel::delete(users.filter(id.eq(p_id))).execute(c))
-            .await
-            .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+impl Auditable for Person {
+    const ENTITY_TYPE: &'static str = "person";
 
-        match count {
-            0 => Err(Errors::DatabaseError("id not found".to_owned())),
-            _ => Ok(()),
-        }
+    fn entity_id(&self) -> Uuid {
+        self.id
     }
 }
 
-impl Case {
-    pub async fn new(conn: &Db, entity: NewCase, editor_id: Uuid) -> Result<Self> {
-        use self::cases::dsl::*;
-
-        let mut results = conn
-            .run(move |c| {
-                diesel::insert_into(cases)
-                    .values((
-                        id.eq(Uuid::from_u128(rand::random())),
-                        active.eq(true),
-                        registration_date.eq(Utc::now().naive_utc()),
-                        editor.eq(editor_id),
-                        address.eq(entity.address),
-                        description.eq(entity.description),
-                    ))
-                    .get_results(c)
-            })
-            .await
-            .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+impl Auditable for PersonJob {
+    const ENTITY_TYPE: &'static str = "person_job";
 
-        match results.pop() {
-            Some(entity) => Ok(entity),
-            None => Err(Errors::DatabaseError(
-                "error while inserting new item to database".to_string(),
-            )),
-        }
+    fn entity_id(&self) -> Uuid {
+        self.id
     }
+}
 
-    pub async fn insert(self, conn: &Db) -> Result<()> {
-        use self::cases::dsl::*;
+impl Auditable for PersonSkill {
+    const ENTITY_TYPE: &'static str = "person_skill";
 
-        let result = conn
-            .run(move |c| {
-                diesel::insert_into(cases)
-                    .values(self)
-                    .returning(id)
-                    .get_results::<Uuid>(c)
-            })
-            .await;
+    fn entity_id(&self) -> Uuid {
+        self.id
+    }
+}
 
-        match result {
-            Ok(_) => Ok(()),
-            Err(e) => Err(Errors::DatabaseError(e.to_string())),
-        }
+impl Auditable for PersonRequirement {
+    const ENTITY_TYPE: &'static str = "person_requirement";
+
+    fn entity_id(&self) -> Uuid {
+        self.id
     }
+}
 
-    pub async fn update(self, conn: &Db) -> Result<()> {
-        use self::cases::dsl::*;
+impl Auditable for CaseAction {
+    const ENTITY_TYPE: &'static str = "case_action";
 
-        let result = conn
-            .run(move |c| {
-                diesel::update(cases)
-                    .filter(id.eq(self.id))
-                    .set(self)
-                    .execute(c)
-            })
-            .await;
+    fn entity_id(&self) -> Uuid {
+        self.id
+    }
+}
 
-        match result {
-            Ok(count) if count == 1 => Ok(()),
-            Ok(_) => Err(Errors::BadRequest("id not found".to_owned())),
-            Err(e) => Err(Errors::DatabaseError(e.to_string())),
-        }
+impl Auditable for CaseActionSeries {
+    const ENTITY_TYPE: &'static str = "case_action_series";
+
+    fn entity_id(&self) -> Uuid {
+        self.id
     }
+}
 
-    pub async fn all(conn: &Db) -> Result<Vec<Case>> {
-        use self::cases::dsl::*;
+impl Auditable for CaseActionComment {
+    const ENTITY_TYPE: &'static str = "case_action_comment";
 
-        conn.run(|c| cases.order(registration_date.desc()).load::<Case>(c))
-            .await
-            .map_err(|e| Errors::DatabaseError(e.to_string()))
+    fn entity_id(&self) -> Uuid {
+        self.id
     }
+}
 
-    pub async fn get(conn: &Db, p_id: Uuid) -> Result<Option<Case>> {
-        use self::cases::dsl::*;
+/// Keyed by person, since a person has at most one default job.
+impl Auditable for DefaultJob {
+    const ENTITY_TYPE: &'static str = "person_default_job";
 
-        let result = conn
-            .run(move |c| cases.find(p_id).get_result::<Case>(c))
-            .await;
-        match result {
-            Ok(r) => Ok(Some(r)),
-            Err(diesel::result::Error::NotFound) => Ok(None),
-            Err(e) => Err(Errors::DatabaseError(e.to_string())),
-        }
+    fn entity_id(&self) -> Uuid {
+        self.person_id
     }
+}
 
-    pub async fn delete(conn: &Db, p_id: Uuid) -> Result<()> {
-        let persons_count = {
-            use self::persons::dsl::*;
+impl Targets for Case {
+    fn targets(&self) -> Vec<Target> {
+        vec![Target::Case(self.id)]
+    }
+}
 
-            let result = conn
-                .run(move |c| {
-                    persons
-                        .count()
-                        .filter(case_id.eq(p_id))
-                        .get_result::<i64>(c)
-                })
-                .await;
+impl Targets for NewPerson {
+    fn targets(&self) -> Vec<Target> {
+        vec![Target::Case(self.case_id)]
+    }
+}
 
-            match result {
-                Ok(c) => Ok(c),
-                Err(e) => Err(Errors::DatabaseError(e.to_string())),
-            }
-        }?;
+/// Both the person as stored and the case it is being moved to.
+impl Targets for Person {
+    fn targets(&self) -> Vec<Target> {
+        vec![Target::Person(self.id), Target::Case(self.case_id)]
+    }
+}
 
-        if persons_count != 0 {
-            return Err(Errors::BadRequest(
-                "remove persons within case before removing case".to_owned(),
-            ));
-        }
+impl Targets for NewPersonJob {
+    fn targets(&self) -> Vec<Target> {
+        vec![Target::Person(self.person_id)]
+    }
+}
 
-        let count = {
-            use self::cases::dsl::*;
-            conn.run(move |c| diesel::delete(cases.filter(id.eq(p_id))).execute(c))
-                .await
-                .map_err(|e| Errors::DatabaseError(e.to_string()))?
-        };
+impl Targets for PersonJob {
+    fn targets(&self) -> Vec<Target> {
+        vec![Target::PersonJob(self.id), Target::Person(self.person_id)]
+    }
+}
 
-        match count {
-            0 => Err(Errors::BadRequest("id not found".to_owned())),
-            _ => Ok(()),
-        }
+impl Targets for NewPersonSkill {
+    fn targets(&self) -> Vec<Target> {
+        vec![Target::Person(self.person_id)]
     }
+}
 
-    pub fn activate(self) -> Self {
-        Self {
-            active: true,
-            ..self
-        }
+impl Targets for PersonSkill {
+    fn targets(&self) -> Vec<Target> {
+        vec![Target::PersonSkill(self.id), Target::Person(self.person_id)]
     }
+}
 
-    pub fn deactivate(self) -> Self {
-        Self {
-            active: false,
-            ..self
-        }
+impl Targets for NewPersonRequirement {
+    fn targets(&self) -> Vec<Target> {
+        vec![Target::Person(self.person_id)]
     }
 }
 
-impl Person {
-    pub async fn new(conn: &Db, entity: NewPerson) -> Result<Self> {
-        use self::persons::dsl::*;
+impl Targets for PersonRequirement {
+    fn targets(&self) -> Vec<Target> {
+        vec![
+            Target::PersonRequirement(self.id),
+            Target::Person(self.person_id),
+        ]
+    }
+}
 
-        let mut results = conn
-            .run(move |c| {
-                diesel::insert_into(persons)
-                    .values((
-                        id.eq(Uuid::from_u128(rand::random())),
-                        first_name.eq(entity.first_name),
-                        last_name.eq(entity.last_name),
-                        father_name.eq(entity.father_name),
-                        birthday.eq(entity.birthday),
-                        national_number.eq(entity.national_number),
-                        phone_number.eq(entity.phone_number),
-                        case_id.eq(entity.case_id),
-                        is_leader.eq(entity.is_leader),
-                        description.eq(entity.description),
-                        family_role.eq(entity.family_role),
-                        education_field.eq(entity.education_field),
-                        education_location.eq(entity.education_location),
-                    ))
-                    .get_results(c)
-            })
-            .await
-            .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+impl Targets for NewCaseAction {
+    fn targets(&self) -> Vec<Target> {
+        vec![Target::Case(self.case_id)]
+    }
+}
 
-        match results.pop() {
-            Some(entity) => Ok(entity),
-            None => Err(Errors::DatabaseError(
-                "error while inserting new item to database".to_string(),
-            )),
-        }
+impl Targets for NewCaseActionSeries {
+    fn targets(&self) -> Vec<Target> {
+        vec![Target::Case(self.case_id)]
     }
+}
 
-    pub async fn insert(self, conn: &Db) -> Result<()> {
-        use self::persons::dsl::*;
+impl Targets for CaseAction {
+    fn targets(&self) -> Vec<Target> {
+        vec![Target::CaseAction(self.id), Target::Case(self.case_id)]
+    }
+}
 
-        let result = conn
-            .run(move |c| {
-                diesel::insert_into(persons)
-                    .values(self)
-                    .returning(id)
-                    .get_results::<Uuid>(c)
-            })
-            .await;
+const NAME_MAX_LENGTH: usize = 30;
+const EDUCATION_MAX_LENGTH: usize = 100;
+
+const JOB_TITLE_MAX_LENGTH: usize = 100;
+const JOB_LOCATION_MAX_LENGTH: usize = 100;
+
+impl Validate for NewPersonJob {
+    fn validate(self) -> Result<Self> {
+        validate_job(
+            &self.title,
+            &self.location,
+            self.income,
+            self.income_period,
+            self.start_date,
+            self.end_date,
+        )?;
+        Ok(self)
+    }
+}
 
-        match result {
-            Ok(_) => Ok(()),
-            Err(e) => Err(Errors::DatabaseError(e.to_string())),
+impl Validate for PersonJob {
+    fn validate(self) -> Result<Self> {
+        validate_job(
+            &self.title,
+            &self.location,
+            self.income,
+            self.income_period,
+            self.start_date,
+            self.end_date,
+        )?;
+        Ok(self)
+    }
+}
+
+/// Checks the fields shared by `NewPersonJob` and `PersonJob`.
+fn validate_job(
+    title: &str,
+    location: &Option<String>,
+    income: Option<Toman>,
+    income_period: IncomePeriod,
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+) -> Result<()> {
+    let mut v = Validator::new();
+    v.required("title", title, JOB_TITLE_MAX_LENGTH);
+    v.optional("location", location, JOB_LOCATION_MAX_LENGTH);
+    if let Some(income) = income {
+        if income < 0 {
+            v.check::<()>(
+                "income",
+                Err(("out_of_range", "must be at least 0".to_owned())),
+            );
+        } else if income_period.monthly(income).is_none() {
+            v.check::<()>(
+                "income",
+                Err((
+                    "out_of_range",
+                    "is too large as a monthly income".to_owned(),
+                )),
+            );
         }
     }
+    if let (Some(start), Some(end)) = (start_date, end_date) {
+        if end < start {
+            v.check::<()>(
+                "end_date",
+                Err(("out_of_range", "must not be before start_date".to_owned())),
+            );
+        }
+    }
+    v.finish()
+}
 
-    pub async fn update(self, conn: &Db) -> Result<()> {
-        use self::persons::dsl::*;
+const REQUIREMENT_MAX_LENGTH: usize = 1000;
+const FULFILMENT_NOTE_MAX_LENGTH: usize = 4000;
 
-        let result = conn
-            .run(move |c| {
-                diesel::update(persons)
-                    .filter(id.eq(self.id))
-                    .set(self)
-                    .execute(c)
-            })
-            .await;
+impl Validate for NewPersonRequirement {
+    fn validate(self) -> Result<Self> {
+        let mut validator = Validator::new();
+        validator.required("description", &self.description, REQUIREMENT_MAX_LENGTH);
+        validator.check("estimated_cost", validate_cost(self.estimated_cost));
+        validator.finish()?;
+        Ok(self)
+    }
+}
 
-        match result {
-            Ok(count) if count == 1 => Ok(()),
-            Ok(_) => Err(Errors::BadRequest("id not found".to_owned())),
-            Err(e) => Err(Errors::DatabaseError(e.to_string())),
+impl Validate for PersonRequirement {
+    fn validate(self) -> Result<Self> {
+        let mut validator = Validator::new();
+        validator.required("description", &self.description, REQUIREMENT_MAX_LENGTH);
+        validator.check("estimated_cost", validate_cost(self.estimated_cost));
+        validator.optional(
+            "fulfilment_note",
+            &self.fulfilment_note,
+            FULFILMENT_NOTE_MAX_LENGTH,
+        );
+        if self.fulfilment_note.is_some() && !self.status.is_closed() {
+            validator.check::<()>(
+                "fulfilment_note",
+                Err((
+                    "not_allowed",
+                    "only fulfilled or rejected requirements have a note".into(),
+                )),
+            );
         }
+        validator.finish()?;
+        Ok(self)
     }
+}
 
-    pub async fn all(conn: &Db) -> Result<Vec<Person>> {
-        use self::persons::dsl::*;
+fn validate_cost(cost: Option<Toman>) -> validation::FieldResult<()> {
+    match cost {
+        Some(cost) if cost < 0 => Err(("out_of_range", "must be at least 0".to_owned())),
+        _ => Ok(()),
+    }
+}
 
-        conn.run(|c| persons.order(id.desc()).load::<Person>(c))
-            .await
-            .map_err(|e| Errors::DatabaseError(e.to_string()))
+const USER_NAME_MAX_LENGTH: usize = 30;
+
+impl Validate for NewUser {
+    fn validate(self) -> Result<Self> {
+        let mut validator = Validator::new();
+        validator.required("username", &self.username, USER_NAME_MAX_LENGTH);
+        validator.required("first_name", &self.first_name, USER_NAME_MAX_LENGTH);
+        validator.required("last_name", &self.last_name, USER_NAME_MAX_LENGTH);
+        validator.check("password", validation::password(&self.password));
+        validator.finish()?;
+        Ok(self)
     }
+}
 
-    pub async fn get(conn: &Db, p_id: Uuid) -> Result<Option<Person>> {
-        use self::persons::dsl::*;
+impl Validate for UserProfile {
+    fn validate(self) -> Result<Self> {
+        let mut validator = Validator::new();
+        validator.required("username", &self.username, USER_NAME_MAX_LENGTH);
+        validator.required("first_name", &self.first_name, USER_NAME_MAX_LENGTH);
+        validator.required("last_name", &self.last_name, USER_NAME_MAX_LENGTH);
+        validator.finish()?;
+        Ok(self)
+    }
+}
+
+impl Validate for NewPerson {
+    fn validate(self) -> Result<Self> {
+        let (national_number, phone_number) = validate_person(
+            &self.first_name,
+            &self.last_name,
+            &self.father_name,
+            &self.national_number,
+            &self.phone_number,
+            &self.education_field,
+            &self.education_location,
+        )?;
+
+        Ok(Self {
+            national_number,
+            phone_number,
+            ..self
+        })
+    }
+}
+
+impl Validate for Person {
+    fn validate(self) -> Result<Self> {
+        let (national_number, phone_number) = validate_person(
+            &self.first_name,
+            &self.last_name,
+            &self.father_name,
+            &self.national_number,
+            &self.phone_number,
+            &self.education_field,
+            &self.education_location,
+        )?;
+
+        Ok(Self {
+            national_number,
+            phone_number,
+            ..self
+        })
+    }
+}
+
+/// Checks the fields shared by `NewPerson` and `Person`, returning the
+/// normalised national and phone numbers.
+fn validate_person(
+    first_name: &str,
+    last_name: &str,
+    father_name: &str,
+    national_number: &str,
+    phone_number: &str,
+    education_field: &Option<String>,
+    education_location: &Option<String>,
+) -> Result<(String, String)> {
+    let mut v = Validator::new();
+
+    v.required("first_name", first_name, NAME_MAX_LENGTH);
+    v.required("last_name", last_name, NAME_MAX_LENGTH);
+    v.required("father_name", father_name, NAME_MAX_LENGTH);
+    v.optional("education_field", education_field, EDUCATION_MAX_LENGTH);
+    v.optional(
+        "education_location",
+        education_location,
+        EDUCATION_MAX_LENGTH,
+    );
+    let national_number = v.check(
+        "national_number",
+        validation::national_number(national_number),
+    );
+    let phone_number = v.check("phone_number", validation::mobile_number(phone_number));
+
+    v.finish()?;
+    Ok((
+        national_number.unwrap_or_default(),
+        phone_number.unwrap_or_default(),
+    ))
+}
+
+pub type LiveCaseIds = diesel::dsl::Filter<
+    diesel::dsl::Select<cases::table, cases::id>,
+    diesel::dsl::IsNull<cases::deleted_at>,
+>;
+type LivePersonIds = diesel::dsl::Filter<
+    diesel::dsl::Select<persons::table, persons::id>,
+    diesel::dsl::IsNull<persons::deleted_at>,
+>;
+
+/// Ids of the cases that are not in the trash, for hiding the rows that
+/// belong to deleted cases.
+pub fn live_case_ids() -> LiveCaseIds {
+    cases::table
+        .select(cases::id)
+        .filter(cases::deleted_at.is_null())
+}
+
+/// Ids of the persons that are not in the trash.
+fn live_person_ids() -> LivePersonIds {
+    persons::table
+        .select(persons::id)
+        .filter(persons::deleted_at.is_null())
+}
+
+pub type CurrentJobs = diesel::dsl::And<
+    diesel::dsl::Or<
+        diesel::dsl::IsNull<person_jobs::start_date>,
+        diesel::dsl::LtEq<person_jobs::start_date, NaiveDate>,
+    >,
+    diesel::dsl::Or<
+        diesel::dsl::IsNull<person_jobs::end_date>,
+        diesel::dsl::GtEq<person_jobs::end_date, NaiveDate>,
+    >,
+>;
+
+/// Jobs going on on `day`. A job without a start or end date is taken to run
+/// from or until any day. Matches `PersonJob::is_current_on`.
+pub fn current_jobs(day: NaiveDate) -> CurrentJobs {
+    person_jobs::start_date
+        .is_null()
+        .or(person_jobs::start_date.le(day))
+        .and(
+            person_jobs::end_date
+                .is_null()
+                .or(person_jobs::end_date.ge(day)),
+        )
+}
+
+/// Error for a national number that a live person already has. It does not
+/// name that person's case, which the caller may not be allowed to see.
+pub fn duplicate_national_number() -> Errors {
+    validation::field_error(
+        "national_number",
+        "duplicate",
+        "is already registered".to_owned(),
+    )
+}
+
+/// An action can only be assigned to an active user who is an admin or is
+/// assigned to the action's case.
+fn check_assignee(c: &PgConnection, p_case_id: Uuid, user: Uuid) -> Result<()> {
+    let found = users::table
+        .find(user)
+        .filter(users::active.eq(true))
+        .select(users::role)
+        .first::<Role>(c)
+        .optional()?;
+
+    let allowed = match found {
+        None => false,
+        Some(Role::Admin) => true,
+        Some(_) => {
+            case_assignments::table
+                .find((p_case_id, user))
+                .count()
+                .get_result::<i64>(c)?
+                > 0
+        }
+    };
+
+    if !allowed {
+        return Err(validation::field_error(
+            "assignee",
+            "invalid",
+            "must be an active user with access to the case".to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+/// Stands in for a missing `action_date` when sorting, so undated actions
+/// come first like they do in `CaseAction::all`.
+fn undated() -> NaiveDateTime {
+    NaiveDate::from_ymd(1, 1, 1).and_hms(0, 0, 0)
+}
+
+/// Stands in for a missing due date when sorting, so requirements without one
+/// come after those that have one.
+fn not_due() -> NaiveDate {
+    NaiveDate::from_ymd(9999, 12, 31)
+}
+
+impl User {
+    pub async fn new(
+        conn: &Db,
+        entity: NewUser,
+        params: HashingParams,
+        actor: Uuid,
+    ) -> Result<Self> {
+        use self::users::dsl::*;
+
+        conn.run(move |c| {
+            let phc = password::hash(&entity.password, &params)?;
+
+            c.transaction::<_, Errors, _>(|| {
+                let created = diesel::insert_into(users)
+                    .values((
+                        id.eq(Uuid::from_u128(rand::random())),
+                        username.eq(entity.username),
+                        first_name.eq(entity.first_name),
+                        last_name.eq(entity.last_name),
+                        password_phc.eq(phc),
+                        role.eq(entity.role),
+                    ))
+                    .get_result::<User>(c)?;
+
+                audit::record(c, actor, Operation::Create, None, Some(&created))?;
+                Ok(created)
+            })
+        })
+        .await
+    }
+
+    /// Looks the user up and checks their password. A password kept as a
+    /// legacy SHA-256 hash, or hashed with other parameters than `params`, is
+    /// hashed again and saved. An unknown username is checked against a dummy
+    /// hash and fails the same way as a wrong password, so neither the answer
+    /// nor its timing tells whether the username exists.
+    pub async fn authenticate(
+        conn: &Db,
+        p_username: String,
+        password: String,
+        params: HashingParams,
+    ) -> Result<User> {
+        use self::users::dsl::*;
+
+        conn.run(move |c| {
+            let user = users
+                .filter(username.eq(p_username))
+                .get_result::<User>(c)
+                .optional()?;
+
+            let verification = match user.as_ref().and_then(User::stored_password) {
+                Some(stored) => password::verify(&password, stored, &params),
+                None => password::verify_dummy(&password, &params),
+            };
+
+            match (verification, user) {
+                (Verification::Invalid, _) | (_, None) => {
+                    Err(Errors::BadRequest("Invalid Credentials".into()))
+                }
+                (_, Some(user)) if !user.active => {
+                    Err(Errors::Forbidden("account is disabled".into()))
+                }
+                (Verification::Valid, Some(user)) => Ok(user),
+                (Verification::ValidNeedsRehash, Some(user)) => {
+                    let phc = password::hash(&password, &params)?;
+                    let user = diesel::update(users.find(user.id))
+                        .set((
+                            password_phc.eq(phc),
+                            password_hash.eq(None::<Vec<u8>>),
+                            password_salt.eq(None::<Vec<u8>>),
+                        ))
+                        .get_result::<User>(c)?;
+                    Ok(user)
+                }
+            }
+        })
+        .await
+    }
+
+    /// Replaces the user's password after checking the current one, and
+    /// clears a pending forced change.
+    pub async fn change_password(
+        conn: &Db,
+        p_id: Uuid,
+        current: String,
+        new: String,
+        params: HashingParams,
+    ) -> Result<()> {
+        use self::users::dsl::*;
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match users.find(p_id).get_result::<User>(c).optional()? {
+                    Some(before) => before,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+
+                let verification = match before.stored_password() {
+                    Some(stored) => password::verify(&current, stored, &params),
+                    None => Verification::Invalid,
+                };
+                if verification == Verification::Invalid {
+                    return Err(validation::field_error(
+                        "current_password",
+                        "invalid",
+                        "is not the current password".to_owned(),
+                    ));
+                }
+
+                let phc = password::hash(&new, &params)?;
+                let after = diesel::update(users.find(p_id))
+                    .set((
+                        password_phc.eq(phc),
+                        password_hash.eq(None::<Vec<u8>>),
+                        password_salt.eq(None::<Vec<u8>>),
+                        must_change_password.eq(false),
+                    ))
+                    .get_result::<User>(c)?;
+
+                audit::record(c, p_id, Operation::Update, Some(&before), Some(&after))?;
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    pub async fn set_timezone(conn: &Db, p_id: Uuid, p_timezone: Option<String>) -> Result<User> {
+        use self::users::dsl::*;
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match users.find(p_id).get_result::<User>(c).optional()? {
+                    Some(before) => before,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+
+                let after = diesel::update(users.find(p_id))
+                    .set(timezone.eq(p_timezone))
+                    .get_result::<User>(c)?;
+
+                audit::record(c, p_id, Operation::Update, Some(&before), Some(&after))?;
+                Ok(after)
+            })
+        })
+        .await
+    }
+
+    /// Gives the user a random password that they must change after logging
+    /// in with it, and returns it so the admin can pass it on.
+    pub async fn reset_password(
+        conn: &Db,
+        p_id: Uuid,
+        params: HashingParams,
+        actor: Uuid,
+    ) -> Result<String> {
+        use self::users::dsl::*;
+
+        conn.run(move |c| {
+            let temporary = password::temporary();
+            let phc = password::hash(&temporary, &params)?;
+
+            c.transaction::<_, Errors, _>(|| {
+                let before = match users.find(p_id).get_result::<User>(c).optional()? {
+                    Some(before) => before,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+                let after = diesel::update(users.find(p_id))
+                    .set((
+                        password_phc.eq(phc),
+                        password_hash.eq(None::<Vec<u8>>),
+                        password_salt.eq(None::<Vec<u8>>),
+                        must_change_password.eq(true),
+                    ))
+                    .get_result::<User>(c)?;
+
+                audit::record(c, actor, Operation::Update, Some(&before), Some(&after))?;
+                Ok(())
+            })?;
+
+            Ok(temporary)
+        })
+        .await
+    }
+
+    pub async fn update_profile(
+        conn: &Db,
+        p_id: Uuid,
+        profile: UserProfile,
+        actor: Uuid,
+    ) -> Result<User> {
+        use self::users::dsl::*;
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match users.find(p_id).get_result::<User>(c).optional()? {
+                    Some(before) => before,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+
+                let taken = users
+                    .filter(username.eq(&profile.username))
+                    .filter(id.ne(p_id))
+                    .count()
+                    .get_result::<i64>(c)?;
+                if taken > 0 {
+                    return Err(validation::field_error(
+                        "username",
+                        "taken",
+                        "is already used by another user".to_owned(),
+                    ));
+                }
+
+                let after = diesel::update(users.find(p_id))
+                    .set(&profile)
+                    .get_result::<User>(c)?;
+
+                audit::record(c, actor, Operation::Update, Some(&before), Some(&after))?;
+                Ok(after)
+            })
+        })
+        .await
+    }
+
+    pub async fn set_role(conn: &Db, p_id: Uuid, p_role: Role, actor: Uuid) -> Result<User> {
+        use self::users::dsl::*;
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match users.find(p_id).get_result::<User>(c).optional()? {
+                    Some(before) => before,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+                let after = diesel::update(users.find(p_id))
+                    .set(role.eq(p_role))
+                    .get_result::<User>(c)?;
+
+                audit::record(c, actor, Operation::Update, Some(&before), Some(&after))?;
+                Ok(after)
+            })
+        })
+        .await
+    }
+
+    pub async fn set_active(conn: &Db, p_id: Uuid, p_active: bool, actor: Uuid) -> Result<User> {
+        use self::users::dsl::*;
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match users.find(p_id).get_result::<User>(c).optional()? {
+                    Some(before) => before,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+                let after = diesel::update(users.find(p_id))
+                    .set(active.eq(p_active))
+                    .get_result::<User>(c)?;
+
+                audit::record(c, actor, Operation::Update, Some(&before), Some(&after))?;
+                Ok(after)
+            })
+        })
+        .await
+    }
+
+    fn stored_password(&self) -> Option<StoredPassword<'_>> {
+        match (&self.password_phc, &self.password_hash, &self.password_salt) {
+            (Some(phc), _, _) => Some(StoredPassword::Phc(phc)),
+            (None, Some(hash), Some(salt)) => Some(StoredPassword::LegacySha256 { hash, salt }),
+            _ => None,
+        }
+    }
+
+    pub async fn insert(self, conn: &Db, actor: Uuid) -> Result<()> {
+        use self::users::dsl::*;
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let created = diesel::insert_into(users)
+                    .values(self)
+                    .get_result::<User>(c)?;
+
+                audit::record(c, actor, Operation::Create, None, Some(&created))?;
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    pub async fn update(self, conn: &Db, actor: Uuid) -> Result<()> {
+        use self::users::dsl::*;
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match users.find(self.id).get_result::<User>(c).optional()? {
+                    Some(before) => before,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+                let after = diesel::update(users)
+                    .filter(id.eq(self.id))
+                    .set(self)
+                    .get_result::<User>(c)?;
+
+                audit::record(c, actor, Operation::Update, Some(&before), Some(&after))?;
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    pub async fn all(conn: &Db) -> Result<Vec<User>> {
+        use self::users::dsl::*;
+
+        conn.run(|c| users.order(id.desc()).load::<User>(c))
+            .await
+            .map_err(|e| Errors::DatabaseError(e.to_string()))
+    }
+
+    pub async fn page(
+        conn: &Db,
+        filter: UserFilter,
+        page: PageRequest<UserSortKey>,
+    ) -> Result<Page<User>> {
+        use self::users::dsl::*;
+
+        conn.run(move |c| {
+            let filtered = || {
+                let mut query = users.into_boxed();
+                if let Some(p_role) = filter.role {
+                    query = query.filter(role.eq(p_role));
+                }
+                if let Some(p_active) = filter.active {
+                    query = query.filter(active.eq(p_active));
+                }
+                query
+            };
+
+            let total = filtered()
+                .count()
+                .get_result::<i64>(c)
+                .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+
+            let query = match page.sort.key {
+                UserSortKey::Id => keyset!(filtered(), page, id, id, Uuid),
+                UserSortKey::Username => keyset!(filtered(), page, username, id, String),
+            };
+
+            let items = query
+                .limit(page.limit + 1)
+                .load::<User>(c)
+                .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+
+            Ok(Page::of(items, page.limit, total, |user| {
+                match page.sort.key {
+                    UserSortKey::Id => Cursor::new(&user.id, user.id),
+                    UserSortKey::Username => Cursor::new(&user.username, user.id),
+                }
+            }))
+        })
+        .await
+    }
+
+    pub async fn get(conn: &Db, p_id: Uuid) -> Result<Option<User>> {
+        use self::users::dsl::*;
+
+        let result = conn
+            .run(move |c| users.find(p_id).get_result::<User>(c))
+            .await;
+        match result {
+            Ok(r) => Ok(Some(r)),
+            Err(diesel::result::Error::NotFound) => Ok(None),
+            Err(e) => Err(Errors::DatabaseError(e.to_string())),
+        }
+    }
+
+    pub async fn status(conn: &Db, p_id: Uuid) -> Result<Option<AccountStatus>> {
+        use self::users::dsl::*;
+
+        conn.run(move |c| {
+            users
+                .find(p_id)
+                .select((active, must_change_password))
+                .get_result::<AccountStatus>(c)
+                .optional()
+        })
+        .await
+        .map_err(|e| Errors::DatabaseError(e.to_string()))
+    }
+
+    pub async fn get_by_username(conn: &Db, p_username: String) -> Result<Option<User>> {
+        use self::users::dsl::*;
+
+        let result = conn
+            .run(move |c| users.filter(username.eq(p_username)).get_result::<User>(c))
+            .await;
+        match result {
+            Ok(r) => Ok(Some(r)),
+            Err(diesel::result::Error::NotFound) => Ok(None),
+            Err(e) => Err(Errors::DatabaseError(e.to_string())),
+        }
+    }
+
+    /// Deletes the user. Cases they are the editor of, deleted ones included,
+    /// must be handed to `reassign_to`, who also becomes owner of the live
+    /// ones. Refused once the user has made any recorded change, as the
+    /// history would lose who made it; such accounts can only be disabled.
+    pub async fn delete(
+        conn: &Db,
+        p_id: Uuid,
+        reassign_to: Option<Uuid>,
+        actor: Uuid,
+    ) -> Result<()> {
+        use self::users::dsl::*;
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match users.find(p_id).get_result::<User>(c).optional()? {
+                    Some(before) => before,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+
+                let edited = cases::table
+                    .filter(cases::editor.eq(p_id))
+                    .load::<Case>(c)?;
+                if !edited.is_empty() {
+                    let successor = match reassign_to {
+                        Some(successor) if successor != p_id => successor,
+                        _ => {
+                            return Err(Errors::BadRequest(format!(
+                                "user is the editor of {} cases, pass reassign_to",
+                                edited.len()
+                            )))
+                        }
+                    };
+                    let exists = users
+                        .filter(id.eq(successor))
+                        .filter(active.eq(true))
+                        .count()
+                        .get_result::<i64>(c)?;
+                    if exists == 0 {
+                        return Err(Errors::BadRequest("reassign_to user not found".into()));
+                    }
+
+                    for case in edited {
+                        let reassigned = diesel::update(cases::table.find(case.id))
+                            .set(cases::editor.eq(successor))
+                            .get_result::<Case>(c)?;
+                        audit::record(c, actor, Operation::Update, Some(&case), Some(&reassigned))?;
+
+                        if reassigned.deleted_at.is_none() {
+                            CaseAssignment {
+                                case_id: reassigned.id,
+                                user_id: successor,
+                                permission: CasePermission::Owner,
+                            }
+                            .assign_in(c, actor)?;
+                        }
+                    }
+                }
+
+                let assignments = diesel::delete(
+                    case_assignments::table.filter(case_assignments::user_id.eq(p_id)),
+                )
+                .get_results::<CaseAssignment>(c)?;
+                for assignment in &assignments {
+                    audit::record(c, actor, Operation::Delete, Some(assignment), None)?;
+                }
+
+                match diesel::delete(users.filter(id.eq(p_id))).execute(c) {
+                    Err(diesel::result::Error::DatabaseError(
+                        DatabaseErrorKind::ForeignKeyViolation,
+                        _,
+                    )) => {
+                        return Err(Errors::BadRequest(
+                            "user has recorded changes, disable the account instead".to_owned(),
+                        ))
+                    }
+                    result => result?,
+                };
+
+                audit::record(c, actor, Operation::Delete, Some(&before), None)?;
+                Ok(())
+            })
+        })
+        .await
+    }
+}
+
+/// Live cases matching `filter` that `visibility` lets the caller see, as
+/// the case list and the case export read them.
+pub fn case_query(filter: &CaseFilter, visibility: Visibility) -> cases::BoxedQuery<'static, Pg> {
+    use self::cases::dsl::*;
+
+    let mut query = cases.filter(deleted_at.is_null()).into_boxed();
+    if let Visibility::AssignedTo(user) = visibility {
+        query = query.filter(id.eq_any(access::assigned_case_ids(user)));
+    }
+    if let Some(p_active) = filter.active {
+        query = query.filter(active.eq(p_active));
+    }
+    if let Some(p_editor) = filter.editor {
+        query = query.filter(editor.eq(p_editor));
+    }
+    if let Some(p_import_batch_id) = filter.import_batch_id {
+        query = query.filter(import_batch_id.eq(p_import_batch_id));
+    }
+    if let Some(QueryDate(from)) = filter.registered_from {
+        query = query.filter(registration_date.ge(from.and_hms(0, 0, 0)));
+    }
+    if let Some(QueryDate(to)) = filter.registered_to {
+        let end = to.and_hms(0, 0, 0) + Duration::days(1);
+        query = query.filter(registration_date.lt(end));
+    }
+    query
+}
+
+impl Case {
+    pub async fn new(conn: &Db, entity: NewCase, editor_id: Uuid) -> Result<Self> {
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| Case::create_in(c, entity, editor_id, None))
+        })
+        .await
+    }
+
+    /// Creates the case and makes `editor_id` its owner, as part of
+    /// `import_batch` when it comes from an import.
+    pub fn create_in(
+        c: &PgConnection,
+        entity: NewCase,
+        editor_id: Uuid,
+        import_batch: Option<Uuid>,
+    ) -> Result<Self> {
+        use self::cases::dsl::*;
+
+        let created = diesel::insert_into(cases)
+            .values((
+                id.eq(Uuid::from_u128(rand::random())),
+                active.eq(true),
+                registration_date.eq(Utc::now().naive_utc()),
+                editor.eq(editor_id),
+                address.eq(entity.address),
+                description.eq(entity.description),
+                import_batch_id.eq(import_batch),
+            ))
+            .get_result::<Case>(c)?;
+
+        audit::record(c, editor_id, Operation::Create, None, Some(&created))?;
+
+        CaseAssignment {
+            case_id: created.id,
+            user_id: editor_id,
+            permission: CasePermission::Owner,
+        }
+        .assign_in(c, editor_id)?;
+        Ok(created)
+    }
+
+    pub async fn insert(self, conn: &Db, actor: Uuid) -> Result<()> {
+        use self::cases::dsl::*;
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let created = diesel::insert_into(cases)
+                    .values(self)
+                    .get_result::<Case>(c)?;
+
+                audit::record(c, actor, Operation::Create, None, Some(&created))?;
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    pub async fn update(self, conn: &Db, actor: Uuid) -> Result<()> {
+        use self::cases::dsl::*;
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match cases
+                    .find(self.id)
+                    .filter(deleted_at.is_null())
+                    .get_result::<Case>(c)
+                    .optional()?
+                {
+                    Some(before) => before,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+                let after = diesel::update(cases)
+                    .filter(id.eq(self.id))
+                    .filter(deleted_at.is_null())
+                    .set(Case {
+                        import_batch_id: before.import_batch_id,
+                        ..self
+                    })
+                    .get_result::<Case>(c)?;
+
+                audit::record(c, actor, Operation::Update, Some(&before), Some(&after))?;
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    pub async fn all(conn: &Db) -> Result<Vec<Case>> {
+        use self::cases::dsl::*;
+
+        conn.run(|c| {
+            cases
+                .filter(deleted_at.is_null())
+                .order(registration_date.desc())
+                .load::<Case>(c)
+        })
+        .await
+        .map_err(|e| Errors::DatabaseError(e.to_string()))
+    }
+
+    pub async fn page(
+        conn: &Db,
+        filter: CaseFilter,
+        page: PageRequest<CaseSortKey>,
+        visibility: Visibility,
+    ) -> Result<Page<Case>> {
+        use self::cases::dsl::*;
+
+        conn.run(move |c| {
+            let filtered = || case_query(&filter, visibility);
+
+            let total = filtered()
+                .count()
+                .get_result::<i64>(c)
+                .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+
+            let query = match page.sort.key {
+                CaseSortKey::RegistrationDate => {
+                    keyset!(filtered(), page, registration_date, id, NaiveDateTime)
+                }
+                CaseSortKey::Number => keyset!(filtered(), page, number, id, i32),
+            };
+
+            let items = query
+                .limit(page.limit + 1)
+                .load::<Case>(c)
+                .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+
+            Ok(Page::of(items, page.limit, total, |case| {
+                match page.sort.key {
+                    CaseSortKey::RegistrationDate => Cursor::new(&case.registration_date, case.id),
+                    CaseSortKey::Number => Cursor::new(&case.number, case.id),
+                }
+            }))
+        })
+        .await
+    }
+
+    pub async fn get(conn: &Db, p_id: Uuid) -> Result<Option<Case>> {
+        use self::cases::dsl::*;
 
         let result = conn
-            .run(move |c| persons.find(p_id).get_result::<Person>(c))
+            .run(move |c| {
+                cases
+                    .find(p_id)
+                    .filter(deleted_at.is_null())
+                    .get_result::<Case>(c)
+            })
             .await;
         match result {
             Ok(r) => Ok(Some(r)),
@@ -511,152 +1581,833 @@ impl Person {
         }
     }
 
-    pub async fn all_by_case_id(conn: &Db, p_case_id: Uuid) -> Result<Vec<Person>> {
+    /// Moves the case to the trash. Its persons have to be deleted first.
+    pub async fn delete(conn: &Db, p_id: Uuid, actor: Uuid) -> Result<()> {
+        use self::cases::dsl::*;
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let persons_count = persons::table
+                    .count()
+                    .filter(persons::case_id.eq(p_id))
+                    .filter(persons::deleted_at.is_null())
+                    .get_result::<i64>(c)?;
+
+                if persons_count != 0 {
+                    return Err(Errors::BadRequest(
+                        "remove persons within case before removing case".to_owned(),
+                    ));
+                }
+
+                let before = match cases
+                    .find(p_id)
+                    .filter(deleted_at.is_null())
+                    .get_result::<Case>(c)
+                    .optional()?
+                {
+                    Some(before) => before,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+                let after = diesel::update(cases.find(p_id))
+                    .set((deleted_at.eq(Utc::now().naive_utc()), deleted_by.eq(actor)))
+                    .get_result::<Case>(c)?;
+
+                audit::record(c, actor, Operation::Delete, Some(&before), Some(&after))?;
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    pub async fn restore(conn: &Db, p_id: Uuid, actor: Uuid) -> Result<()> {
+        use self::cases::dsl::*;
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match cases
+                    .find(p_id)
+                    .filter(deleted_at.is_not_null())
+                    .get_result::<Case>(c)
+                    .optional()?
+                {
+                    Some(before) => before,
+                    None => return Err(Errors::BadRequest("id not found in trash".to_owned())),
+                };
+                let after = diesel::update(cases.find(p_id))
+                    .set((
+                        deleted_at.eq(None::<NaiveDateTime>),
+                        deleted_by.eq(None::<Uuid>),
+                    ))
+                    .get_result::<Case>(c)?;
+
+                audit::record(c, actor, Operation::Restore, Some(&before), Some(&after))?;
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    pub fn activate(self) -> Self {
+        Self {
+            active: true,
+            ..self
+        }
+    }
+
+    pub fn deactivate(self) -> Self {
+        Self {
+            active: false,
+            ..self
+        }
+    }
+}
+
+/// Minimum pg_trgm similarity between two persons' full names (including the
+/// father's name) for them to be reported as possible duplicates.
+const DUPLICATE_NAME_SIMILARITY: f32 = 0.6;
+
+/// Unique index on the national numbers of live persons. It stops two
+/// persons saved at the same time from both getting past the duplicate check,
+/// so admins add it under the "reject" duplicate policy and drop it under
+/// "warn".
+const LIVE_NATIONAL_NUMBER_INDEX: &str = "persons_national_number_live_idx";
+
+/// Reports a save that `LIVE_NATIONAL_NUMBER_INDEX` refused as a duplicate
+/// national number.
+fn unique_national_number(e: diesel::result::Error) -> Errors {
+    match &e {
+        diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, info)
+            if info.constraint_name() == Some(LIVE_NATIONAL_NUMBER_INDEX) =>
+        {
+            duplicate_national_number()
+        }
+        _ => e.into(),
+    }
+}
+
+impl Person {
+    pub async fn new(
+        conn: &Db,
+        entity: NewPerson,
+        policy: DuplicatePolicy,
+        actor: Uuid,
+    ) -> Result<Self> {
+        let entity = entity.validate()?;
+        if policy == DuplicatePolicy::Reject {
+            Person::ensure_unique_national_number(conn, entity.national_number.clone(), None)
+                .await?;
+        }
+
+        conn.run(move |c| c.transaction::<_, Errors, _>(|| Person::create_in(c, entity, actor)))
+            .await
+    }
+
+    /// Inserts a person that has already been validated and checked for a
+    /// duplicate national number.
+    pub fn create_in(c: &PgConnection, entity: NewPerson, actor: Uuid) -> Result<Self> {
+        use self::persons::dsl::*;
+
+        let created = diesel::insert_into(persons)
+            .values((
+                id.eq(Uuid::from_u128(rand::random())),
+                first_name.eq(entity.first_name),
+                last_name.eq(entity.last_name),
+                father_name.eq(entity.father_name),
+                birthday.eq(entity.birthday),
+                national_number.eq(entity.national_number),
+                phone_number.eq(entity.phone_number),
+                case_id.eq(entity.case_id),
+                is_leader.eq(entity.is_leader),
+                description.eq(entity.description),
+                family_role.eq(entity.family_role),
+                education_field.eq(entity.education_field),
+                education_location.eq(entity.education_location),
+            ))
+            .get_result::<Person>(c)
+            .map_err(unique_national_number)?;
+
+        audit::record(c, actor, Operation::Create, None, Some(&created))?;
+        Ok(created)
+    }
+
+    pub async fn insert(self, conn: &Db, actor: Uuid) -> Result<()> {
+        use self::persons::dsl::*;
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let created = diesel::insert_into(persons)
+                    .values(self)
+                    .get_result::<Person>(c)
+                    .map_err(unique_national_number)?;
+
+                audit::record(c, actor, Operation::Create, None, Some(&created))?;
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    pub async fn update(self, conn: &Db, policy: DuplicatePolicy, actor: Uuid) -> Result<()> {
+        use self::persons::dsl::*;
+
+        let entity = self.validate()?;
+        if policy == DuplicatePolicy::Reject {
+            Person::ensure_unique_national_number(
+                conn,
+                entity.national_number.clone(),
+                Some(entity.id),
+            )
+            .await?;
+        }
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match persons
+                    .find(entity.id)
+                    .filter(deleted_at.is_null())
+                    .get_result::<Person>(c)
+                    .optional()?
+                {
+                    Some(before) => before,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+                let after = diesel::update(persons)
+                    .filter(id.eq(entity.id))
+                    .filter(deleted_at.is_null())
+                    .set(entity)
+                    .get_result::<Person>(c)
+                    .map_err(unique_national_number)?;
+
+                audit::record(c, actor, Operation::Update, Some(&before), Some(&after))?;
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    pub async fn all(conn: &Db) -> Result<Vec<Person>> {
+        use self::persons::dsl::*;
+
+        conn.run(|c| {
+            persons
+                .filter(deleted_at.is_null())
+                .order(id.desc())
+                .load::<Person>(c)
+        })
+        .await
+        .map_err(|e| Errors::DatabaseError(e.to_string()))
+    }
+
+    pub async fn page(
+        conn: &Db,
+        filter: PersonFilter,
+        page: PageRequest<PersonSortKey>,
+        visibility: Visibility,
+    ) -> Result<Page<Person>> {
+        use self::persons::dsl::*;
+
+        conn.run(move |c| {
+            let filtered = || {
+                let mut query = persons.filter(deleted_at.is_null()).into_boxed();
+                if let Visibility::AssignedTo(user) = visibility {
+                    query = query.filter(case_id.eq_any(access::assigned_case_ids(user)));
+                }
+                if let Some(p_case_id) = filter.case_id {
+                    query = query.filter(case_id.eq(p_case_id));
+                }
+                if let Some(p_family_role) = filter.family_role {
+                    query = query.filter(family_role.eq(p_family_role));
+                }
+                if let Some(p_is_leader) = filter.is_leader {
+                    query = query.filter(is_leader.eq(p_is_leader));
+                }
+                query
+            };
+
+            let total = filtered()
+                .count()
+                .get_result::<i64>(c)
+                .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+
+            let query = match page.sort.key {
+                PersonSortKey::Id => keyset!(filtered(), page, id, id, Uuid),
+                PersonSortKey::LastName => keyset!(filtered(), page, last_name, id, String),
+                PersonSortKey::Birthday => keyset!(filtered(), page, birthday, id, NaiveDate),
+            };
+
+            let items = query
+                .limit(page.limit + 1)
+                .load::<Person>(c)
+                .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+
+            Ok(Page::of(items, page.limit, total, |person| {
+                match page.sort.key {
+                    PersonSortKey::Id => Cursor::new(&person.id, person.id),
+                    PersonSortKey::LastName => Cursor::new(&person.last_name, person.id),
+                    PersonSortKey::Birthday => Cursor::new(&person.birthday, person.id),
+                }
+            }))
+        })
+        .await
+    }
+
+    pub async fn get(conn: &Db, p_id: Uuid) -> Result<Option<Person>> {
+        use self::persons::dsl::*;
+
+        let result = conn
+            .run(move |c| {
+                persons
+                    .find(p_id)
+                    .filter(deleted_at.is_null())
+                    .get_result::<Person>(c)
+            })
+            .await;
+        match result {
+            Ok(r) => Ok(Some(r)),
+            Err(diesel::result::Error::NotFound) => Ok(None),
+            Err(e) => Err(Errors::DatabaseError(e.to_string())),
+        }
+    }
+
+    pub async fn all_by_case_id(conn: &Db, p_case_id: Uuid) -> Result<Vec<Person>> {
+        use self::persons::dsl::*;
+
+        conn.run(move |c| {
+            persons
+                .order(id.desc())
+                .filter(case_id.eq(p_case_id))
+                .filter(deleted_at.is_null())
+                .load::<Person>(c)
+        })
+        .await
+        .map_err(|e| Errors::DatabaseError(e.to_string()))
+    }
+
+    /// Moves the person to the trash; their jobs, skills and requirements
+    /// are kept and come back with them on restore.
+    pub async fn delete(conn: &Db, p_id: Uuid, actor: Uuid) -> Result<()> {
+        conn.run(move |c| c.transaction(|| Person::delete_in(c, p_id, actor)))
+            .await
+    }
+
+    fn delete_in(c: &PgConnection, p_id: Uuid, actor: Uuid) -> Result<()> {
+        use self::persons::dsl::*;
+
+        let before = match persons
+            .find(p_id)
+            .filter(deleted_at.is_null())
+            .get_result::<Person>(c)
+            .optional()?
+        {
+            Some(before) => before,
+            None => return Err(Errors::BadRequest("id not found".to_owned())),
+        };
+        let after = diesel::update(persons.find(p_id))
+            .set((deleted_at.eq(Utc::now().naive_utc()), deleted_by.eq(actor)))
+            .get_result::<Person>(c)?;
+
+        audit::record(c, actor, Operation::Delete, Some(&before), Some(&after))?;
+        Ok(())
+    }
+
+    pub async fn restore(
+        conn: &Db,
+        p_id: Uuid,
+        policy: DuplicatePolicy,
+        actor: Uuid,
+    ) -> Result<()> {
+        use self::persons::dsl::*;
+
+        let person = conn
+            .run(move |c| {
+                persons
+                    .find(p_id)
+                    .filter(deleted_at.is_not_null())
+                    .get_result::<Person>(c)
+                    .optional()
+            })
+            .await
+            .map_err(|e| Errors::DatabaseError(e.to_string()))?
+            .ok_or_else(|| Errors::BadRequest("id not found in trash".to_owned()))?;
+
+        if Case::get(conn, person.case_id).await?.is_none() {
+            return Err(Errors::BadRequest(
+                "restore the case before restoring its persons".to_owned(),
+            ));
+        }
+        if policy == DuplicatePolicy::Reject {
+            Person::ensure_unique_national_number(
+                conn,
+                person.national_number.clone(),
+                Some(person.id),
+            )
+            .await?;
+        }
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let after = diesel::update(persons.find(p_id))
+                    .filter(deleted_at.is_not_null())
+                    .set((
+                        deleted_at.eq(None::<NaiveDateTime>),
+                        deleted_by.eq(None::<Uuid>),
+                    ))
+                    .get_result::<Person>(c)
+                    .optional()
+                    .map_err(unique_national_number)?
+                    .ok_or_else(|| Errors::BadRequest("id not found in trash".to_owned()))?;
+
+                audit::record(c, actor, Operation::Restore, Some(&person), Some(&after))?;
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    /// Other persons registered with the same national number, among those
+    /// the caller can see.
+    pub async fn same_national_number(
+        &self,
+        conn: &Db,
+        visibility: Visibility,
+    ) -> Result<Vec<Person>> {
         use self::persons::dsl::*;
 
+        let p_id = self.id;
+        let p_national_number = self.national_number.clone();
+
         conn.run(move |c| {
-            persons
+            let mut query = persons
                 .order(id.desc())
-                .filter(case_id.eq(p_case_id))
-                .load::<Person>(c)
+                .filter(national_number.eq(p_national_number))
+                .filter(id.ne(p_id))
+                .filter(deleted_at.is_null())
+                .into_boxed();
+            if let Visibility::AssignedTo(user) = visibility {
+                query = query.filter(case_id.eq_any(access::assigned_case_ids(user)));
+            }
+            query.load::<Person>(c)
         })
         .await
         .map_err(|e| Errors::DatabaseError(e.to_string()))
     }
 
-    pub async fn delete(conn: &Db, p_id: Uuid) -> Result<()> {
+    async fn ensure_unique_national_number(
+        conn: &Db,
+        p_national_number: String,
+        except: Option<Uuid>,
+    ) -> Result<()> {
         use self::persons::dsl::*;
 
-        let count = conn
-            .run(move |c| diesel::delete(persons.filter(id.eq(p_id))).execute(c))
+        let owner = conn
+            .run(move |c| {
+                let mut query = persons
+                    .filter(national_number.eq(p_national_number))
+                    .filter(deleted_at.is_null())
+                    .select(case_id)
+                    .into_boxed();
+                if let Some(except) = except {
+                    query = query.filter(id.ne(except));
+                }
+                query.first::<Uuid>(c).optional()
+            })
             .await
             .map_err(|e| Errors::DatabaseError(e.to_string()))?;
 
-        match count {
-            0 => Err(Errors::BadRequest("id not found".to_owned())),
-            _ => Ok(()),
+        match owner {
+            None => Ok(()),
+            Some(_) => Err(duplicate_national_number()),
         }
     }
 
-    pub async fn set_leader(conn: &Db, person_id: Uuid) -> Result<()> {
-        use self::persons::dsl::*;
+    /// Adds or drops the unique index on live persons' national numbers. It
+    /// cannot be added while live persons still share a number.
+    pub async fn set_unique_national_numbers(conn: &Db, unique: bool) -> Result<()> {
+        conn.run(move |c| {
+            if !unique {
+                let sql = format!("DROP INDEX IF EXISTS {}", LIVE_NATIONAL_NUMBER_INDEX);
+                diesel::sql_query(sql).execute(c)?;
+                return Ok(());
+            }
 
-        let count = conn
-            .run(move |c| {
-                diesel::update(persons)
-                    .filter(id.eq(person_id))
-                    .set(is_leader.eq(true))
-                    .execute(c)
-                    .map_err(|e| Errors::DatabaseError(e.to_string()))
-            })
-            .await?;
+            let sql = format!(
+                "CREATE UNIQUE INDEX IF NOT EXISTS {} ON persons (national_number) \
+                 WHERE deleted_at IS NULL",
+                LIVE_NATIONAL_NUMBER_INDEX
+            );
+            match diesel::sql_query(sql).execute(c) {
+                Err(diesel::result::Error::DatabaseError(
+                    DatabaseErrorKind::UniqueViolation,
+                    _,
+                )) => Err(Errors::BadRequest(
+                    "live persons still share national numbers, merge the persons listed \
+                     by /person/duplicates first"
+                        .to_owned(),
+                )),
+                result => result.map(|_| ()).map_err(Errors::from),
+            }
+        })
+        .await
+    }
+
+    /// Persons that may have been registered more than once: those sharing a
+    /// national number, and pairs born on the same day whose full names
+    /// (with the father's name) are similar. Only persons the caller can see
+    /// are reported.
+    pub async fn duplicates(conn: &Db, visibility: Visibility) -> Result<Vec<DuplicateGroup>> {
+        conn.run(move |c| {
+            let shared = persons::table
+                .inner_join(cases::table)
+                .filter(persons::deleted_at.is_null())
+                .filter(diesel::dsl::sql::<Bool>(
+                    "persons.national_number IN (SELECT national_number FROM persons \
+                     WHERE deleted_at IS NULL \
+                     GROUP BY national_number HAVING count(*) > 1)",
+                ))
+                .order((persons::national_number, persons::id))
+                .select((persons::all_columns, cases::number, cases::active))
+                .load::<(Person, i32, bool)>(c)?;
+
+            let mut groups: Vec<DuplicateGroup> = Vec::new();
+            for (person, case_number, case_active) in shared {
+                let duplicate = DuplicatePerson {
+                    person,
+                    case_number,
+                    case_active,
+                };
+                match groups.last_mut() {
+                    Some(group)
+                        if group.persons[0].person.national_number
+                            == duplicate.person.national_number =>
+                    {
+                        group.persons.push(duplicate)
+                    }
+                    _ => groups.push(DuplicateGroup {
+                        reason: DuplicateReason::NationalNumber,
+                        persons: vec![duplicate],
+                    }),
+                }
+            }
+
+            let pairs = diesel::sql_query(
+                "SELECT a.id AS first, b.id AS second FROM persons a \
+                 JOIN persons b ON a.birthday = b.birthday AND a.id < b.id \
+                 WHERE a.deleted_at IS NULL AND b.deleted_at IS NULL \
+                 AND a.national_number <> b.national_number \
+                 AND similarity( \
+                     persian_normalize(a.first_name || ' ' || a.last_name || ' ' || a.father_name), \
+                     persian_normalize(b.first_name || ' ' || b.last_name || ' ' || b.father_name) \
+                 ) >= $1 \
+                 ORDER BY a.id, b.id",
+            )
+            .bind::<Float, _>(DUPLICATE_NAME_SIMILARITY)
+            .load::<DuplicatePair>(c)?;
+
+            let ids: Vec<Uuid> = pairs.iter().flat_map(|p| [p.first, p.second]).collect();
+            let similar = persons::table
+                .inner_join(cases::table)
+                .filter(persons::id.eq_any(ids))
+                .select((persons::all_columns, cases::number, cases::active))
+                .load::<(Person, i32, bool)>(c)?;
+
+            for pair in pairs {
+                let persons = [pair.first, pair.second]
+                    .iter()
+                    .filter_map(|p_id| similar.iter().find(|(person, _, _)| person.id == *p_id))
+                    .map(|(person, case_number, case_active)| DuplicatePerson {
+                        person: person.clone(),
+                        case_number: *case_number,
+                        case_active: *case_active,
+                    })
+                    .collect();
+
+                groups.push(DuplicateGroup {
+                    reason: DuplicateReason::NameAndBirthday,
+                    persons,
+                });
+            }
+
+            if let Visibility::AssignedTo(user) = visibility {
+                let visible = access::assigned_case_ids(user).load::<Uuid>(c)?;
+                for group in &mut groups {
+                    group
+                        .persons
+                        .retain(|duplicate| visible.contains(&duplicate.person.case_id));
+                }
+                groups.retain(|group| group.persons.len() > 1);
+            }
+
+            Ok(groups)
+        })
+        .await
+    }
 
-        match count {
-            0 => Err(Errors::BadRequest("id not found".to_owned())),
-            _ => Ok(()),
+    /// Moves the jobs, skills and requirements of `from_id` to `into_id` and
+    /// moves `from_id` to the trash. The default job is carried over only when the
+    /// target person has none. Every moved row is recorded in the audit log.
+    pub async fn merge(conn: &Db, from_id: Uuid, into_id: Uuid, actor: Uuid) -> Result<Person> {
+        if from_id == into_id {
+            return Err(Errors::BadRequest(
+                "cannot merge a person into itself".to_owned(),
+            ));
         }
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let target = persons::table
+                    .find(into_id)
+                    .filter(persons::deleted_at.is_null())
+                    .get_result::<Person>(c)
+                    .optional()?;
+                let target = match target {
+                    Some(target) => target,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+
+                let source_default = DefaultJob::of(c, from_id)?;
+                let target_default = DefaultJob::of(c, into_id)?;
+
+                // person_default_job references (person_id, job id), so it has
+                // to be cleared before the jobs change owner.
+                if let Some(default) = &source_default {
+                    diesel::delete(
+                        person_default_job::table.filter(person_default_job::person_id.eq(from_id)),
+                    )
+                    .execute(c)?;
+                    audit::record(c, actor, Operation::Delete, Some(default), None)?;
+                }
+
+                let jobs =
+                    diesel::update(person_jobs::table.filter(person_jobs::person_id.eq(from_id)))
+                        .set(person_jobs::person_id.eq(into_id))
+                        .get_results::<PersonJob>(c)?;
+                for job in &jobs {
+                    let before = PersonJob {
+                        person_id: from_id,
+                        ..job.clone()
+                    };
+                    audit::record(c, actor, Operation::Update, Some(&before), Some(job))?;
+                }
+
+                // Skills both people have stay with the target only.
+                let target_skills = person_skills::table
+                    .filter(person_skills::person_id.eq(into_id))
+                    .select(person_skills::skill_id)
+                    .load::<Uuid>(c)?;
+                let shared = diesel::delete(
+                    person_skills::table
+                        .filter(person_skills::person_id.eq(from_id))
+                        .filter(person_skills::skill_id.eq_any(target_skills)),
+                )
+                .get_results::<PersonSkill>(c)?;
+                for skill in &shared {
+                    audit::record(c, actor, Operation::Delete, Some(skill), None)?;
+                }
+
+                let skills = diesel::update(
+                    person_skills::table.filter(person_skills::person_id.eq(from_id)),
+                )
+                .set(person_skills::person_id.eq(into_id))
+                .get_results::<PersonSkill>(c)?;
+                for skill in &skills {
+                    let before = PersonSkill {
+                        person_id: from_id,
+                        ..skill.clone()
+                    };
+                    audit::record(c, actor, Operation::Update, Some(&before), Some(skill))?;
+                }
+
+                let requirements = diesel::update(
+                    person_requirements::table.filter(person_requirements::person_id.eq(from_id)),
+                )
+                .set(person_requirements::person_id.eq(into_id))
+                .get_results::<PersonRequirement>(c)?;
+                for requirement in &requirements {
+                    let before = PersonRequirement {
+                        person_id: from_id,
+                        ..requirement.clone()
+                    };
+                    audit::record(
+                        c,
+                        actor,
+                        Operation::Update,
+                        Some(&before),
+                        Some(requirement),
+                    )?;
+                }
+
+                AidDistribution::move_to_person_in(c, from_id, into_id, actor)?;
+
+                if let (Some(default), None) = (source_default, target_default) {
+                    let default = diesel::insert_into(person_default_job::table)
+                        .values((
+                            person_default_job::person_id.eq(into_id),
+                            person_default_job::person_job_id.eq(default.person_job_id),
+                        ))
+                        .get_result::<DefaultJob>(c)?;
+                    audit::record(c, actor, Operation::Create, None, Some(&default))?;
+                }
+
+                Person::delete_in(c, from_id, actor)?;
+
+                Ok(target)
+            })
+        })
+        .await
+    }
+
+    pub async fn set_leader(conn: &Db, person_id: Uuid, actor: Uuid) -> Result<()> {
+        Person::update_leader(conn, person_id, true, actor).await
+    }
+
+    pub async fn clear_leader(conn: &Db, person_id: Uuid, actor: Uuid) -> Result<()> {
+        Person::update_leader(conn, person_id, false, actor).await
     }
 
-    pub async fn clear_leader(conn: &Db, person_id: Uuid) -> Result<()> {
+    async fn update_leader(conn: &Db, person_id: Uuid, leader: bool, actor: Uuid) -> Result<()> {
         use self::persons::dsl::*;
 
-        let count = conn
-            .run(move |c| {
-                diesel::update(persons)
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match persons
+                    .find(person_id)
+                    .filter(deleted_at.is_null())
+                    .get_result::<Person>(c)
+                    .optional()?
+                {
+                    Some(before) => before,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+                let after = diesel::update(persons)
                     .filter(id.eq(person_id))
-                    .set(is_leader.eq(false))
-                    .execute(c)
-                    .map_err(|e| Errors::DatabaseError(e.to_string()))
-            })
-            .await?;
+                    .filter(deleted_at.is_null())
+                    .set(is_leader.eq(leader))
+                    .get_result::<Person>(c)?;
 
-        match count {
-            0 => Err(Errors::BadRequest("id not found".to_owned())),
-            _ => Ok(()),
-        }
+                audit::record(c, actor, Operation::Update, Some(&before), Some(&after))?;
+                Ok(())
+            })
+        })
+        .await
     }
 }
 
 impl PersonJob {
-    pub async fn new(conn: &Db, entity: NewPersonJob) -> Result<Self> {
-        use self::person_jobs::dsl::*;
+    pub async fn new(
+        conn: &Db,
+        entity: NewPersonJob,
+        actor: Uuid,
+        today: NaiveDate,
+    ) -> Result<Self> {
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| PersonJob::create_in(c, entity, actor, today))
+        })
+        .await
+    }
 
-        let mut results: Vec<PersonJob> = conn
-            .run(move |c| {
-                diesel::insert_into(person_jobs)
-                    .values((
-                        id.eq(Uuid::from_u128(rand::random())),
-                        person_id.eq(entity.person_id),
-                        title.eq(entity.title),
-                        income.eq(entity.income),
-                        location.eq(entity.location),
-                    ))
-                    .get_results(c)
-            })
-            .await
-            .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+    /// Inserts the job, making it the person's default when it is current on
+    /// `today` and the person has no default yet.
+    pub fn create_in(
+        c: &PgConnection,
+        entity: NewPersonJob,
+        actor: Uuid,
+        today: NaiveDate,
+    ) -> Result<Self> {
+        use self::person_jobs::dsl::*;
 
-        match results.pop() {
-            Some(entity) => {
-                PersonJob::set_default(conn, entity.id).await?;
-                Ok(entity)
-            }
-            None => Err(Errors::DatabaseError(
-                "error while inserting new item to database".to_string(),
-            )),
+        let created = diesel::insert_into(person_jobs)
+            .values((
+                id.eq(Uuid::from_u128(rand::random())),
+                person_id.eq(entity.person_id),
+                title.eq(entity.title),
+                income.eq(entity.income),
+                location.eq(entity.location),
+                start_date.eq(entity.start_date),
+                end_date.eq(entity.end_date),
+                employment_type.eq(entity.employment_type),
+                income_period.eq(entity.income_period),
+                monthly_income.eq(entity
+                    .income
+                    .and_then(|amount| entity.income_period.monthly(amount))),
+            ))
+            .get_result::<PersonJob>(c)?;
+
+        audit::record(c, actor, Operation::Create, None, Some(&created))?;
+        if created.is_current_on(today) && DefaultJob::of(c, created.person_id)?.is_none() {
+            created.set_default_in(c, actor)?;
         }
+        Ok(created)
     }
 
-    pub async fn insert(self, conn: &Db) -> Result<()> {
+    pub async fn insert(self, conn: &Db, actor: Uuid) -> Result<()> {
         use self::person_jobs::dsl::*;
 
-        let result = conn
-            .run(move |c| {
-                diesel::insert_into(person_jobs)
-                    .values(self)
-                    .returning(id)
-                    .get_results::<Uuid>(c)
-            })
-            .await;
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let created = diesel::insert_into(person_jobs)
+                    .values(self.with_monthly_income())
+                    .get_result::<PersonJob>(c)?;
 
-        match result {
-            Ok(_) => Ok(()),
-            Err(e) => Err(Errors::DatabaseError(e.to_string())),
-        }
+                audit::record(c, actor, Operation::Create, None, Some(&created))?;
+                Ok(())
+            })
+        })
+        .await
     }
 
-    pub async fn update(self, conn: &Db) -> Result<()> {
+    /// Saves the job, which stops being the default once it is not current
+    /// on `today`.
+    pub async fn update(self, conn: &Db, actor: Uuid, today: NaiveDate) -> Result<()> {
         use self::person_jobs::dsl::*;
 
-        let result = conn
-            .run(move |c| {
-                diesel::update(person_jobs)
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match person_jobs
+                    .find(self.id)
+                    .get_result::<PersonJob>(c)
+                    .optional()?
+                {
+                    Some(before) => before,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+                let after = diesel::update(person_jobs)
                     .filter(id.eq(self.id))
-                    .set(self)
-                    .execute(c)
-            })
-            .await;
+                    .set(self.with_monthly_income())
+                    .get_result::<PersonJob>(c)?;
 
-        match result {
-            Ok(count) if count == 1 => Ok(()),
-            Ok(_) => Err(Errors::BadRequest("id not found".to_owned())),
-            Err(e) => Err(Errors::DatabaseError(e.to_string())),
-        }
+                audit::record(c, actor, Operation::Update, Some(&before), Some(&after))?;
+                if !after.is_current_on(today) {
+                    after.clear_default_in(c, actor)?;
+                }
+                Ok(())
+            })
+        })
+        .await
     }
 
     pub async fn all(conn: &Db) -> Result<Vec<PersonJob>> {
         use self::person_jobs::dsl::*;
 
-        conn.run(|c| person_jobs.order(id.desc()).load::<PersonJob>(c))
-            .await
-            .map_err(|e| Errors::DatabaseError(e.to_string()))
+        conn.run(|c| {
+            person_jobs
+                .filter(person_id.eq_any(live_person_ids()))
+                .order(id.desc())
+                .load::<PersonJob>(c)
+        })
+        .await
+        .map_err(|e| Errors::DatabaseError(e.to_string()))
     }
 
     pub async fn get(conn: &Db, p_id: Uuid) -> Result<Option<PersonJob>> {
         use self::person_jobs::dsl::*;
 
         let result = conn
-            .run(move |c| person_jobs.find(p_id).get_result::<PersonJob>(c))
+            .run(move |c| {
+                person_jobs
+                    .find(p_id)
+                    .filter(person_id.eq_any(live_person_ids()))
+                    .get_result::<PersonJob>(c)
+            })
             .await;
         match result {
             Ok(r) => Ok(Some(r)),
@@ -672,130 +2423,327 @@ impl PersonJob {
             person_jobs
                 .order(id.desc())
                 .filter(person_id.eq(p_person_id))
+                .filter(person_id.eq_any(live_person_ids()))
                 .load::<PersonJob>(c)
         })
         .await
         .map_err(|e| Errors::DatabaseError(e.to_string()))
     }
 
-    pub async fn delete(conn: &Db, p_id: Uuid) -> Result<()> {
+    pub async fn delete(conn: &Db, p_id: Uuid, actor: Uuid) -> Result<()> {
         use self::person_jobs::dsl::*;
 
-        let count = conn
-            .run(move |c| diesel::delete(person_jobs.filter(id.eq(p_id))).execute(c))
-            .await
-            .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match person_jobs
+                    .find(p_id)
+                    .get_result::<PersonJob>(c)
+                    .optional()?
+                {
+                    Some(before) => before,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+                diesel::delete(person_jobs.filter(id.eq(p_id))).execute(c)?;
+
+                audit::record(c, actor, Operation::Delete, Some(&before), None)?;
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    pub async fn set_default(conn: &Db, p_id: Uuid, actor: Uuid, today: NaiveDate) -> Result<()> {
+        use self::person_jobs::dsl::*;
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let job = match person_jobs
+                    .find(p_id)
+                    .get_result::<PersonJob>(c)
+                    .optional()?
+                {
+                    Some(job) => job,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+                if !job.is_current_on(today) {
+                    return Err(Errors::BadRequest(
+                        "only a current job can be the default".to_owned(),
+                    ));
+                }
+                job.set_default_in(c, actor)
+            })
+        })
+        .await
+    }
+
+    /// The person's jobs along with the spans no job covers up to `today`.
+    pub async fn timeline(
+        conn: &Db,
+        p_person_id: Uuid,
+        today: NaiveDate,
+    ) -> Result<EmploymentTimeline> {
+        use self::person_jobs::dsl::*;
+
+        conn.run(move |c| {
+            let jobs = person_jobs
+                .filter(person_id.eq(p_person_id))
+                .filter(person_id.eq_any(live_person_ids()))
+                .load::<PersonJob>(c)?;
+            let default = DefaultJob::of(c, p_person_id)?.map(|default| default.person_job_id);
+            Ok(timeline(p_person_id, jobs, default, today))
+        })
+        .await
+    }
+
+    pub fn is_current_on(&self, day: NaiveDate) -> bool {
+        !matches!(self.start_date, Some(start) if start > day)
+            && !matches!(self.end_date, Some(end) if end < day)
+    }
 
-        match count {
-            0 => Err(Errors::BadRequest("id not found".to_owned())),
-            _ => Ok(()),
+    fn with_monthly_income(self) -> Self {
+        PersonJob {
+            monthly_income: self
+                .income
+                .and_then(|amount| self.income_period.monthly(amount)),
+            ..self
         }
     }
 
-    pub async fn set_default(conn: &Db, p_id: Uuid) -> Result<()> {
+    /// Stops this job being its person's default, if it is.
+    fn clear_default_in(&self, c: &PgConnection, actor: Uuid) -> Result<()> {
         use self::person_default_job::dsl::*;
 
-        let _ = conn
-            .run(move |c| {
-                diesel::delete(person_default_job.filter(person_job_id.eq(p_id))).execute(c)
-            })
-            .await
-            .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+        let before = match DefaultJob::of(c, self.person_id)? {
+            Some(before) if before.person_job_id == self.id => before,
+            _ => return Ok(()),
+        };
+        diesel::delete(person_default_job.filter(person_id.eq(self.person_id))).execute(c)?;
+        audit::record(c, actor, Operation::Delete, Some(&before), None)?;
+        Ok(())
+    }
 
-        let job = PersonJob::get(conn, p_id).await?;
-
-        match job {
-            None => Err(Errors::BadRequest("id not found".to_owned())),
-            Some(job) => {
-                let _ = conn
-                    .run(move |c| {
-                        diesel::insert_into(person_default_job)
-                            .values((person_id.eq(job.person_id), person_job_id.eq(job.id)))
-                            .execute(c)
-                            .map_err(|e| Errors::DatabaseError(e.to_string()))
-                    })
-                    .await?;
-                Ok(())
+    /// Makes this job its person's default, replacing any previous default.
+    fn set_default_in(&self, c: &PgConnection, actor: Uuid) -> Result<()> {
+        use self::person_default_job::dsl::*;
+
+        let before = DefaultJob::of(c, self.person_id)?;
+        diesel::delete(person_default_job.filter(person_id.eq(self.person_id))).execute(c)?;
+        let after = diesel::insert_into(person_default_job)
+            .values((person_id.eq(self.person_id), person_job_id.eq(self.id)))
+            .get_result::<DefaultJob>(c)?;
+
+        let operation = match before {
+            Some(_) => Operation::Update,
+            None => Operation::Create,
+        };
+        audit::record(c, actor, operation, before.as_ref(), Some(&after))?;
+        Ok(())
+    }
+}
+
+fn timeline(
+    p_person_id: Uuid,
+    jobs: Vec<PersonJob>,
+    default: Option<Uuid>,
+    today: NaiveDate,
+) -> EmploymentTimeline {
+    let mut spans: Vec<(NaiveDate, NaiveDate)> = jobs
+        .iter()
+        .filter_map(|job| {
+            let start = job.start_date?;
+            Some((start, job.end_date.unwrap_or(today).min(today)))
+        })
+        .filter(|(start, end)| start <= end)
+        .collect();
+    spans.sort();
+
+    let mut gaps = Vec::new();
+    let mut covered_until: Option<NaiveDate> = None;
+    for (start, end) in spans {
+        if let Some(until) = covered_until {
+            if start > until.succ() {
+                gaps.push(EmploymentGap {
+                    from: until.succ(),
+                    to: Some(start.pred()),
+                });
             }
         }
+        covered_until = Some(covered_until.map_or(end, |until| until.max(end)));
+    }
+    let working = jobs.iter().any(|job| job.is_current_on(today));
+    if let (Some(until), false) = (covered_until, working) {
+        if until < today {
+            gaps.push(EmploymentGap {
+                from: until.succ(),
+                to: None,
+            });
+        }
+    }
+
+    let monthly_income = jobs
+        .iter()
+        .filter(|job| job.is_current_on(today))
+        .filter_map(|job| job.monthly_income)
+        .map(i64::from)
+        .sum();
+
+    let mut jobs: Vec<TimelineJob> = jobs
+        .into_iter()
+        .map(|job| TimelineJob {
+            is_current: job.is_current_on(today),
+            is_default: default == Some(job.id),
+            job,
+        })
+        .collect();
+    jobs.sort_by(|a, b| match (a.job.start_date, b.job.start_date) {
+        (Some(a), Some(b)) => b.cmp(&a),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => a.job.title.cmp(&b.job.title),
+    });
+
+    EmploymentTimeline {
+        person_id: p_person_id,
+        jobs,
+        monthly_income,
+        gaps,
+    }
+}
+
+impl DefaultJob {
+    fn of(c: &PgConnection, p_person_id: Uuid) -> QueryResult<Option<DefaultJob>> {
+        use self::person_default_job::dsl::*;
+
+        person_default_job
+            .filter(person_id.eq(p_person_id))
+            .first::<DefaultJob>(c)
+            .optional()
     }
 }
 
 impl PersonSkill {
-    pub async fn new(conn: &Db, entity: NewPersonSkill) -> Result<Self> {
-        use self::person_skills::dsl::*;
+    pub async fn new(conn: &Db, entity: NewPersonSkill, actor: Uuid) -> Result<Self> {
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| PersonSkill::create_in(c, entity, actor))
+        })
+        .await
+    }
 
-        let mut results = conn
-            .run(move |c| {
-                diesel::insert_into(person_skills)
-                    .values((
-                        id.eq(Uuid::from_u128(rand::random())),
-                        person_id.eq(entity.person_id),
-                        skill.eq(entity.skill),
-                    ))
-                    .get_results(c)
-            })
-            .await
-            .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+    /// Gives the person the skill, which must be in the catalogue.
+    pub fn create_in(c: &PgConnection, entity: NewPersonSkill, actor: Uuid) -> Result<Self> {
+        use self::person_skills::dsl::*;
 
-        match results.pop() {
-            Some(entity) => Ok(entity),
-            None => Err(Errors::DatabaseError(
-                "error while inserting new item to database".to_string(),
-            )),
-        }
+        check_person_skill(c, entity.person_id, entity.skill_id, None)?;
+        let created = diesel::insert_into(person_skills)
+            .values((
+                id.eq(Uuid::from_u128(rand::random())),
+                person_id.eq(entity.person_id),
+                skill_id.eq(entity.skill_id),
+            ))
+            .get_result::<PersonSkill>(c)?;
+
+        audit::record(c, actor, Operation::Create, None, Some(&created))?;
+        Ok(created)
     }
 
-    pub async fn insert(self, conn: &Db) -> Result<()> {
+    pub async fn insert(self, conn: &Db, actor: Uuid) -> Result<()> {
         use self::person_skills::dsl::*;
 
-        let result = conn
-            .run(move |c| {
-                diesel::insert_into(person_skills)
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                check_person_skill(c, self.person_id, self.skill_id, None)?;
+                let created = diesel::insert_into(person_skills)
                     .values(self)
-                    .returning(id)
-                    .get_results::<Uuid>(c)
-            })
-            .await;
+                    .get_result::<PersonSkill>(c)?;
 
-        match result {
-            Ok(_) => Ok(()),
-            Err(e) => Err(Errors::DatabaseError(e.to_string())),
-        }
+                audit::record(c, actor, Operation::Create, None, Some(&created))?;
+                Ok(())
+            })
+        })
+        .await
     }
 
-    pub async fn update(self, conn: &Db) -> Result<()> {
+    pub async fn update(self, conn: &Db, actor: Uuid) -> Result<()> {
         use self::person_skills::dsl::*;
 
-        let result = conn
-            .run(move |c| {
-                diesel::update(person_skills)
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match person_skills
+                    .find(self.id)
+                    .get_result::<PersonSkill>(c)
+                    .optional()?
+                {
+                    Some(before) => before,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+                check_person_skill(c, self.person_id, self.skill_id, Some(self.id))?;
+                let after = diesel::update(person_skills)
                     .filter(id.eq(self.id))
                     .set(self)
-                    .execute(c)
+                    .get_result::<PersonSkill>(c)?;
+
+                audit::record(c, actor, Operation::Update, Some(&before), Some(&after))?;
+                Ok(())
             })
-            .await;
+        })
+        .await
+    }
 
-        match result {
-            Ok(count) if count == 1 => Ok(()),
-            Ok(_) => Err(Errors::BadRequest("id not found".to_owned())),
-            Err(e) => Err(Errors::DatabaseError(e.to_string())),
+    /// Gives everyone with skill `from_id` skill `into_id` instead, dropping
+    /// the rows of people who already have both.
+    pub fn reassign_in(c: &PgConnection, from_id: Uuid, into_id: Uuid, actor: Uuid) -> Result<()> {
+        use self::person_skills::dsl::*;
+
+        let holders = person_skills
+            .filter(skill_id.eq(into_id))
+            .select(person_id)
+            .load::<Uuid>(c)?;
+        let shared = diesel::delete(
+            person_skills
+                .filter(skill_id.eq(from_id))
+                .filter(person_id.eq_any(holders)),
+        )
+        .get_results::<PersonSkill>(c)?;
+        for skill in &shared {
+            audit::record(c, actor, Operation::Delete, Some(skill), None)?;
+        }
+
+        let moved = diesel::update(person_skills.filter(skill_id.eq(from_id)))
+            .set(skill_id.eq(into_id))
+            .get_results::<PersonSkill>(c)?;
+        for skill in &moved {
+            let before = PersonSkill {
+                skill_id: from_id,
+                ..skill.clone()
+            };
+            audit::record(c, actor, Operation::Update, Some(&before), Some(skill))?;
         }
+        Ok(())
     }
 
     pub async fn all(conn: &Db) -> Result<Vec<PersonSkill>> {
         use self::person_skills::dsl::*;
 
-        conn.run(|c| person_skills.order(id.desc()).load::<PersonSkill>(c))
-            .await
-            .map_err(|e| Errors::DatabaseError(e.to_string()))
+        conn.run(|c| {
+            person_skills
+                .filter(person_id.eq_any(live_person_ids()))
+                .order(id.desc())
+                .load::<PersonSkill>(c)
+        })
+        .await
+        .map_err(|e| Errors::DatabaseError(e.to_string()))
     }
 
     pub async fn get(conn: &Db, p_id: Uuid) -> Result<Option<PersonSkill>> {
         use self::person_skills::dsl::*;
 
         let result = conn
-            .run(move |c| person_skills.find(p_id).get_result::<PersonSkill>(c))
+            .run(move |c| {
+                person_skills
+                    .find(p_id)
+                    .filter(person_id.eq_any(live_person_ids()))
+                    .get_result::<PersonSkill>(c)
+            })
             .await;
         match result {
             Ok(r) => Ok(Some(r)),
@@ -811,87 +2759,142 @@ impl PersonSkill {
             person_skills
                 .order(id.desc())
                 .filter(person_id.eq(p_person_id))
+                .filter(person_id.eq_any(live_person_ids()))
                 .load::<PersonSkill>(c)
         })
         .await
         .map_err(|e| Errors::DatabaseError(e.to_string()))
     }
 
-    pub async fn delete(conn: &Db, p_id: Uuid) -> Result<()> {
+    pub async fn delete(conn: &Db, p_id: Uuid, actor: Uuid) -> Result<()> {
         use self::person_skills::dsl::*;
 
-        let count = conn
-            .run(move |c| diesel::delete(person_skills.filter(id.eq(p_id))).execute(c))
-            .await
-            .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match person_skills
+                    .find(p_id)
+                    .get_result::<PersonSkill>(c)
+                    .optional()?
+                {
+                    Some(before) => before,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+                diesel::delete(person_skills.filter(id.eq(p_id))).execute(c)?;
+
+                audit::record(c, actor, Operation::Delete, Some(&before), None)?;
+                Ok(())
+            })
+        })
+        .await
+    }
+}
 
-        match count {
-            0 => Err(Errors::BadRequest("id not found".to_owned())),
-            _ => Ok(()),
-        }
+/// Fails unless `p_skill_id` is in the catalogue and the person does not have
+/// it yet, apart from in the row `p_id`.
+fn check_person_skill(
+    c: &PgConnection,
+    p_person_id: Uuid,
+    p_skill_id: Uuid,
+    p_id: Option<Uuid>,
+) -> Result<()> {
+    let known = skills::table
+        .find(p_skill_id)
+        .select(skills::id)
+        .first::<Uuid>(c)
+        .optional()?;
+    if known.is_none() {
+        return Err(validation::field_error(
+            "skill_id",
+            "not_found",
+            "no such skill in the catalogue".to_owned(),
+        ));
     }
+
+    let mut query = person_skills::table
+        .filter(person_skills::person_id.eq(p_person_id))
+        .filter(person_skills::skill_id.eq(p_skill_id))
+        .select(person_skills::id)
+        .into_boxed();
+    if let Some(p_id) = p_id {
+        query = query.filter(person_skills::id.ne(p_id));
+    }
+    if query.first::<Uuid>(c).optional()?.is_some() {
+        return Err(validation::field_error(
+            "skill_id",
+            "taken",
+            "the person already has this skill".to_owned(),
+        ));
+    }
+    Ok(())
 }
 
 impl PersonRequirement {
-    pub async fn new(conn: &Db, entity: NewPersonRequirement) -> Result<Self> {
-        use self::person_requirements::dsl::*;
+    pub async fn new(conn: &Db, entity: NewPersonRequirement, actor: Uuid) -> Result<Self> {
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| PersonRequirement::create_in(c, entity, actor))
+        })
+        .await
+    }
 
-        let mut results = conn
-            .run(move |c| {
-                diesel::insert_into(person_requirements)
-                    .values((
-                        id.eq(Uuid::from_u128(rand::random())),
-                        person_id.eq(entity.person_id),
-                        description.eq(entity.description),
-                    ))
-                    .get_results(c)
-            })
-            .await
-            .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+    /// Inserts a requirement that has already been validated.
+    pub fn create_in(c: &PgConnection, entity: NewPersonRequirement, actor: Uuid) -> Result<Self> {
+        use self::person_requirements::dsl::*;
 
-        match results.pop() {
-            Some(entity) => Ok(entity),
-            None => Err(Errors::DatabaseError(
-                "error while inserting new item to database".to_string(),
-            )),
-        }
+        let created = diesel::insert_into(person_requirements)
+            .values((
+                id.eq(Uuid::from_u128(rand::random())),
+                person_id.eq(entity.person_id),
+                description.eq(entity.description),
+                category.eq(entity.category),
+                priority.eq(entity.priority),
+                estimated_cost.eq(entity.estimated_cost),
+                due_date.eq(entity.due_date),
+            ))
+            .get_result::<PersonRequirement>(c)?;
+
+        audit::record(c, actor, Operation::Create, None, Some(&created))?;
+        Ok(created)
     }
 
-    pub async fn insert(self, conn: &Db) -> Result<()> {
+    pub async fn insert(self, conn: &Db, actor: Uuid) -> Result<()> {
         use self::person_requirements::dsl::*;
 
-        let result = conn
-            .run(move |c| {
-                diesel::insert_into(person_requirements)
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let created = diesel::insert_into(person_requirements)
                     .values(self)
-                    .returning(id)
-                    .get_results::<Uuid>(c)
-            })
-            .await;
+                    .get_result::<PersonRequirement>(c)?;
 
-        match result {
-            Ok(_) => Ok(()),
-            Err(e) => Err(Errors::DatabaseError(e.to_string())),
-        }
+                audit::record(c, actor, Operation::Create, None, Some(&created))?;
+                Ok(())
+            })
+        })
+        .await
     }
 
-    pub async fn update(self, conn: &Db) -> Result<()> {
+    pub async fn update(self, conn: &Db, actor: Uuid) -> Result<()> {
         use self::person_requirements::dsl::*;
-
-        let result = conn
-            .run(move |c| {
-                diesel::update(person_requirements)
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match person_requirements
+                    .find(self.id)
+                    .get_result::<PersonRequirement>(c)
+                    .optional()?
+                {
+                    Some(before) => before,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+                let after = diesel::update(person_requirements)
                     .filter(id.eq(self.id))
                     .set(self)
-                    .execute(c)
-            })
-            .await;
+                    .get_result::<PersonRequirement>(c)?;
 
-        match result {
-            Ok(count) if count == 1 => Ok(()),
-            Ok(_) => Err(Errors::BadRequest("id not found".to_owned())),
-            Err(e) => Err(Errors::DatabaseError(e.to_string())),
-        }
+                audit::record(c, actor, Operation::Update, Some(&before), Some(&after))?;
+                Ok(())
+            })
+        })
+        .await
     }
 
     pub async fn all(conn: &Db) -> Result<Vec<PersonRequirement>> {
@@ -899,6 +2902,7 @@ impl PersonRequirement {
 
         conn.run(|c| {
             person_requirements
+                .filter(person_id.eq_any(live_person_ids()))
                 .order(id.desc())
                 .load::<PersonRequirement>(c)
         })
@@ -913,12 +2917,106 @@ impl PersonRequirement {
             person_requirements
                 .order(id.desc())
                 .filter(person_id.eq(p_person_id))
+                .filter(person_id.eq_any(live_person_ids()))
                 .load::<PersonRequirement>(c)
         })
         .await
         .map_err(|e| Errors::DatabaseError(e.to_string()))
     }
 
+    pub async fn page(
+        conn: &Db,
+        filter: RequirementFilter,
+        page: PageRequest<RequirementSortKey>,
+        visibility: Visibility,
+    ) -> Result<Page<PersonRequirement>> {
+        use self::person_requirements::dsl::*;
+
+        let statuses = match filter.status.is_empty() {
+            true => RequirementStatus::OPEN.to_vec(),
+            false => filter.status,
+        };
+
+        conn.run(move |c| {
+            let filtered = || {
+                let mut query = person_requirements
+                    .filter(person_id.eq_any(live_person_ids()))
+                    .filter(status.eq_any(statuses.clone()))
+                    .into_boxed();
+                if let Visibility::AssignedTo(user) = visibility {
+                    query = query.filter(
+                        person_id.eq_any(
+                            persons::table
+                                .filter(persons::case_id.eq_any(access::assigned_case_ids(user)))
+                                .select(persons::id),
+                        ),
+                    );
+                }
+                if let Some(p_case_id) = filter.case_id {
+                    query = query.filter(
+                        person_id.eq_any(
+                            persons::table
+                                .filter(persons::case_id.eq(p_case_id))
+                                .select(persons::id),
+                        ),
+                    );
+                }
+                if let Some(p_person_id) = filter.person_id {
+                    query = query.filter(person_id.eq(p_person_id));
+                }
+                if let Some(p_category) = filter.category {
+                    query = query.filter(category.eq(p_category));
+                }
+                if let Some(p_priority) = filter.priority {
+                    query = query.filter(priority.eq(p_priority));
+                }
+                if let Some(QueryDate(due_by)) = filter.due_by {
+                    query = query.filter(due_date.le(due_by));
+                }
+                query
+            };
+
+            let total = filtered()
+                .count()
+                .get_result::<i64>(c)
+                .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+
+            let query = match page.sort.key {
+                RequirementSortKey::Id => keyset!(filtered(), page, id, id, Uuid),
+                RequirementSortKey::Priority => keyset!(filtered(), page, priority, id, i32),
+                RequirementSortKey::DueDate => keyset!(
+                    filtered(),
+                    page,
+                    coalesce_date(due_date, not_due()),
+                    id,
+                    NaiveDate
+                ),
+            };
+
+            let items = query
+                .limit(page.limit + 1)
+                .load::<PersonRequirement>(c)
+                .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+
+            Ok(Page::of(
+                items,
+                page.limit,
+                total,
+                |requirement| match page.sort.key {
+                    RequirementSortKey::Id => Cursor::new(&requirement.id, requirement.id),
+                    RequirementSortKey::Priority => {
+                        Cursor::new(&i32::from(requirement.priority), requirement.id)
+                    }
+                    RequirementSortKey::DueDate => Cursor::new(
+                        &requirement.due_date.unwrap_or_else(not_due),
+                        requirement.id,
+                    ),
+                },
+            ))
+        })
+        .await
+    }
+
     pub async fn get(conn: &Db, p_id: Uuid) -> Result<Option<PersonRequirement>> {
         use self::person_requirements::dsl::*;
 
@@ -926,6 +3024,7 @@ impl PersonRequirement {
             .run(move |c| {
                 person_requirements
                     .find(p_id)
+                    .filter(person_id.eq_any(live_person_ids()))
                     .get_result::<PersonRequirement>(c)
             })
             .await;
@@ -936,90 +3035,220 @@ impl PersonRequirement {
         }
     }
 
-    pub async fn delete(conn: &Db, p_id: Uuid) -> Result<()> {
+    pub async fn delete(conn: &Db, p_id: Uuid, actor: Uuid) -> Result<()> {
         use self::person_requirements::dsl::*;
 
-        let count = conn
-            .run(move |c| diesel::delete(person_requirements.filter(id.eq(p_id))).execute(c))
-            .await
-            .map_err(|e| Errors::DatabaseError(e.to_string()))?;
-
-        match count {
-            0 => Err(Errors::BadRequest("id not found".to_owned())),
-            _ => Ok(()),
-        }
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match person_requirements
+                    .find(p_id)
+                    .get_result::<PersonRequirement>(c)
+                    .optional()?
+                {
+                    Some(before) => before,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+                diesel::delete(person_requirements.filter(id.eq(p_id))).execute(c)?;
+
+                audit::record(c, actor, Operation::Delete, Some(&before), None)?;
+                Ok(())
+            })
+        })
+        .await
     }
 }
 
 impl CaseAction {
-    pub async fn new(conn: &Db, entity: NewCaseAction) -> Result<Self> {
-        use self::case_actions::dsl::*;
-
-        let mut results = conn
-            .run(move |c| {
-                diesel::insert_into(case_actions)
-                    .values((
-                        id.eq(Uuid::from_u128(rand::random())),
-                        case_id.eq(entity.case_id),
-                        action.eq(entity.action),
-                        action_date.eq(entity.action_date),
-                        status.eq(0),
-                    ))
-                    .get_results(c)
-            })
+    pub async fn new(conn: &Db, entity: NewCaseAction, actor: Uuid) -> Result<Self> {
+        conn.run(move |c| c.transaction::<_, Errors, _>(|| CaseAction::create_in(c, entity, actor)))
             .await
-            .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+    }
 
-        match results.pop() {
-            Some(entity) => Ok(entity),
-            None => Err(Errors::DatabaseError(
-                "error while inserting new item to database".to_string(),
-            )),
+    /// Inserts the action as to do, checking that its assignee may work on
+    /// the case.
+    pub fn create_in(c: &PgConnection, entity: NewCaseAction, actor: Uuid) -> Result<Self> {
+        use self::case_actions::dsl::*;
+
+        if let Some(p_assignee) = entity.assignee {
+            check_assignee(c, entity.case_id, p_assignee)?;
         }
+
+        let created = diesel::insert_into(case_actions)
+            .values((
+                id.eq(Uuid::from_u128(rand::random())),
+                case_id.eq(entity.case_id),
+                action.eq(entity.action),
+                action_date.eq(entity.action_date),
+                status.eq(ActionStatus::Todo),
+                assignee.eq(entity.assignee),
+            ))
+            .get_result::<CaseAction>(c)?;
+
+        audit::record(c, actor, Operation::Create, None, Some(&created))?;
+        Ok(created)
     }
 
-    pub async fn insert(self, conn: &Db) -> Result<()> {
+    pub async fn insert(self, conn: &Db, actor: Uuid) -> Result<()> {
         use self::case_actions::dsl::*;
 
-        let result = conn
-            .run(move |c| {
-                diesel::insert_into(case_actions)
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let created = diesel::insert_into(case_actions)
                     .values(self)
-                    .returning(id)
-                    .get_results::<Uuid>(c)
+                    .get_result::<CaseAction>(c)?;
+
+                audit::record(c, actor, Operation::Create, None, Some(&created))?;
+                Ok(())
             })
-            .await;
+        })
+        .await
+    }
 
-        match result {
-            Ok(_) => Ok(()),
-            Err(e) => Err(Errors::DatabaseError(e.to_string())),
-        }
+    /// Saves the case, text, date and assignee of the action. Its status and
+    /// completion only change through `transition`.
+    pub async fn update(self, conn: &Db, actor: Uuid) -> Result<()> {
+        use self::case_actions::dsl::*;
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match case_actions
+                    .find(self.id)
+                    .get_result::<CaseAction>(c)
+                    .optional()?
+                {
+                    Some(before) => before,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+                if self.status != before.status {
+                    return Err(Errors::BadRequest(
+                        "status can only be changed by start, complete, cancel or reopen".into(),
+                    ));
+                }
+                if let Some(p_assignee) = self.assignee {
+                    check_assignee(c, self.case_id, p_assignee)?;
+                }
+
+                let after = diesel::update(case_actions.find(self.id))
+                    .set((
+                        case_id.eq(self.case_id),
+                        action.eq(self.action),
+                        action_date.eq(self.action_date),
+                        assignee.eq(self.assignee),
+                    ))
+                    .get_result::<CaseAction>(c)?;
+
+                audit::record(c, actor, Operation::Update, Some(&before), Some(&after))?;
+                Ok(())
+            })
+        })
+        .await
     }
 
-    pub async fn update(self, conn: &Db) -> Result<()> {
+    /// Moves the action to the status `transition` leads to. Completing it
+    /// records who did it and when, and `note` becomes its result; reopening
+    /// clears them.
+    pub async fn transition(
+        conn: &Db,
+        p_id: Uuid,
+        transition: Transition,
+        note: Option<String>,
+        actor: Uuid,
+    ) -> Result<CaseAction> {
         use self::case_actions::dsl::*;
 
-        let result = conn
-            .run(move |c| {
-                diesel::update(case_actions)
-                    .filter(id.eq(self.id))
-                    .set(self)
-                    .execute(c)
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match case_actions
+                    .find(p_id)
+                    .filter(case_id.eq_any(live_case_ids()))
+                    .for_update()
+                    .get_result::<CaseAction>(c)
+                    .optional()?
+                {
+                    Some(before) => before,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+
+                let next = before.status.after(transition).ok_or_else(|| {
+                    Errors::BadRequest(format!(
+                        "cannot {:?} an action that is {:?}",
+                        transition, before.status
+                    ))
+                })?;
+
+                let (p_completed_at, p_completed_by, p_result_note) = match transition {
+                    Transition::Complete => (Some(Utc::now().naive_utc()), Some(actor), note),
+                    Transition::Cancel => (None, None, note),
+                    Transition::Start => (None, None, before.result_note.clone()),
+                    Transition::Reopen => (None, None, None),
+                };
+
+                let after = diesel::update(case_actions.find(p_id))
+                    .set((
+                        status.eq(next),
+                        completed_at.eq(p_completed_at),
+                        completed_by.eq(p_completed_by),
+                        result_note.eq(p_result_note),
+                    ))
+                    .get_result::<CaseAction>(c)?;
+
+                audit::record(c, actor, Operation::Update, Some(&before), Some(&after))?;
+                Ok(after)
             })
-            .await;
+        })
+        .await
+    }
 
-        match result {
-            Ok(count) if count == 1 => Ok(()),
-            Ok(_) => Err(Errors::BadRequest("id not found".to_owned())),
-            Err(e) => Err(Errors::DatabaseError(e.to_string())),
-        }
+    pub async fn comments(conn: &Db, p_id: Uuid) -> Result<Vec<CaseActionComment>> {
+        use self::case_action_comments::dsl::*;
+
+        conn.run(move |c| {
+            case_action_comments
+                .filter(case_action_id.eq(p_id))
+                .order((created_at.asc(), id.asc()))
+                .load::<CaseActionComment>(c)
+        })
+        .await
+        .map_err(|e| Errors::DatabaseError(e.to_string()))
+    }
+
+    pub async fn add_comment(
+        conn: &Db,
+        p_id: Uuid,
+        comment: NewCaseActionComment,
+        actor: Uuid,
+    ) -> Result<CaseActionComment> {
+        use self::case_action_comments::dsl::*;
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let created = diesel::insert_into(case_action_comments)
+                    .values((
+                        id.eq(Uuid::from_u128(rand::random())),
+                        case_action_id.eq(p_id),
+                        author.eq(actor),
+                        body.eq(comment.body),
+                        created_at.eq(Utc::now().naive_utc()),
+                    ))
+                    .get_result::<CaseActionComment>(c)?;
+
+                audit::record(c, actor, Operation::Create, None, Some(&created))?;
+                Ok(created)
+            })
+        })
+        .await
     }
 
     pub async fn all(conn: &Db) -> Result<Vec<CaseAction>> {
         use self::case_actions::dsl::*;
 
         let mut result = conn
-            .run(|c| case_actions.order(action_date.desc()).load::<CaseAction>(c))
+            .run(|c| {
+                case_actions
+                    .filter(case_id.eq_any(live_case_ids()))
+                    .order(action_date.desc())
+                    .load::<CaseAction>(c)
+            })
             .await
             .map_err(|e| Errors::DatabaseError(e.to_string()))?;
 
@@ -1027,6 +3256,78 @@ impl CaseAction {
         Ok(result)
     }
 
+    pub async fn page(
+        conn: &Db,
+        filter: CaseActionFilter,
+        page: PageRequest<CaseActionSortKey>,
+        visibility: Visibility,
+    ) -> Result<Page<CaseAction>> {
+        use self::case_actions::dsl::*;
+
+        conn.run(move |c| {
+            let filtered = || {
+                let mut query = case_actions
+                    .filter(case_id.eq_any(live_case_ids()))
+                    .into_boxed();
+                if let Visibility::AssignedTo(user) = visibility {
+                    query = query.filter(case_id.eq_any(access::assigned_case_ids(user)));
+                }
+                if let Some(p_case_id) = filter.case_id {
+                    query = query.filter(case_id.eq(p_case_id));
+                }
+                if let Some(p_status) = filter.status {
+                    query = query.filter(status.eq(p_status));
+                }
+                if let Some(p_assignee) = filter.assignee {
+                    query = query.filter(assignee.eq(p_assignee));
+                }
+                if let Some(QueryDate(from)) = filter.from {
+                    query = query.filter(action_date.ge(from.and_hms(0, 0, 0)));
+                }
+                if let Some(QueryDate(to)) = filter.to {
+                    let end = to.and_hms(0, 0, 0) + Duration::days(1);
+                    query = query.filter(action_date.lt(end));
+                }
+                query
+            };
+
+            let total = filtered()
+                .count()
+                .get_result::<i64>(c)
+                .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+
+            let query = match page.sort.key {
+                CaseActionSortKey::Id => keyset!(filtered(), page, id, id, Uuid),
+                CaseActionSortKey::ActionDate => keyset!(
+                    filtered(),
+                    page,
+                    coalesce(action_date, undated()),
+                    id,
+                    NaiveDateTime
+                ),
+            };
+
+            let items = query
+                .limit(page.limit + 1)
+                .load::<CaseAction>(c)
+                .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+
+            Ok(Page::of(
+                items,
+                page.limit,
+                total,
+                |case_action| match page.sort.key {
+                    CaseActionSortKey::Id => Cursor::new(&case_action.id, case_action.id),
+                    CaseActionSortKey::ActionDate => Cursor::new(
+                        &case_action.action_date.unwrap_or_else(undated),
+                        case_action.id,
+                    ),
+                },
+            ))
+        })
+        .await
+    }
+
     pub async fn all_by_case_id(conn: &Db, p_case_id: Uuid) -> Result<Vec<CaseAction>> {
         use self::case_actions::dsl::*;
 
@@ -1035,6 +3336,7 @@ impl CaseAction {
                 case_actions
                     .order(action_date.desc())
                     .filter(case_id.eq(p_case_id))
+                    .filter(case_id.eq_any(live_case_ids()))
                     .load::<CaseAction>(c)
             })
             .await
@@ -1048,7 +3350,12 @@ impl CaseAction {
         use self::case_actions::dsl::*;
 
         let result = conn
-            .run(move |c| case_actions.find(p_id).get_result::<CaseAction>(c))
+            .run(move |c| {
+                case_actions
+                    .find(p_id)
+                    .filter(case_id.eq_any(live_case_ids()))
+                    .get_result::<CaseAction>(c)
+            })
             .await;
         match result {
             Ok(r) => Ok(Some(r)),
@@ -1057,107 +3364,636 @@ impl CaseAction {
         }
     }
 
-    pub async fn delete(conn: &Db, p_id: Uuid) -> Result<()> {
+    pub async fn delete(conn: &Db, p_id: Uuid, actor: Uuid) -> Result<()> {
         use self::case_actions::dsl::*;
 
-        let count = conn
-            .run(move |c| diesel::delete(case_actions.filter(id.eq(p_id))).execute(c))
-            .await
-            .map_err(|e| Errors::DatabaseError(e.to_string()))?;
-
-        match count {
-            0 => Err(Errors::BadRequest("id not found".to_owned())),
-            _ => Ok(()),
-        }
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match case_actions
+                    .find(p_id)
+                    .get_result::<CaseAction>(c)
+                    .optional()?
+                {
+                    Some(before) => before,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+                diesel::delete(case_actions.filter(id.eq(p_id))).execute(c)?;
+
+                audit::record(c, actor, Operation::Delete, Some(&before), None)?;
+                Ok(())
+            })
+        })
+        .await
     }
 
-    pub async fn today_actions_for_case(conn: &Db, p_case_id: Uuid) -> Result<Vec<CaseAction>> {
+    /// Actions for an agenda, ordered by date. Actions in the query's
+    /// window are kept when their status is one asked for; overdue ones when
+    /// they are still open.
+    pub async fn agenda(
+        conn: &Db,
+        query: AgendaQuery,
+        visibility: Visibility,
+    ) -> Result<Vec<CaseAction>> {
         use self::case_actions::dsl::*;
 
-        let today = Utc::now().date().naive_utc().and_hms(0, 0, 0);
-        let tomarrow = today + Duration::days(1);
+        type Condition = Box<dyn BoxableExpression<case_actions, Pg, SqlType = Bool>>;
 
-        let mut result = conn
+        conn.run(move |c| {
+            let mut due: Option<Condition> = None;
+            if let Some(window) = query.window {
+                due = Some(Box::new(
+                    action_date
+                        .ge(window.from)
+                        .and(action_date.lt(window.to))
+                        .and(status.eq_any(query.statuses)),
+                ));
+            }
+            if let Some(before) = query.overdue_before {
+                let overdue: Condition = Box::new(
+                    action_date
+                        .lt(before)
+                        .and(status.eq_any(ActionStatus::OPEN.to_vec())),
+                );
+                due = Some(match due {
+                    Some(due) => Box::new(due.or(overdue)),
+                    None => overdue,
+                });
+            }
+            let due = match due {
+                Some(due) => due,
+                None => return Ok(Vec::new()),
+            };
+
+            let mut agenda = case_actions
+                .filter(case_id.eq_any(live_case_ids()))
+                .filter(due)
+                .into_boxed();
+            if let Visibility::AssignedTo(user) = visibility {
+                agenda = agenda.filter(case_id.eq_any(access::assigned_case_ids(user)));
+            }
+            if let Some(p_case_id) = query.case_id {
+                agenda = agenda.filter(case_id.eq(p_case_id));
+            }
+            if let Some(p_assignee) = query.assignee {
+                agenda = agenda.filter(assignee.eq(p_assignee));
+            }
+
+            agenda
+                .order((action_date.asc(), id.asc()))
+                .load::<CaseAction>(c)
+        })
+        .await
+        .map_err(|e| Errors::DatabaseError(e.to_string()))
+    }
+
+    /// The agenda as calendar events, titled and placed with the number and
+    /// address of each action's case.
+    pub async fn calendar(
+        conn: &Db,
+        query: AgendaQuery,
+        visibility: Visibility,
+    ) -> Result<Vec<ical::Event>> {
+        let actions = Self::agenda(conn, query, visibility).await?;
+        let case_ids: Vec<Uuid> = actions.iter().map(|action| action.case_id).collect();
+
+        let cases = conn
             .run(move |c| {
-                case_actions
-                    .order(action_date)
-                    .filter(case_id.eq(p_case_id))
-                    .filter(action_date.gt(today))
-                    .filter(action_date.lt(tomarrow))
-                    .filter(status.lt(ACTION_STATUS_DONE))
-                    .load::<CaseAction>(c)
+                cases::table
+                    .filter(cases::id.eq_any(case_ids))
+                    .load::<Case>(c)
             })
             .await
             .map_err(|e| Errors::DatabaseError(e.to_string()))?;
 
-        result.sort_by(|i, j| i.action_date.cmp(&j.action_date));
-        Ok(result)
+        Ok(actions
+            .into_iter()
+            .filter_map(|action| {
+                let start = action.action_date?;
+                let case = cases.iter().find(|case| case.id == action.case_id)?;
+
+                let mut description = format!("Case {}\nStatus: {:?}", case.number, action.status);
+                if let Some(note) = &action.result_note {
+                    description.push_str(&format!("\n{}", note));
+                }
+
+                Some(ical::Event {
+                    id: action.id,
+                    start,
+                    summary: format!("Case {}: {}", case.number, action.action),
+                    location: case.address.clone(),
+                    description: Some(description),
+                    cancelled: action.status == ActionStatus::Cancelled,
+                })
+            })
+            .collect())
     }
+}
 
-    pub async fn today_actions_for_all_cases(conn: &Db) -> Result<Vec<CaseAction>> {
-        use self::case_actions::dsl::*;
+impl CaseActionSeries {
+    pub fn rule(&self) -> Rule {
+        Rule {
+            frequency: self.frequency,
+            repeat_every: self.repeat_every,
+            repeat_until: self.repeat_until,
+            occurrence_count: self.occurrence_count,
+        }
+    }
 
-        let today = Utc::now().date().naive_utc().and_hms(0, 0, 0);
-        let tomarrow = today + Duration::days(1);
+    /// Creates the series and its occurrences from now up to `horizon`.
+    pub async fn new(
+        conn: &Db,
+        entity: NewCaseActionSeries,
+        horizon: NaiveDateTime,
+        actor: Uuid,
+    ) -> Result<Self> {
+        use self::case_action_series::dsl::*;
+
+        let p_rule = entity.rule.rule()?;
+        // A series starting in the past only gets occurrences from now on, so
+        // backdating it does not fill the agenda with overdue actions.
+        let now = Utc::now().naive_utc();
+        let skipped_until = Some(now).filter(|now| entity.starts_at < *now);
 
-        let mut result = conn
-            .run(move |c| {
-                case_actions
-                    .order(action_date)
-                    .filter(action_date.gt(today))
-                    .filter(action_date.lt(tomarrow))
-                    .filter(status.lt(ACTION_STATUS_DONE))
-                    .load::<CaseAction>(c)
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                if let Some(p_assignee) = entity.assignee {
+                    check_assignee(c, entity.case_id, p_assignee)?;
+                }
+
+                let created = diesel::insert_into(case_action_series)
+                    .values((
+                        id.eq(Uuid::from_u128(rand::random())),
+                        case_id.eq(entity.case_id),
+                        action.eq(entity.action),
+                        assignee.eq(entity.assignee),
+                        starts_at.eq(entity.starts_at),
+                        frequency.eq(p_rule.frequency),
+                        repeat_every.eq(p_rule.repeat_every),
+                        repeat_until.eq(p_rule.repeat_until),
+                        occurrence_count.eq(p_rule.occurrence_count),
+                        materialized_until.eq(skipped_until),
+                    ))
+                    .get_result::<CaseActionSeries>(c)?;
+                audit::record(c, actor, Operation::Create, None, Some(&created))?;
+
+                created
+                    .materialize_in(c, horizon, actor)
+                    .map(|(created, _)| created)
             })
-            .await
-            .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+        })
+        .await
+    }
 
-        result.sort_by(|i, j| i.action_date.cmp(&j.action_date));
-        Ok(result)
+    pub async fn get(conn: &Db, p_id: Uuid) -> Result<Option<CaseActionSeries>> {
+        use self::case_action_series::dsl::*;
+
+        conn.run(move |c| {
+            case_action_series
+                .find(p_id)
+                .filter(case_id.eq_any(live_case_ids()))
+                .get_result::<CaseActionSeries>(c)
+                .optional()
+        })
+        .await
+        .map_err(|e| Errors::DatabaseError(e.to_string()))
     }
 
-    pub async fn week_actions_for_all_cases(conn: &Db) -> Result<Vec<CaseAction>> {
-        use self::case_actions::dsl::*;
+    pub async fn all_by_case_id(conn: &Db, p_case_id: Uuid) -> Result<Vec<CaseActionSeries>> {
+        use self::case_action_series::dsl::*;
+
+        conn.run(move |c| {
+            case_action_series
+                .filter(case_id.eq(p_case_id))
+                .filter(case_id.eq_any(live_case_ids()))
+                .order(starts_at.asc())
+                .load::<CaseActionSeries>(c)
+        })
+        .await
+        .map_err(|e| Errors::DatabaseError(e.to_string()))
+    }
+
+    pub async fn occurrences(conn: &Db, p_id: Uuid) -> Result<Vec<CaseAction>> {
+        conn.run(move |c| {
+            case_actions::table
+                .filter(case_actions::series_id.eq(p_id))
+                .filter(case_actions::case_id.eq_any(live_case_ids()))
+                .order(case_actions::occurrence_at.asc())
+                .load::<CaseAction>(c)
+        })
+        .await
+        .map_err(|e| Errors::DatabaseError(e.to_string()))
+    }
 
-        let today = Utc::now().date().naive_utc().and_hms(0, 0, 0);
-        let next_week = today + Duration::days(7);
+    /// Changes the series and its occurrences still waiting to be done. When
+    /// the rule or start changes, those occurrences are replaced by the ones
+    /// of the new rule; ones already started, done or cancelled are kept.
+    pub async fn update(
+        conn: &Db,
+        p_id: Uuid,
+        entity: CaseActionSeriesUpdate,
+        horizon: NaiveDateTime,
+        actor: Uuid,
+    ) -> Result<Self> {
+        use self::case_action_series::dsl::*;
+
+        let p_rule = match entity.rule.is_empty() {
+            true => None,
+            false => Some(entity.rule.rule()?),
+        };
 
-        let mut result = conn
-            .run(move |c| {
-                case_actions
-                    .order(action_date)
-                    .filter(action_date.gt(today))
-                    .filter(action_date.lt(next_week))
-                    .filter(status.lt(ACTION_STATUS_DONE))
-                    .load::<CaseAction>(c)
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match case_action_series
+                    .find(p_id)
+                    .filter(case_id.eq_any(live_case_ids()))
+                    .for_update()
+                    .get_result::<CaseActionSeries>(c)
+                    .optional()?
+                {
+                    Some(before) => before,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+                if before.cancelled_at.is_some() {
+                    return Err(Errors::BadRequest("series is cancelled".into()));
+                }
+                if let Some(p_assignee) = entity.assignee {
+                    check_assignee(c, before.case_id, p_assignee)?;
+                }
+
+                let now = Utc::now().naive_utc();
+                let p_rule = p_rule.unwrap_or_else(|| before.rule());
+                let p_starts_at = entity.starts_at.unwrap_or(before.starts_at);
+                let reschedule = p_rule != before.rule() || p_starts_at != before.starts_at;
+
+                let after = diesel::update(case_action_series.find(p_id))
+                    .set((
+                        action.eq(&entity.action),
+                        assignee.eq(entity.assignee),
+                        starts_at.eq(p_starts_at),
+                        frequency.eq(p_rule.frequency),
+                        repeat_every.eq(p_rule.repeat_every),
+                        repeat_until.eq(p_rule.repeat_until),
+                        occurrence_count.eq(p_rule.occurrence_count),
+                    ))
+                    .get_result::<CaseActionSeries>(c)?;
+                audit::record(c, actor, Operation::Update, Some(&before), Some(&after))?;
+
+                let upcoming = || {
+                    case_actions::table
+                        .filter(case_actions::series_id.eq(p_id))
+                        .filter(case_actions::status.eq(ActionStatus::Todo))
+                        .filter(case_actions::occurrence_at.ge(now))
+                };
+
+                if !reschedule {
+                    for occurrence in upcoming().load::<CaseAction>(c)? {
+                        let changed = diesel::update(case_actions::table.find(occurrence.id))
+                            .set((
+                                case_actions::action.eq(&entity.action),
+                                case_actions::assignee.eq(entity.assignee),
+                            ))
+                            .get_result::<CaseAction>(c)?;
+                        audit::record(
+                            c,
+                            actor,
+                            Operation::Update,
+                            Some(&occurrence),
+                            Some(&changed),
+                        )?;
+                    }
+                    return Ok(after);
+                }
+
+                let removed_ids = upcoming().select(case_actions::id).load::<Uuid>(c)?;
+                let removed = diesel::delete(
+                    case_actions::table.filter(case_actions::id.eq_any(removed_ids)),
+                )
+                .get_results::<CaseAction>(c)?;
+                for occurrence in &removed {
+                    audit::record(c, actor, Operation::Delete, Some(occurrence), None)?;
+                }
+
+                // Occurrences of the new rule are only created from now on.
+                let after = diesel::update(case_action_series.find(p_id))
+                    .set(materialized_until.eq(Some(now)))
+                    .get_result::<CaseActionSeries>(c)?;
+                after
+                    .materialize_in(c, horizon, actor)
+                    .map(|(after, _)| after)
             })
-            .await
-            .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+        })
+        .await
+    }
 
-        result.sort_by(|i, j| i.action_date.cmp(&j.action_date));
-        Ok(result)
+    /// Stops the series and cancels its occurrences that are still open and
+    /// due from now on. Past ones stay as they are.
+    pub async fn cancel(conn: &Db, p_id: Uuid, actor: Uuid) -> Result<Self> {
+        use self::case_action_series::dsl::*;
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = match case_action_series
+                    .find(p_id)
+                    .filter(case_id.eq_any(live_case_ids()))
+                    .for_update()
+                    .get_result::<CaseActionSeries>(c)
+                    .optional()?
+                {
+                    Some(before) => before,
+                    None => return Err(Errors::BadRequest("id not found".to_owned())),
+                };
+                if before.cancelled_at.is_some() {
+                    return Err(Errors::BadRequest("series is already cancelled".into()));
+                }
+
+                let now = Utc::now().naive_utc();
+                let after = diesel::update(case_action_series.find(p_id))
+                    .set(cancelled_at.eq(Some(now)))
+                    .get_result::<CaseActionSeries>(c)?;
+                audit::record(c, actor, Operation::Update, Some(&before), Some(&after))?;
+
+                let open = case_actions::table
+                    .filter(case_actions::series_id.eq(p_id))
+                    .filter(case_actions::status.eq_any(ActionStatus::OPEN.to_vec()))
+                    .filter(case_actions::occurrence_at.ge(now))
+                    .load::<CaseAction>(c)?;
+                for occurrence in open {
+                    let cancelled = diesel::update(case_actions::table.find(occurrence.id))
+                        .set((
+                            case_actions::status.eq(ActionStatus::Cancelled),
+                            case_actions::result_note.eq("series cancelled"),
+                        ))
+                        .get_result::<CaseAction>(c)?;
+                    audit::record(
+                        c,
+                        actor,
+                        Operation::Update,
+                        Some(&occurrence),
+                        Some(&cancelled),
+                    )?;
+                }
+
+                Ok(after)
+            })
+        })
+        .await
     }
 
-    pub async fn week_actions_for_case(conn: &Db, p_case_id: Uuid) -> Result<Vec<CaseAction>> {
-        use self::case_actions::dsl::*;
+    /// Creates the occurrences of every running series up to `horizon`.
+    /// Returns how many were created.
+    pub fn extend_all_in(c: &PgConnection, horizon: NaiveDateTime) -> Result<usize> {
+        use self::case_action_series::dsl::*;
+
+        let running = case_action_series
+            .filter(cancelled_at.is_null())
+            .filter(case_id.eq_any(live_case_ids()))
+            .filter(
+                materialized_until
+                    .is_null()
+                    .or(materialized_until.lt(horizon)),
+            )
+            .load::<CaseActionSeries>(c)?;
+
+        let mut created = 0;
+        for series in running {
+            let (_, count) =
+                c.transaction::<_, Errors, _>(|| series.materialize_in(c, horizon, audit::SYSTEM))?;
+            created += count;
+        }
+        Ok(created)
+    }
 
-        let today = Utc::now().date().naive_utc().and_hms(0, 0, 0);
-        let next_week = today + Duration::days(7);
+    /// Inserts the occurrences between `materialized_until` and `horizon`,
+    /// moves `materialized_until` up to `horizon` and returns how many
+    /// occurrences were inserted.
+    fn materialize_in(
+        self,
+        c: &PgConnection,
+        horizon: NaiveDateTime,
+        actor: impl Into<Option<Uuid>> + Copy,
+    ) -> Result<(Self, usize)> {
+        use self::case_action_series::dsl::*;
+
+        if self.cancelled_at.is_some() {
+            return Ok((self, 0));
+        }
 
-        let mut result = conn
-            .run(move |c| {
-                case_actions
-                    .order(action_date)
-                    .filter(case_id.eq(p_case_id))
-                    .filter(action_date.gt(today))
-                    .filter(action_date.lt(next_week))
-                    .filter(status.lt(ACTION_STATUS_DONE))
-                    .load::<CaseAction>(c)
-            })
-            .await
-            .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+        let due = self
+            .rule()
+            .occurrences(self.starts_at, self.materialized_until, horizon);
+        let mut inserted = 0;
+
+        for occurrence in &due {
+            let created = diesel::insert_into(case_actions::table)
+                .values((
+                    case_actions::id.eq(Uuid::from_u128(rand::random())),
+                    case_actions::case_id.eq(self.case_id),
+                    case_actions::action.eq(&self.action),
+                    case_actions::action_date.eq(Some(*occurrence)),
+                    case_actions::status.eq(ActionStatus::Todo),
+                    case_actions::assignee.eq(self.assignee),
+                    case_actions::series_id.eq(Some(self.id)),
+                    case_actions::occurrence_at.eq(Some(*occurrence)),
+                ))
+                .on_conflict_do_nothing()
+                .get_result::<CaseAction>(c)
+                .optional()?;
+
+            if let Some(created) = created {
+                audit::record(c, actor, Operation::Create, None, Some(&created))?;
+                inserted += 1;
+            }
+        }
 
-        result.sort_by(|i, j| i.action_date.cmp(&j.action_date));
-        Ok(result)
+        // A full batch may have stopped short of the horizon; the next run
+        // carries on from its last occurrence.
+        let reached = match due.last() {
+            Some(last) if due.len() >= MAX_OCCURRENCES => *last,
+            _ => horizon,
+        };
+
+        let after = diesel::update(case_action_series.find(self.id))
+            .set(materialized_until.eq(Some(reached)))
+            .get_result::<CaseActionSeries>(c)?;
+        Ok((after, inserted))
+    }
+}
+
+impl Trash {
+    pub async fn get(conn: &Db) -> Result<Trash> {
+        conn.run(|c| {
+            let cases = cases::table
+                .filter(cases::deleted_at.is_not_null())
+                .order(cases::deleted_at.desc())
+                .load::<Case>(c)?;
+            let persons = persons::table
+                .filter(persons::deleted_at.is_not_null())
+                .order(persons::deleted_at.desc())
+                .load::<Person>(c)?;
+
+            Ok(Trash { cases, persons })
+        })
+        .await
+    }
+
+    /// Permanently removes what was deleted before `deleted_before`, together
+    /// with the rows that cascade from it. Returns how many cases and persons
+    /// were removed. Cases and persons that were given aid stay in the trash,
+    /// so the aid ledger keeps who it was given to.
+    pub fn purge_in(c: &PgConnection, deleted_before: NaiveDateTime) -> Result<usize> {
+        c.transaction::<_, Errors, _>(|| {
+            let recipients = aid_distributions::table
+                .select(aid_distributions::person_id)
+                .filter(aid_distributions::person_id.is_not_null());
+            let persons = diesel::delete(
+                persons::table
+                    .filter(persons::deleted_at.lt(deleted_before))
+                    .filter(diesel::dsl::not(persons::id.nullable().eq_any(recipients))),
+            )
+            .get_results::<Person>(c)?;
+            for person in &persons {
+                audit::record(c, audit::SYSTEM, Operation::Purge, Some(person), None)?;
+            }
+
+            let cases = diesel::delete(
+                cases::table
+                    .filter(cases::deleted_at.lt(deleted_before))
+                    .filter(diesel::dsl::not(cases::id.eq_any(
+                        aid_distributions::table.select(aid_distributions::case_id),
+                    ))),
+            )
+            .get_results::<Case>(c)?;
+            for case in &cases {
+                audit::record(c, audit::SYSTEM, Operation::Purge, Some(case), None)?;
+            }
+
+            Ok(persons.len() + cases.len())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd(year, month, day)
+    }
+
+    fn job(title: &str, start_date: Option<NaiveDate>, end_date: Option<NaiveDate>) -> PersonJob {
+        PersonJob {
+            id: Uuid::from_u128(rand::random()),
+            person_id: Uuid::nil(),
+            title: title.to_owned(),
+            income: None,
+            location: None,
+            start_date,
+            end_date,
+            employment_type: None,
+            income_period: IncomePeriod::default(),
+            monthly_income: None,
+        }
+    }
+
+    fn gaps(jobs: Vec<PersonJob>) -> Vec<EmploymentGap> {
+        timeline(Uuid::nil(), jobs, None, date(2026, 10, 18)).gaps
+    }
+
+    #[test]
+    fn gaps_between_jobs() {
+        assert_eq!(
+            gaps(vec![
+                job("b", Some(date(2020, 9, 1)), None),
+                job("a", Some(date(2020, 1, 1)), Some(date(2020, 6, 30))),
+            ]),
+            vec![EmploymentGap {
+                from: date(2020, 7, 1),
+                to: Some(date(2020, 8, 31)),
+            }]
+        );
+        // A job starting the day after another ends leaves no gap.
+        assert_eq!(
+            gaps(vec![
+                job("a", Some(date(2020, 1, 1)), Some(date(2020, 6, 30))),
+                job("b", Some(date(2020, 7, 1)), None),
+            ]),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn overlapping_jobs() {
+        assert_eq!(
+            gaps(vec![
+                job("a", Some(date(2020, 1, 1)), Some(date(2021, 12, 31))),
+                job("b", Some(date(2020, 6, 1)), Some(date(2020, 12, 31))),
+                job("c", Some(date(2021, 6, 1)), Some(date(2022, 1, 31))),
+                job("d", Some(date(2022, 3, 1)), Some(date(2022, 5, 31))),
+            ]),
+            vec![
+                EmploymentGap {
+                    from: date(2022, 2, 1),
+                    to: Some(date(2022, 2, 28)),
+                },
+                EmploymentGap {
+                    from: date(2022, 6, 1),
+                    to: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn undated_and_future_jobs() {
+        // A job without dates covers no span but still counts as current.
+        assert_eq!(
+            gaps(vec![
+                job("a", Some(date(2020, 1, 1)), Some(date(2020, 6, 30))),
+                job("b", None, None),
+            ]),
+            vec![]
+        );
+        // A job yet to start neither closes the gap nor counts as current.
+        assert_eq!(
+            gaps(vec![
+                job("a", Some(date(2020, 1, 1)), Some(date(2020, 6, 30))),
+                job("b", Some(date(2026, 11, 1)), None),
+            ]),
+            vec![EmploymentGap {
+                from: date(2020, 7, 1),
+                to: None,
+            }]
+        );
+        assert_eq!(gaps(vec![job("a", None, None)]), vec![]);
+    }
+
+    #[test]
+    fn timeline_jobs() {
+        let mut current = job("current", Some(date(2024, 1, 1)), None);
+        current.monthly_income = Some(30_000_000);
+        let mut past = job("past", Some(date(2020, 1, 1)), Some(date(2023, 12, 31)));
+        past.monthly_income = Some(10_000_000);
+        let default = past.id;
+
+        let found = timeline(
+            Uuid::nil(),
+            vec![job("undated", None, None), past, current],
+            Some(default),
+            date(2026, 10, 18),
+        );
+        assert_eq!(found.monthly_income, 30_000_000);
+        let jobs: Vec<(&str, bool, bool)> = found
+            .jobs
+            .iter()
+            .map(|job| (job.job.title.as_str(), job.is_current, job.is_default))
+            .collect();
+        assert_eq!(
+            jobs,
+            vec![
+                ("current", true, false),
+                ("past", false, true),
+                ("undated", true, false),
+            ]
+        );
     }
 }
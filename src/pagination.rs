@@ -0,0 +1,254 @@
+use crate::errors::{self, Errors};
+use chrono::{NaiveDate, NaiveDateTime};
+use rocket::form::{self, FromFormField, ValueField};
+use rocket::serde::Serialize;
+use uuid::Uuid;
+
+pub const DEFAULT_PAGE_LIMIT: i64 = 50;
+pub const MAX_PAGE_LIMIT: i64 = 500;
+
+const CURSOR_SEPARATOR: char = '|';
+const CURSOR_DATETIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+/// One page of a list endpoint. `next_cursor` is `None` on the last page and
+/// `total` counts every row matching the filters, not just this page.
+#[derive(Serialize, Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+    pub total: i64,
+}
+
+impl<T> Page<T> {
+    /// Builds a page out of rows loaded with `limit + 1`; the extra row only
+    /// tells us that another page exists and is dropped.
+    pub fn of(mut items: Vec<T>, limit: i64, total: i64, cursor_of: impl Fn(&T) -> Cursor) -> Self {
+        let has_more = items.len() as i64 > limit;
+        items.truncate(limit as usize);
+
+        let next_cursor = match items.last() {
+            Some(last) if has_more => Some(cursor_of(last).encode()),
+            _ => None,
+        };
+
+        Self {
+            items,
+            next_cursor,
+            total,
+        }
+    }
+}
+
+#[derive(FromFormField, Debug, Clone, Copy, PartialEq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// Sort query parameter, `?sort=key` for ascending and `?sort=-key` for
+/// descending order.
+#[derive(Debug, Clone, Copy)]
+pub struct Sort<K> {
+    pub key: K,
+    pub order: SortOrder,
+}
+
+impl<K> Sort<K> {
+    pub fn asc(key: K) -> Self {
+        Self {
+            key,
+            order: SortOrder::Asc,
+        }
+    }
+
+    pub fn desc(key: K) -> Self {
+        Self {
+            key,
+            order: SortOrder::Desc,
+        }
+    }
+}
+
+impl<'v, K: FromFormField<'v>> FromFormField<'v> for Sort<K> {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        let (order, value) = match field.value.strip_prefix('-') {
+            Some(value) => (SortOrder::Desc, value),
+            None => (SortOrder::Asc, field.value),
+        };
+
+        let key = K::from_value(ValueField { value, ..field })?;
+        Ok(Self { key, order })
+    }
+}
+
+pub struct PageRequest<K> {
+    pub limit: i64,
+    pub cursor: Option<Cursor>,
+    pub sort: Sort<K>,
+}
+
+impl<K> PageRequest<K> {
+    pub fn new(limit: Option<i64>, cursor: Option<String>, sort: Sort<K>) -> errors::Result<Self> {
+        let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+        if !(1..=MAX_PAGE_LIMIT).contains(&limit) {
+            return Err(Errors::BadRequest(format!(
+                "limit must be between 1 and {}",
+                MAX_PAGE_LIMIT
+            )));
+        }
+
+        let cursor = cursor.as_deref().map(Cursor::decode).transpose()?;
+
+        Ok(Self {
+            limit,
+            cursor,
+            sort,
+        })
+    }
+}
+
+/// Position of the last row of a page: the value of the sort column plus the
+/// row id, which breaks ties between rows sharing the same sort value.
+pub struct Cursor {
+    key: String,
+    id: Uuid,
+}
+
+impl Cursor {
+    pub fn new<K: CursorKey>(key: &K, id: Uuid) -> Self {
+        Self {
+            key: key.to_cursor_key(),
+            id,
+        }
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn key<K: CursorKey>(&self) -> errors::Result<K> {
+        K::from_cursor_key(&self.key).ok_or_else(|| Errors::BadRequest("invalid cursor".into()))
+    }
+
+    pub fn encode(&self) -> String {
+        let raw = format!("{}{}{}", self.id, CURSOR_SEPARATOR, self.key);
+        base64::encode_config(raw, base64::URL_SAFE_NO_PAD)
+    }
+
+    pub fn decode(encoded: &str) -> errors::Result<Self> {
+        let invalid = || Errors::BadRequest("invalid cursor".into());
+
+        let raw = base64::decode_config(encoded, base64::URL_SAFE_NO_PAD).map_err(|_| invalid())?;
+        let raw = String::from_utf8(raw).map_err(|_| invalid())?;
+        let (id, key) = raw.split_once(CURSOR_SEPARATOR).ok_or_else(invalid)?;
+
+        Ok(Self {
+            key: key.to_owned(),
+            id: Uuid::parse_str(id).map_err(|_| invalid())?,
+        })
+    }
+}
+
+pub trait CursorKey: Sized {
+    fn to_cursor_key(&self) -> String;
+    fn from_cursor_key(key: &str) -> Option<Self>;
+}
+
+impl CursorKey for i32 {
+    fn to_cursor_key(&self) -> String {
+        self.to_string()
+    }
+
+    fn from_cursor_key(key: &str) -> Option<Self> {
+        key.parse().ok()
+    }
+}
+
+impl CursorKey for String {
+    fn to_cursor_key(&self) -> String {
+        self.clone()
+    }
+
+    fn from_cursor_key(key: &str) -> Option<Self> {
+        Some(key.to_owned())
+    }
+}
+
+impl CursorKey for Uuid {
+    fn to_cursor_key(&self) -> String {
+        self.to_string()
+    }
+
+    fn from_cursor_key(key: &str) -> Option<Self> {
+        Uuid::parse_str(key).ok()
+    }
+}
+
+impl CursorKey for NaiveDate {
+    fn to_cursor_key(&self) -> String {
+        self.to_string()
+    }
+
+    fn from_cursor_key(key: &str) -> Option<Self> {
+        key.parse().ok()
+    }
+}
+
+impl CursorKey for NaiveDateTime {
+    fn to_cursor_key(&self) -> String {
+        self.format(CURSOR_DATETIME_FORMAT).to_string()
+    }
+
+    fn from_cursor_key(key: &str) -> Option<Self> {
+        NaiveDateTime::parse_from_str(key, CURSOR_DATETIME_FORMAT).ok()
+    }
+}
+
+/// `YYYY-MM-DD` date in a query string.
+#[derive(Debug, Clone, Copy)]
+pub struct QueryDate(pub NaiveDate);
+
+impl<'v> FromFormField<'v> for QueryDate {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        NaiveDate::parse_from_str(field.value, "%Y-%m-%d")
+            .map(QueryDate)
+            .map_err(|_| form::Error::validation("expected a YYYY-MM-DD date").into())
+    }
+}
+
+/// Orders a boxed query by `$column` then `$id` in the page's sort order and,
+/// when the page has a cursor, keeps only the rows after it.
+///
+/// Must be used inside a function returning `errors::Result`, as decoding the
+/// cursor key can fail.
+macro_rules! keyset {
+    ($query:expr, $page:expr, $column:expr, $id:expr, $key_type:ty) => {{
+        use $crate::pagination::SortOrder;
+
+        let query = match $page.sort.order {
+            SortOrder::Asc => $query.order(($column.asc(), $id.asc())),
+            SortOrder::Desc => $query.order(($column.desc(), $id.desc())),
+        };
+
+        match &$page.cursor {
+            None => query,
+            Some(cursor) => {
+                let key = cursor.key::<$key_type>()?;
+                let cursor_id = cursor.id();
+
+                match $page.sort.order {
+                    SortOrder::Asc => query.filter(
+                        $column
+                            .gt(key.clone())
+                            .or($column.eq(key).and($id.gt(cursor_id))),
+                    ),
+                    SortOrder::Desc => query.filter(
+                        $column
+                            .lt(key.clone())
+                            .or($column.eq(key).and($id.lt(cursor_id))),
+                    ),
+                }
+            }
+        }
+    }};
+}
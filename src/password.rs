@@ -0,0 +1,138 @@
+use crate::errors::*;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use rocket::serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+use subtle::ConstantTimeEq;
+
+const TEMPORARY_PASSWORD_LENGTH: usize = 16;
+
+/// Argon2id cost, read from the `password_hashing` table in `Rocket.toml`.
+/// Raising any of them makes every older hash be redone on its next login.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct HashingParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for HashingParams {
+    fn default() -> Self {
+        Self {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl HashingParams {
+    pub fn validate(&self) -> Result<()> {
+        self.argon2().map(|_| ())
+    }
+
+    fn argon2(&self) -> Result<Argon2<'static>> {
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+            .map_err(|e| Errors::InternalError(format!("invalid password_hashing: {}", e)))?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+
+    fn matches(&self, hash: &PasswordHash) -> bool {
+        match Params::try_from(hash) {
+            Ok(params) => {
+                hash.algorithm == Algorithm::Argon2id.ident()
+                    && params.m_cost() == self.memory_kib
+                    && params.t_cost() == self.iterations
+                    && params.p_cost() == self.parallelism
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// A password as it is kept in the `users` table.
+pub enum StoredPassword<'a> {
+    /// Argon2 hash in PHC string format.
+    Phc(&'a str),
+    /// SHA-256 of the password followed by the salt, from before Argon2.
+    LegacySha256 { hash: &'a [u8], salt: &'a [u8] },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verification {
+    Invalid,
+    Valid,
+    /// The password is right but is stored in an outdated form and should be
+    /// hashed again.
+    ValidNeedsRehash,
+}
+
+/// Random password handed to a user whose password an admin has reset.
+pub fn temporary() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TEMPORARY_PASSWORD_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+/// Hashes `password` into a PHC string with a fresh random salt.
+pub fn hash(password: &str, params: &HashingParams) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    params
+        .argon2()?
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| Errors::InternalError(format!("cannot hash password: {}", e)))
+}
+
+/// Checks `password` against a stored one. Both kinds are compared in
+/// constant time.
+pub fn verify(password: &str, stored: StoredPassword, params: &HashingParams) -> Verification {
+    match stored {
+        StoredPassword::Phc(phc) => {
+            let hash = match PasswordHash::new(phc) {
+                Ok(hash) => hash,
+                Err(_) => return Verification::Invalid,
+            };
+            if Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_err()
+            {
+                Verification::Invalid
+            } else if params.matches(&hash) {
+                Verification::Valid
+            } else {
+                Verification::ValidNeedsRehash
+            }
+        }
+        StoredPassword::LegacySha256 { hash, salt } => {
+            let mut hasher = Sha256::new();
+            hasher.update(password.as_bytes());
+            hasher.update(salt);
+
+            if bool::from(hasher.finalize().as_slice().ct_eq(hash)) {
+                Verification::ValidNeedsRehash
+            } else {
+                Verification::Invalid
+            }
+        }
+    }
+}
+
+/// Checks `password` against a made-up hash and always fails. Called when
+/// there is no hash to check against, so that an unknown username costs as
+/// much time as a wrong password.
+pub fn verify_dummy(password: &str, params: &HashingParams) -> Verification {
+    static DUMMY: OnceLock<Option<String>> = OnceLock::new();
+
+    if let Some(phc) = DUMMY.get_or_init(|| hash(&temporary(), params).ok()) {
+        verify(password, StoredPassword::Phc(phc), params);
+    }
+    Verification::Invalid
+}
@@ -0,0 +1,843 @@
+use crate::errors::*;
+use crate::persian;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use pdf_writer::types::{CidFontType, FontFlags, SystemInfo, UnicodeCmap};
+use pdf_writer::{Content, Filter, Finish, Name, Pdf, Rect, Ref, Str, TextStr};
+use rustybuzz::ttf_parser::GlyphId;
+use rustybuzz::{Direction, Face, UnicodeBuffer};
+use std::collections::BTreeMap;
+use std::io::Write;
+use unicode_bidi::{bidi_class, BidiClass, BidiInfo, Level};
+
+const REGULAR_FONT: &[u8] = include_bytes!("../fonts/DejaVuSans.ttf");
+const BOLD_FONT: &[u8] = include_bytes!("../fonts/DejaVuSans-Bold.ttf");
+
+/// A4, in points.
+const PAGE_WIDTH: f32 = 595.28;
+const PAGE_HEIGHT: f32 = 841.89;
+const MARGIN: f32 = 48.0;
+/// Room kept at the bottom of every page for its number.
+const FOOTER_HEIGHT: f32 = 20.0;
+const LINE_SPACING: f32 = 1.45;
+const CELL_PADDING: f32 = 4.0;
+const BULLET_INDENT: f32 = 12.0;
+
+const SYSTEM_INFO: SystemInfo = SystemInfo {
+    registry: Str(b"Adobe"),
+    ordering: Str(b"Identity"),
+    supplement: 0,
+};
+
+/// A piece of a document, as written one per line in the markup `parse`
+/// reads.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Block {
+    /// `# text`
+    Title(String),
+    /// `## text`
+    Heading(String),
+    /// `- text`
+    Bullet(String),
+    /// Consecutive `| cell | cell |` lines; the first is the header row.
+    Table(Vec<Vec<String>>),
+    /// `---`
+    Rule,
+    /// `===`
+    PageBreak,
+    /// An empty line.
+    Space,
+    /// Any other line.
+    Paragraph(String),
+}
+
+/// Reads the line-based markup documents are written in. Every line is a
+/// block of its own, so long paragraphs are written on a single line.
+pub fn parse(markup: &str) -> Vec<Block> {
+    let mut blocks: Vec<Block> = Vec::new();
+    for line in markup.lines() {
+        let line = line.trim();
+        let block = if line.is_empty() {
+            match blocks.last() {
+                None | Some(Block::Space) => continue,
+                Some(_) => Block::Space,
+            }
+        } else if line == "===" {
+            Block::PageBreak
+        } else if line == "---" {
+            Block::Rule
+        } else if let Some(text) = line.strip_prefix("## ") {
+            Block::Heading(text.trim().to_owned())
+        } else if let Some(text) = line.strip_prefix("# ") {
+            Block::Title(text.trim().to_owned())
+        } else if let Some(text) = line.strip_prefix("- ") {
+            Block::Bullet(text.trim().to_owned())
+        } else if line.starts_with('|') {
+            let inner = line.strip_prefix('|').unwrap_or(line);
+            let inner = inner.strip_suffix('|').unwrap_or(inner);
+            let row = inner
+                .split('|')
+                .map(|cell| cell.trim().to_owned())
+                .collect();
+            if let Some(Block::Table(rows)) = blocks.last_mut() {
+                rows.push(row);
+                continue;
+            }
+            Block::Table(vec![row])
+        } else {
+            Block::Paragraph(line.to_owned())
+        };
+        blocks.push(block);
+    }
+    while blocks.last() == Some(&Block::Space) {
+        blocks.pop();
+    }
+    blocks
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Weight {
+    Regular,
+    Bold,
+}
+
+#[derive(Clone, Copy)]
+struct Style {
+    weight: Weight,
+    size: f32,
+    gray: f32,
+}
+
+const TITLE: Style = Style {
+    weight: Weight::Bold,
+    size: 16.0,
+    gray: 0.0,
+};
+const HEADING: Style = Style {
+    weight: Weight::Bold,
+    size: 12.5,
+    gray: 0.0,
+};
+const BODY: Style = Style {
+    weight: Weight::Regular,
+    size: 10.0,
+    gray: 0.0,
+};
+const HEADER_CELL: Style = Style {
+    weight: Weight::Bold,
+    size: 9.0,
+    gray: 0.0,
+};
+const CELL: Style = Style {
+    weight: Weight::Regular,
+    size: 9.0,
+    gray: 0.0,
+};
+const FOOTER: Style = Style {
+    weight: Weight::Regular,
+    size: 8.0,
+    gray: 0.4,
+};
+
+impl Style {
+    fn line_height(self) -> f32 {
+        self.size * LINE_SPACING
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Align {
+    Start,
+    Center,
+}
+
+struct Glyph {
+    id: u16,
+    /// In thousandths of the font size, as PDF measures glyphs.
+    advance: f32,
+    offset: f32,
+}
+
+struct Font {
+    face: Face<'static>,
+    data: &'static [u8],
+    base_name: &'static [u8],
+    resource: &'static [u8],
+    /// Glyphs drawn so far, with the text they stand for.
+    used: BTreeMap<u16, String>,
+}
+
+impl Font {
+    fn new(data: &'static [u8], base_name: &'static [u8], resource: &'static [u8]) -> Result<Self> {
+        let face = Face::from_slice(data, 0)
+            .ok_or_else(|| Errors::InternalError("cannot read the embedded font".to_owned()))?;
+        Ok(Font {
+            face,
+            data,
+            base_name,
+            resource,
+            used: BTreeMap::new(),
+        })
+    }
+
+    fn scale(&self, units: i32) -> f32 {
+        units as f32 * 1000.0 / self.face.units_per_em() as f32
+    }
+
+    /// Shapes a run of a single direction, its glyphs in the order they are
+    /// drawn from left to right.
+    fn shape(&self, text: &str, rtl: bool) -> Vec<(Glyph, usize)> {
+        let mut buffer = UnicodeBuffer::new();
+        buffer.push_str(text);
+        buffer.set_direction(match rtl {
+            true => Direction::RightToLeft,
+            false => Direction::LeftToRight,
+        });
+        let shaped = rustybuzz::shape(&self.face, &[], buffer);
+        shaped
+            .glyph_infos()
+            .iter()
+            .zip(shaped.glyph_positions())
+            .map(|(info, position)| {
+                let glyph = Glyph {
+                    id: info.glyph_id as u16,
+                    advance: self.scale(position.x_advance),
+                    offset: self.scale(position.x_offset),
+                };
+                (glyph, info.cluster as usize)
+            })
+            .collect()
+    }
+
+    /// Width of a run in points.
+    fn measure(&self, text: &str, size: f32) -> f32 {
+        let rtl = text.chars().any(is_rtl);
+        let width: f32 = self
+            .shape(text, rtl)
+            .iter()
+            .map(|(glyph, _)| glyph.advance)
+            .sum();
+        width * size / 1000.0
+    }
+
+    /// Notes which text each glyph of `shaped` stands for, so that the
+    /// document can be searched and copied from.
+    fn record(&mut self, text: &str, shaped: &[(Glyph, usize)]) {
+        let mut clusters: Vec<usize> = shaped.iter().map(|(_, cluster)| *cluster).collect();
+        clusters.sort_unstable();
+        clusters.dedup();
+        for (glyph, cluster) in shaped {
+            if self.used.contains_key(&glyph.id) {
+                continue;
+            }
+            let end = clusters
+                .iter()
+                .find(|other| **other > *cluster)
+                .copied()
+                .unwrap_or(text.len());
+            let chars = text.get(*cluster..end).unwrap_or("").to_owned();
+            self.used.insert(glyph.id, chars);
+        }
+    }
+
+    fn width_of(&self, id: u16) -> f32 {
+        let advance = self.face.glyph_hor_advance(GlyphId(id)).unwrap_or(0);
+        self.scale(i32::from(advance))
+    }
+}
+
+fn is_rtl(c: char) -> bool {
+    matches!(bidi_class(c), BidiClass::R | BidiClass::AL)
+}
+
+/// Whether the first letter of text is right to left, if it has letters.
+fn first_letter_rtl(text: &str) -> Option<bool> {
+    text.chars().find_map(|c| match bidi_class(c) {
+        BidiClass::R | BidiClass::AL => Some(true),
+        BidiClass::L => Some(false),
+        _ => None,
+    })
+}
+
+/// Whether text is aligned as right to left: it is when its first letter is
+/// Persian, or when it has no letters at all, as the documents are.
+fn reads_rtl(text: &str) -> bool {
+    first_letter_rtl(text).unwrap_or(true)
+}
+
+struct Layout {
+    fonts: [Font; 2],
+    pages: Vec<Content>,
+    y: f32,
+}
+
+impl Layout {
+    fn font(&self, weight: Weight) -> &Font {
+        &self.fonts[weight as usize]
+    }
+
+    fn content(&mut self) -> &mut Content {
+        if self.pages.is_empty() {
+            self.new_page();
+        }
+        let last = self.pages.len() - 1;
+        &mut self.pages[last]
+    }
+
+    fn new_page(&mut self) {
+        self.pages.push(Content::new());
+        self.y = PAGE_HEIGHT - MARGIN;
+    }
+
+    /// Starts a new page unless `height` still fits on this one.
+    fn ensure(&mut self, height: f32) -> bool {
+        if self.pages.is_empty() || self.y - height < MARGIN + FOOTER_HEIGHT {
+            self.new_page();
+            return true;
+        }
+        false
+    }
+
+    /// Splits text into lines no wider than `width`, breaking between words,
+    /// or inside a word that is too wide on its own.
+    fn wrap(&self, text: &str, style: Style, width: f32) -> Vec<String> {
+        let font = self.font(style.weight);
+        let fits = |line: &str| font.measure(line, style.size) <= width;
+
+        let mut lines = Vec::new();
+        let mut line = String::new();
+        for word in text.split_whitespace() {
+            let candidate = match line.is_empty() {
+                true => word.to_owned(),
+                false => format!("{} {}", line, word),
+            };
+            if fits(&candidate) {
+                line = candidate;
+                continue;
+            }
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+            for c in word.chars() {
+                line.push(c);
+                if !fits(&line) && line.chars().count() > 1 {
+                    line.pop();
+                    lines.push(std::mem::replace(&mut line, c.to_string()));
+                }
+            }
+        }
+        if !line.is_empty() || lines.is_empty() {
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// Draws one line with its baseline at `y`, between `left` and `right`.
+    /// Right-to-left lines are aligned to the right.
+    fn draw_line(&mut self, text: &str, style: Style, left: f32, right: f32, y: f32, align: Align) {
+        let rtl = reads_rtl(text);
+        // Lines of numbers alone, such as phone numbers, are laid out left
+        // to right so that their signs stay in place.
+        let level = match first_letter_rtl(text) {
+            Some(true) => Level::rtl(),
+            _ => Level::ltr(),
+        };
+        let bidi = BidiInfo::new(text, Some(level));
+        let runs = match bidi.paragraphs.first() {
+            Some(paragraph) => bidi.visual_runs(paragraph, paragraph.range.clone()),
+            None => return,
+        };
+
+        let font = &mut self.fonts[style.weight as usize];
+        let mut shaped = Vec::new();
+        for run in runs.1 {
+            let run_text = &text[run.clone()];
+            let glyphs = font.shape(run_text, runs.0[run.start].is_rtl());
+            font.record(run_text, &glyphs);
+            shaped.extend(glyphs.into_iter().map(|(glyph, _)| glyph));
+        }
+        let width: f32 =
+            shaped.iter().map(|glyph| glyph.advance).sum::<f32>() * style.size / 1000.0;
+        let x = match (align, rtl) {
+            (Align::Center, _) => (left + right - width) / 2.0,
+            (Align::Start, true) => right - width,
+            (Align::Start, false) => left,
+        };
+
+        let widths: Vec<f32> = shaped.iter().map(|glyph| font.width_of(glyph.id)).collect();
+        let resource = font.resource;
+        let content = self.content();
+        content.begin_text();
+        content.set_font(Name(resource), style.size);
+        content.set_fill_gray(style.gray);
+        content.set_text_matrix([1.0, 0.0, 0.0, 1.0, x, y]);
+        let mut positioned = content.show_positioned();
+        let mut items = positioned.items();
+        for (glyph, width) in shaped.iter().zip(widths) {
+            if glyph.offset != 0.0 {
+                items.adjust(-glyph.offset);
+            }
+            items.show(Str(&glyph.id.to_be_bytes()));
+            let rest = glyph.advance - glyph.offset - width;
+            if rest.abs() > 0.01 {
+                items.adjust(-rest);
+            }
+        }
+        items.finish();
+        positioned.finish();
+        content.end_text();
+    }
+
+    fn text(&mut self, text: &str, style: Style, left: f32, right: f32, align: Align) {
+        for line in self.wrap(text, style, right - left) {
+            self.ensure(style.line_height());
+            self.y -= style.line_height();
+            let baseline = self.y + (style.line_height() - style.size) / 2.0 + style.size * 0.2;
+            self.draw_line(&line, style, left, right, baseline, align);
+        }
+    }
+
+    fn rule(&mut self, gray: f32, width: f32) {
+        let y = self.y;
+        let content = self.content();
+        content.set_stroke_gray(gray);
+        content.set_line_width(width);
+        content.move_to(MARGIN, y);
+        content.line_to(PAGE_WIDTH - MARGIN, y);
+        content.stroke();
+    }
+
+    fn bullet(&mut self, text: &str) {
+        let rtl = reads_rtl(text);
+        let (left, right) = match rtl {
+            true => (MARGIN, PAGE_WIDTH - MARGIN - BULLET_INDENT),
+            false => (MARGIN + BULLET_INDENT, PAGE_WIDTH - MARGIN),
+        };
+        self.ensure(BODY.line_height());
+        let baseline =
+            self.y - BODY.line_height() + (BODY.line_height() - BODY.size) / 2.0 + BODY.size * 0.2;
+        let (bullet_left, bullet_right) = match rtl {
+            true => (right, PAGE_WIDTH - MARGIN),
+            false => (MARGIN, left),
+        };
+        self.draw_line(
+            "•",
+            BODY,
+            bullet_left,
+            bullet_right,
+            baseline,
+            Align::Center,
+        );
+        self.text(text, BODY, left, right, Align::Start);
+    }
+
+    fn table(&mut self, rows: &[Vec<String>]) {
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        if columns == 0 {
+            return;
+        }
+        let rtl = rows[0]
+            .iter()
+            .any(|cell| reads_rtl(cell) && !cell.is_empty());
+        let available = PAGE_WIDTH - 2.0 * MARGIN;
+
+        let mut natural = vec![0.0_f32; columns];
+        let mut narrowest = vec![0.0_f32; columns];
+        for (index, row) in rows.iter().enumerate() {
+            let style = if index == 0 { HEADER_CELL } else { CELL };
+            let font = self.font(style.weight);
+            for (column, cell) in row.iter().enumerate() {
+                let padded = |width: f32| (width + 2.0 * CELL_PADDING).min(available);
+                natural[column] = natural[column].max(padded(font.measure(cell, style.size)));
+                let word = cell
+                    .split_whitespace()
+                    .map(|word| font.measure(word, style.size))
+                    .fold(0.0, f32::max);
+                narrowest[column] = narrowest[column].max(padded(word));
+            }
+        }
+        let widths = column_widths(&natural, &narrowest, available);
+
+        let mut header_drawn_on = None;
+        for (index, row) in rows.iter().enumerate() {
+            let style = if index == 0 { HEADER_CELL } else { CELL };
+            let lines: Vec<Vec<String>> = (0..columns)
+                .map(|column| {
+                    let cell = row.get(column).map(String::as_str).unwrap_or("");
+                    self.wrap(cell, style, widths[column] - 2.0 * CELL_PADDING)
+                })
+                .collect();
+            let height = lines.iter().map(Vec::len).max().unwrap_or(1) as f32 * style.line_height()
+                + 2.0 * CELL_PADDING;
+
+            if self.ensure(height) && index > 0 && header_drawn_on != Some(self.pages.len()) {
+                let header = &rows[0];
+                let header_lines: Vec<Vec<String>> = (0..columns)
+                    .map(|column| {
+                        let cell = header.get(column).map(String::as_str).unwrap_or("");
+                        self.wrap(cell, HEADER_CELL, widths[column] - 2.0 * CELL_PADDING)
+                    })
+                    .collect();
+                let header_height = header_lines.iter().map(Vec::len).max().unwrap_or(1) as f32
+                    * HEADER_CELL.line_height()
+                    + 2.0 * CELL_PADDING;
+                self.row(
+                    &header_lines,
+                    &widths,
+                    HEADER_CELL,
+                    header_height,
+                    rtl,
+                    true,
+                );
+            }
+            if index == 0 {
+                header_drawn_on = Some(self.pages.len());
+            }
+            self.row(&lines, &widths, style, height, rtl, index == 0);
+        }
+    }
+
+    fn row(
+        &mut self,
+        lines: &[Vec<String>],
+        widths: &[f32],
+        style: Style,
+        height: f32,
+        rtl: bool,
+        header: bool,
+    ) {
+        let top = self.y;
+        let bottom = top - height;
+        let mut edge = match rtl {
+            true => PAGE_WIDTH - MARGIN,
+            false => MARGIN,
+        };
+        for (cell, width) in lines.iter().zip(widths) {
+            let (left, right) = match rtl {
+                true => (edge - width, edge),
+                false => (edge, edge + width),
+            };
+            edge = match rtl {
+                true => left,
+                false => right,
+            };
+
+            let content = self.content();
+            if header {
+                content.set_fill_gray(0.92);
+                content.rect(left, bottom, *width, height);
+                content.fill_nonzero();
+            }
+            content.set_stroke_gray(0.6);
+            content.set_line_width(0.5);
+            content.rect(left, bottom, *width, height);
+            content.stroke();
+
+            for (number, line) in cell.iter().enumerate() {
+                let line_top = top - CELL_PADDING - number as f32 * style.line_height();
+                let baseline = line_top - style.line_height()
+                    + (style.line_height() - style.size) / 2.0
+                    + style.size * 0.2;
+                self.draw_line(
+                    line,
+                    style,
+                    left + CELL_PADDING,
+                    right - CELL_PADDING,
+                    baseline,
+                    Align::Start,
+                );
+            }
+        }
+        self.y = bottom;
+    }
+
+    fn footer(&mut self) {
+        let count = self.pages.len();
+        let last = std::mem::take(&mut self.pages);
+        for (index, page) in last.into_iter().enumerate() {
+            self.pages.push(page);
+            let label = persian::to_persian_digits(&format!("صفحه {} از {}", index + 1, count));
+            self.draw_line(
+                &label,
+                FOOTER,
+                MARGIN,
+                PAGE_WIDTH - MARGIN,
+                MARGIN / 2.0,
+                Align::Center,
+            );
+        }
+    }
+}
+
+/// Widths that fill `available`: natural widths when they fit, otherwise
+/// as much of them as fits without going below the widest word.
+fn column_widths(natural: &[f32], narrowest: &[f32], available: f32) -> Vec<f32> {
+    let total: f32 = natural.iter().sum();
+    if total <= available {
+        let extra = (available - total) / natural.len() as f32;
+        return natural.iter().map(|width| width + extra).collect();
+    }
+    let minimum: f32 = narrowest.iter().sum();
+    if minimum >= available {
+        return narrowest
+            .iter()
+            .map(|width| width * available / minimum)
+            .collect();
+    }
+    let share = (available - minimum) / (total - minimum);
+    natural
+        .iter()
+        .zip(narrowest)
+        .map(|(natural, narrowest)| narrowest + (natural - narrowest) * share)
+        .collect()
+}
+
+fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Lays the blocks out on A4 pages, right to left where the text is
+/// Persian, with page numbers at the bottom. The fonts are embedded with
+/// only the glyphs the document uses.
+pub fn render(blocks: &[Block], title: &str) -> Result<Vec<u8>> {
+    let mut layout = Layout {
+        fonts: [
+            Font::new(REGULAR_FONT, b"DJVREG+DejaVuSans", b"F1")?,
+            Font::new(BOLD_FONT, b"DJVBLD+DejaVuSans-Bold", b"F2")?,
+        ],
+        pages: Vec::new(),
+        y: PAGE_HEIGHT - MARGIN,
+    };
+    let (left, right) = (MARGIN, PAGE_WIDTH - MARGIN);
+    for block in blocks {
+        match block {
+            Block::Title(text) => layout.text(text, TITLE, left, right, Align::Center),
+            Block::Heading(text) => {
+                layout.ensure(HEADING.line_height() * 2.0);
+                layout.y -= HEADING.size * 0.5;
+                layout.text(text, HEADING, left, right, Align::Start);
+                layout.rule(0.5, 0.6);
+                layout.y -= 3.0;
+            }
+            Block::Paragraph(text) => layout.text(text, BODY, left, right, Align::Start),
+            Block::Bullet(text) => layout.bullet(text),
+            Block::Table(rows) => layout.table(rows),
+            Block::Rule => {
+                layout.ensure(BODY.size);
+                layout.y -= BODY.size / 2.0;
+                layout.rule(0.7, 0.5);
+                layout.y -= BODY.size / 2.0;
+            }
+            Block::Space => {
+                if !layout.ensure(BODY.size) {
+                    layout.y -= BODY.size * 0.8;
+                }
+            }
+            Block::PageBreak => layout.new_page(),
+        }
+    }
+    if layout.pages.is_empty() {
+        layout.new_page();
+    }
+    layout.footer();
+
+    write(layout, title)
+}
+
+fn write(layout: Layout, title: &str) -> Result<Vec<u8>> {
+    let mut next = 0;
+    let mut reference = || {
+        next += 1;
+        Ref::new(next)
+    };
+    let catalog_id = reference();
+    let tree_id = reference();
+    let info_id = reference();
+
+    let mut pdf = Pdf::new();
+    let mut font_ids = Vec::new();
+    for font in &layout.fonts {
+        let ids = [
+            reference(),
+            reference(),
+            reference(),
+            reference(),
+            reference(),
+        ];
+        write_font(&mut pdf, font, ids)?;
+        font_ids.push((font.resource, ids[0]));
+    }
+
+    let mut page_ids = Vec::new();
+    for page in layout.pages {
+        let page_id = reference();
+        let content_id = reference();
+        let mut writer = pdf.page(page_id);
+        writer.media_box(Rect::new(0.0, 0.0, PAGE_WIDTH, PAGE_HEIGHT));
+        writer.parent(tree_id);
+        writer.contents(content_id);
+        let mut resources = writer.resources();
+        let mut fonts = resources.fonts();
+        for (resource, id) in &font_ids {
+            fonts.pair(Name(resource), *id);
+        }
+        fonts.finish();
+        resources.finish();
+        writer.finish();
+
+        let data = compress(&page.finish())?;
+        pdf.stream(content_id, &data).filter(Filter::FlateDecode);
+        page_ids.push(page_id);
+    }
+
+    pdf.catalog(catalog_id).pages(tree_id);
+    let count = page_ids.len() as i32;
+    pdf.pages(tree_id).kids(page_ids).count(count);
+    pdf.document_info(info_id)
+        .title(TextStr(title))
+        .producer(TextStr("form-website"));
+    Ok(pdf.finish())
+}
+
+/// Writes `font` as a Type 0 font with an embedded subset, under ids for
+/// the font, its CID font, descriptor, font file and ToUnicode map.
+fn write_font(pdf: &mut Pdf, font: &Font, ids: [Ref; 5]) -> Result<()> {
+    let [font_id, cid_id, descriptor_id, file_id, cmap_id] = ids;
+    let face = &font.face;
+
+    pdf.type0_font(font_id)
+        .base_font(Name(font.base_name))
+        .encoding_predefined(Name(b"Identity-H"))
+        .descendant_font(cid_id)
+        .to_unicode(cmap_id);
+
+    let mut cid = pdf.cid_font(cid_id);
+    cid.subtype(CidFontType::Type2)
+        .base_font(Name(font.base_name))
+        .system_info(SYSTEM_INFO)
+        .font_descriptor(descriptor_id)
+        .default_width(0.0)
+        .cid_to_gid_map_predefined(Name(b"Identity"));
+    let mut widths = cid.widths();
+    for id in font.used.keys() {
+        widths.consecutive(*id, [font.width_of(*id)]);
+    }
+    widths.finish();
+    cid.finish();
+
+    let bbox = face.global_bounding_box();
+    pdf.font_descriptor(descriptor_id)
+        .name(Name(font.base_name))
+        .flags(FontFlags::SYMBOLIC)
+        .bbox(Rect::new(
+            font.scale(i32::from(bbox.x_min)),
+            font.scale(i32::from(bbox.y_min)),
+            font.scale(i32::from(bbox.x_max)),
+            font.scale(i32::from(bbox.y_max)),
+        ))
+        .italic_angle(0.0)
+        .ascent(font.scale(i32::from(face.ascender())))
+        .descent(font.scale(i32::from(face.descender())))
+        .cap_height(font.scale(i32::from(face.capital_height().unwrap_or(face.ascender()))))
+        .stem_v(80.0)
+        .font_file2(file_id);
+
+    let mut glyphs: Vec<u16> = font.used.keys().copied().collect();
+    glyphs.push(0);
+    let subset = subsetter::subset(font.data, 0, subsetter::Profile::pdf(&glyphs))
+        .map_err(|e| Errors::InternalError(format!("cannot subset the font: {}", e)))?;
+    pdf.stream(file_id, &compress(&subset)?)
+        .filter(Filter::FlateDecode);
+
+    let mut cmap = UnicodeCmap::new(Name(b"Custom"), SYSTEM_INFO);
+    for (id, text) in &font.used {
+        if !text.is_empty() {
+            cmap.pair_with_multiple(*id, text.chars());
+        }
+    }
+    pdf.stream(cmap_id, &cmap.finish());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cells(row: &[&str]) -> Vec<String> {
+        row.iter().map(|cell| cell.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_blocks() {
+        let markup = "
+            # پرونده ۱۲
+            ## اعضا
+
+            - یک
+            | نام | سن |
+            |علی|۳۰|
+            ---
+
+
+            متن
+            ===
+            |تنها|
+
+        ";
+        assert_eq!(
+            parse(markup),
+            vec![
+                Block::Title("پرونده ۱۲".to_owned()),
+                Block::Heading("اعضا".to_owned()),
+                Block::Space,
+                Block::Bullet("یک".to_owned()),
+                Block::Table(vec![cells(&["نام", "سن"]), cells(&["علی", "۳۰"])]),
+                Block::Rule,
+                Block::Space,
+                Block::Paragraph("متن".to_owned()),
+                Block::PageBreak,
+                Block::Table(vec![cells(&["تنها"])]),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_separates_tables() {
+        assert_eq!(
+            parse("| a |\n\n| b |\n#no space\n-no space"),
+            vec![
+                Block::Table(vec![cells(&["a"])]),
+                Block::Space,
+                Block::Table(vec![cells(&["b"])]),
+                Block::Paragraph("#no space".to_owned()),
+                Block::Paragraph("-no space".to_owned()),
+            ]
+        );
+        assert_eq!(parse("\n\n"), vec![]);
+    }
+
+    #[test]
+    fn widths_that_fit_share_the_rest() {
+        assert_eq!(
+            column_widths(&[100.0, 50.0], &[20.0, 10.0], 200.0),
+            vec![125.0, 75.0]
+        );
+    }
+
+    #[test]
+    fn widths_shrink_towards_the_widest_words() {
+        let widths = column_widths(&[300.0, 100.0], &[50.0, 50.0], 250.0);
+        assert_eq!(widths, vec![175.0, 75.0]);
+        assert_eq!(widths.iter().sum::<f32>(), 250.0);
+    }
+
+    #[test]
+    fn widths_below_the_widest_words_scale_them() {
+        assert_eq!(
+            column_widths(&[300.0, 100.0], &[120.0, 80.0], 100.0),
+            vec![60.0, 40.0]
+        );
+    }
+}
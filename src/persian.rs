@@ -0,0 +1,45 @@
+const ZERO_WIDTH_NON_JOINER: char = '\u{200c}';
+const TATWEEL: char = '\u{0640}';
+
+/// Folds text typed on Arabic and Persian keyboard layouts into one form:
+/// Arabic yeh and kaf become the Persian letters, digits become ASCII,
+/// zero-width non-joiners become spaces and tatweel and diacritics are
+/// dropped. Mirrors the `persian_normalize` SQL function.
+pub fn normalize(text: &str) -> String {
+    text.chars()
+        .filter_map(|c| match c {
+            'ي' | 'ى' => Some('ی'),
+            'ك' => Some('ک'),
+            ZERO_WIDTH_NON_JOINER => Some(' '),
+            TATWEEL | '\u{064b}'..='\u{0652}' => None,
+            c => Some(normalize_digit(c)),
+        })
+        .collect()
+}
+
+/// Maps Persian (`۰`-`۹`) and Arabic-Indic (`٠`-`٩`) digits to ASCII and
+/// leaves every other character untouched.
+pub fn normalize_digits(text: &str) -> String {
+    text.chars().map(normalize_digit).collect()
+}
+
+fn normalize_digit(c: char) -> char {
+    let zero = match c {
+        '۰'..='۹' => '۰',
+        '٠'..='٩' => '٠',
+        _ => return c,
+    };
+
+    char::from(b'0' + (c as u32 - zero as u32) as u8)
+}
+
+/// Writes ASCII digits as Persian ones, for text meant to be read rather
+/// than parsed.
+pub fn to_persian_digits(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '0'..='9' => char::from_u32('۰' as u32 + (c as u32 - '0' as u32)).unwrap_or(c),
+            c => c,
+        })
+        .collect()
+}
@@ -0,0 +1,410 @@
+use crate::enums::Frequency;
+use crate::errors;
+use crate::validation::{FieldResult, Validator};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use rocket::serde::Deserialize;
+
+/// Upper bound on the occurrences generated in one go, so a daily rule with
+/// a start far in the past cannot flood the table.
+pub const MAX_OCCURRENCES: usize = 1000;
+
+const MAX_REPEAT_EVERY: i32 = 366;
+
+/// How a series of case actions repeats: every `repeat_every` days, weeks or
+/// months from its start, until a date or for a number of occurrences. This
+/// is the part of an RFC 5545 RRULE we support.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rule {
+    pub frequency: Frequency,
+    pub repeat_every: i32,
+    pub repeat_until: Option<NaiveDateTime>,
+    pub occurrence_count: Option<i32>,
+}
+
+impl Rule {
+    /// Reads an RRULE such as `FREQ=MONTHLY;INTERVAL=3;COUNT=4`, with or
+    /// without the `RRULE:` prefix. Only FREQ (DAILY, WEEKLY or MONTHLY),
+    /// INTERVAL, COUNT and UNTIL are understood.
+    pub fn parse(rrule: &str) -> FieldResult<Rule> {
+        let invalid = |message: String| ("invalid_rrule", message);
+
+        let rrule = rrule.trim();
+        let rrule = rrule.strip_prefix("RRULE:").unwrap_or(rrule);
+
+        let mut frequency = None;
+        let mut repeat_every = 1;
+        let mut repeat_until = None;
+        let mut occurrence_count = None;
+
+        for part in rrule.split(';').filter(|part| !part.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("expected KEY=VALUE, found {}", part)))?;
+
+            match key.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    frequency = Some(match value.to_ascii_uppercase().as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        _ => return Err(invalid(format!("unsupported FREQ {}", value))),
+                    })
+                }
+                "INTERVAL" => {
+                    repeat_every = value
+                        .parse()
+                        .map_err(|_| invalid("INTERVAL must be a number".to_owned()))?
+                }
+                "COUNT" => {
+                    occurrence_count = Some(
+                        value
+                            .parse()
+                            .map_err(|_| invalid("COUNT must be a number".to_owned()))?,
+                    )
+                }
+                "UNTIL" => {
+                    repeat_until = Some(
+                        parse_until(value)
+                            .ok_or_else(|| invalid(format!("invalid UNTIL {}", value)))?,
+                    )
+                }
+                _ => return Err(invalid(format!("unsupported rule part {}", key))),
+            }
+        }
+
+        let rule = Rule {
+            frequency: frequency.ok_or_else(|| invalid("FREQ is required".to_owned()))?,
+            repeat_every,
+            repeat_until,
+            occurrence_count,
+        };
+        rule.check()?;
+        Ok(rule)
+    }
+
+    pub fn to_rrule(self) -> String {
+        let frequency = match self.frequency {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+        };
+
+        let mut rrule = format!("FREQ={};INTERVAL={}", frequency, self.repeat_every);
+        if let Some(count) = self.occurrence_count {
+            rrule.push_str(&format!(";COUNT={}", count));
+        }
+        if let Some(until) = self.repeat_until {
+            rrule.push_str(&format!(";UNTIL={}", until.format("%Y%m%dT%H%M%S")));
+        }
+        rrule
+    }
+
+    fn check(&self) -> FieldResult<()> {
+        if !(1..=MAX_REPEAT_EVERY).contains(&self.repeat_every) {
+            return Err((
+                "out_of_range",
+                format!("interval must be between 1 and {}", MAX_REPEAT_EVERY),
+            ));
+        }
+        if matches!(self.occurrence_count, Some(count) if count < 1) {
+            return Err(("out_of_range", "count must be at least 1".to_owned()));
+        }
+        if self.repeat_until.is_some() && self.occurrence_count.is_some() {
+            return Err((
+                "conflict",
+                "a rule cannot have both an end date and a count".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The `n`th occurrence of a series starting at `start`, counting from
+    /// zero. Monthly series starting late in a month fall on the last day of
+    /// shorter months.
+    fn nth(&self, start: NaiveDateTime, n: i32) -> Option<NaiveDateTime> {
+        let steps = i64::from(n) * i64::from(self.repeat_every);
+        match self.frequency {
+            Frequency::Daily => start.checked_add_signed(Duration::days(steps)),
+            Frequency::Weekly => start.checked_add_signed(Duration::weeks(steps)),
+            Frequency::Monthly => {
+                let months = i64::from(start.month0()) + steps;
+                let year = start.year() + i32::try_from(months / 12).ok()?;
+                let month = (months % 12) as u32 + 1;
+                let day = start.day().min(days_in_month(year, month)?);
+                NaiveDate::from_ymd_opt(year, month, day).map(|date| date.and_time(start.time()))
+            }
+        }
+    }
+
+    /// Occurrences of a series starting at `start` that fall after `after`
+    /// (when given) and no later than `to`.
+    pub fn occurrences(
+        &self,
+        start: NaiveDateTime,
+        after: Option<NaiveDateTime>,
+        to: NaiveDateTime,
+    ) -> Vec<NaiveDateTime> {
+        let mut found = Vec::new();
+        let mut n = 0;
+
+        while found.len() < MAX_OCCURRENCES {
+            if matches!(self.occurrence_count, Some(count) if n >= count) {
+                break;
+            }
+            let occurrence = match self.nth(start, n) {
+                Some(occurrence) => occurrence,
+                None => break,
+            };
+            if occurrence > to || matches!(self.repeat_until, Some(until) if occurrence > until) {
+                break;
+            }
+            if !matches!(after, Some(after) if occurrence <= after) {
+                found.push(occurrence);
+            }
+            n += 1;
+        }
+
+        found
+    }
+}
+
+fn days_in_month(year: i32, month: u32) -> Option<u32> {
+    let (next_year, next_month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+    let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+    let next = NaiveDate::from_ymd_opt(next_year, next_month, 1)?;
+    Some((next - first).num_days() as u32)
+}
+
+/// UNTIL as a date (`20270101`) or a date-time (`20270101T120000`, with an
+/// optional `Z`).
+fn parse_until(value: &str) -> Option<NaiveDateTime> {
+    let value = value.trim_end_matches('Z');
+    NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y%m%d")
+                .ok()
+                .map(|date| date.and_hms(23, 59, 59))
+        })
+}
+
+/// Recurrence as sent by clients: either an `rrule` string or the rule's
+/// fields spelled out.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct RuleRequest {
+    #[serde(default)]
+    pub rrule: Option<String>,
+    #[serde(default)]
+    pub frequency: Option<Frequency>,
+    #[serde(default)]
+    pub repeat_every: Option<i32>,
+    #[serde(default)]
+    pub repeat_until: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub occurrence_count: Option<i32>,
+}
+
+impl RuleRequest {
+    pub fn is_empty(&self) -> bool {
+        self.rrule.is_none()
+            && self.frequency.is_none()
+            && self.repeat_every.is_none()
+            && self.repeat_until.is_none()
+            && self.occurrence_count.is_none()
+    }
+
+    pub fn rule(&self) -> errors::Result<Rule> {
+        let mut validator = Validator::new();
+
+        let rule = match (&self.rrule, self.frequency) {
+            (Some(_), Some(_)) => validator.check(
+                "rrule",
+                Err((
+                    "conflict",
+                    "give either rrule or frequency, not both".to_owned(),
+                )),
+            ),
+            (Some(rrule), None) => validator.check("rrule", Rule::parse(rrule)),
+            (None, Some(frequency)) => {
+                let rule = Rule {
+                    frequency,
+                    repeat_every: self.repeat_every.unwrap_or(1),
+                    repeat_until: self.repeat_until,
+                    occurrence_count: self.occurrence_count,
+                };
+                validator.check("frequency", rule.check().map(|_| rule))
+            }
+            (None, None) => validator.check(
+                "frequency",
+                Err(("required", "give rrule or frequency".to_owned())),
+            ),
+        };
+
+        validator.finish()?;
+        rule.ok_or_else(|| errors::Errors::InternalError("rule was not checked".into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(year: i32, month: u32, day: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(year, month, day).and_hms(9, 30, 0)
+    }
+
+    fn rule(frequency: Frequency, repeat_every: i32) -> Rule {
+        Rule {
+            frequency,
+            repeat_every,
+            repeat_until: None,
+            occurrence_count: None,
+        }
+    }
+
+    fn code(rrule: &str) -> &'static str {
+        Rule::parse(rrule).unwrap_err().0
+    }
+
+    #[test]
+    fn parse_forms() {
+        let quarterly = Ok(Rule {
+            occurrence_count: Some(4),
+            ..rule(Frequency::Monthly, 3)
+        });
+        assert_eq!(Rule::parse("FREQ=MONTHLY;INTERVAL=3;COUNT=4"), quarterly);
+        assert_eq!(
+            Rule::parse("RRULE:FREQ=MONTHLY;INTERVAL=3;COUNT=4"),
+            quarterly
+        );
+        assert_eq!(Rule::parse(" freq=monthly;Interval=3;count=4; "), quarterly);
+        assert_eq!(Rule::parse("FREQ=DAILY"), Ok(rule(Frequency::Daily, 1)));
+    }
+
+    #[test]
+    fn parse_until_date_or_date_time() {
+        // A date alone runs to the end of that day.
+        assert_eq!(
+            Rule::parse("FREQ=WEEKLY;UNTIL=20260331").map(|rule| rule.repeat_until),
+            Ok(Some(NaiveDate::from_ymd(2026, 3, 31).and_hms(23, 59, 59)))
+        );
+        assert_eq!(
+            Rule::parse("FREQ=WEEKLY;UNTIL=20260331T093000Z").map(|rule| rule.repeat_until),
+            Ok(Some(at(2026, 3, 31)))
+        );
+        assert_eq!(code("FREQ=WEEKLY;UNTIL=2026-03-31"), "invalid_rrule");
+        assert_eq!(code("FREQ=WEEKLY;COUNT=2;UNTIL=20260331"), "conflict");
+    }
+
+    #[test]
+    fn parse_rejects() {
+        assert_eq!(code(""), "invalid_rrule");
+        assert_eq!(code("INTERVAL=2"), "invalid_rrule");
+        assert_eq!(code("FREQ=YEARLY"), "invalid_rrule");
+        assert_eq!(code("FREQ=HOURLY"), "invalid_rrule");
+        assert_eq!(code("FREQ=WEEKLY;BYDAY=MO"), "invalid_rrule");
+        assert_eq!(code("FREQ=WEEKLY;COUNT"), "invalid_rrule");
+        assert_eq!(code("FREQ=WEEKLY;INTERVAL=two"), "invalid_rrule");
+        assert_eq!(code("FREQ=WEEKLY;INTERVAL=0"), "out_of_range");
+        assert_eq!(code("FREQ=WEEKLY;COUNT=0"), "out_of_range");
+    }
+
+    #[test]
+    fn daily_and_weekly() {
+        assert_eq!(
+            rule(Frequency::Daily, 2).occurrences(at(2026, 2, 27), None, at(2026, 3, 5)),
+            vec![
+                at(2026, 2, 27),
+                at(2026, 3, 1),
+                at(2026, 3, 3),
+                at(2026, 3, 5)
+            ]
+        );
+        assert_eq!(
+            rule(Frequency::Weekly, 1).occurrences(at(2026, 12, 24), None, at(2027, 1, 14)),
+            vec![
+                at(2026, 12, 24),
+                at(2026, 12, 31),
+                at(2027, 1, 7),
+                at(2027, 1, 14)
+            ]
+        );
+    }
+
+    #[test]
+    fn monthly_keeps_to_month_ends() {
+        assert_eq!(
+            rule(Frequency::Monthly, 1).occurrences(at(2024, 1, 31), None, at(2024, 5, 31)),
+            vec![
+                at(2024, 1, 31),
+                at(2024, 2, 29),
+                at(2024, 3, 31),
+                at(2024, 4, 30),
+                at(2024, 5, 31),
+            ]
+        );
+        assert_eq!(
+            rule(Frequency::Monthly, 3).occurrences(at(2026, 11, 30), None, at(2027, 12, 31)),
+            vec![
+                at(2026, 11, 30),
+                at(2027, 2, 28),
+                at(2027, 5, 30),
+                at(2027, 8, 30),
+                at(2027, 11, 30),
+            ]
+        );
+    }
+
+    #[test]
+    fn window() {
+        let daily = rule(Frequency::Daily, 1);
+        // `after` is exclusive and `to` inclusive.
+        assert_eq!(
+            daily.occurrences(at(2026, 1, 1), Some(at(2026, 1, 3)), at(2026, 1, 5)),
+            vec![at(2026, 1, 4), at(2026, 1, 5)]
+        );
+        assert!(daily
+            .occurrences(at(2026, 1, 1), Some(at(2026, 1, 5)), at(2026, 1, 5))
+            .is_empty());
+        assert!(daily
+            .occurrences(at(2026, 1, 1), None, at(2025, 12, 31))
+            .is_empty());
+    }
+
+    #[test]
+    fn count_and_until() {
+        let counted = Rule {
+            occurrence_count: Some(3),
+            ..rule(Frequency::Weekly, 2)
+        };
+        assert_eq!(
+            counted.occurrences(at(2026, 1, 1), None, at(2027, 1, 1)),
+            vec![at(2026, 1, 1), at(2026, 1, 15), at(2026, 1, 29)]
+        );
+        // Occurrences already passed still count towards COUNT.
+        assert_eq!(
+            counted.occurrences(at(2026, 1, 1), Some(at(2026, 1, 15)), at(2027, 1, 1)),
+            vec![at(2026, 1, 29)]
+        );
+
+        let until = Rule {
+            repeat_until: Some(at(2026, 3, 31)),
+            ..rule(Frequency::Monthly, 1)
+        };
+        assert_eq!(
+            until.occurrences(at(2026, 1, 31), None, at(2027, 1, 1)),
+            vec![at(2026, 1, 31), at(2026, 2, 28), at(2026, 3, 31)]
+        );
+    }
+
+    #[test]
+    fn capped() {
+        let found = rule(Frequency::Daily, 1).occurrences(at(2000, 1, 1), None, at(2026, 1, 1));
+        assert_eq!(found.len(), MAX_OCCURRENCES);
+        assert_eq!(found.last(), Some(&at(2002, 9, 26)));
+    }
+}
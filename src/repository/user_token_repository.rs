@@ -113,7 +113,6 @@ impl T {
         }
     }
 
-    #[allow(dead_code)]
     pub async fn delete(&self, p_id: Uuid) -> Result<()> {
         use self::user_tokens::dsl::*;
 
@@ -188,6 +187,86 @@ impl T {
             Err(e) => Err(Errors::DatabaseError(e.to_string())),
         }
     }
+    pub async fn all_by_user_and_subject(
+        &self,
+        p_user_id: Uuid,
+        p_subject: String,
+    ) -> Result<Vec<(Uuid, Token)>> {
+        use self::user_tokens::dsl::*;
+
+        self.db_pool
+            .run(move |c| {
+                user_tokens
+                    .filter(user_id.eq(p_user_id))
+                    .filter(subject.eq(p_subject))
+                    .order(created_at.asc())
+                    .load::<UserToken>(c)
+            })
+            .await
+            .map(|r| r.into_iter().map(UserToken::into_token).collect())
+            .map_err(|e| Errors::DatabaseError(e.to_string()))
+    }
+
+    /// Replaces the payload only if it is still `expected`, so of two
+    /// requests racing on the same token only one succeeds.
+    pub async fn replace_payload(
+        &self,
+        p_id: Uuid,
+        expected: Option<String>,
+        replacement: Option<String>,
+    ) -> Result<bool> {
+        use self::user_tokens::dsl::*;
+
+        let count = self
+            .db_pool
+            .run(move |c| {
+                let query = diesel::update(user_tokens).filter(id.eq(p_id));
+                match expected {
+                    Some(expected) => query
+                        .filter(payload.eq(expected))
+                        .set(payload.eq(replacement))
+                        .execute(c),
+                    None => query
+                        .filter(payload.is_null())
+                        .set(payload.eq(replacement))
+                        .execute(c),
+                }
+            })
+            .await
+            .map_err(|e| Errors::DatabaseError(e.to_string()))?;
+
+        Ok(count == 1)
+    }
+
+    pub async fn delete_many(&self, p_ids: Vec<Uuid>) -> Result<usize> {
+        use self::user_tokens::dsl::*;
+
+        self.db_pool
+            .run(move |c| diesel::delete(user_tokens.filter(id.eq_any(p_ids))).execute(c))
+            .await
+            .map_err(|e| Errors::DatabaseError(e.to_string()))
+    }
+
+    pub async fn delete_expired(
+        &self,
+        p_user_id: Uuid,
+        p_subject: String,
+        now: NaiveDateTime,
+    ) -> Result<()> {
+        use self::user_tokens::dsl::*;
+
+        self.db_pool
+            .run(move |c| {
+                diesel::delete(user_tokens)
+                    .filter(user_id.eq(p_user_id))
+                    .filter(subject.eq(p_subject))
+                    .filter(expires_at.le(now))
+                    .execute(c)
+            })
+            .await
+            .map(|_| ())
+            .map_err(|e| Errors::DatabaseError(e.to_string()))
+    }
 }
 
 #[rocket::async_trait]
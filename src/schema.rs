@@ -1,3 +1,69 @@
+table! {
+    aid_distribution_items (id) {
+        id -> Uuid,
+        distribution_id -> Uuid,
+        name -> Varchar,
+        quantity -> Int4,
+        unit -> Nullable<Varchar>,
+        estimated_value -> Nullable<Int4>,
+    }
+}
+
+table! {
+    aid_distributions (id) {
+        id -> Uuid,
+        case_id -> Uuid,
+        person_id -> Nullable<Uuid>,
+        requirement_id -> Nullable<Uuid>,
+        kind -> Int4,
+        amount -> Nullable<Int4>,
+        distributed_on -> Date,
+        source -> Varchar,
+        issued_by -> Nullable<Uuid>,
+        note -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    audit_log (id) {
+        id -> Uuid,
+        actor -> Nullable<Uuid>,
+        entity_type -> Text,
+        entity_id -> Uuid,
+        operation -> Text,
+        created_at -> Timestamp,
+        before -> Nullable<Jsonb>,
+        after -> Nullable<Jsonb>,
+    }
+}
+
+table! {
+    case_action_comments (id) {
+        id -> Uuid,
+        case_action_id -> Uuid,
+        author -> Nullable<Uuid>,
+        body -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    case_action_series (id) {
+        id -> Uuid,
+        case_id -> Uuid,
+        action -> Text,
+        assignee -> Nullable<Uuid>,
+        starts_at -> Timestamp,
+        frequency -> Int4,
+        repeat_every -> Int4,
+        repeat_until -> Nullable<Timestamp>,
+        occurrence_count -> Nullable<Int4>,
+        materialized_until -> Nullable<Timestamp>,
+        cancelled_at -> Nullable<Timestamp>,
+    }
+}
+
 table! {
     case_actions (id) {
         id -> Uuid,
@@ -5,6 +71,20 @@ table! {
         action -> Text,
         status -> Int4,
         action_date -> Nullable<Timestamp>,
+        assignee -> Nullable<Uuid>,
+        completed_at -> Nullable<Timestamp>,
+        completed_by -> Nullable<Uuid>,
+        result_note -> Nullable<Text>,
+        series_id -> Nullable<Uuid>,
+        occurrence_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    case_assignments (case_id, user_id) {
+        case_id -> Uuid,
+        user_id -> Uuid,
+        permission -> Int4,
     }
 }
 
@@ -17,6 +97,59 @@ table! {
         editor -> Uuid,
         address -> Nullable<Varchar>,
         description -> Nullable<Text>,
+        deleted_at -> Nullable<Timestamp>,
+        deleted_by -> Nullable<Uuid>,
+        import_batch_id -> Nullable<Uuid>,
+    }
+}
+
+table! {
+    dossier_template (id) {
+        id -> Bool,
+        body -> Nullable<Text>,
+        updated_by -> Nullable<Uuid>,
+        updated_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    import_batch_jobs (import_batch_id, person_job_id) {
+        import_batch_id -> Uuid,
+        person_job_id -> Uuid,
+    }
+}
+
+table! {
+    import_batch_persons (import_batch_id, person_id) {
+        import_batch_id -> Uuid,
+        person_id -> Uuid,
+    }
+}
+
+table! {
+    import_batches (id) {
+        id -> Uuid,
+        file_name -> Varchar,
+        imported_by -> Nullable<Uuid>,
+        imported_at -> Timestamp,
+        row_count -> Int4,
+        case_count -> Int4,
+        person_count -> Int4,
+        job_count -> Int4,
+        rolled_back_by -> Nullable<Uuid>,
+        rolled_back_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    need_weights (id) {
+        id -> Bool,
+        income -> Float8,
+        household_size -> Float8,
+        dependents -> Float8,
+        no_earner -> Float8,
+        poverty_line -> Int4,
+        large_household -> Int4,
     }
 }
 
@@ -34,6 +167,11 @@ table! {
         title -> Varchar,
         income -> Nullable<Int4>,
         location -> Nullable<Varchar>,
+        start_date -> Nullable<Date>,
+        end_date -> Nullable<Date>,
+        employment_type -> Nullable<Int4>,
+        income_period -> Int4,
+        monthly_income -> Nullable<Int4>,
     }
 }
 
@@ -42,6 +180,12 @@ table! {
         id -> Uuid,
         person_id -> Uuid,
         description -> Text,
+        category -> Int4,
+        priority -> Int4,
+        status -> Int4,
+        estimated_cost -> Nullable<Int4>,
+        due_date -> Nullable<Date>,
+        fulfilment_note -> Nullable<Text>,
     }
 }
 
@@ -49,7 +193,7 @@ table! {
     person_skills (id) {
         id -> Uuid,
         person_id -> Uuid,
-        skill -> Varchar,
+        skill_id -> Uuid,
     }
 }
 
@@ -68,6 +212,25 @@ table! {
         description -> Nullable<Text>,
         education_field -> Nullable<Varchar>,
         education_location -> Nullable<Varchar>,
+        deleted_at -> Nullable<Timestamp>,
+        deleted_by -> Nullable<Uuid>,
+    }
+}
+
+table! {
+    skill_synonyms (name_key) {
+        name_key -> Varchar,
+        skill_id -> Uuid,
+        name -> Varchar,
+    }
+}
+
+table! {
+    skills (id) {
+        id -> Uuid,
+        name -> Varchar,
+        name_key -> Varchar,
+        category -> Nullable<Varchar>,
     }
 }
 
@@ -89,29 +252,67 @@ table! {
         username -> Varchar,
         first_name -> Varchar,
         last_name -> Varchar,
-        password_hash -> Bytea,
-        password_salt -> Bytea,
+        password_hash -> Nullable<Bytea>,
+        password_salt -> Nullable<Bytea>,
         role -> Int4,
+        password_phc -> Nullable<Text>,
+        active -> Bool,
+        must_change_password -> Bool,
+        timezone -> Nullable<Text>,
     }
 }
 
+joinable!(aid_distribution_items -> aid_distributions (distribution_id));
+joinable!(aid_distributions -> cases (case_id));
+joinable!(aid_distributions -> person_requirements (requirement_id));
+joinable!(aid_distributions -> persons (person_id));
+joinable!(aid_distributions -> users (issued_by));
+joinable!(audit_log -> users (actor));
+joinable!(case_action_comments -> case_actions (case_action_id));
+joinable!(case_action_comments -> users (author));
+joinable!(case_action_series -> cases (case_id));
+joinable!(case_action_series -> users (assignee));
+joinable!(case_actions -> case_action_series (series_id));
 joinable!(case_actions -> cases (case_id));
-joinable!(cases -> users (editor));
+joinable!(case_assignments -> cases (case_id));
+joinable!(case_assignments -> users (user_id));
+joinable!(cases -> import_batches (import_batch_id));
+joinable!(dossier_template -> users (updated_by));
+joinable!(import_batch_jobs -> import_batches (import_batch_id));
+joinable!(import_batch_jobs -> person_jobs (person_job_id));
+joinable!(import_batch_persons -> import_batches (import_batch_id));
+joinable!(import_batch_persons -> persons (person_id));
 joinable!(person_default_job -> person_jobs (person_id));
 joinable!(person_jobs -> persons (person_id));
 joinable!(person_requirements -> persons (person_id));
 joinable!(person_skills -> persons (person_id));
+joinable!(person_skills -> skills (skill_id));
 joinable!(persons -> cases (case_id));
+joinable!(persons -> users (deleted_by));
+joinable!(skill_synonyms -> skills (skill_id));
 joinable!(user_tokens -> users (user_id));
 
 allow_tables_to_appear_in_same_query!(
+    aid_distribution_items,
+    aid_distributions,
+    audit_log,
+    case_action_comments,
+    case_action_series,
     case_actions,
+    case_assignments,
     cases,
+    dossier_template,
+    import_batch_jobs,
+    import_batch_persons,
+    import_batches,
+    need_weights,
     person_default_job,
     person_jobs,
     person_requirements,
     person_skills,
     persons,
+    skill_synonyms,
+    skills,
     user_tokens,
     users,
 );
@@ -0,0 +1,216 @@
+use crate::access::Visibility;
+use crate::errors::*;
+use crate::persian;
+use crate::website::Db;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Float, Int4, Nullable, Text, Uuid as SqlUuid, Varchar};
+use rocket::serde::Serialize;
+use uuid::Uuid;
+
+// The documents below must stay identical to the expressions indexed in the
+// `search` and `skill_catalogue` migrations.
+const CASE_DOCUMENT: &str = "coalesce(address, '') || ' ' || coalesce(description, '')";
+const PERSON_DOCUMENT: &str = "first_name || ' ' || last_name || ' ' || father_name || ' ' \
+     || national_number || ' ' || ltrim(phone_number, '+') || ' ' \
+     || regexp_replace(phone_number, '^\\+98', '0')";
+/// Name of a skill or of one of its synonyms.
+const SKILL_DOCUMENT: &str = "name";
+const REQUIREMENT_DOCUMENT: &str = "r.description";
+
+/// SQL condition keeping the rows whose case the user bound to `$3` is
+/// assigned to; a NULL `$3` keeps every row.
+fn visible(case_column: &str) -> String {
+    format!(
+        "($3::uuid IS NULL OR {} IN (SELECT case_id FROM case_assignments WHERE user_id = $3))",
+        case_column
+    )
+}
+
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct CaseHit {
+    #[sql_type = "SqlUuid"]
+    pub id: Uuid,
+    #[sql_type = "SqlUuid"]
+    pub case_id: Uuid,
+    #[sql_type = "Int4"]
+    pub number: i32,
+    #[sql_type = "Nullable<Varchar>"]
+    pub address: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    pub description: Option<String>,
+    #[sql_type = "Float"]
+    pub rank: f32,
+}
+
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct PersonHit {
+    #[sql_type = "SqlUuid"]
+    pub id: Uuid,
+    #[sql_type = "SqlUuid"]
+    pub case_id: Uuid,
+    #[sql_type = "Varchar"]
+    pub first_name: String,
+    #[sql_type = "Varchar"]
+    pub last_name: String,
+    #[sql_type = "Varchar"]
+    pub father_name: String,
+    #[sql_type = "Text"]
+    pub national_number: String,
+    #[sql_type = "Text"]
+    pub phone_number: String,
+    #[sql_type = "Float"]
+    pub rank: f32,
+}
+
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct SkillHit {
+    #[sql_type = "SqlUuid"]
+    pub id: Uuid,
+    #[sql_type = "SqlUuid"]
+    pub person_id: Uuid,
+    #[sql_type = "SqlUuid"]
+    pub case_id: Uuid,
+    #[sql_type = "SqlUuid"]
+    pub skill_id: Uuid,
+    #[sql_type = "Varchar"]
+    pub skill: String,
+    #[sql_type = "Float"]
+    pub rank: f32,
+}
+
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct RequirementHit {
+    #[sql_type = "SqlUuid"]
+    pub id: Uuid,
+    #[sql_type = "SqlUuid"]
+    pub person_id: Uuid,
+    #[sql_type = "SqlUuid"]
+    pub case_id: Uuid,
+    #[sql_type = "Text"]
+    pub description: String,
+    #[sql_type = "Float"]
+    pub rank: f32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResults {
+    pub cases: Vec<CaseHit>,
+    pub persons: Vec<PersonHit>,
+    pub skills: Vec<SkillHit>,
+    pub requirements: Vec<RequirementHit>,
+}
+
+/// Turns free text into a prefix-matching `to_tsquery` expression, so that
+/// `رضا` also finds `رضایی`. Returns `None` when nothing searchable is left.
+pub fn to_tsquery(text: &str) -> Option<String> {
+    let terms: Vec<String> = persian::normalize(text)
+        .split_whitespace()
+        .map(|term| {
+            term.chars()
+                .filter(|c| c.is_alphanumeric())
+                .collect::<String>()
+        })
+        .filter(|term| !term.is_empty())
+        .map(|term| format!("{}:*", term.to_lowercase()))
+        .collect();
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" & "))
+    }
+}
+
+impl SearchResults {
+    pub async fn find(conn: &Db, text: &str, limit: i64, visibility: Visibility) -> Result<Self> {
+        let query = to_tsquery(text)
+            .ok_or_else(|| Errors::BadRequest("search query is empty".to_owned()))?;
+        let user = match visibility {
+            Visibility::All => None,
+            Visibility::AssignedTo(user) => Some(user),
+        };
+
+        conn.run(move |c| {
+            let cases = diesel::sql_query(format!(
+                "SELECT id, id AS case_id, number, address, description, \
+                 ts_rank(search_vector({doc}), query) AS rank \
+                 FROM cases, to_tsquery('simple', $1) query \
+                 WHERE deleted_at IS NULL AND {visible} AND search_vector({doc}) @@ query \
+                 ORDER BY rank DESC, id LIMIT $2",
+                doc = CASE_DOCUMENT,
+                visible = visible("id")
+            ))
+            .bind::<Text, _>(&query)
+            .bind::<BigInt, _>(limit)
+            .bind::<Nullable<SqlUuid>, _>(user)
+            .load::<CaseHit>(c)?;
+
+            let persons = diesel::sql_query(format!(
+                "SELECT id, case_id, first_name, last_name, father_name, \
+                 national_number::TEXT, phone_number::TEXT, \
+                 ts_rank(search_vector({doc}), query) AS rank \
+                 FROM persons, to_tsquery('simple', $1) query \
+                 WHERE deleted_at IS NULL AND {visible} AND search_vector({doc}) @@ query \
+                 ORDER BY rank DESC, id LIMIT $2",
+                doc = PERSON_DOCUMENT,
+                visible = visible("case_id")
+            ))
+            .bind::<Text, _>(&query)
+            .bind::<BigInt, _>(limit)
+            .bind::<Nullable<SqlUuid>, _>(user)
+            .load::<PersonHit>(c)?;
+
+            // People whose skill matches by its name or any of its synonyms,
+            // ranked by the best matching one.
+            let skills = diesel::sql_query(format!(
+                "SELECT s.id, s.person_id, p.case_id, s.skill_id, k.name AS skill, m.rank \
+                 FROM ( \
+                     SELECT skill_id, max(rank) AS rank FROM ( \
+                         SELECT id AS skill_id, ts_rank(search_vector({doc}), query) AS rank \
+                         FROM skills, to_tsquery('simple', $1) query \
+                         WHERE search_vector({doc}) @@ query \
+                         UNION ALL \
+                         SELECT skill_id, ts_rank(search_vector({doc}), query) \
+                         FROM skill_synonyms, to_tsquery('simple', $1) query \
+                         WHERE search_vector({doc}) @@ query \
+                     ) named GROUP BY skill_id \
+                 ) m \
+                 JOIN skills k ON k.id = m.skill_id \
+                 JOIN person_skills s ON s.skill_id = m.skill_id \
+                 JOIN persons p ON p.id = s.person_id \
+                 WHERE p.deleted_at IS NULL AND {visible} \
+                 ORDER BY m.rank DESC, s.id LIMIT $2",
+                doc = SKILL_DOCUMENT,
+                visible = visible("p.case_id")
+            ))
+            .bind::<Text, _>(&query)
+            .bind::<BigInt, _>(limit)
+            .bind::<Nullable<SqlUuid>, _>(user)
+            .load::<SkillHit>(c)?;
+
+            let requirements = diesel::sql_query(format!(
+                "SELECT r.id, r.person_id, p.case_id, r.description, \
+                 ts_rank(search_vector({doc}), query) AS rank \
+                 FROM person_requirements r JOIN persons p ON p.id = r.person_id, \
+                 to_tsquery('simple', $1) query \
+                 WHERE p.deleted_at IS NULL AND {visible} AND search_vector({doc}) @@ query \
+                 ORDER BY rank DESC, r.id LIMIT $2",
+                doc = REQUIREMENT_DOCUMENT,
+                visible = visible("p.case_id")
+            ))
+            .bind::<Text, _>(&query)
+            .bind::<BigInt, _>(limit)
+            .bind::<Nullable<SqlUuid>, _>(user)
+            .load::<RequirementHit>(c)?;
+
+            Ok(SearchResults {
+                cases,
+                persons,
+                skills,
+                requirements,
+            })
+        })
+        .await
+        .map_err(|e: diesel::result::Error| Errors::DatabaseError(e.to_string()))
+    }
+}
@@ -7,14 +7,37 @@ use rocket::{
 };
 
 use crate::errors::{self, Errors};
+use crate::password::HashingParams;
+use rocket::serde::Deserialize;
 
 pub struct JWTKeys {
     pub encoding_key: EncodingKey,
     pub decoding_key: DecodingKey,
 }
 
+/// What to do when a person is saved with a national number that another
+/// person already has.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicatePolicy {
+    Reject,
+    Warn,
+}
+
+const DEFAULT_TRASH_RETENTION_DAYS: i64 = 30;
+const DEFAULT_RECURRENCE_HORIZON_DAYS: i64 = 60;
+
 pub struct ServiceOptions {
     pub jwt_keys: JWTKeys,
+    pub duplicate_policy: DuplicatePolicy,
+    /// Days a deleted case or person stays restorable before it is purged.
+    pub trash_retention_days: i64,
+    /// Days ahead for which the actions of recurring series are created.
+    pub recurrence_horizon_days: i64,
+    /// Most a case may receive in one month, in Toman, counting cash and the
+    /// estimated value of items. `None` when there is no cap.
+    pub aid_monthly_cap: Option<i64>,
+    pub password_hashing: HashingParams,
 }
 
 impl ServiceOptions {
@@ -28,11 +51,68 @@ impl ServiceOptions {
             Ok(s) => s,
         };
 
+        let duplicate_policy = match figment.extract_inner("duplicate_national_number") {
+            Err(e) if e.missing() => DuplicatePolicy::Reject,
+            Err(_) => {
+                return Err(errors::Errors::InternalError(
+                    "duplicate_national_number must be \"reject\" or \"warn\"".into(),
+                ));
+            }
+            Ok(p) => p,
+        };
+
+        let trash_retention_days = match figment.extract_inner("trash_retention_days") {
+            Err(e) if e.missing() => DEFAULT_TRASH_RETENTION_DAYS,
+            Ok(days) if days >= 0 => days,
+            _ => {
+                return Err(errors::Errors::InternalError(
+                    "trash_retention_days must be a non-negative number of days".into(),
+                ));
+            }
+        };
+
+        let recurrence_horizon_days = match figment.extract_inner("recurrence_horizon_days") {
+            Err(e) if e.missing() => DEFAULT_RECURRENCE_HORIZON_DAYS,
+            Ok(days) if (1..=3660).contains(&days) => days,
+            _ => {
+                return Err(errors::Errors::InternalError(
+                    "recurrence_horizon_days must be between 1 and 3660 days".into(),
+                ));
+            }
+        };
+
+        let aid_monthly_cap = match figment.extract_inner("aid_monthly_cap") {
+            Err(e) if e.missing() => None,
+            Ok(cap) if cap > 0 => Some(cap),
+            _ => {
+                return Err(errors::Errors::InternalError(
+                    "aid_monthly_cap must be a positive amount of Toman".into(),
+                ));
+            }
+        };
+
+        let password_hashing: HashingParams = match figment.extract_inner("password_hashing") {
+            Err(e) if e.missing() => HashingParams::default(),
+            Err(_) => {
+                return Err(errors::Errors::InternalError(
+                    "password_hashing must have numeric memory_kib, iterations and parallelism"
+                        .into(),
+                ));
+            }
+            Ok(params) => params,
+        };
+        password_hashing.validate()?;
+
         let opts = Self {
             jwt_keys: JWTKeys {
                 encoding_key: EncodingKey::from_secret(secret_key.as_bytes()),
                 decoding_key: DecodingKey::from_secret(secret_key.as_bytes()),
             },
+            duplicate_policy,
+            trash_retention_days,
+            recurrence_horizon_days,
+            aid_monthly_cap,
+            password_hashing,
         };
         Ok(opts)
     }
@@ -0,0 +1,424 @@
+use crate::access::{self, Visibility};
+use crate::audit::{self, Auditable, Operation};
+use crate::errors::*;
+use crate::filters::SkillFilter;
+use crate::household::ADULT_AGE;
+use crate::models::{current_jobs, Person, PersonSkill};
+use crate::persian;
+use crate::schema::*;
+use crate::validation::{Validate, Validator};
+use crate::website::Db;
+use chrono::{Datelike, NaiveDate, Utc};
+use diesel::dsl::{exists, not};
+use diesel::prelude::*;
+use diesel::PgConnection;
+use rocket::serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use uuid::Uuid;
+
+const NAME_MAX_LENGTH: usize = 200;
+const CATEGORY_MAX_LENGTH: usize = 100;
+
+type Columns = (skills::id, skills::name, skills::category);
+const COLUMNS: Columns = (skills::id, skills::name, skills::category);
+
+/// Form a skill name is compared in: Persian-normalised, lower-cased and with
+/// runs of spaces collapsed. Must match the expression of the
+/// `skill_catalogue` migration.
+pub fn name_key(name: &str) -> String {
+    persian::normalize(name)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+#[derive(Debug, Queryable, Serialize, Clone)]
+pub struct Skill {
+    pub id: Uuid,
+    pub name: String,
+    pub category: Option<String>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct SkillDetails {
+    #[serde(flatten)]
+    pub skill: Skill,
+    pub synonyms: Vec<String>,
+}
+
+impl Auditable for SkillDetails {
+    const ENTITY_TYPE: &'static str = "skill";
+
+    fn entity_id(&self) -> Uuid {
+        self.skill.id
+    }
+}
+
+/// Body of creating a skill, and of replacing one along with its synonyms.
+#[derive(Debug, Deserialize, Clone)]
+pub struct SkillRequest {
+    pub name: String,
+    #[serde(default)]
+    pub category: Option<String>,
+    #[serde(default)]
+    pub synonyms: Vec<String>,
+}
+
+impl Validate for SkillRequest {
+    fn validate(self) -> Result<Self> {
+        let mut validator = Validator::new();
+        validator.required("name", &self.name, NAME_MAX_LENGTH);
+        validator.optional("category", &self.category, CATEGORY_MAX_LENGTH);
+        for synonym in &self.synonyms {
+            validator.required("synonyms", synonym, NAME_MAX_LENGTH);
+        }
+        validator.finish()?;
+
+        Ok(SkillRequest {
+            name: self.name.trim().to_owned(),
+            category: self
+                .category
+                .map(|category| category.trim().to_owned())
+                .filter(|category| !category.is_empty()),
+            synonyms: self
+                .synonyms
+                .iter()
+                .map(|synonym| synonym.trim().to_owned())
+                .collect(),
+        })
+    }
+}
+
+/// A person out of work who has some of the skills asked for.
+#[derive(Debug, Serialize, Clone)]
+pub struct SkillMatch {
+    #[serde(flatten)]
+    pub person: Person,
+    /// The skills asked for that the person has.
+    pub skills: Vec<Uuid>,
+}
+
+fn load_details(c: &PgConnection, found: Vec<Skill>) -> Result<Vec<SkillDetails>> {
+    let ids: Vec<Uuid> = found.iter().map(|skill| skill.id).collect();
+    let mut synonyms: BTreeMap<Uuid, Vec<String>> = BTreeMap::new();
+    for (skill_id, name) in skill_synonyms::table
+        .filter(skill_synonyms::skill_id.eq_any(ids))
+        .order(skill_synonyms::name)
+        .select((skill_synonyms::skill_id, skill_synonyms::name))
+        .load::<(Uuid, String)>(c)?
+    {
+        synonyms.entry(skill_id).or_default().push(name);
+    }
+
+    Ok(found
+        .into_iter()
+        .map(|skill| SkillDetails {
+            synonyms: synonyms.remove(&skill.id).unwrap_or_default(),
+            skill,
+        })
+        .collect())
+}
+
+fn get_in(c: &PgConnection, p_id: Uuid) -> Result<Option<SkillDetails>> {
+    let found = skills::table
+        .find(p_id)
+        .select(COLUMNS)
+        .for_update()
+        .first::<Skill>(c)
+        .optional()?;
+    match found {
+        None => Ok(None),
+        Some(found) => Ok(load_details(c, vec![found])?.pop()),
+    }
+}
+
+/// Fails when the name or a synonym of `request` is already used by a skill
+/// other than `p_id`, or when the request repeats a name.
+fn check_names(c: &PgConnection, request: &SkillRequest, p_id: Option<Uuid>) -> Result<()> {
+    let other = p_id.unwrap_or_else(Uuid::nil);
+    let taken = |key: &str| -> Result<Option<String>> {
+        let skill = skills::table
+            .filter(skills::name_key.eq(key))
+            .filter(skills::id.ne(other))
+            .select(skills::name)
+            .first::<String>(c)
+            .optional()?;
+        let synonym = skill_synonyms::table
+            .inner_join(skills::table)
+            .filter(skill_synonyms::name_key.eq(key))
+            .filter(skill_synonyms::skill_id.ne(other))
+            .select(skills::name)
+            .first::<String>(c)
+            .optional()?;
+        Ok(skill.or(synonym))
+    };
+
+    let mut validator = Validator::new();
+    let key = name_key(&request.name);
+    if let Some(existing) = taken(&key)? {
+        validator.check::<()>(
+            "name",
+            Err(("taken", format!("already a name of the skill {}", existing))),
+        );
+    }
+
+    let mut keys = BTreeSet::from([key]);
+    for synonym in &request.synonyms {
+        let key = name_key(synonym);
+        if !keys.insert(key.clone()) {
+            validator.check::<()>(
+                "synonyms",
+                Err(("duplicate", format!("{} is given more than once", synonym))),
+            );
+        } else if let Some(existing) = taken(&key)? {
+            validator.check::<()>(
+                "synonyms",
+                Err((
+                    "taken",
+                    format!("{} is already a name of the skill {}", synonym, existing),
+                )),
+            );
+        }
+    }
+    validator.finish()
+}
+
+fn insert_synonyms(c: &PgConnection, p_skill_id: Uuid, names: &[String]) -> Result<()> {
+    let rows: Vec<_> = names
+        .iter()
+        .map(|name| {
+            (
+                skill_synonyms::name_key.eq(name_key(name)),
+                skill_synonyms::skill_id.eq(p_skill_id),
+                skill_synonyms::name.eq(name),
+            )
+        })
+        .collect();
+    diesel::insert_into(skill_synonyms::table)
+        .values(rows)
+        .execute(c)?;
+    Ok(())
+}
+
+impl Skill {
+    /// The catalogue by name, narrowed to skills in `category` or with a name
+    /// or synonym containing `q`.
+    pub async fn all(conn: &Db, filter: SkillFilter) -> Result<Vec<SkillDetails>> {
+        conn.run(move |c| {
+            let mut query = skills::table
+                .select(COLUMNS)
+                .order(skills::name)
+                .into_boxed();
+            if let Some(category) = filter.category {
+                query = query.filter(skills::category.eq(category));
+            }
+            if let Some(q) = filter.q {
+                let pattern = format!(
+                    "%{}%",
+                    name_key(&q)
+                        .replace('\\', "\\\\")
+                        .replace('%', "\\%")
+                        .replace('_', "\\_")
+                );
+                query = query.filter(
+                    skills::name_key.like(pattern.clone()).or(skills::id.eq_any(
+                        skill_synonyms::table
+                            .filter(skill_synonyms::name_key.like(pattern))
+                            .select(skill_synonyms::skill_id),
+                    )),
+                );
+            }
+
+            let found = query.load::<Skill>(c)?;
+            load_details(c, found)
+        })
+        .await
+    }
+
+    pub async fn get(conn: &Db, p_id: Uuid) -> Result<Option<SkillDetails>> {
+        conn.run(move |c| get_in(c, p_id)).await
+    }
+
+    pub async fn create(conn: &Db, request: SkillRequest, actor: Uuid) -> Result<SkillDetails> {
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                check_names(c, &request, None)?;
+
+                let skill = diesel::insert_into(skills::table)
+                    .values((
+                        skills::id.eq(Uuid::from_u128(rand::random())),
+                        skills::name.eq(&request.name),
+                        skills::name_key.eq(name_key(&request.name)),
+                        skills::category.eq(&request.category),
+                    ))
+                    .returning(COLUMNS)
+                    .get_result::<Skill>(c)?;
+                insert_synonyms(c, skill.id, &request.synonyms)?;
+
+                let created = SkillDetails {
+                    skill,
+                    synonyms: request.synonyms,
+                };
+                audit::record(c, actor, Operation::Create, None, Some(&created))?;
+                Ok(created)
+            })
+        })
+        .await
+    }
+
+    /// Renames the skill and replaces its category and synonyms.
+    pub async fn update(
+        conn: &Db,
+        p_id: Uuid,
+        request: SkillRequest,
+        actor: Uuid,
+    ) -> Result<SkillDetails> {
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = get_in(c, p_id)?
+                    .ok_or_else(|| Errors::BadRequest("id not found".to_owned()))?;
+                check_names(c, &request, Some(p_id))?;
+
+                let skill = diesel::update(skills::table.find(p_id))
+                    .set((
+                        skills::name.eq(&request.name),
+                        skills::name_key.eq(name_key(&request.name)),
+                        skills::category.eq(&request.category),
+                    ))
+                    .returning(COLUMNS)
+                    .get_result::<Skill>(c)?;
+                diesel::delete(skill_synonyms::table.filter(skill_synonyms::skill_id.eq(p_id)))
+                    .execute(c)?;
+                insert_synonyms(c, p_id, &request.synonyms)?;
+
+                let after = load_details(c, vec![skill])?.remove(0);
+                audit::record(c, actor, Operation::Update, Some(&before), Some(&after))?;
+                Ok(after)
+            })
+        })
+        .await
+    }
+
+    /// Removes a skill nobody has. Skills in use are merged instead.
+    pub async fn delete(conn: &Db, p_id: Uuid, actor: Uuid) -> Result<()> {
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let before = get_in(c, p_id)?
+                    .ok_or_else(|| Errors::BadRequest("id not found".to_owned()))?;
+
+                let holders = person_skills::table
+                    .filter(person_skills::skill_id.eq(p_id))
+                    .count()
+                    .get_result::<i64>(c)?;
+                if holders > 0 {
+                    return Err(Errors::BadRequest(format!(
+                        "{} people have this skill; merge it into another one instead",
+                        holders
+                    )));
+                }
+
+                diesel::delete(skills::table.find(p_id)).execute(c)?;
+                audit::record(c, actor, Operation::Delete, Some(&before), None)?;
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    /// Folds skill `from_id` into `into_id`: its holders get the target
+    /// skill, and its name and synonyms become synonyms of the target.
+    pub async fn merge(
+        conn: &Db,
+        from_id: Uuid,
+        into_id: Uuid,
+        actor: Uuid,
+    ) -> Result<SkillDetails> {
+        if from_id == into_id {
+            return Err(Errors::BadRequest(
+                "cannot merge a skill into itself".to_owned(),
+            ));
+        }
+
+        conn.run(move |c| {
+            c.transaction::<_, Errors, _>(|| {
+                let source = get_in(c, from_id)?
+                    .ok_or_else(|| Errors::BadRequest("id not found".to_owned()))?;
+                let target = get_in(c, into_id)?
+                    .ok_or_else(|| Errors::BadRequest("target id not found".to_owned()))?;
+
+                PersonSkill::reassign_in(c, from_id, into_id, actor)?;
+                diesel::update(skill_synonyms::table.filter(skill_synonyms::skill_id.eq(from_id)))
+                    .set(skill_synonyms::skill_id.eq(into_id))
+                    .execute(c)?;
+                diesel::delete(skills::table.find(from_id)).execute(c)?;
+                insert_synonyms(c, into_id, std::slice::from_ref(&source.skill.name))?;
+                audit::record(c, actor, Operation::Delete, Some(&source), None)?;
+
+                let after = get_in(c, into_id)?
+                    .ok_or_else(|| Errors::BadRequest("target id not found".to_owned()))?;
+                audit::record(c, actor, Operation::Update, Some(&target), Some(&after))?;
+                Ok(after)
+            })
+        })
+        .await
+    }
+}
+
+/// Latest birthday of someone who is an adult on `today`.
+fn adults_born_by(today: NaiveDate) -> NaiveDate {
+    let year = today.year() - ADULT_AGE;
+    NaiveDate::from_ymd_opt(year, today.month(), today.day())
+        .unwrap_or_else(|| NaiveDate::from_ymd(year, today.month(), today.day() - 1))
+}
+
+/// Live adults the caller can see who have any of `skill_ids` and no current
+/// job, those with the most of them first.
+pub async fn unemployed_with(
+    conn: &Db,
+    skill_ids: Vec<Uuid>,
+    visibility: Visibility,
+    limit: i64,
+) -> Result<Vec<SkillMatch>> {
+    conn.run(move |c| {
+        let today = Utc::now().date().naive_utc();
+        let born_by = adults_born_by(today);
+        let mut query = person_skills::table
+            .inner_join(persons::table)
+            .filter(person_skills::skill_id.eq_any(skill_ids))
+            .filter(persons::deleted_at.is_null())
+            .filter(persons::birthday.le(born_by))
+            .filter(not(exists(
+                person_jobs::table
+                    .filter(person_jobs::person_id.eq(persons::id))
+                    .filter(current_jobs(today)),
+            )))
+            .select((persons::id, persons::all_columns, person_skills::skill_id))
+            .order((persons::last_name, persons::first_name, persons::id))
+            .into_boxed();
+        if let Visibility::AssignedTo(user) = visibility {
+            query = query.filter(persons::case_id.eq_any(access::assigned_case_ids(user)));
+        }
+
+        let mut matches: Vec<(Uuid, SkillMatch)> = Vec::new();
+        for (person_id, person, skill_id) in query.load::<(Uuid, Person, Uuid)>(c)? {
+            match matches.last_mut() {
+                Some((last, found)) if *last == person_id => found.skills.push(skill_id),
+                _ => matches.push((
+                    person_id,
+                    SkillMatch {
+                        person,
+                        skills: vec![skill_id],
+                    },
+                )),
+            }
+        }
+
+        let mut matches: Vec<SkillMatch> = matches.into_iter().map(|(_, found)| found).collect();
+        matches.sort_by_key(|found| Reverse(found.skills.len()));
+        matches.truncate(limit as usize);
+        Ok(matches)
+    })
+    .await
+}
@@ -5,6 +5,7 @@ use crate::{
 use chrono::{Duration, NaiveDateTime, Utc};
 use rocket::{
     request::{self, FromRequest, Outcome},
+    serde::{Deserialize, Serialize},
     Request,
 };
 use uuid::Uuid;
@@ -23,6 +24,67 @@ pub struct Token {
     pub payload: Option<String>,
 }
 
+/// What a refresh token keeps in its `payload`. Every token handed out by
+/// rotating another one carries the same `session_id`, so the tokens of one
+/// login form a family that can be revoked together.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SessionPayload {
+    pub session_id: Uuid,
+    pub started_at: NaiveDateTime,
+    pub device: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    /// Set once the token has been exchanged for a new one. A rotated token
+    /// that shows up again has leaked.
+    pub rotated_at: Option<NaiveDateTime>,
+}
+
+impl SessionPayload {
+    fn of_token(token: &Token) -> Option<Self> {
+        token
+            .payload
+            .as_deref()
+            .and_then(|payload| serde_json::from_str(payload).ok())
+    }
+
+    fn to_payload(&self) -> errors::Result<String> {
+        serde_json::to_string(self)
+            .map_err(|e| Errors::InternalError(format!("cannot encode session: {}", e)))
+    }
+}
+
+/// The client a session is opened or refreshed from.
+#[derive(Clone, Debug, Default)]
+pub struct Device {
+    pub name: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+/// One signed-in device, as listed to its owner.
+#[derive(Serialize, Debug)]
+pub struct Session {
+    pub id: Uuid,
+    pub started_at: NaiveDateTime,
+    pub last_used_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub device: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+/// Outcome of exchanging a refresh token for a new one.
+pub enum Rotation {
+    Rotated {
+        user_id: Uuid,
+        session_id: Uuid,
+    },
+    /// Unknown or expired token.
+    Invalid,
+    /// The token had already been rotated; its whole session was revoked.
+    Reused,
+}
+
 impl T {
     pub fn make(repo: user_token_repository::T) -> Self {
         Self { repo }
@@ -60,6 +122,193 @@ impl T {
     pub async fn revoke(&self, user_id: Uuid, subject: String) -> errors::Result<()> {
         self.repo.revoke_by_user_and_subject(user_id, subject).await
     }
+
+    /// Opens a new session whose first refresh token is `token` and returns
+    /// its id. Other sessions of the user are left alone.
+    pub async fn start_session(
+        &self,
+        user_id: Uuid,
+        token: String,
+        subject: String,
+        validity_period: Duration,
+        device: Device,
+    ) -> errors::Result<Uuid> {
+        let now = Utc::now().naive_utc();
+        self.repo
+            .delete_expired(user_id, subject.clone(), now)
+            .await?;
+
+        let session = SessionPayload {
+            session_id: Uuid::from_u128(rand::random()),
+            started_at: now,
+            device: device.name,
+            user_agent: device.user_agent,
+            ip: device.ip,
+            rotated_at: None,
+        };
+        self.create(
+            user_id,
+            token,
+            subject,
+            validity_period,
+            Some(session.to_payload()?),
+        )
+        .await?;
+
+        Ok(session.session_id)
+    }
+
+    /// Exchanges `presented` for `replacement`. The presented token stays in
+    /// the table, marked as rotated, until it expires so that a second use
+    /// of it can be detected.
+    pub async fn rotate(
+        &self,
+        subject: String,
+        presented: String,
+        replacement: String,
+        validity_period: Duration,
+        device: Device,
+    ) -> errors::Result<Rotation> {
+        let now = Utc::now().naive_utc();
+
+        let (id, token) = match self
+            .repo
+            .get_by_subject_and_token(subject.clone(), presented)
+            .await?
+        {
+            Some(found) => found,
+            None => return Ok(Rotation::Invalid),
+        };
+
+        if token.expires_at <= now {
+            self.repo.delete(id).await?;
+            return Ok(Rotation::Invalid);
+        }
+
+        // Tokens issued before sessions existed start one of their own.
+        let session = match SessionPayload::of_token(&token) {
+            Some(session) => session,
+            None => {
+                self.repo.delete(id).await?;
+                let session_id = self
+                    .start_session(token.user_id, replacement, subject, validity_period, device)
+                    .await?;
+                return Ok(Rotation::Rotated {
+                    user_id: token.user_id,
+                    session_id,
+                });
+            }
+        };
+
+        let rotated = SessionPayload {
+            rotated_at: Some(now),
+            ..session.clone()
+        };
+        let won = session.rotated_at.is_none()
+            && self
+                .repo
+                .replace_payload(id, token.payload.clone(), Some(rotated.to_payload()?))
+                .await?;
+        if !won {
+            self.revoke_session(token.user_id, subject, session.session_id)
+                .await?;
+            return Ok(Rotation::Reused);
+        }
+
+        let next = SessionPayload {
+            user_agent: device.user_agent.or(session.user_agent),
+            ip: device.ip.or(session.ip),
+            device: device.name.or(session.device),
+            rotated_at: None,
+            ..session
+        };
+        self.create(
+            token.user_id,
+            replacement,
+            subject,
+            validity_period,
+            Some(next.to_payload()?),
+        )
+        .await?;
+
+        Ok(Rotation::Rotated {
+            user_id: token.user_id,
+            session_id: next.session_id,
+        })
+    }
+
+    /// The user's sessions that can still be refreshed, oldest first.
+    pub async fn sessions(&self, user_id: Uuid, subject: String) -> errors::Result<Vec<Session>> {
+        let now = Utc::now().naive_utc();
+        let tokens = self.repo.all_by_user_and_subject(user_id, subject).await?;
+
+        Ok(tokens
+            .into_iter()
+            .filter(|(_, token)| token.expires_at > now)
+            .filter_map(|(_, token)| {
+                let session = SessionPayload::of_token(&token)?;
+                match session.rotated_at {
+                    Some(_) => None,
+                    None => Some(Session {
+                        id: session.session_id,
+                        started_at: session.started_at,
+                        last_used_at: token.created_at,
+                        expires_at: token.expires_at,
+                        device: session.device,
+                        user_agent: session.user_agent,
+                        ip: session.ip,
+                    }),
+                }
+            })
+            .collect())
+    }
+
+    /// Revokes every session of the user except `keep`.
+    pub async fn revoke_other_sessions(
+        &self,
+        user_id: Uuid,
+        subject: String,
+        keep: Option<Uuid>,
+    ) -> errors::Result<()> {
+        let ids: Vec<Uuid> = self
+            .repo
+            .all_by_user_and_subject(user_id, subject)
+            .await?
+            .into_iter()
+            .filter(|(_, token)| {
+                keep.is_none()
+                    || SessionPayload::of_token(token).map(|session| session.session_id) != keep
+            })
+            .map(|(id, _)| id)
+            .collect();
+
+        self.repo.delete_many(ids).await.map(|_| ())
+    }
+
+    /// Revokes every token of one session of the user.
+    pub async fn revoke_session(
+        &self,
+        user_id: Uuid,
+        subject: String,
+        session_id: Uuid,
+    ) -> errors::Result<()> {
+        let ids: Vec<Uuid> = self
+            .repo
+            .all_by_user_and_subject(user_id, subject)
+            .await?
+            .into_iter()
+            .filter(|(_, token)| {
+                SessionPayload::of_token(token).map(|session| session.session_id)
+                    == Some(session_id)
+            })
+            .map(|(id, _)| id)
+            .collect();
+
+        match self.repo.delete_many(ids).await? {
+            0 => Err(Errors::BadRequest("session not found".into())),
+            _ => Ok(()),
+        }
+    }
 }
 
 #[rocket::async_trait]
@@ -0,0 +1,309 @@
+use crate::errors::{self, Errors};
+use crate::persian;
+use chrono_tz::Tz;
+use rocket::serde::json::Json;
+use rocket::serde::Serialize;
+use std::borrow::Cow;
+
+const NATIONAL_NUMBER_LENGTH: usize = 10;
+const MOBILE_NUMBER_LENGTH: usize = 10;
+const IRAN_CALLING_CODE: &str = "98";
+const MIN_PASSWORD_LENGTH: usize = 8;
+const MAX_PASSWORD_LENGTH: usize = 128;
+
+/// Error code and message of a single field check.
+pub type FieldResult<T> = std::result::Result<T, (&'static str, String)>;
+
+#[derive(Debug, Serialize, Clone)]
+pub struct FieldError {
+    /// Name of the field, or its path in a nested request, as in
+    /// `persons[0].first_name`.
+    pub field: Cow<'static, str>,
+    pub code: &'static str,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct ValidationErrors {
+    pub errors: Vec<FieldError>,
+}
+
+/// Implemented by request payloads that need checking before they reach the
+/// database. Returns the entity with its fields normalised.
+pub trait Validate: Sized {
+    fn validate(self) -> errors::Result<Self>;
+}
+
+/// Collects every field error of an entity so they can be reported together.
+#[derive(Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the checked value, or records its error under `field`.
+    pub fn check<T>(
+        &mut self,
+        field: impl Into<Cow<'static, str>>,
+        result: FieldResult<T>,
+    ) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err((code, message)) => {
+                self.errors.push(FieldError {
+                    field: field.into(),
+                    code,
+                    message,
+                });
+                None
+            }
+        }
+    }
+
+    pub fn required(&mut self, field: &'static str, value: &str, max_length: usize) {
+        self.check(field, required(value, max_length));
+    }
+
+    pub fn optional(&mut self, field: &'static str, value: &Option<String>, max_length: usize) {
+        if let Some(value) = value {
+            self.check(field, max_chars(value, max_length));
+        }
+    }
+
+    /// Returns the value of a check made on an entity nested in a request,
+    /// or records its field errors under `path`, as in `persons[0]`. Errors
+    /// other than validation ones are handed back.
+    pub fn nested<T>(
+        &mut self,
+        path: &str,
+        result: errors::Result<T>,
+    ) -> errors::Result<Option<T>> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(Errors::ValidationError(Json(failed))) => {
+                self.errors
+                    .extend(failed.errors.into_iter().map(|error| FieldError {
+                        field: match error.field.is_empty() {
+                            true => path.to_owned().into(),
+                            false => format!("{}.{}", path, error.field).into(),
+                        },
+                        ..error
+                    }));
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn finish(self) -> errors::Result<()> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Errors::ValidationError(Json(ValidationErrors {
+                errors: self.errors,
+            })))
+        }
+    }
+}
+
+/// Builds the error for a single field check done outside of a `Validator`,
+/// e.g. one that needs the database.
+pub fn field_error(field: &'static str, code: &'static str, message: String) -> Errors {
+    Errors::ValidationError(Json(ValidationErrors {
+        errors: vec![FieldError {
+            field: field.into(),
+            code,
+            message,
+        }],
+    }))
+}
+
+pub fn required(value: &str, max_length: usize) -> FieldResult<()> {
+    if value.trim().is_empty() {
+        return Err(("required", "must not be empty".to_owned()));
+    }
+    max_chars(value, max_length)
+}
+
+pub fn max_chars(value: &str, max_length: usize) -> FieldResult<()> {
+    if value.chars().count() > max_length {
+        return Err((
+            "too_long",
+            format!("must be at most {} characters", max_length),
+        ));
+    }
+    Ok(())
+}
+
+pub fn password(value: &str) -> FieldResult<()> {
+    if value.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err((
+            "too_short",
+            format!("must be at least {} characters", MIN_PASSWORD_LENGTH),
+        ));
+    }
+    max_chars(value, MAX_PASSWORD_LENGTH)
+}
+
+/// Reads an IANA time zone name such as `Asia/Tehran`.
+pub fn timezone(value: &str) -> FieldResult<Tz> {
+    value.trim().parse::<Tz>().map_err(|_| {
+        (
+            "invalid_timezone",
+            format!("{} is not an IANA time zone", value),
+        )
+    })
+}
+
+/// Validates an Iranian national code (کد ملی) and returns it as ten ASCII
+/// digits. Codes missing their leading zeros are padded back.
+pub fn national_number(value: &str) -> FieldResult<String> {
+    let digits: String = persian::normalize_digits(value)
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect();
+
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(("invalid_format", "must contain only digits".to_owned()));
+    }
+    if !(8..=NATIONAL_NUMBER_LENGTH).contains(&digits.len()) {
+        return Err(("invalid_format", "must be 10 digits".to_owned()));
+    }
+
+    let code = format!("{:0>width$}", digits, width = NATIONAL_NUMBER_LENGTH);
+    let digits: Vec<u32> = code.chars().filter_map(|c| c.to_digit(10)).collect();
+
+    if digits.iter().all(|d| *d == digits[0]) {
+        return Err((
+            "invalid_checksum",
+            "is not a valid national code".to_owned(),
+        ));
+    }
+
+    let sum: u32 = digits[..9]
+        .iter()
+        .enumerate()
+        .map(|(i, d)| d * (10 - i as u32))
+        .sum();
+    let remainder = sum % 11;
+    let expected = if remainder < 2 {
+        remainder
+    } else {
+        11 - remainder
+    };
+
+    if digits[9] != expected {
+        return Err((
+            "invalid_checksum",
+            "is not a valid national code".to_owned(),
+        ));
+    }
+
+    Ok(code)
+}
+
+/// Validates an Iranian mobile number written in any of the usual forms
+/// (`0912...`, `912...`, `98912...`, `0098912...`, `+98912...`) and returns
+/// it in E.164 form, e.g. `+989121234567`.
+pub fn mobile_number(value: &str) -> FieldResult<String> {
+    let normalized: String = persian::normalize_digits(value)
+        .chars()
+        .filter(|c| !c.is_whitespace() && !matches!(c, '-' | '(' | ')'))
+        .collect();
+
+    let digits = normalized.strip_prefix('+').unwrap_or(&normalized);
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(("invalid_format", "must contain only digits".to_owned()));
+    }
+
+    let national = if let Some(rest) = digits.strip_prefix("00") {
+        rest.strip_prefix(IRAN_CALLING_CODE)
+    } else if normalized.starts_with('+') || digits.len() > MOBILE_NUMBER_LENGTH + 1 {
+        digits.strip_prefix(IRAN_CALLING_CODE)
+    } else {
+        Some(digits.strip_prefix('0').unwrap_or(digits))
+    };
+
+    match national {
+        Some(national) if national.len() == MOBILE_NUMBER_LENGTH && national.starts_with('9') => {
+            Ok(format!("+{}{}", IRAN_CALLING_CODE, national))
+        }
+        Some(_) => Err((
+            "invalid_format",
+            "is not a valid Iranian mobile number".to_owned(),
+        )),
+        None => Err((
+            "invalid_country",
+            "must be an Iranian (+98) mobile number".to_owned(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code(result: FieldResult<String>) -> &'static str {
+        result.unwrap_err().0
+    }
+
+    #[test]
+    fn national_number_checksum() {
+        assert_eq!(national_number("0499370899"), Ok("0499370899".to_owned()));
+        // Remainders below 2 are the check digit themselves.
+        assert_eq!(national_number("1234567891"), Ok("1234567891".to_owned()));
+        assert_eq!(national_number("1000000060"), Ok("1000000060".to_owned()));
+
+        assert_eq!(code(national_number("0499370898")), "invalid_checksum");
+        assert_eq!(code(national_number("1234567890")), "invalid_checksum");
+        assert_eq!(code(national_number("1111111111")), "invalid_checksum");
+        assert_eq!(code(national_number("0000000000")), "invalid_checksum");
+    }
+
+    #[test]
+    fn national_number_forms() {
+        let expected = Ok("0012345679".to_owned());
+        assert_eq!(national_number("12345679"), expected);
+        assert_eq!(national_number("012345679"), expected);
+        assert_eq!(national_number("001-234567-9"), expected);
+        assert_eq!(national_number(" 0012 3456 79 "), expected);
+        assert_eq!(national_number("۰۰۱۲۳۴۵۶۷۹"), expected);
+
+        assert_eq!(code(national_number("")), "invalid_format");
+        assert_eq!(code(national_number("00123a5679")), "invalid_format");
+        assert_eq!(code(national_number("1234567")), "invalid_format");
+        assert_eq!(code(national_number("00123456790")), "invalid_format");
+    }
+
+    #[test]
+    fn mobile_number_forms() {
+        let expected = Ok("+989121234567".to_owned());
+        assert_eq!(mobile_number("09121234567"), expected);
+        assert_eq!(mobile_number("9121234567"), expected);
+        assert_eq!(mobile_number("989121234567"), expected);
+        assert_eq!(mobile_number("00989121234567"), expected);
+        assert_eq!(mobile_number("+989121234567"), expected);
+        assert_eq!(mobile_number("+98 (912) 123-4567"), expected);
+        assert_eq!(mobile_number("۰۹۱۲۱۲۳۴۵۶۷"), expected);
+        assert_eq!(mobile_number("٠٩١٢١٢٣٤٥٦٧"), expected);
+    }
+
+    #[test]
+    fn mobile_number_rejects() {
+        assert_eq!(code(mobile_number("")), "invalid_format");
+        assert_eq!(code(mobile_number("0912123456a")), "invalid_format");
+        assert_eq!(code(mobile_number("+9891212345678")), "invalid_format");
+        assert_eq!(code(mobile_number("0912123456")), "invalid_format");
+        // Landlines start with an area code rather than 9.
+        assert_eq!(code(mobile_number("02188776655")), "invalid_format");
+        assert_eq!(code(mobile_number("+982188776655")), "invalid_format");
+
+        assert_eq!(code(mobile_number("+14155552671")), "invalid_country");
+        assert_eq!(code(mobile_number("00447911123456")), "invalid_country");
+        assert_eq!(code(mobile_number("+9647701234567")), "invalid_country");
+    }
+}
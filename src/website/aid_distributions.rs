@@ -0,0 +1,85 @@
+use super::calendar::CalendarJson;
+use super::case_access::{Authorized, CaseAccess, Editor, OfAidDistribution, Viewer};
+use super::Db;
+use crate::access::CasePermission;
+use crate::aid::{AidDistribution, AidDistributionDetails, AidTotal, NewAidDistribution};
+use crate::errors::*;
+use crate::filters::*;
+use crate::jalali::Calendar;
+use crate::service_options::ServiceOptions;
+use crate::validation::Validate;
+use rocket::serde::json::Json;
+use rocket::Route;
+use uuid::Uuid;
+
+/// Totals per month, or per year with `period=year`, of the cases the caller
+/// can see. Periods are Jalali months and years when Jalali dates were asked
+/// for.
+#[get("/totals?<filter..>")]
+async fn get_totals(
+    filter: AidTotalsFilter,
+    calendar: Calendar,
+    conn: Db,
+    access: CaseAccess,
+) -> Result<Json<Vec<AidTotal>>> {
+    let totals = AidDistribution::totals(&conn, filter, calendar, access.visibility()).await?;
+    Ok(Json(totals))
+}
+
+#[get("/<id>")]
+async fn get(
+    id: Uuid,
+    conn: Db,
+    _token: Authorized<OfAidDistribution, Viewer>,
+) -> Result<Option<Json<AidDistributionDetails>>> {
+    let distribution = AidDistribution::get(&conn, id).await?;
+    Ok(distribution.map(Json))
+}
+
+#[post("/", data = "<distribution>")]
+async fn insert(
+    distribution: CalendarJson<NewAidDistribution>,
+    conn: Db,
+    access: CaseAccess,
+    opts: ServiceOptions,
+) -> Result<Json<AidDistributionDetails>> {
+    access
+        .require(&conn, &*distribution, CasePermission::Editor)
+        .await?;
+    let distribution = AidDistribution::create(
+        &conn,
+        distribution.into_inner().validate()?,
+        opts.aid_monthly_cap,
+        access.0.user_id,
+    )
+    .await?;
+    Ok(Json(distribution))
+}
+
+#[put("/<id>", data = "<distribution>")]
+async fn update(
+    id: Uuid,
+    distribution: CalendarJson<NewAidDistribution>,
+    conn: Db,
+    token: Authorized<OfAidDistribution, Editor>,
+    opts: ServiceOptions,
+) -> Result<Json<AidDistributionDetails>> {
+    let distribution = AidDistribution::update(
+        &conn,
+        id,
+        distribution.into_inner().validate()?,
+        opts.aid_monthly_cap,
+        token.0.user_id,
+    )
+    .await?;
+    Ok(Json(distribution))
+}
+
+#[delete("/<id>")]
+async fn delete(id: Uuid, conn: Db, token: Authorized<OfAidDistribution, Editor>) -> Result<()> {
+    AidDistribution::delete(&conn, id, token.0.user_id).await
+}
+
+pub fn get_routes() -> Vec<Route> {
+    routes![get_totals, get, insert, update, delete]
+}
@@ -0,0 +1,39 @@
+use super::jwt::IsAdmin;
+use super::Db;
+use crate::audit::AuditEntry;
+use crate::errors::*;
+use crate::pagination::{Page, PageRequest, Sort};
+use rocket::serde::json::Json;
+use rocket::Route;
+use uuid::Uuid;
+
+#[get("/entity/<entity_type>/<entity_id>?<limit>&<cursor>")]
+async fn get_by_entity(
+    entity_type: String,
+    entity_id: Uuid,
+    limit: Option<i64>,
+    cursor: Option<String>,
+    conn: Db,
+    _admin: IsAdmin,
+) -> Result<Json<Page<AuditEntry>>> {
+    let page = PageRequest::new(limit, cursor, Sort::desc(()))?;
+    let entries = AuditEntry::page_by_entity(&conn, entity_type, entity_id, page).await?;
+    Ok(Json(entries))
+}
+
+#[get("/actor/<user_id>?<limit>&<cursor>")]
+async fn get_by_actor(
+    user_id: Uuid,
+    limit: Option<i64>,
+    cursor: Option<String>,
+    conn: Db,
+    _admin: IsAdmin,
+) -> Result<Json<Page<AuditEntry>>> {
+    let page = PageRequest::new(limit, cursor, Sort::desc(()))?;
+    let entries = AuditEntry::page_by_actor(&conn, user_id, page).await?;
+    Ok(Json(entries))
+}
+
+pub fn get_routes() -> Vec<Route> {
+    routes![get_by_entity, get_by_actor]
+}
@@ -4,32 +4,41 @@ use super::Db;
 use crate::errors::*;
 use crate::models::*;
 use crate::service_options::ServiceOptions;
-use crate::user_token_service;
+use crate::user_token_service::{self, Device, Rotation, Session};
 use chrono::Duration;
 use chrono::Utc;
+use rocket::request::{self, FromRequest, Outcome, Request};
 use rocket::serde::json::Json;
 use rocket::Route;
+use uuid::Uuid;
 
 const ACCESS_TOKEN_SUBJECT: &str = "ACCESS_TOKEN";
 const ACCESS_TOKEN_DURATION_SECONDS: i64 = 300;
 
-const REFRESH_TOKEN_SUBJECT: &str = "REFRESH_TOKEN";
+pub const REFRESH_TOKEN_SUBJECT: &str = "REFRESH_TOKEN";
 const REFRESH_TOKEN_DURATION_SECONDS: i64 = 604800;
 
 mod models {
+    use crate::enums::Role;
     use serde::{Deserialize, Serialize};
 
     #[derive(Deserialize)]
     pub struct LoginRequest {
         pub username: String,
         pub password: String,
+        /// Name the user gives this device, shown in their session list.
+        #[serde(default)]
+        pub device: Option<String>,
     }
 
     #[derive(Serialize)]
     pub struct LoginResponse {
         pub access_token: String,
-        pub role: i32,
+        pub role: Role,
         pub refresh_token: String,
+        /// Set after an admin reset the password; the client should ask for
+        /// a new one before doing anything else.
+        pub must_change_password: bool,
     }
 
     #[derive(Deserialize)]
@@ -38,140 +47,210 @@ mod models {
     }
 }
 
-#[get("/user-info")]
-async fn get_info(conn: Db, token: jwt::IsLoggedIn) -> Result<Option<Json<UserInfo>>> {
-    let user = User::get(&conn, token.0.user_id).await?;
-    Ok(user.map(UserInfo::of_user).map(Json))
+/// User agent and address of the caller, recorded on their session.
+struct Client {
+    user_agent: Option<String>,
+    ip: Option<String>,
 }
 
-#[post("/login", data = "<login_request>")]
-async fn login(
-    login_request: Json<models::LoginRequest>,
-    conn: Db,
-    user_token_service: user_token_service::T,
-    opts: ServiceOptions,
-) -> Result<Option<Json<models::LoginResponse>>> {
-    use sha2::{Digest, Sha256};
-
-    let user = match User::get_by_username(&conn, login_request.username.clone()).await? {
-        None => return Ok(None),
-        Some(user) => user,
-    };
+impl Client {
+    fn device(self, name: Option<String>) -> Device {
+        Device {
+            name,
+            user_agent: self.user_agent,
+            ip: self.ip,
+        }
+    }
+}
 
-    let mut hasher = Sha256::new();
-    hasher.update(login_request.password.as_bytes());
-    hasher.update(user.password_salt.clone());
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Client {
+    type Error = Errors;
 
-    if hasher.finalize().as_slice() != user.password_hash {
-        return Err(Errors::BadRequest("Invalid Credentials".into()));
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        Outcome::Success(Client {
+            user_agent: request.headers().get_one("user-agent").map(str::to_owned),
+            ip: request.client_ip().map(|ip| ip.to_string()),
+        })
     }
+}
+
+fn new_refresh_token() -> Result<String> {
+    rand::random::<[u8; 32]>()
+        .iter()
+        .map(|x| format!("{:x}", x))
+        .reduce(|ac, item| format!("{}{}", ac, item))
+        .ok_or_else(|| Errors::InternalError("cannot create refresh token".into()))
+}
 
+fn login_response(
+    user: &User,
+    session_id: Uuid,
+    refresh_token: String,
+    opts: &ServiceOptions,
+) -> Result<Json<models::LoginResponse>> {
     let access_token = jwt::Claims::new(
         user.id,
-        user.role.into(),
+        user.role,
+        session_id,
         ACCESS_TOKEN_SUBJECT.into(),
         Utc::now().naive_utc(),
         Duration::seconds(ACCESS_TOKEN_DURATION_SECONDS),
     )
     .to_jwt(&opts.jwt_keys.encoding_key)?;
 
-    let refresh_token = rand::random::<[u8; 32]>()
-        .iter()
-        .map(|x| format!("{:x}", x))
-        .reduce(|ac, item| format!("{}{}", ac, item))
-        .ok_or_else(|| Errors::InternalError("cannot create refresh token".into()))?;
+    Ok(Json(models::LoginResponse {
+        access_token,
+        role: user.role,
+        refresh_token,
+        must_change_password: user.must_change_password,
+    }))
+}
 
-    //TODO: we need multiple logins with same credentials
-    user_token_service
-        .revoke(user.id, REFRESH_TOKEN_SUBJECT.into())
-        .await?;
+#[get("/user-info")]
+async fn get_info(conn: Db, token: jwt::IsLoggedIn) -> Result<Option<Json<UserInfo>>> {
+    let user = User::get(&conn, token.0.user_id).await?;
+    Ok(user.map(UserInfo::of_user).map(Json))
+}
 
-    let _ = user_token_service
-        .create(
+#[post("/login", data = "<login_request>")]
+async fn login(
+    login_request: Json<models::LoginRequest>,
+    conn: Db,
+    client: Client,
+    user_token_service: user_token_service::T,
+    opts: ServiceOptions,
+) -> Result<Json<models::LoginResponse>> {
+    let login_request = login_request.into_inner();
+    let user = User::authenticate(
+        &conn,
+        login_request.username,
+        login_request.password,
+        opts.password_hashing,
+    )
+    .await?;
+
+    let refresh_token = new_refresh_token()?;
+    let session_id = user_token_service
+        .start_session(
             user.id,
             refresh_token.clone(),
             REFRESH_TOKEN_SUBJECT.into(),
             Duration::seconds(REFRESH_TOKEN_DURATION_SECONDS),
-            None,
+            client.device(login_request.device),
         )
         .await?;
 
-    let response = models::LoginResponse {
-        access_token,
-        role: user.role,
-        refresh_token,
-    };
-
-    Ok(Some(Json(response)))
+    login_response(&user, session_id, refresh_token, &opts)
 }
 
+/// Ends the session the access token was issued for. Tokens from before
+/// sessions existed do not name one, so all of the user's sessions end.
 #[post("/logout")]
 async fn logout(claims: jwt::IsLoggedIn, user_token_service: user_token_service::T) -> Result<()> {
-    //TODO: just log out that specific token, not all of tokens from this user.
-    user_token_service
-        .revoke(claims.0.user_id, REFRESH_TOKEN_SUBJECT.into())
-        .await
+    match claims.0.sid {
+        Some(session_id) => {
+            user_token_service
+                .revoke_session(claims.0.user_id, REFRESH_TOKEN_SUBJECT.into(), session_id)
+                .await
+        }
+        None => {
+            user_token_service
+                .revoke(claims.0.user_id, REFRESH_TOKEN_SUBJECT.into())
+                .await
+        }
+    }
 }
 
 #[post("/refresh", data = "<refresh_request>")]
 async fn refresh(
     refresh_request: Json<models::RefreshRequest>,
     conn: Db,
+    client: Client,
     user_token_service: user_token_service::T,
     opts: ServiceOptions,
 ) -> Result<Option<Json<models::LoginResponse>>> {
-    if let Some(token) = user_token_service
-        .get(
+    let refresh_token = new_refresh_token()?;
+    let rotation = user_token_service
+        .rotate(
             REFRESH_TOKEN_SUBJECT.into(),
-            refresh_request.refresh_token.clone(),
+            refresh_request.into_inner().refresh_token,
+            refresh_token.clone(),
+            Duration::seconds(REFRESH_TOKEN_DURATION_SECONDS),
+            client.device(None),
         )
-        .await?
-    {
-        let user = User::get(&conn, token.user_id)
-            .await?
-            .ok_or_else(|| Errors::InternalError("user not found".into()))?;
+        .await?;
 
-        let access_token = jwt::Claims::new(
-            user.id,
-            user.role.into(),
-            ACCESS_TOKEN_SUBJECT.into(),
-            Utc::now().naive_utc(),
-            Duration::seconds(ACCESS_TOKEN_DURATION_SECONDS),
-        )
-        .to_jwt(&opts.jwt_keys.encoding_key)?;
-
-        let refresh_token = rand::random::<[u8; 32]>()
-            .iter()
-            .map(|x| format!("{:x}", x))
-            .reduce(|ac, item| format!("{}{}", ac, item))
-            .ok_or_else(|| Errors::InternalError("cannot create refresh token".into()))?;
-
-        user_token_service
-            .revoke(user.id, REFRESH_TOKEN_SUBJECT.into())
-            .await?;
-
-        let _ = user_token_service
-            .create(
-                user.id,
-                refresh_token.clone(),
-                REFRESH_TOKEN_SUBJECT.into(),
-                Duration::seconds(REFRESH_TOKEN_DURATION_SECONDS),
-                None,
-            )
-            .await?;
-
-        let response = models::LoginResponse {
-            access_token,
-            role: user.role,
-            refresh_token,
-        };
-
-        Ok(Some(Json(response)))
-    } else {
-        Err(Errors::BadRequest("invalid refresh token".into()))
+    match rotation {
+        Rotation::Rotated {
+            user_id,
+            session_id,
+        } => {
+            let user = User::get(&conn, user_id)
+                .await?
+                .ok_or_else(|| Errors::InternalError("user not found".into()))?;
+            if !user.active {
+                user_token_service
+                    .revoke(user.id, REFRESH_TOKEN_SUBJECT.into())
+                    .await?;
+                return Err(Errors::Forbidden("account is disabled".into()));
+            }
+
+            login_response(&user, session_id, refresh_token, &opts).map(Some)
+        }
+        Rotation::Invalid => Err(Errors::BadRequest("invalid refresh token".into())),
+        Rotation::Reused => Err(Errors::BadRequest(
+            "refresh token was already used, the session has been ended".into(),
+        )),
     }
 }
 
+#[derive(rocket::serde::Serialize)]
+struct SessionInfo {
+    #[serde(flatten)]
+    session: Session,
+    /// Whether this is the session making the request.
+    current: bool,
+}
+
+#[get("/sessions")]
+async fn get_sessions(
+    claims: jwt::IsLoggedIn,
+    user_token_service: user_token_service::T,
+) -> Result<Json<Vec<SessionInfo>>> {
+    let sessions = user_token_service
+        .sessions(claims.0.user_id, REFRESH_TOKEN_SUBJECT.into())
+        .await?;
+
+    Ok(Json(
+        sessions
+            .into_iter()
+            .map(|session| SessionInfo {
+                current: claims.0.sid == Some(session.id),
+                session,
+            })
+            .collect(),
+    ))
+}
+
+#[delete("/sessions/<session_id>")]
+async fn revoke_session(
+    session_id: Uuid,
+    claims: jwt::IsLoggedIn,
+    user_token_service: user_token_service::T,
+) -> Result<()> {
+    user_token_service
+        .revoke_session(claims.0.user_id, REFRESH_TOKEN_SUBJECT.into(), session_id)
+        .await
+}
+
 pub fn get_routes() -> Vec<Route> {
-    routes![get_info, login, refresh, logout]
+    routes![
+        get_info,
+        login,
+        refresh,
+        logout,
+        get_sessions,
+        revoke_session
+    ]
 }
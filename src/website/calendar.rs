@@ -0,0 +1,268 @@
+use super::jwt::Claims;
+use super::Db;
+use crate::agenda;
+use crate::errors::*;
+use crate::jalali::{self, Calendar, JalaliDate};
+use crate::validation;
+use chrono::{NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use rocket::data::{self, Data, FromData, Limits};
+use rocket::fairing::AdHoc;
+use rocket::http::{ContentType, Status};
+use rocket::outcome::Outcome;
+use rocket::request::{self, FromRequest, Request};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::convert::Infallible;
+use std::io::Cursor;
+
+const CALENDAR_HEADER: &str = "Accept-Calendar";
+const CALENDAR_PARAM: &str = "calendar";
+const JALALI: &str = "jalali";
+const JALALI_SUFFIX: &str = "_jalali";
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+const DATE_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+/// Fields of the API's records that hold a date or a date-time. Only these
+/// get a Jalali twin, so free text that happens to look like a date is left
+/// as it is.
+const DATE_FIELDS: [&str; 25] = [
+    "action_date",
+    "birthday",
+    "cancelled_at",
+    "completed_at",
+    "created_at",
+    "deleted_at",
+    "distributed_on",
+    "due_date",
+    "end_date",
+    "expires_at",
+    "from",
+    "generated_at",
+    "imported_at",
+    "last_used_at",
+    "materialized_until",
+    "occurrence_at",
+    "registration_date",
+    "repeat_until",
+    "rolled_back_at",
+    "rotated_at",
+    "start_date",
+    "started_at",
+    "starts_at",
+    "to",
+    "updated_at",
+];
+
+/// Whether the caller asked for Jalali dates, with an `Accept-Calendar:
+/// jalali` header or a `calendar=jalali` query parameter.
+fn calendar(req: &Request<'_>) -> Calendar {
+    let header = req.headers().get_one(CALENDAR_HEADER);
+    let param = req
+        .query_value::<&str>(CALENDAR_PARAM)
+        .and_then(|param| param.ok());
+    match header.or(param) {
+        Some(name) if name.trim().eq_ignore_ascii_case(JALALI) => Calendar::Jalali,
+        _ => Calendar::Gregorian,
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Calendar {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        Outcome::Success(calendar(req))
+    }
+}
+
+/// Zone Jalali times are read and shown in, looked up once per request.
+struct CallerTimezone(Tz);
+
+async fn timezone(req: &Request<'_>) -> Tz {
+    req.local_cache_async(async { CallerTimezone(caller_timezone(req).await) })
+        .await
+        .0
+}
+
+/// The `tz` query parameter, the caller's own time zone, or UTC.
+async fn caller_timezone(req: &Request<'_>) -> Tz {
+    let requested = req
+        .query_value::<String>("tz")
+        .and_then(|requested| requested.ok());
+    let claims = req.guard::<Claims>().await.succeeded();
+    let conn = req.guard::<Db>().await.succeeded();
+
+    let tz = match (claims, conn) {
+        (Some(claims), Some(conn)) => agenda::timezone(&conn, claims.user_id, requested).await,
+        _ => requested.map_or(Ok(Tz::UTC), |requested| {
+            validation::timezone(&requested)
+                .map_err(|(code, message)| validation::field_error("tz", code, message))
+        }),
+    };
+    tz.unwrap_or(Tz::UTC)
+}
+
+/// Adds `<key>_jalali` next to the dates and date-times of `DATE_FIELDS` in
+/// JSON responses of callers that asked for Jalali dates. Times are shown in the caller's
+/// time zone, while the ISO ones stay in UTC.
+pub fn calendar_fairing() -> AdHoc {
+    AdHoc::on_response("Jalali dates", |req, res| {
+        Box::pin(async move {
+            if calendar(req) != Calendar::Jalali || res.content_type() != Some(ContentType::JSON) {
+                return;
+            }
+            let body = match res.body_mut().to_string().await {
+                Ok(body) => body,
+                Err(e) => {
+                    error!("jalali dates: could not read response: {:?}", e);
+                    return;
+                }
+            };
+
+            let body = match serde_json::from_str::<Value>(&body) {
+                Ok(mut value) => {
+                    let tz = timezone(req).await;
+                    add_jalali(&mut value, tz);
+                    value.to_string()
+                }
+                Err(_) => body,
+            };
+            res.set_sized_body(body.len(), Cursor::new(body));
+        })
+    })
+}
+
+fn add_jalali(value: &mut Value, tz: Tz) {
+    match value {
+        Value::Array(items) => items.iter_mut().for_each(|item| add_jalali(item, tz)),
+        Value::Object(fields) => {
+            let mut added = Map::new();
+            for (key, field) in fields.iter_mut() {
+                match field {
+                    Value::String(text) if DATE_FIELDS.contains(&key.as_str()) => {
+                        if let Some(jalali) = to_jalali(text, tz) {
+                            added.insert(format!("{}{}", key, JALALI_SUFFIX), jalali.into());
+                        }
+                    }
+                    Value::String(_) => {}
+                    field => add_jalali(field, tz),
+                }
+            }
+            fields.extend(added);
+        }
+        _ => {}
+    }
+}
+
+fn to_jalali(text: &str, tz: Tz) -> Option<String> {
+    if let Ok(date) = NaiveDate::parse_from_str(text, DATE_FORMAT) {
+        return JalaliDate::from_gregorian(date).map(|date| date.to_string());
+    }
+    let at = NaiveDateTime::parse_from_str(text, DATE_TIME_FORMAT).ok()?;
+    jalali::format_date_time(Utc.from_utc_datetime(&at).with_timezone(&tz).naive_local())
+}
+
+/// Reads `<key>_jalali` fields as `<key>`, the way `calendar_fairing` writes
+/// them, and replaces the ISO field when both are given so that a record can
+/// be sent back as it was read.
+fn from_jalali(value: &mut Value, tz: Tz) -> Result<()> {
+    let fields = match value {
+        Value::Array(items) => return items.iter_mut().try_for_each(|item| from_jalali(item, tz)),
+        Value::Object(fields) => fields,
+        _ => return Ok(()),
+    };
+
+    let keys: Vec<String> = fields
+        .keys()
+        .filter(|key| key.len() > JALALI_SUFFIX.len() && key.ends_with(JALALI_SUFFIX))
+        .cloned()
+        .collect();
+    for key in keys {
+        let jalali = fields.remove(&key).unwrap_or(Value::Null);
+        let converted = match &jalali {
+            Value::Null => Value::Null,
+            Value::String(text) => from_jalali_text(text, tz)
+                .ok_or_else(|| Errors::BadRequest(format!("{} is not a Jalali date", key)))?
+                .into(),
+            _ => return Err(Errors::BadRequest(format!("{} must be a string", key))),
+        };
+        fields.insert(key[..key.len() - JALALI_SUFFIX.len()].to_owned(), converted);
+    }
+
+    fields
+        .values_mut()
+        .try_for_each(|field| from_jalali(field, tz))
+}
+
+fn from_jalali_text(text: &str, tz: Tz) -> Option<String> {
+    if !text.trim().contains(['T', ' ']) {
+        let date = JalaliDate::parse(text)?.to_gregorian()?;
+        return Some(date.format(DATE_FORMAT).to_string());
+    }
+    let local = jalali::parse_date_time(text)?;
+    let at = tz.from_local_datetime(&local).earliest()?;
+    Some(at.naive_utc().format("%Y-%m-%dT%H:%M:%S").to_string())
+}
+
+/// JSON request body whose dates may also be given in the Jalali calendar,
+/// as `<key>_jalali` next to or instead of `<key>`. Jalali times are read in
+/// the caller's time zone.
+#[derive(Debug)]
+pub struct CalendarJson<T>(pub T);
+
+impl<T> CalendarJson<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> std::ops::Deref for CalendarJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, T: DeserializeOwned> FromData<'r> for CalendarJson<T> {
+    type Error = Errors;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let limit = req.limits().get("json").unwrap_or(Limits::JSON);
+        let body = match data.open(limit).into_string().await {
+            Ok(body) if body.is_complete() => body.into_inner(),
+            Ok(_) => {
+                return Outcome::Failure((
+                    Status::PayloadTooLarge,
+                    Errors::BadRequest(format!("body is larger than {}", limit)),
+                ))
+            }
+            Err(e) => {
+                return Outcome::Failure((Status::BadRequest, Errors::BadRequest(e.to_string())))
+            }
+        };
+
+        let mut value = match serde_json::from_str::<Value>(&body) {
+            Ok(value) => value,
+            Err(e) => {
+                return Outcome::Failure((Status::BadRequest, Errors::BadRequest(e.to_string())))
+            }
+        };
+        if body.contains(JALALI_SUFFIX) {
+            if let Err(e) = from_jalali(&mut value, timezone(req).await) {
+                return Outcome::Failure((Status::UnprocessableEntity, e));
+            }
+        }
+
+        match serde_json::from_value(value) {
+            Ok(value) => Outcome::Success(CalendarJson(value)),
+            Err(e) => Outcome::Failure((
+                Status::UnprocessableEntity,
+                Errors::BadRequest(e.to_string()),
+            )),
+        }
+    }
+}
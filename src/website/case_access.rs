@@ -0,0 +1,177 @@
+use super::jwt::{Claims, HasEditorPermissions};
+use super::Db;
+use crate::access::{self, CasePermission, Target, Targets, Visibility};
+use crate::enums::Role;
+use crate::errors::{self, Errors};
+use rocket::{
+    http::Status,
+    request::{self, FromRequest, Outcome, Request},
+};
+use std::marker::PhantomData;
+use uuid::Uuid;
+
+/// An admin or editor whose access to a case still has to be checked, for
+/// routes that find the case in the request body or list many cases.
+pub struct CaseAccess(pub Claims);
+
+impl CaseAccess {
+    pub fn visibility(&self) -> Visibility {
+        match self.0.role {
+            Role::Admin => Visibility::All,
+            _ => Visibility::AssignedTo(self.0.user_id),
+        }
+    }
+
+    /// Fails with `Forbidden` unless the caller holds `level` on every case
+    /// `targets` belongs to. Admins hold every level on every case.
+    pub async fn require(
+        &self,
+        conn: &Db,
+        targets: &impl Targets,
+        level: CasePermission,
+    ) -> errors::Result<()> {
+        match self.0.role {
+            Role::Admin => Ok(()),
+            _ => access::check(conn, self.0.user_id, targets.targets(), level).await,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CaseAccess {
+    type Error = Errors;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match request.guard::<HasEditorPermissions>().await {
+            Outcome::Success(HasEditorPermissions(token)) => Outcome::Success(CaseAccess(token)),
+            Outcome::Failure(e) => Outcome::Failure(e),
+            Outcome::Forward(f) => Outcome::Forward(f),
+        }
+    }
+}
+
+/// Kind of row named by the first dynamic segment of a route.
+pub trait Resource {
+    fn target(id: Uuid) -> Target;
+}
+
+pub struct OfCase;
+pub struct OfPerson;
+pub struct OfPersonJob;
+pub struct OfPersonSkill;
+pub struct OfPersonRequirement;
+pub struct OfCaseAction;
+pub struct OfCaseActionSeries;
+pub struct OfAidDistribution;
+
+impl Resource for OfCase {
+    fn target(id: Uuid) -> Target {
+        Target::Case(id)
+    }
+}
+
+impl Resource for OfPerson {
+    fn target(id: Uuid) -> Target {
+        Target::Person(id)
+    }
+}
+
+impl Resource for OfPersonJob {
+    fn target(id: Uuid) -> Target {
+        Target::PersonJob(id)
+    }
+}
+
+impl Resource for OfPersonSkill {
+    fn target(id: Uuid) -> Target {
+        Target::PersonSkill(id)
+    }
+}
+
+impl Resource for OfPersonRequirement {
+    fn target(id: Uuid) -> Target {
+        Target::PersonRequirement(id)
+    }
+}
+
+impl Resource for OfCaseAction {
+    fn target(id: Uuid) -> Target {
+        Target::CaseAction(id)
+    }
+}
+
+impl Resource for OfCaseActionSeries {
+    fn target(id: Uuid) -> Target {
+        Target::CaseActionSeries(id)
+    }
+}
+
+impl Resource for OfAidDistribution {
+    fn target(id: Uuid) -> Target {
+        Target::AidDistribution(id)
+    }
+}
+
+pub trait Level {
+    const PERMISSION: CasePermission;
+}
+
+pub struct Viewer;
+pub struct Editor;
+pub struct Owner;
+
+impl Level for Viewer {
+    const PERMISSION: CasePermission = CasePermission::Viewer;
+}
+
+impl Level for Editor {
+    const PERMISSION: CasePermission = CasePermission::Editor;
+}
+
+impl Level for Owner {
+    const PERMISSION: CasePermission = CasePermission::Owner;
+}
+
+/// Caller holding at least `L` on the case of the `R` whose id is the first
+/// dynamic segment of the route, e.g. `Authorized<OfPerson, Editor>` on
+/// `/person/<id>/set-leader`. Unknown or deleted ids are forwarded, which
+/// ends in a 404.
+pub struct Authorized<R, L>(pub Claims, PhantomData<(R, L)>);
+
+#[rocket::async_trait]
+impl<'r, R, L> FromRequest<'r> for Authorized<R, L>
+where
+    R: Resource + Send + Sync + 'static,
+    L: Level + Send + Sync + 'static,
+{
+    type Error = Errors;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let access = match request.guard::<CaseAccess>().await {
+            Outcome::Success(access) => access,
+            Outcome::Failure(e) => return Outcome::Failure(e),
+            Outcome::Forward(f) => return Outcome::Forward(f),
+        };
+        let id = match request.param::<Uuid>(0) {
+            Some(Ok(id)) => id,
+            _ => return Outcome::Forward(()),
+        };
+        let conn = match request.guard::<Db>().await {
+            Outcome::Success(conn) => conn,
+            Outcome::Failure((status, ())) => {
+                return Outcome::Failure((
+                    status,
+                    Errors::InternalError("database is not available".to_owned()),
+                ))
+            }
+            Outcome::Forward(f) => return Outcome::Forward(f),
+        };
+
+        match access.require(&conn, &R::target(id), L::PERMISSION).await {
+            Ok(()) => Outcome::Success(Authorized(access.0, PhantomData)),
+            Err(e @ Errors::Forbidden(_)) => Outcome::Failure((Status::Forbidden, e)),
+            Err(Errors::BadRequest(_)) => Outcome::Forward(()),
+            Err(e) => Outcome::Failure((Status::InternalServerError, e)),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use super::calendar::CalendarJson;
+use super::case_access::{Authorized, CaseAccess, Editor, OfCaseActionSeries, Viewer};
+use super::{Db, JobDatabase};
+use crate::access::CasePermission;
+use crate::errors::*;
+use crate::models::*;
+use crate::service_options::ServiceOptions;
+use chrono::{Duration, NaiveDateTime, Utc};
+use rocket::fairing::AdHoc;
+use rocket::serde::json::Json;
+use rocket::Route;
+use uuid::Uuid;
+
+/// How often running series are extended up to the horizon.
+const EXTEND_INTERVAL_SECS: u64 = 60 * 60;
+
+fn horizon(opts: &ServiceOptions) -> NaiveDateTime {
+    Utc::now().naive_utc() + Duration::days(opts.recurrence_horizon_days)
+}
+
+#[get("/<id>")]
+async fn get(
+    id: Uuid,
+    conn: Db,
+    _token: Authorized<OfCaseActionSeries, Viewer>,
+) -> Result<Option<Json<CaseActionSeriesDetails>>> {
+    let series = CaseActionSeries::get(&conn, id).await?;
+    Ok(series.map(|series| Json(series.into())))
+}
+
+#[get("/<id>/occurrences")]
+async fn occurrences(
+    id: Uuid,
+    conn: Db,
+    _token: Authorized<OfCaseActionSeries, Viewer>,
+) -> Result<Json<Vec<CaseAction>>> {
+    let occurrences = CaseActionSeries::occurrences(&conn, id).await?;
+    Ok(Json(occurrences))
+}
+
+#[post("/", data = "<series>")]
+async fn insert(
+    series: CalendarJson<NewCaseActionSeries>,
+    conn: Db,
+    access: CaseAccess,
+    opts: ServiceOptions,
+) -> Result<Json<CaseActionSeriesDetails>> {
+    access
+        .require(&conn, &*series, CasePermission::Editor)
+        .await?;
+    let series =
+        CaseActionSeries::new(&conn, series.into_inner(), horizon(&opts), access.0.user_id).await?;
+    Ok(Json(series.into()))
+}
+
+#[put("/<id>", data = "<series>")]
+async fn update(
+    id: Uuid,
+    series: CalendarJson<CaseActionSeriesUpdate>,
+    conn: Db,
+    token: Authorized<OfCaseActionSeries, Editor>,
+    opts: ServiceOptions,
+) -> Result<Json<CaseActionSeriesDetails>> {
+    let series = CaseActionSeries::update(
+        &conn,
+        id,
+        series.into_inner(),
+        horizon(&opts),
+        token.0.user_id,
+    )
+    .await?;
+    Ok(Json(series.into()))
+}
+
+#[post("/<id>/cancel")]
+async fn cancel(
+    id: Uuid,
+    conn: Db,
+    token: Authorized<OfCaseActionSeries, Editor>,
+) -> Result<Json<CaseActionSeriesDetails>> {
+    let series = CaseActionSeries::cancel(&conn, id, token.0.user_id).await?;
+    Ok(Json(series.into()))
+}
+
+pub fn get_routes() -> Vec<Route> {
+    routes![get, occurrences, insert, update, cancel]
+}
+
+/// Extends every running series once at launch and then every
+/// `EXTEND_INTERVAL_SECS`, so there are always actions created up to
+/// `recurrence_horizon_days` ahead.
+pub fn extend_fairing() -> AdHoc {
+    AdHoc::on_liftoff("Recurring actions", |rocket| {
+        Box::pin(async move {
+            let opts = match ServiceOptions::create(rocket.figment()) {
+                Ok(opts) => opts,
+                Err(e) => {
+                    error!("recurring actions disabled: {:?}", e);
+                    return;
+                }
+            };
+            let db = match JobDatabase::from(rocket) {
+                Ok(db) => db,
+                Err(e) => {
+                    error!("recurring actions disabled: {:?}", e);
+                    return;
+                }
+            };
+
+            rocket::tokio::spawn(async move {
+                let mut interval = rocket::tokio::time::interval(std::time::Duration::from_secs(
+                    EXTEND_INTERVAL_SECS,
+                ));
+                loop {
+                    interval.tick().await;
+                    let until = horizon(&opts);
+                    if let Err(e) = db
+                        .run(move |c| CaseActionSeries::extend_all_in(c, until))
+                        .await
+                    {
+                        error!("recurring actions failed: {:?}", e);
+                    }
+                }
+            });
+        })
+    })
+}
@@ -1,47 +1,318 @@
-use super::jwt::HasEditorPermissions;
+use super::calendar::CalendarJson;
+use super::case_access::{Authorized, CaseAccess, Editor, OfCaseAction, Viewer};
 use super::Db;
+use crate::access::{self, CasePermission, Target, Visibility};
+use crate::agenda::{self, AgendaQuery, Window};
+use crate::enums::{ActionStatus, Role, Transition};
 use crate::errors::*;
+use crate::filters::*;
+use crate::ical;
+use crate::jalali::Calendar;
 use crate::models::*;
+use crate::pagination::{Page, PageRequest, Sort};
+use crate::user_token_service;
+use crate::validation::Validate;
+use chrono::{Duration, Utc};
+use rocket::http::ContentType;
 use rocket::serde::json::Json;
 use rocket::Route;
 use uuid::Uuid;
 
-#[get("/")]
-async fn get_all(conn: Db, _token: HasEditorPermissions) -> Result<Json<Vec<CaseAction>>> {
-    let actions = CaseAction::all(&conn).await?;
+const CALENDAR_FEED_SUBJECT: &str = "CALENDAR_FEED";
+const CALENDAR_FEED_DURATION_DAYS: i64 = 365;
+
+/// Days before and after today that a calendar feed covers.
+const CALENDAR_PAST_DAYS: i64 = 30;
+const CALENDAR_FUTURE_DAYS: i64 = 365;
+
+mod models {
+    use chrono::NaiveDateTime;
+    use serde::{Deserialize, Serialize};
+
+    /// Optional body of `complete` and `cancel`.
+    #[derive(Deserialize)]
+    pub struct TransitionRequest {
+        pub result_note: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    pub struct CalendarFeedResponse {
+        pub token: String,
+        /// Path of the feed with the token in it, for calendar apps that take
+        /// a single URL.
+        pub path: String,
+        pub expires_at: NaiveDateTime,
+    }
+}
+
+#[get("/?<limit>&<cursor>&<sort>&<filter..>")]
+async fn get_all(
+    limit: Option<i64>,
+    cursor: Option<String>,
+    sort: Option<Sort<CaseActionSortKey>>,
+    filter: CaseActionFilter,
+    conn: Db,
+    access: CaseAccess,
+) -> Result<Json<Page<CaseAction>>> {
+    let page = PageRequest::new(limit, cursor, sort.unwrap_or_default())?;
+    let actions = CaseAction::page(&conn, filter, page, access.visibility()).await?;
+    Ok(Json(actions))
+}
+
+#[get("/agenda?<filter..>")]
+async fn get_agenda(
+    filter: AgendaFilter,
+    calendar: Calendar,
+    conn: Db,
+    access: CaseAccess,
+) -> Result<Json<Vec<CaseAction>>> {
+    let tz = agenda::timezone(&conn, access.0.user_id, filter.tz.clone()).await?;
+    let query = AgendaQuery::from_filter(filter, tz, calendar)?;
+    let actions = CaseAction::agenda(&conn, query, access.visibility()).await?;
+    Ok(Json(actions))
+}
+
+#[get("/overdue?<case_id>&<assignee>")]
+async fn get_overdue(
+    case_id: Option<Uuid>,
+    assignee: Option<Uuid>,
+    conn: Db,
+    access: CaseAccess,
+) -> Result<Json<Vec<CaseAction>>> {
+    let query = AgendaQuery {
+        case_id,
+        assignee,
+        ..AgendaQuery::overdue()
+    };
+    let actions = CaseAction::agenda(&conn, query, access.visibility()).await?;
     Ok(Json(actions))
 }
 
+/// Gives the caller a new calendar feed token, which stops any earlier one
+/// from working.
+#[post("/calendar-token")]
+async fn create_calendar_token(
+    access: CaseAccess,
+    user_token_service: user_token_service::T,
+) -> Result<Json<models::CalendarFeedResponse>> {
+    let token: String = rand::random::<[u8; 32]>()
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect();
+
+    user_token_service
+        .revoke(access.0.user_id, CALENDAR_FEED_SUBJECT.into())
+        .await?;
+    let feed = user_token_service
+        .create(
+            access.0.user_id,
+            token,
+            CALENDAR_FEED_SUBJECT.into(),
+            Duration::days(CALENDAR_FEED_DURATION_DAYS),
+            None,
+        )
+        .await?;
+
+    Ok(Json(models::CalendarFeedResponse {
+        path: format!("/case-action/calendar.ics?token={}", feed.token),
+        token: feed.token,
+        expires_at: feed.expires_at,
+    }))
+}
+
+#[delete("/calendar-token")]
+async fn revoke_calendar_token(
+    access: CaseAccess,
+    user_token_service: user_token_service::T,
+) -> Result<()> {
+    user_token_service
+        .revoke(access.0.user_id, CALENDAR_FEED_SUBJECT.into())
+        .await
+}
+
+/// Case actions as an iCalendar feed for the owner of `token`: all the
+/// actions they can see, or those of one case or one assignee. Calendar apps
+/// cannot send a bearer token, so the feed token stands in for it.
+#[get("/calendar.ics?<token>&<case_id>&<assignee>")]
+async fn calendar(
+    token: String,
+    case_id: Option<Uuid>,
+    assignee: Option<Uuid>,
+    conn: Db,
+    user_token_service: user_token_service::T,
+) -> Result<(ContentType, String)> {
+    let now = Utc::now().naive_utc();
+    let feed = match user_token_service
+        .get(CALENDAR_FEED_SUBJECT.into(), token)
+        .await?
+    {
+        Some(feed) if feed.expires_at > now => feed,
+        _ => return Err(Errors::Forbidden("invalid calendar feed token".into())),
+    };
+
+    let visibility = match User::get(&conn, feed.user_id).await? {
+        Some(user) if user.active && user.role == Role::Admin => Visibility::All,
+        Some(user) if user.active && user.role == Role::Editor => Visibility::AssignedTo(user.id),
+        _ => return Err(Errors::Forbidden("invalid calendar feed token".into())),
+    };
+    if let (Some(case_id), Visibility::AssignedTo(user)) = (case_id, visibility) {
+        access::check(
+            &conn,
+            user,
+            vec![Target::Case(case_id)],
+            CasePermission::Viewer,
+        )
+        .await?;
+    }
+
+    let query = AgendaQuery {
+        window: Some(Window {
+            from: now - Duration::days(CALENDAR_PAST_DAYS),
+            to: now + Duration::days(CALENDAR_FUTURE_DAYS),
+        }),
+        statuses: vec![
+            ActionStatus::Todo,
+            ActionStatus::Doing,
+            ActionStatus::Done,
+            ActionStatus::Cancelled,
+        ],
+        overdue_before: None,
+        case_id,
+        assignee,
+    };
+    let events = CaseAction::calendar(&conn, query, visibility).await?;
+    Ok((
+        ContentType::Calendar,
+        ical::calendar("Case actions", &events),
+    ))
+}
+
 #[get("/<id>")]
-async fn get(id: Uuid, conn: Db, _token: HasEditorPermissions) -> Result<Option<Json<CaseAction>>> {
+async fn get(
+    id: Uuid,
+    conn: Db,
+    _token: Authorized<OfCaseAction, Viewer>,
+) -> Result<Option<Json<CaseAction>>> {
     let case_action = CaseAction::get(&conn, id).await?;
     Ok(case_action.map(Json))
 }
 
 #[post("/", data = "<case_action>")]
 async fn insert(
-    case_action: Json<NewCaseAction>,
+    case_action: CalendarJson<NewCaseAction>,
     conn: Db,
-    _token: HasEditorPermissions,
+    access: CaseAccess,
 ) -> Result<Json<CaseAction>> {
-    let case_action = CaseAction::new(&conn, case_action.into_inner()).await?;
+    access
+        .require(&conn, &*case_action, CasePermission::Editor)
+        .await?;
+    let case_action = CaseAction::new(&conn, case_action.into_inner(), access.0.user_id).await?;
     Ok(Json(case_action))
 }
 
 #[put("/", data = "<case_action>")]
-async fn update(
-    case_action: Json<CaseAction>,
-    conn: Db,
-    _token: HasEditorPermissions,
-) -> Result<()> {
-    case_action.into_inner().update(&conn).await
+async fn update(case_action: CalendarJson<CaseAction>, conn: Db, access: CaseAccess) -> Result<()> {
+    access
+        .require(&conn, &*case_action, CasePermission::Editor)
+        .await?;
+    case_action
+        .into_inner()
+        .update(&conn, access.0.user_id)
+        .await
 }
 
 #[delete("/<id>")]
-async fn delete(id: Uuid, conn: Db, _token: HasEditorPermissions) -> Result<()> {
-    CaseAction::delete(&conn, id).await
+async fn delete(id: Uuid, conn: Db, token: Authorized<OfCaseAction, Editor>) -> Result<()> {
+    CaseAction::delete(&conn, id, token.0.user_id).await
+}
+
+#[post("/<id>/start")]
+async fn start(
+    id: Uuid,
+    conn: Db,
+    token: Authorized<OfCaseAction, Editor>,
+) -> Result<Json<CaseAction>> {
+    let case_action =
+        CaseAction::transition(&conn, id, Transition::Start, None, token.0.user_id).await?;
+    Ok(Json(case_action))
+}
+
+#[post("/<id>/complete", data = "<request>")]
+async fn complete(
+    id: Uuid,
+    request: Option<Json<models::TransitionRequest>>,
+    conn: Db,
+    token: Authorized<OfCaseAction, Editor>,
+) -> Result<Json<CaseAction>> {
+    let note = request.and_then(|r| r.into_inner().result_note);
+    let case_action =
+        CaseAction::transition(&conn, id, Transition::Complete, note, token.0.user_id).await?;
+    Ok(Json(case_action))
+}
+
+#[post("/<id>/cancel", data = "<request>")]
+async fn cancel(
+    id: Uuid,
+    request: Option<Json<models::TransitionRequest>>,
+    conn: Db,
+    token: Authorized<OfCaseAction, Editor>,
+) -> Result<Json<CaseAction>> {
+    let note = request.and_then(|r| r.into_inner().result_note);
+    let case_action =
+        CaseAction::transition(&conn, id, Transition::Cancel, note, token.0.user_id).await?;
+    Ok(Json(case_action))
+}
+
+#[post("/<id>/reopen")]
+async fn reopen(
+    id: Uuid,
+    conn: Db,
+    token: Authorized<OfCaseAction, Editor>,
+) -> Result<Json<CaseAction>> {
+    let case_action =
+        CaseAction::transition(&conn, id, Transition::Reopen, None, token.0.user_id).await?;
+    Ok(Json(case_action))
+}
+
+#[get("/<id>/comment")]
+async fn get_comments(
+    id: Uuid,
+    conn: Db,
+    _token: Authorized<OfCaseAction, Viewer>,
+) -> Result<Json<Vec<CaseActionComment>>> {
+    let comments = CaseAction::comments(&conn, id).await?;
+    Ok(Json(comments))
+}
+
+#[post("/<id>/comment", data = "<comment>")]
+async fn add_comment(
+    id: Uuid,
+    comment: Json<NewCaseActionComment>,
+    conn: Db,
+    token: Authorized<OfCaseAction, Editor>,
+) -> Result<Json<CaseActionComment>> {
+    let comment = comment.into_inner().validate()?;
+    let comment = CaseAction::add_comment(&conn, id, comment, token.0.user_id).await?;
+    Ok(Json(comment))
 }
 
 pub fn get_routes() -> Vec<Route> {
-    routes![get, get_all, insert, update, delete]
+    routes![
+        get,
+        get_all,
+        get_agenda,
+        get_overdue,
+        create_calendar_token,
+        revoke_calendar_token,
+        calendar,
+        insert,
+        update,
+        delete,
+        start,
+        complete,
+        cancel,
+        reopen,
+        get_comments,
+        add_comment
+    ]
 }
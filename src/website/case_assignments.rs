@@ -0,0 +1,41 @@
+use super::jwt::IsAdmin;
+use super::Db;
+use crate::access::CaseAssignment;
+use crate::errors::*;
+use rocket::serde::json::Json;
+use rocket::Route;
+use uuid::Uuid;
+
+#[get("/case/<case_id>")]
+async fn get_by_case(
+    case_id: Uuid,
+    conn: Db,
+    _admin: IsAdmin,
+) -> Result<Json<Vec<CaseAssignment>>> {
+    let assignments = CaseAssignment::all_by_case_id(&conn, case_id).await?;
+    Ok(Json(assignments))
+}
+
+#[get("/user/<user_id>")]
+async fn get_by_user(
+    user_id: Uuid,
+    conn: Db,
+    _admin: IsAdmin,
+) -> Result<Json<Vec<CaseAssignment>>> {
+    let assignments = CaseAssignment::all_by_user_id(&conn, user_id).await?;
+    Ok(Json(assignments))
+}
+
+#[put("/", data = "<assignment>")]
+async fn assign(assignment: Json<CaseAssignment>, conn: Db, admin: IsAdmin) -> Result<()> {
+    assignment.into_inner().assign(&conn, admin.0.user_id).await
+}
+
+#[delete("/<case_id>/<user_id>")]
+async fn unassign(case_id: Uuid, user_id: Uuid, conn: Db, admin: IsAdmin) -> Result<()> {
+    CaseAssignment::unassign(&conn, case_id, user_id, admin.0.user_id).await
+}
+
+pub fn get_routes() -> Vec<Route> {
+    routes![get_by_case, get_by_user, assign, unassign]
+}
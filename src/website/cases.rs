@@ -1,55 +1,120 @@
-use super::jwt::HasEditorPermissions;
-use super::Db;
+use super::calendar::CalendarJson;
+use super::case_access::{Authorized, CaseAccess, Editor, OfCase, Owner, Viewer};
+use super::jwt::IsAdmin;
+use super::{Db, JobDatabase};
+use crate::access::{CasePermission, Visibility};
+use crate::agenda::{self, AgendaQuery, Window};
+use crate::aid::{AidDistribution, AidDistributionDetails};
+use crate::case_file::{CaseFile, NewCaseFile};
+use crate::dossier::{self, Dossier, DossierTemplate, DossierTemplateDetails, NewDossierTemplate};
 use crate::errors::*;
+use crate::export::{self, DateStyle, Export};
+use crate::filters::*;
+use crate::household::{self, CaseSummary, NeedWeights};
+use crate::jalali::Calendar;
 use crate::models::*;
+use crate::pagination::{Page, PageRequest, Sort, DEFAULT_PAGE_LIMIT, MAX_PAGE_LIMIT};
+use crate::service_options::ServiceOptions;
+use crate::validation::Validate;
+use futures::stream::{self, StreamExt};
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::Request;
+use rocket::response::stream::ByteStream;
+use rocket::response::{self, Responder, Response};
 use rocket::serde::json::Json;
 use rocket::Route;
 use uuid::Uuid;
 
 #[get("/<id>")]
-async fn get(id: Uuid, conn: Db, _token: HasEditorPermissions) -> Result<Option<Json<Case>>> {
+async fn get(id: Uuid, conn: Db, _token: Authorized<OfCase, Viewer>) -> Result<Option<Json<Case>>> {
     let case = Case::get(&conn, id).await?;
     Ok(case.map(Json))
 }
 
-#[get("/")]
-async fn get_all(conn: Db, _token: HasEditorPermissions) -> Result<Json<Vec<Case>>> {
-    //TODO: Needs pagination
-    let cases = Case::all(&conn).await?;
+#[get("/?<limit>&<cursor>&<sort>&<filter..>")]
+async fn get_all(
+    limit: Option<i64>,
+    cursor: Option<String>,
+    sort: Option<Sort<CaseSortKey>>,
+    filter: CaseFilter,
+    conn: Db,
+    access: CaseAccess,
+) -> Result<Json<Page<Case>>> {
+    let page = PageRequest::new(limit, cursor, sort.unwrap_or_default())?;
+    let cases = Case::page(&conn, filter, page, access.visibility()).await?;
     Ok(Json(cases))
 }
 
 #[post("/", data = "<case>")]
-async fn insert(case: Json<NewCase>, conn: Db, token: HasEditorPermissions) -> Result<Json<Case>> {
-    let case = Case::new(&conn, case.into_inner(), token.0.user_id).await?;
+async fn insert(case: CalendarJson<NewCase>, conn: Db, access: CaseAccess) -> Result<Json<Case>> {
+    let case = Case::new(&conn, case.into_inner(), access.0.user_id).await?;
     Ok(Json(case))
 }
 
+#[get("/<id>/full")]
+async fn get_full(
+    id: Uuid,
+    conn: Db,
+    _token: Authorized<OfCase, Viewer>,
+) -> Result<Option<Json<CaseFile>>> {
+    let file = CaseFile::get(&conn, id).await?;
+    Ok(file.map(Json))
+}
+
+#[post("/full?<tz>", data = "<file>")]
+async fn insert_full(
+    file: CalendarJson<NewCaseFile>,
+    tz: Option<String>,
+    conn: Db,
+    opts: ServiceOptions,
+    access: CaseAccess,
+) -> Result<Json<CaseFile>> {
+    let tz = agenda::timezone(&conn, access.0.user_id, tz).await?;
+    let file = file
+        .into_inner()
+        .create(
+            &conn,
+            opts.duplicate_policy,
+            access.0.user_id,
+            agenda::today(tz),
+        )
+        .await?;
+    Ok(Json(file))
+}
+
 #[put("/", data = "<case>")]
-async fn update(case: Json<Case>, conn: Db, _token: HasEditorPermissions) -> Result<()> {
-    case.into_inner().update(&conn).await
+async fn update(case: CalendarJson<Case>, conn: Db, access: CaseAccess) -> Result<()> {
+    access
+        .require(&conn, &*case, CasePermission::Editor)
+        .await?;
+    case.into_inner().update(&conn, access.0.user_id).await
 }
 
 #[delete("/<id>")]
-async fn delete(id: Uuid, conn: Db, _token: HasEditorPermissions) -> Result<()> {
-    Case::delete(&conn, id).await
+async fn delete(id: Uuid, conn: Db, token: Authorized<OfCase, Owner>) -> Result<()> {
+    Case::delete(&conn, id, token.0.user_id).await
+}
+
+#[post("/<id>/restore")]
+async fn restore(id: Uuid, conn: Db, admin: IsAdmin) -> Result<()> {
+    Case::restore(&conn, id, admin.0.user_id).await
 }
 
 #[post("/<id>/activate")]
-async fn activate(id: Uuid, conn: Db, _token: HasEditorPermissions) -> Result<()> {
+async fn activate(id: Uuid, conn: Db, token: Authorized<OfCase, Editor>) -> Result<()> {
     let case = Case::get(&conn, id).await?;
     match case {
         None => Err(Errors::BadRequest("invalid id".to_owned())),
-        Some(case) => case.activate().update(&conn).await,
+        Some(case) => case.activate().update(&conn, token.0.user_id).await,
     }
 }
 
 #[post("/<id>/deactivate")]
-async fn deactivate(id: Uuid, conn: Db, _token: HasEditorPermissions) -> Result<()> {
+async fn deactivate(id: Uuid, conn: Db, token: Authorized<OfCase, Editor>) -> Result<()> {
     let case = Case::get(&conn, id).await?;
     match case {
         None => Err(Errors::BadRequest("invalid id".to_owned())),
-        Some(case) => case.deactivate().update(&conn).await,
+        Some(case) => case.deactivate().update(&conn, token.0.user_id).await,
     }
 }
 
@@ -57,7 +122,7 @@ async fn deactivate(id: Uuid, conn: Db, _token: HasEditorPermissions) -> Result<
 async fn get_all_persons(
     id: Uuid,
     conn: Db,
-    _token: HasEditorPermissions,
+    _token: Authorized<OfCase, Viewer>,
 ) -> Result<Json<Vec<Person>>> {
     let persons = Person::all_by_case_id(&conn, id).await;
     persons.map(Json)
@@ -67,30 +132,256 @@ async fn get_all_persons(
 async fn get_all_actions(
     id: Uuid,
     conn: Db,
-    _token: HasEditorPermissions,
+    _token: Authorized<OfCase, Viewer>,
 ) -> Result<Json<Vec<CaseAction>>> {
     let actions = CaseAction::all_by_case_id(&conn, id).await;
     actions.map(Json)
 }
 
-#[get("/<id>/action/week")]
+#[get("/<id>/action/week?<tz>")]
 async fn get_week_actions(
     id: Uuid,
+    tz: Option<String>,
     conn: Db,
-    _token: HasEditorPermissions,
+    token: Authorized<OfCase, Viewer>,
 ) -> Result<Json<Vec<CaseAction>>> {
-    let actions = CaseAction::week_actions_for_case(&conn, id).await;
-    actions.map(Json)
+    let tz = agenda::timezone(&conn, token.0.user_id, tz).await?;
+    let query = AgendaQuery {
+        case_id: Some(id),
+        ..AgendaQuery::window(Window::week(tz))
+    };
+    let actions = CaseAction::agenda(&conn, query, Visibility::All).await?;
+    Ok(Json(actions))
 }
 
-#[get("/<id>/action/today")]
+#[get("/<id>/action/today?<tz>")]
 async fn get_today_actions(
     id: Uuid,
+    tz: Option<String>,
     conn: Db,
-    _token: HasEditorPermissions,
+    token: Authorized<OfCase, Viewer>,
 ) -> Result<Json<Vec<CaseAction>>> {
-    let actions = CaseAction::today_actions_for_case(&conn, id).await;
-    actions.map(Json)
+    let tz = agenda::timezone(&conn, token.0.user_id, tz).await?;
+    let query = AgendaQuery {
+        case_id: Some(id),
+        ..AgendaQuery::window(Window::today(tz))
+    };
+    let actions = CaseAction::agenda(&conn, query, Visibility::All).await?;
+    Ok(Json(actions))
+}
+
+#[get("/<id>/action-series")]
+async fn get_action_series(
+    id: Uuid,
+    conn: Db,
+    _token: Authorized<OfCase, Viewer>,
+) -> Result<Json<Vec<CaseActionSeriesDetails>>> {
+    let series = CaseActionSeries::all_by_case_id(&conn, id).await?;
+    Ok(Json(series.into_iter().map(Into::into).collect()))
+}
+
+#[get("/<id>/aid")]
+async fn get_aid(
+    id: Uuid,
+    conn: Db,
+    _token: Authorized<OfCase, Viewer>,
+) -> Result<Json<Vec<AidDistributionDetails>>> {
+    let distributions = AidDistribution::all_by_case_id(&conn, id).await?;
+    Ok(Json(distributions))
+}
+
+#[get("/<id>/summary")]
+async fn get_summary(
+    id: Uuid,
+    conn: Db,
+    _token: Authorized<OfCase, Viewer>,
+) -> Result<Option<Json<CaseSummary>>> {
+    let summary = household::summary(&conn, id).await?;
+    Ok(summary.map(Json))
+}
+
+/// Active cases by need score, highest first.
+#[get("/ranking?<limit>")]
+async fn get_ranking(
+    limit: Option<i64>,
+    conn: Db,
+    access: CaseAccess,
+) -> Result<Json<Vec<CaseSummary>>> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+    if !(1..=MAX_PAGE_LIMIT).contains(&limit) {
+        return Err(Errors::BadRequest(format!(
+            "limit must be between 1 and {}",
+            MAX_PAGE_LIMIT
+        )));
+    }
+    let ranking = household::ranking(&conn, access.visibility(), limit).await?;
+    Ok(Json(ranking))
+}
+
+#[get("/need-weights")]
+async fn get_need_weights(conn: Db, _access: CaseAccess) -> Result<Json<NeedWeights>> {
+    let weights = NeedWeights::get(&conn).await?;
+    Ok(Json(weights))
+}
+
+#[put("/need-weights", data = "<weights>")]
+async fn update_need_weights(
+    weights: Json<NeedWeights>,
+    conn: Db,
+    admin: IsAdmin,
+) -> Result<Json<NeedWeights>> {
+    let weights = weights.into_inner().validate()?;
+    let weights = NeedWeights::update(&conn, weights, admin.0.user_id).await?;
+    Ok(Json(weights))
+}
+
+#[get("/dossier-template")]
+async fn get_dossier_template(conn: Db, _admin: IsAdmin) -> Result<Json<DossierTemplateDetails>> {
+    let template = DossierTemplate::get(&conn).await?;
+    Ok(Json(template.into()))
+}
+
+#[put("/dossier-template", data = "<template>")]
+async fn update_dossier_template(
+    template: Json<NewDossierTemplate>,
+    conn: Db,
+    admin: IsAdmin,
+) -> Result<Json<DossierTemplateDetails>> {
+    let template = template.into_inner().validate()?;
+    let template = DossierTemplate::update(&conn, Some(template), admin.0.user_id).await?;
+    Ok(Json(template.into()))
+}
+
+/// Goes back to the built-in template.
+#[delete("/dossier-template")]
+async fn reset_dossier_template(conn: Db, admin: IsAdmin) -> Result<Json<DossierTemplateDetails>> {
+    let template = DossierTemplate::update(&conn, None, admin.0.user_id).await?;
+    Ok(Json(template.into()))
+}
+
+/// A PDF shown in the browser, to be printed or saved under `file_name`.
+struct Printout {
+    body: Vec<u8>,
+    file_name: String,
+}
+
+impl<'r> Responder<'r, 'static> for Printout {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        Response::build_from(self.body.respond_to(req)?)
+            .header(ContentType::PDF)
+            .header(Header::new(
+                "Content-Disposition",
+                format!("inline; filename=\"{}\"", self.file_name),
+            ))
+            .ok()
+    }
+}
+
+/// The case with its members, their default jobs, incomes, skills and open
+/// requirements, and its recent actions, printed from the dossier template.
+#[get("/<id>/dossier.pdf?<tz>")]
+async fn get_dossier(
+    id: Uuid,
+    tz: Option<String>,
+    conn: Db,
+    token: Authorized<OfCase, Viewer>,
+) -> Result<Option<Printout>> {
+    let tz = agenda::timezone(&conn, token.0.user_id, tz).await?;
+    let dossier = match Dossier::load(&conn, id, tz).await? {
+        Some(dossier) => dossier,
+        None => return Ok(None),
+    };
+    let template = DossierTemplate::get(&conn).await?;
+    let file_name = dossier.file_name();
+    let body = rocket::tokio::task::spawn_blocking(move || dossier::render(&template, &dossier))
+        .await
+        .map_err(|e| Errors::InternalError(e.to_string()))??;
+    Ok(Some(Printout { body, file_name }))
+}
+
+/// A file sent while it is being written. The export starts once the
+/// response is sent, on a database connection of its own, so the request's
+/// pooled connection is back in the pool while the client reads.
+struct Download(Export);
+
+impl<'r> Responder<'r, 'r> for Download {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'r> {
+        let export = self.0;
+        let content_type = match export.format {
+            SpreadsheetFormat::Csv => ContentType::CSV,
+            SpreadsheetFormat::Xlsx => ContentType::new(
+                "application",
+                "vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            ),
+        };
+        let file_name = export.file_name();
+        let db = req
+            .rocket()
+            .state::<JobDatabase>()
+            .ok_or(Status::InternalServerError)?;
+
+        let chunks = export::spawn(db.clone(), export);
+        let body = stream::unfold(chunks, |mut chunks| async move {
+            chunks.recv().await.map(|chunk| (chunk, chunks))
+        })
+        .boxed();
+        Response::build_from(ByteStream(body).respond_to(req)?)
+            .header(content_type)
+            .header(Header::new(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", file_name),
+            ))
+            .ok()
+    }
+}
+
+/// Cases matching the same filters as the case list, with their members,
+/// jobs, skills, requirements and actions. See `Export::sheets` for what
+/// each format holds.
+#[get("/export?<format>&<sheet>&<tz>&<filter..>")]
+async fn export_all(
+    format: Option<SpreadsheetFormat>,
+    sheet: Option<ExportSheet>,
+    tz: Option<String>,
+    filter: CaseFilter,
+    calendar: Calendar,
+    conn: Db,
+    access: CaseAccess,
+) -> Result<Download> {
+    let format = format.unwrap_or(SpreadsheetFormat::Csv);
+    let tz = agenda::timezone(&conn, access.0.user_id, tz).await?;
+    let export = Export {
+        format,
+        sheets: Export::sheets(format, sheet),
+        filter,
+        case_id: None,
+        visibility: access.visibility(),
+        dates: DateStyle { calendar, tz },
+    };
+    Ok(Download(export))
+}
+
+#[get("/<id>/export?<format>&<sheet>&<tz>")]
+async fn export_case(
+    id: Uuid,
+    format: Option<SpreadsheetFormat>,
+    sheet: Option<ExportSheet>,
+    tz: Option<String>,
+    calendar: Calendar,
+    conn: Db,
+    token: Authorized<OfCase, Viewer>,
+) -> Result<Download> {
+    let format = format.unwrap_or(SpreadsheetFormat::Csv);
+    let tz = agenda::timezone(&conn, token.0.user_id, tz).await?;
+    let export = Export {
+        format,
+        sheets: Export::sheets(format, sheet),
+        filter: CaseFilter::default(),
+        case_id: Some(id),
+        visibility: Visibility::All,
+        dates: DateStyle { calendar, tz },
+    };
+    Ok(Download(export))
 }
 
 pub fn get_routes() -> Vec<Route> {
@@ -99,12 +390,27 @@ pub fn get_routes() -> Vec<Route> {
         get_all,
         get_all_persons,
         insert,
+        get_full,
+        insert_full,
         update,
         delete,
+        restore,
         activate,
         deactivate,
         get_all_actions,
         get_week_actions,
-        get_today_actions
+        get_today_actions,
+        get_action_series,
+        get_aid,
+        get_summary,
+        get_ranking,
+        export_all,
+        export_case,
+        get_need_weights,
+        update_need_weights,
+        get_dossier,
+        get_dossier_template,
+        update_dossier_template,
+        reset_dossier_template
     ]
 }
@@ -0,0 +1,110 @@
+use self::models::ImportUpload;
+
+use super::jwt::IsAdmin;
+use super::Db;
+use crate::agenda;
+use crate::errors::*;
+use crate::filters::SpreadsheetFormat;
+use crate::import::{self, ImportBatch, ImportReport, Upload};
+use crate::service_options::ServiceOptions;
+use rocket::form::Form;
+use rocket::serde::json::Json;
+use rocket::Route;
+use uuid::Uuid;
+
+mod models {
+    use crate::filters::SpreadsheetFormat;
+    use crate::import::Mapping;
+    use rocket::fs::TempFile;
+    use rocket::serde::json::Json;
+
+    /// A spreadsheet of families, with its columns mapped from field name to
+    /// header. The format is told from the file when it is left out.
+    #[derive(FromForm)]
+    pub struct ImportUpload<'r> {
+        pub file: TempFile<'r>,
+        pub mapping: Option<Json<Mapping>>,
+        pub format: Option<SpreadsheetFormat>,
+    }
+}
+
+const FILE_NAME_MAX_LENGTH: usize = 255;
+
+/// Checks the file and reports every row that would not import. Only with
+/// `commit=true`, and only when no row has errors, is it saved as a batch.
+#[post("/?<commit>&<tz>", data = "<upload>")]
+async fn upload(
+    upload: Form<ImportUpload<'_>>,
+    commit: Option<bool>,
+    tz: Option<String>,
+    conn: Db,
+    opts: ServiceOptions,
+    admin: IsAdmin,
+) -> Result<Json<ImportReport>> {
+    let upload = upload.into_inner();
+    let path = match upload.file.path() {
+        Some(path) => path,
+        None => {
+            return Err(Errors::BadRequest(
+                "file must be an uploaded file".to_owned(),
+            ))
+        }
+    };
+    let content = rocket::tokio::fs::read(path).await?;
+    let format = upload
+        .format
+        .unwrap_or(match content.starts_with(b"PK\x03\x04") {
+            true => SpreadsheetFormat::Xlsx,
+            false => SpreadsheetFormat::Csv,
+        });
+    let file_name = upload
+        .file
+        .raw_name()
+        .map(|name| name.dangerous_unsafe_unsanitized_raw().as_str())
+        .unwrap_or("import")
+        .chars()
+        .take(FILE_NAME_MAX_LENGTH)
+        .collect();
+
+    let upload = Upload {
+        file_name,
+        format,
+        content,
+        mapping: upload.mapping.map(Json::into_inner),
+    };
+    let tz = agenda::timezone(&conn, admin.0.user_id, tz).await?;
+    let report = import::import(
+        &conn,
+        upload,
+        opts.duplicate_policy,
+        commit.unwrap_or(false),
+        admin.0.user_id,
+        agenda::today(tz),
+    )
+    .await?;
+    Ok(Json(report))
+}
+
+#[get("/")]
+async fn get_all(conn: Db, _admin: IsAdmin) -> Result<Json<Vec<ImportBatch>>> {
+    let batches = ImportBatch::all(&conn).await?;
+    Ok(Json(batches))
+}
+
+#[get("/<id>")]
+async fn get(id: Uuid, conn: Db, _admin: IsAdmin) -> Result<Json<ImportBatch>> {
+    let batch = ImportBatch::get(&conn, id).await?;
+    Ok(Json(batch))
+}
+
+/// Removes the cases the import created, as long as no work has been
+/// recorded on them and no persons or jobs have been added to them since.
+#[post("/<id>/rollback")]
+async fn rollback(id: Uuid, conn: Db, admin: IsAdmin) -> Result<Json<ImportBatch>> {
+    let batch = ImportBatch::rollback(&conn, id, admin.0.user_id).await?;
+    Ok(Json(batch))
+}
+
+pub fn get_routes() -> Vec<Route> {
+    routes![upload, get_all, get, rollback]
+}
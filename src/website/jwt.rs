@@ -1,4 +1,6 @@
-use crate::{errors::Errors, service_options::ServiceOptions};
+use super::Db;
+use crate::models::{AccountStatus, User};
+use crate::{enums::Role, errors::Errors, service_options::ServiceOptions};
 use chrono::{Duration, NaiveDateTime};
 use jsonwebtoken::{decode, encode, errors::ErrorKind, Algorithm, EncodingKey, Header, Validation};
 use rocket::{
@@ -12,24 +14,6 @@ const TOKEN_SCHEME: &str = "Bearer";
 
 pub struct T {}
 
-#[derive(Deserialize, Serialize, PartialEq, Debug)]
-pub enum Role {
-    Admin,
-    Editor,
-    User,
-}
-
-impl From<i32> for Role {
-    fn from(number: i32) -> Self {
-        match number {
-            0 => Role::Admin,
-            1 => Role::Editor,
-            2 => Role::User,
-            _ => panic!("invalid user role number {}", number),
-        }
-    }
-}
-
 #[derive(Deserialize, Serialize, Debug)]
 pub struct Claims {
     pub sub: String,
@@ -37,12 +21,17 @@ pub struct Claims {
     pub exp: i64,
     pub user_id: Uuid,
     pub role: Role,
+    /// Session the token was issued for; missing on tokens issued before
+    /// sessions existed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<Uuid>,
 }
 
 impl Claims {
     pub fn new(
         user_id: Uuid,
         role: Role,
+        session_id: Uuid,
         subject: String,
         created_at: NaiveDateTime,
         validity_period: Duration,
@@ -52,6 +41,7 @@ impl Claims {
         Self {
             user_id,
             role,
+            sid: Some(session_id),
             sub: subject,
             iat: created_at.timestamp(),
             exp: expires_at.timestamp(),
@@ -65,8 +55,11 @@ impl Claims {
     }
 }
 
+/// Claims of a well-formed, unexpired token, before its user is checked.
+struct Token(Claims);
+
 #[rocket::async_trait]
-impl<'r> FromRequest<'r> for Claims {
+impl<'r> FromRequest<'r> for Token {
     type Error = Errors;
 
     async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
@@ -105,7 +98,7 @@ impl<'r> FromRequest<'r> for Claims {
                         Ok(token) => token,
                     };
 
-                    Outcome::Success(token.claims)
+                    Outcome::Success(Token(token.claims))
                 } else {
                     return Outcome::Failure((
                         Status::Unauthorized,
@@ -117,6 +110,73 @@ impl<'r> FromRequest<'r> for Claims {
     }
 }
 
+/// Status of the token's user, looked up once per request.
+struct CachedStatus(Result<Option<AccountStatus>, String>);
+
+async fn account_status(
+    request: &Request<'_>,
+    user_id: Uuid,
+) -> Result<Option<AccountStatus>, Errors> {
+    let cached = request
+        .local_cache_async(async {
+            let status = match request.guard::<Db>().await.succeeded() {
+                Some(conn) => User::status(&conn, user_id)
+                    .await
+                    .map_err(|e| format!("{:?}", e)),
+                None => Err("no database connection".to_owned()),
+            };
+            CachedStatus(status)
+        })
+        .await;
+    cached.0.clone().map_err(Errors::DatabaseError)
+}
+
+/// Caller changing their own password: the one thing a user given a
+/// temporary password may do. Disabled users are refused.
+pub struct ChangingPassword(pub Claims);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ChangingPassword {
+    type Error = Errors;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let claims = match request.guard::<Token>().await {
+            Outcome::Success(Token(claims)) => claims,
+            Outcome::Failure(e) => return Outcome::Failure(e),
+            Outcome::Forward(()) => return Outcome::Forward(()),
+        };
+        match account_status(request, claims.user_id).await {
+            Ok(Some(status)) if status.active => Outcome::Success(ChangingPassword(claims)),
+            Ok(_) => Outcome::Failure((
+                Status::Unauthorized,
+                Errors::BadRequest("account is disabled".to_string()),
+            )),
+            Err(e) => Outcome::Failure((Status::InternalServerError, e)),
+        }
+    }
+}
+
+/// Claims of an active user who has no temporary password left to change.
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Claims {
+    type Error = Errors;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let claims = match request.guard::<ChangingPassword>().await {
+            Outcome::Success(ChangingPassword(claims)) => claims,
+            Outcome::Failure(e) => return Outcome::Failure(e),
+            Outcome::Forward(()) => return Outcome::Forward(()),
+        };
+        match account_status(request, claims.user_id).await {
+            Ok(Some(status)) if status.must_change_password => Outcome::Failure((
+                Status::Forbidden,
+                Errors::Forbidden("the password must be changed first".to_string()),
+            )),
+            _ => Outcome::Success(claims),
+        }
+    }
+}
+
 pub struct IsAdmin(pub Claims);
 pub struct IsEditor(pub Claims);
 pub struct IsUser(pub Claims);
@@ -1,30 +1,102 @@
+use crate::errors::*;
+use diesel::{Connection, PgConnection};
+use rocket::fairing::AdHoc;
+use rocket::{Phase, Rocket};
 use rocket_sync_db_pools::database;
 
+mod aid_distributions;
+mod audit;
 mod auth;
+mod calendar;
+mod case_access;
+mod case_action_series;
 mod case_actions;
+mod case_assignments;
 mod cases;
 mod cors;
+mod imports;
 mod jwt;
 mod person_jobs;
 mod person_requirements;
 mod person_skills;
 mod persons;
+mod search;
+mod skills;
+mod trash;
 mod users;
 
 #[database("form_website")]
 pub struct Db(diesel::PgConnection);
 
+/// Where background jobs and downloads connect to the database. Jobs have
+/// no request to take a pooled connection from, and a download would keep
+/// one for as long as the client takes to read it, so each run opens a
+/// connection of its own.
+#[derive(Clone)]
+pub struct JobDatabase(String);
+
+impl JobDatabase {
+    pub fn from<P: Phase>(rocket: &Rocket<P>) -> Result<Self> {
+        rocket
+            .figment()
+            .extract_inner::<String>("databases.form_website.url")
+            .map(JobDatabase)
+            .map_err(|e| Errors::InternalError(e.to_string()))
+    }
+
+    /// Makes the database available to routes as managed state.
+    pub fn fairing() -> AdHoc {
+        AdHoc::try_on_ignite("Job database", |rocket| async {
+            match JobDatabase::from(&rocket) {
+                Ok(db) => Ok(rocket.manage(db)),
+                Err(e) => {
+                    error!("job database not configured: {:?}", e);
+                    Err(rocket)
+                }
+            }
+        })
+    }
+
+    /// Runs `f` on a new connection, which is closed when it returns.
+    pub async fn run<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&PgConnection) -> Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let url = self.0.clone();
+        rocket::tokio::task::spawn_blocking(move || {
+            let c =
+                PgConnection::establish(&url).map_err(|e| Errors::DatabaseError(e.to_string()))?;
+            f(&c)
+        })
+        .await
+        .map_err(|e| Errors::InternalError(e.to_string()))?
+    }
+}
+
 pub async fn run() -> std::result::Result<(), rocket::Error> {
     let rocket = rocket::build()
         .mount("/auth", auth::get_routes())
+        .mount("/audit", audit::get_routes())
         .mount("/user", users::get_routes())
         .mount("/case", cases::get_routes())
         .mount("/case-action", case_actions::get_routes())
+        .mount("/case-action-series", case_action_series::get_routes())
+        .mount("/aid-distribution", aid_distributions::get_routes())
+        .mount("/case-assignment", case_assignments::get_routes())
         .mount("/person", persons::get_routes())
         .mount("/person-job", person_jobs::get_routes())
         .mount("/person-skill", person_skills::get_routes())
         .mount("/person-requirement", person_requirements::get_routes())
+        .mount("/skill", skills::get_routes())
+        .mount("/import", imports::get_routes())
+        .mount("/search", search::get_routes())
+        .mount("/trash", trash::get_routes())
         .attach(Db::fairing())
+        .attach(JobDatabase::fairing())
+        .attach(trash::purge_fairing())
+        .attach(case_action_series::extend_fairing())
+        .attach(calendar::calendar_fairing())
         .attach(cors::cors_fairing());
 
     rocket.ignite().await?.launch().await
@@ -1,40 +1,73 @@
-use super::jwt::HasEditorPermissions;
+use super::calendar::CalendarJson;
+use super::case_access::{Authorized, CaseAccess, Editor, OfPersonJob, Viewer};
 use super::Db;
+use crate::access::CasePermission;
+use crate::agenda;
 use crate::errors::*;
 use crate::models::*;
+use crate::validation::Validate;
 use rocket::serde::json::Json;
 use rocket::Route;
 use uuid::Uuid;
 
 #[get("/<id>")]
-async fn get(id: Uuid, conn: Db, _token: HasEditorPermissions) -> Result<Option<Json<PersonJob>>> {
+async fn get(
+    id: Uuid,
+    conn: Db,
+    _token: Authorized<OfPersonJob, Viewer>,
+) -> Result<Option<Json<PersonJob>>> {
     let job = PersonJob::get(&conn, id).await?;
     Ok(job.map(Json))
 }
 
-#[post("/", data = "<job>")]
+#[post("/?<tz>", data = "<job>")]
 async fn insert(
-    job: Json<NewPersonJob>,
+    job: CalendarJson<NewPersonJob>,
+    tz: Option<String>,
     conn: Db,
-    _token: HasEditorPermissions,
+    access: CaseAccess,
 ) -> Result<Json<PersonJob>> {
-    let job = PersonJob::new(&conn, job.into_inner()).await?;
+    access.require(&conn, &*job, CasePermission::Editor).await?;
+    let tz = agenda::timezone(&conn, access.0.user_id, tz).await?;
+    let job = PersonJob::new(
+        &conn,
+        job.into_inner().validate()?,
+        access.0.user_id,
+        agenda::today(tz),
+    )
+    .await?;
     Ok(Json(job))
 }
 
-#[put("/", data = "<job>")]
-async fn update(job: Json<PersonJob>, conn: Db, _token: HasEditorPermissions) -> Result<()> {
-    job.into_inner().update(&conn).await
+#[put("/?<tz>", data = "<job>")]
+async fn update(
+    job: CalendarJson<PersonJob>,
+    tz: Option<String>,
+    conn: Db,
+    access: CaseAccess,
+) -> Result<()> {
+    access.require(&conn, &*job, CasePermission::Editor).await?;
+    let tz = agenda::timezone(&conn, access.0.user_id, tz).await?;
+    job.into_inner()
+        .validate()?
+        .update(&conn, access.0.user_id, agenda::today(tz))
+        .await
 }
 
 #[delete("/<id>")]
-async fn delete(id: Uuid, conn: Db) -> Result<()> {
-    PersonJob::delete(&conn, id).await
+async fn delete(id: Uuid, conn: Db, token: Authorized<OfPersonJob, Editor>) -> Result<()> {
+    PersonJob::delete(&conn, id, token.0.user_id).await
 }
 
-#[post("/<id>/set-default")]
-async fn set_default(id: Uuid, conn: Db, _token: HasEditorPermissions) -> Result<()> {
-    PersonJob::set_default(&conn, id).await
+#[post("/<id>/set-default?<tz>")]
+async fn set_default(
+    id: Uuid,
+    tz: Option<String>,
+    conn: Db,
+    token: Authorized<OfPersonJob, Editor>,
+) -> Result<()> {
+    let tz = agenda::timezone(&conn, token.0.user_id, tz).await?;
+    PersonJob::set_default(&conn, id, token.0.user_id, agenda::today(tz)).await
 }
 
 pub fn get_routes() -> Vec<Route> {
@@ -1,16 +1,37 @@
-use super::jwt::HasEditorPermissions;
+use super::calendar::CalendarJson;
+use super::case_access::{Authorized, CaseAccess, Editor, OfPersonRequirement, Viewer};
 use super::Db;
+use crate::access::CasePermission;
 use crate::errors::*;
+use crate::filters::*;
 use crate::models::*;
+use crate::pagination::{Page, PageRequest, Sort};
+use crate::validation::Validate;
 use rocket::serde::json::Json;
 use rocket::Route;
 use uuid::Uuid;
 
+/// Requirements across every case the caller can see, most urgent first, so
+/// they can be matched to donations.
+#[get("/?<limit>&<cursor>&<sort>&<filter..>")]
+async fn get_all(
+    limit: Option<i64>,
+    cursor: Option<String>,
+    sort: Option<Sort<RequirementSortKey>>,
+    filter: RequirementFilter,
+    conn: Db,
+    access: CaseAccess,
+) -> Result<Json<Page<PersonRequirement>>> {
+    let page = PageRequest::new(limit, cursor, sort.unwrap_or_default())?;
+    let requirements = PersonRequirement::page(&conn, filter, page, access.visibility()).await?;
+    Ok(Json(requirements))
+}
+
 #[get("/<id>")]
 async fn get(
     id: Uuid,
     conn: Db,
-    _token: HasEditorPermissions,
+    _token: Authorized<OfPersonRequirement, Viewer>,
 ) -> Result<Option<Json<PersonRequirement>>> {
     let requirement = PersonRequirement::get(&conn, id).await?;
     Ok(requirement.map(Json))
@@ -18,28 +39,43 @@ async fn get(
 
 #[post("/", data = "<requirement>")]
 async fn insert(
-    requirement: Json<NewPersonRequirement>,
+    requirement: CalendarJson<NewPersonRequirement>,
     conn: Db,
-    _token: HasEditorPermissions,
+    access: CaseAccess,
 ) -> Result<Json<PersonRequirement>> {
-    let requirement = PersonRequirement::new(&conn, requirement.into_inner()).await?;
+    access
+        .require(&conn, &*requirement, CasePermission::Editor)
+        .await?;
+    let requirement = PersonRequirement::new(
+        &conn,
+        requirement.into_inner().validate()?,
+        access.0.user_id,
+    )
+    .await?;
     Ok(Json(requirement))
 }
 
 #[put("/", data = "<requirement>")]
 async fn update(
-    requirement: Json<PersonRequirement>,
+    requirement: CalendarJson<PersonRequirement>,
     conn: Db,
-    _token: HasEditorPermissions,
+    access: CaseAccess,
 ) -> Result<()> {
-    requirement.into_inner().update(&conn).await
+    access
+        .require(&conn, &*requirement, CasePermission::Editor)
+        .await?;
+    requirement
+        .into_inner()
+        .validate()?
+        .update(&conn, access.0.user_id)
+        .await
 }
 
 #[delete("/<id>")]
-async fn delete(id: Uuid, conn: Db, _token: HasEditorPermissions) -> Result<()> {
-    PersonRequirement::delete(&conn, id).await
+async fn delete(id: Uuid, conn: Db, token: Authorized<OfPersonRequirement, Editor>) -> Result<()> {
+    PersonRequirement::delete(&conn, id, token.0.user_id).await
 }
 
 pub fn get_routes() -> Vec<Route> {
-    routes![get, insert, update, delete]
+    routes![get_all, get, insert, update, delete]
 }
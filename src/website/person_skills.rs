@@ -1,5 +1,6 @@
-use super::jwt::HasEditorPermissions;
+use super::case_access::{Authorized, CaseAccess, Editor, OfPersonSkill, Viewer};
 use super::Db;
+use crate::access::CasePermission;
 use crate::errors::*;
 use crate::models::*;
 use rocket::serde::json::Json;
@@ -10,7 +11,7 @@ use uuid::Uuid;
 async fn get(
     id: Uuid,
     conn: Db,
-    _token: HasEditorPermissions,
+    _token: Authorized<OfPersonSkill, Viewer>,
 ) -> Result<Option<Json<PersonSkill>>> {
     let skill = PersonSkill::get(&conn, id).await?;
     Ok(skill.map(Json))
@@ -20,20 +21,26 @@ async fn get(
 async fn insert(
     skill: Json<NewPersonSkill>,
     conn: Db,
-    _token: HasEditorPermissions,
+    access: CaseAccess,
 ) -> Result<Json<PersonSkill>> {
-    let skill = PersonSkill::new(&conn, skill.into_inner()).await?;
+    access
+        .require(&conn, &*skill, CasePermission::Editor)
+        .await?;
+    let skill = PersonSkill::new(&conn, skill.into_inner(), access.0.user_id).await?;
     Ok(Json(skill))
 }
 
 #[put("/", data = "<skill>")]
-async fn update(skill: Json<PersonSkill>, conn: Db, _token: HasEditorPermissions) -> Result<()> {
-    skill.into_inner().update(&conn).await
+async fn update(skill: Json<PersonSkill>, conn: Db, access: CaseAccess) -> Result<()> {
+    access
+        .require(&conn, &*skill, CasePermission::Editor)
+        .await?;
+    skill.into_inner().update(&conn, access.0.user_id).await
 }
 
 #[delete("/<id>")]
-async fn delete(id: Uuid, conn: Db, _token: HasEditorPermissions) -> Result<()> {
-    PersonSkill::delete(&conn, id).await
+async fn delete(id: Uuid, conn: Db, token: Authorized<OfPersonSkill, Editor>) -> Result<()> {
+    PersonSkill::delete(&conn, id, token.0.user_id).await
 }
 
 pub fn get_routes() -> Vec<Route> {
@@ -1,69 +1,211 @@
-use super::jwt::HasEditorPermissions;
+use self::models::PersonWithDuplicates;
+
+use super::calendar::CalendarJson;
+use super::case_access::{Authorized, CaseAccess, Editor, OfPerson, Viewer};
+use super::jwt::IsAdmin;
 use super::Db;
+use crate::access::{CasePermission, Target};
+use crate::agenda;
 use crate::errors::*;
+use crate::filters::*;
 use crate::models::*;
+use crate::pagination::{Page, PageRequest, Sort, DEFAULT_PAGE_LIMIT, MAX_PAGE_LIMIT};
+use crate::service_options::ServiceOptions;
+use crate::skills::{self, SkillMatch};
+use crate::validation::Validate;
 use rocket::serde::json::Json;
 use rocket::Route;
 use uuid::Uuid;
 
+mod models {
+    use crate::models::Person;
+    use serde::Serialize;
+
+    /// A saved person along with the other persons sharing its national
+    /// number, which is only non-empty under the "warn" duplicate policy.
+    #[derive(Serialize)]
+    pub struct PersonWithDuplicates {
+        #[serde(flatten)]
+        pub person: Person,
+        pub duplicates: Vec<Person>,
+    }
+}
+
 #[get("/<id>")]
-async fn get(id: Uuid, conn: Db, _token: HasEditorPermissions) -> Result<Option<Json<Person>>> {
+async fn get(
+    id: Uuid,
+    conn: Db,
+    _token: Authorized<OfPerson, Viewer>,
+) -> Result<Option<Json<Person>>> {
     let person = Person::get(&conn, id).await?;
     Ok(person.map(Json))
 }
 
-#[get("/")]
-async fn get_all(conn: Db, _token: HasEditorPermissions) -> Result<Json<Vec<Person>>> {
-    //TODO: Needs pagination
-    let persons = Person::all(&conn).await;
+#[get("/?<limit>&<cursor>&<sort>&<filter..>")]
+async fn get_all(
+    limit: Option<i64>,
+    cursor: Option<String>,
+    sort: Option<Sort<PersonSortKey>>,
+    filter: PersonFilter,
+    conn: Db,
+    access: CaseAccess,
+) -> Result<Json<Page<Person>>> {
+    let page = PageRequest::new(limit, cursor, sort.unwrap_or_default())?;
+    let persons = Person::page(&conn, filter, page, access.visibility()).await;
     persons.map(Json)
 }
 
 #[post("/", data = "<person>")]
 async fn insert(
-    person: Json<NewPerson>,
+    person: CalendarJson<NewPerson>,
     conn: Db,
-    _token: HasEditorPermissions,
+    opts: ServiceOptions,
+    access: CaseAccess,
+) -> Result<Json<PersonWithDuplicates>> {
+    access
+        .require(&conn, &*person, CasePermission::Editor)
+        .await?;
+    let person = Person::new(
+        &conn,
+        person.into_inner(),
+        opts.duplicate_policy,
+        access.0.user_id,
+    )
+    .await?;
+    let duplicates = person
+        .same_national_number(&conn, access.visibility())
+        .await?;
+    Ok(Json(PersonWithDuplicates { person, duplicates }))
+}
+
+#[put("/", data = "<person>")]
+async fn update(
+    person: CalendarJson<Person>,
+    conn: Db,
+    opts: ServiceOptions,
+    access: CaseAccess,
+) -> Result<Json<Vec<Person>>> {
+    access
+        .require(&conn, &*person, CasePermission::Editor)
+        .await?;
+    let person = person.into_inner().validate()?;
+    person
+        .clone()
+        .update(&conn, opts.duplicate_policy, access.0.user_id)
+        .await?;
+    let duplicates = person
+        .same_national_number(&conn, access.visibility())
+        .await?;
+    Ok(Json(duplicates))
+}
+
+/// Adults without a current job who have any of the given skills, for
+/// employment placement.
+#[get("/by-skill?<skill>&<limit>")]
+async fn get_by_skill(
+    skill: Vec<Uuid>,
+    limit: Option<i64>,
+    conn: Db,
+    access: CaseAccess,
+) -> Result<Json<Vec<SkillMatch>>> {
+    if skill.is_empty() {
+        return Err(Errors::BadRequest(
+            "at least one skill is required".to_owned(),
+        ));
+    }
+    let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+    if !(1..=MAX_PAGE_LIMIT).contains(&limit) {
+        return Err(Errors::BadRequest(format!(
+            "limit must be between 1 and {}",
+            MAX_PAGE_LIMIT
+        )));
+    }
+    let matches = skills::unemployed_with(&conn, skill, access.visibility(), limit).await?;
+    Ok(Json(matches))
+}
+
+#[get("/duplicates")]
+async fn get_duplicates(conn: Db, access: CaseAccess) -> Result<Json<Vec<DuplicateGroup>>> {
+    let groups = Person::duplicates(&conn, access.visibility()).await?;
+    Ok(Json(groups))
+}
+
+#[post("/<id>/merge-into/<target_id>")]
+async fn merge(
+    id: Uuid,
+    target_id: Uuid,
+    conn: Db,
+    token: Authorized<OfPerson, Editor>,
+    access: CaseAccess,
 ) -> Result<Json<Person>> {
-    let person = Person::new(&conn, person.into_inner()).await?;
+    access
+        .require(&conn, &Target::Person(target_id), CasePermission::Editor)
+        .await?;
+    let person = Person::merge(&conn, id, target_id, token.0.user_id).await?;
     Ok(Json(person))
 }
 
-#[put("/", data = "<person>")]
-async fn update(person: Json<Person>, conn: Db, _token: HasEditorPermissions) -> Result<()> {
-    person.into_inner().update(&conn).await
+/// Has the database refuse a second live person with the same national
+/// number, as the "reject" duplicate policy expects.
+#[put("/unique-national-numbers")]
+async fn add_unique_national_numbers(conn: Db, _admin: IsAdmin) -> Result<()> {
+    Person::set_unique_national_numbers(&conn, true).await
+}
+
+/// Lets live persons share national numbers again, as "warn" allows.
+#[delete("/unique-national-numbers")]
+async fn drop_unique_national_numbers(conn: Db, _admin: IsAdmin) -> Result<()> {
+    Person::set_unique_national_numbers(&conn, false).await
 }
 
 #[delete("/<id>")]
-async fn delete(id: Uuid, conn: Db, _token: HasEditorPermissions) -> Result<()> {
-    Person::delete(&conn, id).await
+async fn delete(id: Uuid, conn: Db, token: Authorized<OfPerson, Editor>) -> Result<()> {
+    Person::delete(&conn, id, token.0.user_id).await
+}
+
+#[post("/<id>/restore")]
+async fn restore(id: Uuid, conn: Db, opts: ServiceOptions, admin: IsAdmin) -> Result<()> {
+    Person::restore(&conn, id, opts.duplicate_policy, admin.0.user_id).await
 }
 
 #[post("/<id>/set-leader")]
-async fn set_leader(id: Uuid, conn: Db, _token: HasEditorPermissions) -> Result<()> {
-    Person::set_leader(&conn, id).await
+async fn set_leader(id: Uuid, conn: Db, token: Authorized<OfPerson, Editor>) -> Result<()> {
+    Person::set_leader(&conn, id, token.0.user_id).await
 }
 
 #[post("/<id>/clear-leader")]
-async fn clear_leader(id: Uuid, conn: Db, _token: HasEditorPermissions) -> Result<()> {
-    Person::clear_leader(&conn, id).await
+async fn clear_leader(id: Uuid, conn: Db, token: Authorized<OfPerson, Editor>) -> Result<()> {
+    Person::clear_leader(&conn, id, token.0.user_id).await
 }
 
 #[get("/<id>/job")]
 async fn get_jobs(
     id: Uuid,
     conn: Db,
-    _token: HasEditorPermissions,
+    _token: Authorized<OfPerson, Viewer>,
 ) -> Result<Json<Vec<PersonJob>>> {
     let jobs = PersonJob::all_by_person_id(&conn, id).await?;
     Ok(Json(jobs))
 }
 
+/// Jobs from latest to earliest, with the spans between them.
+#[get("/<id>/employment?<tz>")]
+async fn get_employment(
+    id: Uuid,
+    tz: Option<String>,
+    conn: Db,
+    token: Authorized<OfPerson, Viewer>,
+) -> Result<Json<EmploymentTimeline>> {
+    let tz = agenda::timezone(&conn, token.0.user_id, tz).await?;
+    let timeline = PersonJob::timeline(&conn, id, agenda::today(tz)).await?;
+    Ok(Json(timeline))
+}
+
 #[get("/<id>/requirement")]
 async fn get_requirements(
     id: Uuid,
     conn: Db,
-    _token: HasEditorPermissions,
+    _token: Authorized<OfPerson, Viewer>,
 ) -> Result<Json<Vec<PersonRequirement>>> {
     let requirements = PersonRequirement::all_by_person_id(&conn, id).await?;
     Ok(Json(requirements))
@@ -73,7 +215,7 @@ async fn get_requirements(
 async fn get_skills(
     id: Uuid,
     conn: Db,
-    _token: HasEditorPermissions,
+    _token: Authorized<OfPerson, Viewer>,
 ) -> Result<Json<Vec<PersonSkill>>> {
     let skills = PersonSkill::all_by_person_id(&conn, id).await?;
     Ok(Json(skills))
@@ -86,10 +228,17 @@ pub fn get_routes() -> Vec<Route> {
         insert,
         update,
         delete,
+        restore,
         set_leader,
         clear_leader,
+        get_duplicates,
+        get_by_skill,
+        merge,
+        add_unique_national_numbers,
+        drop_unique_national_numbers,
         get_requirements,
         get_jobs,
+        get_employment,
         get_skills,
     ]
 }
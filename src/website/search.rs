@@ -0,0 +1,23 @@
+use super::case_access::CaseAccess;
+use super::Db;
+use crate::errors::*;
+use crate::pagination::{DEFAULT_PAGE_LIMIT, MAX_PAGE_LIMIT};
+use crate::search::SearchResults;
+use rocket::serde::json::Json;
+use rocket::Route;
+
+#[get("/?<q>&<limit>")]
+async fn search(
+    q: String,
+    limit: Option<i64>,
+    conn: Db,
+    access: CaseAccess,
+) -> Result<Json<SearchResults>> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT).clamp(1, MAX_PAGE_LIMIT);
+    let results = SearchResults::find(&conn, &q, limit, access.visibility()).await?;
+    Ok(Json(results))
+}
+
+pub fn get_routes() -> Vec<Route> {
+    routes![search]
+}
@@ -0,0 +1,60 @@
+use super::case_access::CaseAccess;
+use super::jwt::IsAdmin;
+use super::Db;
+use crate::errors::*;
+use crate::filters::*;
+use crate::skills::{Skill, SkillDetails, SkillRequest};
+use crate::validation::Validate;
+use rocket::serde::json::Json;
+use rocket::Route;
+use uuid::Uuid;
+
+#[get("/?<filter..>")]
+async fn get_all(
+    filter: SkillFilter,
+    conn: Db,
+    _access: CaseAccess,
+) -> Result<Json<Vec<SkillDetails>>> {
+    let skills = Skill::all(&conn, filter).await?;
+    Ok(Json(skills))
+}
+
+#[get("/<id>")]
+async fn get(id: Uuid, conn: Db, _access: CaseAccess) -> Result<Option<Json<SkillDetails>>> {
+    let skill = Skill::get(&conn, id).await?;
+    Ok(skill.map(Json))
+}
+
+#[post("/", data = "<skill>")]
+async fn insert(skill: Json<SkillRequest>, conn: Db, admin: IsAdmin) -> Result<Json<SkillDetails>> {
+    let skill = Skill::create(&conn, skill.into_inner().validate()?, admin.0.user_id).await?;
+    Ok(Json(skill))
+}
+
+#[put("/<id>", data = "<skill>")]
+async fn update(
+    id: Uuid,
+    skill: Json<SkillRequest>,
+    conn: Db,
+    admin: IsAdmin,
+) -> Result<Json<SkillDetails>> {
+    let skill = Skill::update(&conn, id, skill.into_inner().validate()?, admin.0.user_id).await?;
+    Ok(Json(skill))
+}
+
+#[delete("/<id>")]
+async fn delete(id: Uuid, conn: Db, admin: IsAdmin) -> Result<()> {
+    Skill::delete(&conn, id, admin.0.user_id).await
+}
+
+/// Folds a duplicate skill into `target_id`, which keeps its holders and
+/// takes its names as synonyms.
+#[post("/<id>/merge-into/<target_id>")]
+async fn merge(id: Uuid, target_id: Uuid, conn: Db, admin: IsAdmin) -> Result<Json<SkillDetails>> {
+    let skill = Skill::merge(&conn, id, target_id, admin.0.user_id).await?;
+    Ok(Json(skill))
+}
+
+pub fn get_routes() -> Vec<Route> {
+    routes![get_all, get, insert, update, delete, merge]
+}
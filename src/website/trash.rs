@@ -0,0 +1,58 @@
+use super::jwt::IsAdmin;
+use super::{Db, JobDatabase};
+use crate::errors::*;
+use crate::models::Trash;
+use crate::service_options::ServiceOptions;
+use chrono::{Duration, Utc};
+use rocket::fairing::AdHoc;
+use rocket::serde::json::Json;
+use rocket::Route;
+
+/// How often the trash is checked for rows past their retention period.
+const PURGE_INTERVAL_SECS: u64 = 60 * 60;
+
+#[get("/")]
+async fn get(conn: Db, _admin: IsAdmin) -> Result<Json<Trash>> {
+    let trash = Trash::get(&conn).await?;
+    Ok(Json(trash))
+}
+
+pub fn get_routes() -> Vec<Route> {
+    routes![get]
+}
+
+/// Purges the trash once at launch and then every `PURGE_INTERVAL_SECS`,
+/// removing what was deleted more than `trash_retention_days` ago.
+pub fn purge_fairing() -> AdHoc {
+    AdHoc::on_liftoff("Trash purge", |rocket| {
+        Box::pin(async move {
+            let retention = match ServiceOptions::create(rocket.figment()) {
+                Ok(opts) => Duration::days(opts.trash_retention_days),
+                Err(e) => {
+                    error!("trash purge disabled: {:?}", e);
+                    return;
+                }
+            };
+            let db = match JobDatabase::from(rocket) {
+                Ok(db) => db,
+                Err(e) => {
+                    error!("trash purge disabled: {:?}", e);
+                    return;
+                }
+            };
+
+            rocket::tokio::spawn(async move {
+                let mut interval = rocket::tokio::time::interval(std::time::Duration::from_secs(
+                    PURGE_INTERVAL_SECS,
+                ));
+                loop {
+                    interval.tick().await;
+                    let deleted_before = Utc::now().naive_utc() - retention;
+                    if let Err(e) = db.run(move |c| Trash::purge_in(c, deleted_before)).await {
+                        error!("trash purge failed: {:?}", e);
+                    }
+                }
+            });
+        })
+    })
+}
@@ -1,16 +1,24 @@
-use self::models::UserInfo;
+use self::models::{
+    ChangePasswordRequest, PasswordResetResponse, RoleRequest, TimezoneRequest, UserInfo,
+};
 
+use super::auth::REFRESH_TOKEN_SUBJECT;
 use super::jwt;
 use super::Db;
 use crate::errors::*;
+use crate::filters::*;
 use crate::models::*;
+use crate::pagination::{Page, PageRequest, Sort};
+use crate::service_options::ServiceOptions;
+use crate::user_token_service;
+use crate::validation::{self, Validate};
 use rocket::serde::json::Json;
 use rocket::Route;
 use uuid::Uuid;
 
 pub mod models {
-    use crate::{models::User, website::jwt::Role};
-    use serde::Serialize;
+    use crate::{enums::Role, models::User};
+    use serde::{Deserialize, Serialize};
     use uuid::Uuid;
 
     #[derive(Serialize)]
@@ -20,6 +28,30 @@ pub mod models {
         pub first_name: String,
         pub last_name: String,
         pub role: Role,
+        pub active: bool,
+        pub must_change_password: bool,
+        pub timezone: Option<String>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct RoleRequest {
+        pub role: Role,
+    }
+
+    #[derive(Deserialize)]
+    pub struct ChangePasswordRequest {
+        pub current_password: String,
+        pub new_password: String,
+    }
+
+    #[derive(Deserialize)]
+    pub struct TimezoneRequest {
+        pub timezone: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    pub struct PasswordResetResponse {
+        pub temporary_password: String,
     }
 
     impl UserInfo {
@@ -29,7 +61,10 @@ pub mod models {
                 username: user.username,
                 first_name: user.first_name,
                 last_name: user.last_name,
-                role: user.role.into(),
+                role: user.role,
+                active: user.active,
+                must_change_password: user.must_change_password,
+                timezone: user.timezone,
             }
         }
     }
@@ -41,24 +76,179 @@ async fn get(id: Uuid, conn: Db, _admin: jwt::IsAdmin) -> Result<Option<Json<Use
     Ok(user.map(UserInfo::of_user).map(Json))
 }
 
-#[get("/")]
-async fn get_all(conn: Db, _admin: jwt::IsAdmin) -> Result<Json<Vec<UserInfo>>> {
-    //TODO: Needs pagination
-    let mut results = Vec::new();
+#[get("/?<limit>&<cursor>&<sort>&<filter..>")]
+async fn get_all(
+    limit: Option<i64>,
+    cursor: Option<String>,
+    sort: Option<Sort<UserSortKey>>,
+    filter: UserFilter,
+    conn: Db,
+    _admin: jwt::IsAdmin,
+) -> Result<Json<Page<UserInfo>>> {
+    let page = PageRequest::new(limit, cursor, sort.unwrap_or_default())?;
+    let users = User::page(&conn, filter, page).await?;
+
+    Ok(Json(Page {
+        items: users.items.into_iter().map(UserInfo::of_user).collect(),
+        next_cursor: users.next_cursor,
+        total: users.total,
+    }))
+}
+
+#[post("/", data = "<user>")]
+async fn insert(
+    user: Json<NewUser>,
+    conn: Db,
+    opts: ServiceOptions,
+    admin: jwt::IsAdmin,
+) -> Result<Json<UserInfo>> {
+    let user = User::new(
+        &conn,
+        user.into_inner().validate()?,
+        opts.password_hashing,
+        admin.0.user_id,
+    )
+    .await?;
+    Ok(Json(UserInfo::of_user(user)))
+}
+
+/// Admins may not lock themselves out by demoting, disabling or deleting
+/// their own account.
+fn not_self(admin: &jwt::IsAdmin, id: Uuid) -> Result<()> {
+    if admin.0.user_id == id {
+        return Err(Errors::BadRequest(
+            "admins cannot do this to their own account".into(),
+        ));
+    }
+    Ok(())
+}
+
+#[put("/<id>", data = "<profile>")]
+async fn update(
+    id: Uuid,
+    profile: Json<UserProfile>,
+    conn: Db,
+    admin: jwt::IsAdmin,
+) -> Result<Json<UserInfo>> {
+    let profile = profile.into_inner().validate()?;
+    let user = User::update_profile(&conn, id, profile, admin.0.user_id).await?;
+    Ok(Json(UserInfo::of_user(user)))
+}
+
+#[put("/<id>/role", data = "<request>")]
+async fn set_role(
+    id: Uuid,
+    request: Json<RoleRequest>,
+    conn: Db,
+    admin: jwt::IsAdmin,
+) -> Result<Json<UserInfo>> {
+    not_self(&admin, id)?;
+    let user = User::set_role(&conn, id, request.role, admin.0.user_id).await?;
+    Ok(Json(UserInfo::of_user(user)))
+}
+
+#[post("/<id>/disable")]
+async fn disable(
+    id: Uuid,
+    conn: Db,
+    user_token_service: user_token_service::T,
+    admin: jwt::IsAdmin,
+) -> Result<Json<UserInfo>> {
+    not_self(&admin, id)?;
+    let user = User::set_active(&conn, id, false, admin.0.user_id).await?;
+    user_token_service
+        .revoke(id, REFRESH_TOKEN_SUBJECT.into())
+        .await?;
+    Ok(Json(UserInfo::of_user(user)))
+}
+
+#[post("/<id>/enable")]
+async fn enable(id: Uuid, conn: Db, admin: jwt::IsAdmin) -> Result<Json<UserInfo>> {
+    let user = User::set_active(&conn, id, true, admin.0.user_id).await?;
+    Ok(Json(UserInfo::of_user(user)))
+}
 
-    for user in User::all(&conn).await? {
-        results.push(UserInfo::of_user(user));
+/// Logs the user out everywhere and gives them a temporary password.
+#[post("/<id>/reset-password")]
+async fn reset_password(
+    id: Uuid,
+    conn: Db,
+    opts: ServiceOptions,
+    user_token_service: user_token_service::T,
+    admin: jwt::IsAdmin,
+) -> Result<Json<PasswordResetResponse>> {
+    let temporary_password =
+        User::reset_password(&conn, id, opts.password_hashing, admin.0.user_id).await?;
+    user_token_service
+        .revoke(id, REFRESH_TOKEN_SUBJECT.into())
+        .await?;
+    Ok(Json(PasswordResetResponse { temporary_password }))
+}
+
+#[delete("/<id>?<reassign_to>")]
+async fn delete(id: Uuid, reassign_to: Option<Uuid>, conn: Db, admin: jwt::IsAdmin) -> Result<()> {
+    not_self(&admin, id)?;
+    User::delete(&conn, id, reassign_to, admin.0.user_id).await
+}
+
+/// Changes the caller's own password and ends their other sessions.
+#[post("/password", data = "<request>")]
+async fn change_password(
+    request: Json<ChangePasswordRequest>,
+    conn: Db,
+    opts: ServiceOptions,
+    user_token_service: user_token_service::T,
+    claims: jwt::ChangingPassword,
+) -> Result<()> {
+    let request = request.into_inner();
+    if let Err((code, message)) = validation::password(&request.new_password) {
+        return Err(validation::field_error("new_password", code, message));
     }
 
-    Ok(Json(results))
+    User::change_password(
+        &conn,
+        claims.0.user_id,
+        request.current_password,
+        request.new_password,
+        opts.password_hashing,
+    )
+    .await?;
+    user_token_service
+        .revoke_other_sessions(claims.0.user_id, REFRESH_TOKEN_SUBJECT.into(), claims.0.sid)
+        .await
 }
 
-#[post("/", data = "<user>")]
-async fn insert(user: Json<NewUser>, conn: Db, _admin: jwt::IsAdmin) -> Result<Json<UserInfo>> {
-    let user = User::new(&conn, user.into_inner()).await?;
+/// Sets the time zone the caller's agenda is read in; `null` goes back to
+/// UTC.
+#[put("/timezone", data = "<request>")]
+async fn set_timezone(
+    request: Json<TimezoneRequest>,
+    conn: Db,
+    claims: jwt::IsLoggedIn,
+) -> Result<Json<UserInfo>> {
+    let timezone = match &request.timezone {
+        Some(tz) => match validation::timezone(tz) {
+            Ok(tz) => Some(tz.name().to_owned()),
+            Err((code, message)) => return Err(validation::field_error("timezone", code, message)),
+        },
+        None => None,
+    };
+    let user = User::set_timezone(&conn, claims.0.user_id, timezone).await?;
     Ok(Json(UserInfo::of_user(user)))
 }
 
 pub fn get_routes() -> Vec<Route> {
-    routes![get, get_all, insert]
+    routes![
+        get,
+        get_all,
+        insert,
+        update,
+        set_role,
+        disable,
+        enable,
+        reset_password,
+        delete,
+        change_password,
+        set_timezone
+    ]
 }